@@ -0,0 +1,1596 @@
+#![allow(clippy::module_inception)]
+#[allow(dead_code)]
+pub mod asm {
+    //! This module implements a small two-pass assembler for RV64IMAC
+    //! text in GNU syntax, so that programs can be built in memory without
+    //! a cross toolchain. It knows labels, `.equ` constants, `%hi`/`%lo`,
+    //! the common pseudo-instructions (`li`, `la`, `mv`, `call`, `ret`,
+    //! `beqz`, ...), explicit `c.` compressed forms and the data
+    //! directives (.byte/.half/.word/.dword/.ascii/.asciz/.zero/.align).
+    //! Instructions are never compressed implicitly. Vector instructions
+    //! take their masks as a trailing `v0.t` operand, and `vsetvli` its
+    //! vtype as `e32,m2,ta,ma`.
+    //!
+    //! ```text
+    //!         .data
+    //! msg:    .asciz "Hello"
+    //!         .text
+    //! main:   addi sp, sp, -16
+    //!         sd ra, 8(sp)
+    //!         la a0, msg
+    //!         call puts
+    //!         ld ra, 8(sp)
+    //!         addi sp, sp, 16
+    //!         ret
+    //! ```
+
+    use std::collections::HashMap;
+    use crate::{SimError, VMA, Program, Loader, InstCode, RegID, VSrc};
+    use crate::disasm::disasm::CSRS;
+    use crate::counters::counters::counter_number;
+    use crate::vector::vector::{vector_csr_number, ARITH, REDUCTIONS, MASK_LOGICAL};
+
+    /// The simulated library functions, which get a stub in .text when
+    /// used but not defined by the source.
+    const LIBRARY_FUNCS: [&str; 2] = ["puts", "printf"];
+
+    /// Constructors of the instructions by operand types.
+    type RType = fn(RegID, RegID, RegID) -> InstCode;
+    type IType = fn(RegID, RegID, i16) -> InstCode;
+    type Unary = fn(RegID, RegID) -> InstCode;
+    type Atomic = fn(RegID, RegID, RegID, u8, u8) -> InstCode;
+
+    /// Pseudo-struct for assembling a piece of source text.
+    pub struct Assembler {
+        source: String,
+        /// XLEN of the encodings, 64 unless given
+        xlen: u32,
+    }
+
+    impl Assembler {
+        /// Where .text starts, as with the GNU linker. The .data section
+        /// follows on the next page.
+        pub const TEXT_BASE: u64 = 0x10000u64;
+        pub const PAGE_SIZE: u64 = 0x1000u64;
+
+        pub fn new(source: &str) -> Self {
+            Assembler {
+                source: String::from(source),
+                xlen: 64,
+            }
+        }
+
+        /// Use the encodings of `xlen`, where they differ between RV32
+        /// and RV64.
+        pub fn with_xlen(mut self, xlen: u32) -> Self {
+            self.xlen = xlen;
+            self
+        }
+
+        /// Assemble into a runnable program. Execution starts at `main`,
+        /// `_start` or else the first instruction, and text labels other
+        /// than the local ones (`.L...`, `1:`) become the functions of
+        /// the program.
+        pub fn load(&self) -> Result<Program, SimError> {
+            let mut source = self.source.clone();
+            let mut pass = Pass::run(&source, [Assembler::TEXT_BASE, 0], None, true, self.xlen)?;
+            let stubs: Vec<&str> = LIBRARY_FUNCS.iter().copied()
+                .filter(|name| !pass.defines(name) && mentions(&source, name))
+                .collect();
+            if !stubs.is_empty() {
+                source.push_str("\n.text\n");
+                for name in stubs.iter() {
+                    source.push_str(&format!("{}: ret\n", name));
+                }
+                pass = Pass::run(&source, [Assembler::TEXT_BASE, 0], None, true, self.xlen)?;
+            }
+
+            let text_end = Assembler::TEXT_BASE + pass.sections[0].len() as u64;
+            let data_base = (text_end + Assembler::PAGE_SIZE - 1) & !(Assembler::PAGE_SIZE - 1);
+            let bases = [Assembler::TEXT_BASE, data_base];
+            let labels = pass.addresses(bases);
+            let pass = Pass::run(&source, bases, Some(&labels), true, self.xlen)?;
+
+            let mut vmas = Vec::new();
+            for (idx, memory) in pass.sections.into_iter().enumerate() {
+                if !memory.is_empty() {
+                    vmas.push(VMA {
+                        lower_bound: bases[idx],
+                        size: memory.len() as u64,
+                        readable: true,
+                        writeble: idx == 1,
+                        executable: idx == 0,
+                        memory,
+                    });
+                }
+            }
+
+            // Functions extend to the next text label
+            let mut starts: Vec<(u64, &str)> = pass.defined.iter()
+                .filter(|(name, section, _)| *section == Section::Text && !name.starts_with(|c: char| c == '.' || c.is_ascii_digit()))
+                .map(|(name, _, ofs)| (Assembler::TEXT_BASE + *ofs as u64, name.as_str()))
+                .collect();
+            starts.sort();
+            let mut funcs = Vec::new();
+            for (idx, &(start, name)) in starts.iter().enumerate() {
+                let end = starts[idx+1..].iter()
+                    .map(|&(addr, _)| addr)
+                    .find(|&addr| addr > start)
+                    .unwrap_or(text_end);
+                if end > start {
+                    funcs.push((start, (end - start) as usize, String::from(name)));
+                }
+            }
+
+            let simulated_library_funcs = stubs.iter()
+                .map(|name| (labels[*name], String::from(*name)))
+                .collect();
+            let entry_point = labels.get("main")
+                .or(labels.get("_start"))
+                .copied()
+                .unwrap_or(Assembler::TEXT_BASE);
+
+            Ok(Loader::program(entry_point, vmas, simulated_library_funcs, funcs, None))
+        }
+
+        /// Assemble as code placed at `addr`, for patching a loaded program.
+        /// `symbols` (start, sz, name) may be referred to, such as the
+        /// functions of the program. Switching to .data is not allowed.
+        pub fn assemble_at(&self, addr: u64, symbols: &[(u64, usize, String)]) -> Result<Vec<u8>, SimError> {
+            let pass = Pass::run(&self.source, [addr, addr], None, false, self.xlen)?;
+            let mut labels: HashMap<String, u64> = symbols.iter()
+                .map(|(start, _, name)| (name.clone(), *start))
+                .collect();
+            labels.extend(pass.addresses([addr, addr]));
+            let pass = Pass::run(&self.source, [addr, addr], Some(&labels), false, self.xlen)?;
+            Ok(pass.sections.into_iter().next().unwrap())
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Section {
+        Text,
+        Data,
+    }
+
+    /// State of one pass over the source. The first pass has no `labels`
+    /// and only lays out the sections, with every symbol standing in for
+    /// the current address; the second one encodes for real.
+    struct Pass<'a> {
+        labels: Option<&'a HashMap<String, u64>>,
+        /// Values of `.equ` constants
+        constants: HashMap<String, i64>,
+        bases: [u64; 2],
+        sections: [Vec<u8>; 2],
+        section: Section,
+        /// Whether .data may be used
+        data: bool,
+        /// Labels defined in this pass
+        defined: Vec<(String, Section, usize)>, // (name, section, offset)
+        /// Number of times each numeric local label was defined so far
+        local: HashMap<String, usize>,
+        xlen: u32,
+    }
+
+    impl<'a> Pass<'a> {
+        fn run(source: &str, bases: [u64; 2], labels: Option<&'a HashMap<String, u64>>, data: bool, xlen: u32) -> Result<Self, SimError> {
+            let mut pass = Pass {
+                labels,
+                constants: HashMap::new(),
+                bases,
+                sections: [Vec::new(), Vec::new()],
+                section: Section::Text,
+                data,
+                defined: Vec::new(),
+                local: HashMap::new(),
+                xlen,
+            };
+            for (nr, statement) in statements(source) {
+                if let Err(e) = pass.statement(&statement) {
+                    return Err(SimError::AsmError(format!("line {}: {}", nr, e)));
+                }
+            }
+            Ok(pass)
+        }
+
+        fn defines(&self, name: &str) -> bool {
+            self.defined.iter().any(|(label, _, _)| label == name) || self.constants.contains_key(name)
+        }
+
+        /// Addresses of the labels defined, given the section bases.
+        fn addresses(&self, bases: [u64; 2]) -> HashMap<String, u64> {
+            self.defined.iter()
+                .map(|(name, section, ofs)| (name.clone(), bases[*section as usize] + *ofs as u64))
+                .collect()
+        }
+
+        fn pc(&self) -> u64 {
+            self.bases[self.section as usize] + self.sections[self.section as usize].len() as u64
+        }
+
+        fn emit(&mut self, bytes: &[u8]) {
+            self.sections[self.section as usize].extend_from_slice(bytes);
+        }
+
+        /// Assemble one statement: labels, then a directive or instruction.
+        fn statement(&mut self, statement: &str) -> Result<(), String> {
+            let mut rest = statement.trim();
+            while let Some(idx) = rest.find(':') {
+                let name = &rest[..idx];
+                if name.is_empty() || !name.chars().all(is_symbol_char) {
+                    break;
+                }
+                let ofs = self.sections[self.section as usize].len();
+                if name.chars().all(|c| c.is_ascii_digit()) {
+                    // Local labels may be redefined, and are told apart by
+                    // how many came before
+                    let count = self.local.entry(String::from(name)).or_insert(0);
+                    self.defined.push((format!("{}:{}", name, count), self.section, ofs));
+                    *count += 1;
+                }
+                else if name.starts_with(|c: char| c.is_ascii_digit()) {
+                    return Err(format!("bad label `{}`", name));
+                }
+                else if self.defines(name) {
+                    return Err(format!("symbol `{}` is already defined", name));
+                }
+                else {
+                    self.defined.push((String::from(name), self.section, ofs));
+                }
+                rest = rest[idx+1..].trim_start();
+            }
+            if rest.is_empty() {
+                return Ok(());
+            }
+
+            let (mnemonic, operands) = match rest.find(char::is_whitespace) {
+                Some(idx) => (&rest[..idx], rest[idx..].trim()),
+                None => (rest, ""),
+            };
+            let mnemonic = mnemonic.to_lowercase();
+            let ops = split_operands(operands);
+            if mnemonic.starts_with('.') {
+                return self.directive(&mnemonic, &ops);
+            }
+
+            if let Some(base) = mnemonic.strip_prefix("c.") {
+                let codes = self.compressed(base, &ops)?;
+                let half = match codes.as_slice() {
+                    [code] => code.encode_compressed(self.xlen),
+                    _ => None,
+                };
+                return match half {
+                    Some(half) => {
+                        self.emit(&half.to_le_bytes());
+                        Ok(())
+                    },
+                    None => Err(format!("`{}` has no compressed form", rest)),
+                };
+            }
+            for code in self.instruction(&mnemonic, &ops)? {
+                self.emit(&code.encode(self.xlen).to_le_bytes());
+            }
+            Ok(())
+        }
+
+        fn directive(&mut self, directive: &str, ops: &[&str]) -> Result<(), String> {
+            match directive {
+                ".text" => self.switch(Section::Text),
+                ".data" | ".rodata" | ".bss" => self.switch(Section::Data),
+                ".section" => {
+                    let name = ops.first().ok_or("expected a section name")?;
+                    if name.starts_with(".text") {
+                        self.switch(Section::Text)
+                    }
+                    else if [".data", ".rodata", ".bss", ".sdata", ".sbss"].iter().any(|prefix| name.starts_with(prefix)) {
+                        self.switch(Section::Data)
+                    }
+                    else {
+                        Err(format!("unknown section `{}`", name))
+                    }
+                },
+                ".byte" | ".half" | ".short" | ".2byte" | ".word" | ".long" | ".4byte" | ".dword" | ".quad" | ".8byte" => {
+                    let width = match directive {
+                        ".byte" => 1,
+                        ".half" | ".short" | ".2byte" => 2,
+                        ".word" | ".long" | ".4byte" => 4,
+                        _ => 8,
+                    };
+                    for op in ops {
+                        let val = self.eval(op, true)?;
+                        let bits = width * 8;
+                        if bits < 64 && (val < -(1i64 << (bits - 1)) || val >= 1i64 << bits) {
+                            return Err(format!("value {:#x} does not fit in {}", val, directive));
+                        }
+                        self.emit(&val.to_le_bytes()[..width]);
+                    }
+                    Ok(())
+                },
+                ".ascii" | ".asciz" | ".string" => {
+                    for op in ops {
+                        let mut bytes = string(op)?;
+                        if directive != ".ascii" {
+                            bytes.push(0);
+                        }
+                        self.emit(&bytes);
+                    }
+                    Ok(())
+                },
+                ".zero" | ".space" | ".skip" => {
+                    let len = self.eval(ops.first().ok_or("expected a size")?, false)?;
+                    let fill = match ops.get(1) {
+                        Some(op) => self.eval(op, false)? as u8,
+                        None => 0,
+                    };
+                    if !(0..=0x1000000).contains(&len) {
+                        return Err(format!("bad size {}", len));
+                    }
+                    self.emit(&vec![fill; len as usize]);
+                    Ok(())
+                },
+                ".align" | ".p2align" | ".balign" => {
+                    let val = self.eval(ops.first().ok_or("expected an alignment")?, false)?;
+                    let align = if directive == ".balign" { val } else if (0..=12).contains(&val) { 1 << val } else { 0 };
+                    if align <= 0 || align > Assembler::PAGE_SIZE as i64 || align & (align - 1) != 0 {
+                        return Err(format!("bad alignment {}", val));
+                    }
+                    let pad = ((align as u64 - self.pc() % align as u64) % align as u64) as usize;
+                    if self.section == Section::Text && pad.is_multiple_of(4) {
+                        // Pad code with nops
+                        for _ in 0..pad / 4 {
+                            self.emit(&InstCode::Addi(RegID::X0, RegID::X0, 0).encode(self.xlen).to_le_bytes());
+                        }
+                    }
+                    else {
+                        self.emit(&vec![0u8; pad]);
+                    }
+                    Ok(())
+                },
+                ".equ" | ".set" => {
+                    if ops.len() != 2 || !ops[0].chars().all(is_symbol_char) {
+                        return Err(format!("expected \"{} name, value\"", directive));
+                    }
+                    let val = self.eval(ops[1], false)?;
+                    self.constants.insert(String::from(ops[0]), val);
+                    Ok(())
+                },
+                // Only meaningful to the linker or the debug info
+                ".globl" | ".global" | ".local" | ".weak" | ".type" | ".size" | ".option" | ".file" |
+                ".attribute" | ".ident" | ".loc" => Ok(()),
+                _ if directive.starts_with(".cfi_") => Ok(()),
+                _ => Err(format!("unknown directive `{}`", directive)),
+            }
+        }
+
+        fn switch(&mut self, section: Section) -> Result<(), String> {
+            if section == Section::Data && !self.data {
+                return Err(String::from("cannot switch to .data here"));
+            }
+            self.section = section;
+            Ok(())
+        }
+
+        /// Evaluate `src`: sums and differences of numbers, character
+        /// literals, symbols, `.` and `%hi()`/`%lo()`. Unless `symbols`,
+        /// only numbers and `.equ` constants may be used, as for values
+        /// deciding the layout.
+        fn eval(&self, src: &str, symbols: bool) -> Result<i64, String> {
+            let mut value = 0i64;
+            let mut sign = 1i64;
+            let mut rest = src.trim();
+            loop {
+                loop {
+                    if let Some(r) = rest.strip_prefix('-') {
+                        sign = -sign;
+                        rest = r.trim_start();
+                    }
+                    else if let Some(r) = rest.strip_prefix('+') {
+                        rest = r.trim_start();
+                    }
+                    else {
+                        break;
+                    }
+                }
+                let (term, r) = self.term(rest, symbols)?;
+                value = value.wrapping_add(sign.wrapping_mul(term));
+                rest = r.trim_start();
+                match rest.chars().next() {
+                    None => return Ok(value),
+                    Some('+') => sign = 1,
+                    Some('-') => sign = -1,
+                    Some(_) => return Err(format!("bad expression `{}`", src.trim())),
+                }
+                rest = rest[1..].trim_start();
+            }
+        }
+
+        /// Evaluate the term `src` starts with, returning the rest.
+        fn term<'s>(&self, src: &'s str, symbols: bool) -> Result<(i64, &'s str), String> {
+            if let Some(rest) = src.strip_prefix('(') {
+                let end = closing(rest)?;
+                return Ok((self.eval(&rest[..end], symbols)?, &rest[end+1..]));
+            }
+            if let Some(rest) = src.strip_prefix('%') {
+                let open = rest.find('(').ok_or(format!("bad expression `{}`", src))?;
+                let end = open + 1 + closing(&rest[open+1..])?;
+                let val = self.eval(&rest[open+1..end], symbols)?;
+                let val = match &rest[..open] {
+                    "hi" => hi(val),
+                    "lo" => lo(val),
+                    func => return Err(format!("unsupported relocation `%{}`", func)),
+                };
+                return Ok((val, &rest[end+1..]));
+            }
+            if let Some(rest) = src.strip_prefix('\'') {
+                let mut escaped = false;
+                let end = rest.char_indices()
+                    .find(|&(_, c)| {
+                        let close = c == '\'' && !escaped;
+                        escaped = c == '\\' && !escaped;
+                        close
+                    })
+                    .map(|(idx, _)| idx)
+                    .ok_or(format!("unterminated character `{}`", src))?;
+                return match unescape(&rest[..end])?.as_slice() {
+                    [byte] => Ok((*byte as i64, &rest[end+1..])),
+                    _ => Err(format!("bad character `{}`", &src[..end+2])),
+                };
+            }
+
+            let len = src.find(|c: char| !is_symbol_char(c)).unwrap_or(src.len());
+            let word = &src[..len];
+            if word.is_empty() {
+                return Err(if src.is_empty() { String::from("missing operand") } else { format!("bad expression `{}`", src) });
+            }
+            let local = word.strip_suffix('b').map(|num| (num, 1)).or(word.strip_suffix('f').map(|num| (num, 0)));
+            if let Some((num, back)) = local.filter(|(num, _)| !num.is_empty() && num.chars().all(|c| c.is_ascii_digit())) {
+                // The last local label `num` before, or the next one after
+                let count = self.local.get(num).copied().unwrap_or(0);
+                if count < back {
+                    return Err(format!("undefined local label `{}`", word));
+                }
+                return self.symbol(&format!("{}:{}", num, count - back), word, &src[len..]);
+            }
+            if word.starts_with(|c: char| c.is_ascii_digit()) {
+                return Ok((number(word)?, &src[len..]));
+            }
+            if word == "." {
+                return Ok((self.pc() as i64, &src[len..]));
+            }
+            if let Some(val) = self.constants.get(word) {
+                return Ok((*val, &src[len..]));
+            }
+            if !symbols {
+                return Err(format!("`{}` is not a constant", word));
+            }
+            self.symbol(word, word, &src[len..])
+        }
+
+        /// Look up the label `name`, written as `word`.
+        fn symbol<'s>(&self, name: &str, word: &str, rest: &'s str) -> Result<(i64, &'s str), String> {
+            match self.labels {
+                Some(labels) => match labels.get(name) {
+                    Some(addr) => Ok((*addr as i64, rest)),
+                    None => Err(format!("undefined symbol `{}`", word)),
+                },
+                // Laying out, any address does
+                None => Ok((self.pc() as i64, rest)),
+            }
+        }
+
+        fn imm(&self, src: &str, min: i64, max: i64) -> Result<i64, String> {
+            let val = self.eval(src, true)?;
+            if val < min || val > max {
+                return Err(format!("immediate {} out of range [{}, {}]", val, min, max));
+            }
+            Ok(val)
+        }
+
+        fn imm12(&self, src: &str) -> Result<i16, String> {
+            Ok(self.imm(src, -2048, 2047)? as i16)
+        }
+
+        /// Offset of the target `src` from `pc`, for a branch or jump
+        /// reaching `bits` bits.
+        fn offset(&self, src: &str, pc: u64, bits: u32) -> Result<i64, String> {
+            let ofs = self.eval(src, true)?.wrapping_sub(pc as i64);
+            if ofs % 2 != 0 || ofs < -(1 << (bits - 1)) || ofs >= 1 << (bits - 1) {
+                return Err(format!("target {} out of reach", src.trim()));
+            }
+            Ok(ofs)
+        }
+
+        /// `offset(reg)`, as in loads and stores.
+        fn mem(&self, src: &str) -> Result<(RegID, i16), String> {
+            let src = src.trim();
+            let open = src.rfind('(').filter(|_| src.ends_with(')'))
+                .ok_or(format!("expected offset(register), found `{}`", src))?;
+            let base = reg(&src[open+1..src.len()-1])?;
+            let ofs = if src[..open].trim().is_empty() { 0 } else { self.imm12(&src[..open])? };
+            Ok((base, ofs))
+        }
+
+        /// `auipc` plus the low part for reaching `target` from `pc`.
+        fn pcrel(&self, target: &str, pc: u64) -> Result<(i32, i16), String> {
+            let ofs = self.eval(target, true)?.wrapping_sub(pc as i64);
+            let hi = ofs.wrapping_add(0x800) >> 12;
+            if !(-0x80000..0x80000).contains(&hi) {
+                return Err(format!("{} out of reach", target.trim()));
+            }
+            Ok(((hi << 12) as i32, (ofs - (hi << 12)) as i16))
+        }
+
+        /// Assemble an instruction or pseudo-instruction at the current
+        /// address.
+        fn instruction(&self, mnemonic: &str, ops: &[&str]) -> Result<Vec<InstCode>, String> {
+            let pc = self.pc();
+            let count = |n: usize| -> Result<(), String> {
+                if ops.len() != n {
+                    return Err(format!("`{}` expects {} operands, found {}", mnemonic, n, ops.len()));
+                }
+                Ok(())
+            };
+            let zero = RegID::X0;
+
+            if mnemonic.starts_with('v') {
+                return Ok(vec![self.vector(mnemonic, ops)?]);
+            }
+            if let Some(op) = r_type(mnemonic) {
+                count(3)?;
+                return Ok(vec![op(reg(ops[0])?, reg(ops[1])?, reg(ops[2])?)]);
+            }
+            if let Some(op) = i_type(mnemonic) {
+                count(3)?;
+                return Ok(vec![op(reg(ops[0])?, reg(ops[1])?, self.imm12(ops[2])?)]);
+            }
+            if let Some((op, max, func)) = shift(mnemonic) {
+                count(3)?;
+                let shamt = self.imm(ops[2], 0, max)? as i16;
+                return Ok(vec![op(reg(ops[0])?, reg(ops[1])?, shamt | func)]);
+            }
+            if let Some(op) = unary(mnemonic) {
+                count(2)?;
+                return Ok(vec![op(reg(ops[0])?, reg(ops[1])?)]);
+            }
+            if let Some(op) = load(mnemonic) {
+                count(2)?;
+                let (base, ofs) = self.mem(ops[1])?;
+                return Ok(vec![op(reg(ops[0])?, base, ofs)]);
+            }
+            if let Some(op) = store(mnemonic) {
+                count(2)?;
+                let (base, ofs) = self.mem(ops[1])?;
+                return Ok(vec![op(base, reg(ops[0])?, ofs)]);
+            }
+            if let Some(op) = branch(mnemonic) {
+                count(3)?;
+                return Ok(vec![op(reg(ops[0])?, reg(ops[1])?, self.offset(ops[2], pc, 13)? as i16)]);
+            }
+            if let Some((op, swap)) = branch_zero(mnemonic) {
+                count(2)?;
+                let rs = reg(ops[0])?;
+                let (rs1, rs2) = if swap { (zero, rs) } else { (rs, zero) };
+                return Ok(vec![op(rs1, rs2, self.offset(ops[1], pc, 13)? as i16)]);
+            }
+            if let Some(op) = branch_swapped(mnemonic) {
+                count(3)?;
+                return Ok(vec![op(reg(ops[1])?, reg(ops[0])?, self.offset(ops[2], pc, 13)? as i16)]);
+            }
+            if let Some((op, width, order)) = atomic(mnemonic) {
+                let (rs2, addr) = match (mnemonic.starts_with("lr."), ops.len()) {
+                    (true, 2) => (zero, ops[1]),
+                    (false, 3) => (reg(ops[1])?, ops[2]),
+                    _ => return Err(format!("`{}` expects {} operands", mnemonic, if mnemonic.starts_with("lr.") { 2 } else { 3 })),
+                };
+                let (base, ofs) = self.mem(addr)?;
+                if ofs != 0 {
+                    return Err(String::from("atomics take no offset"));
+                }
+                return Ok(vec![op(reg(ops[0])?, base, rs2, width, order)]);
+            }
+            if let Some((op, imm)) = csr(mnemonic) {
+                count(3)?;
+                let num = csr_number(ops[1])?;
+                let rd = reg(ops[0])?;
+                return Ok(vec![if imm {
+                    let uimm = self.imm(ops[2], 0, 31)? as u8;
+                    match op {
+                        "rw" => InstCode::Csrrwi(rd, num, uimm),
+                        "rs" => InstCode::Csrrsi(rd, num, uimm),
+                        _ => InstCode::Csrrci(rd, num, uimm),
+                    }
+                }
+                else {
+                    let rs1 = reg(ops[2])?;
+                    match op {
+                        "rw" => InstCode::Csrrw(rd, rs1, num),
+                        "rs" => InstCode::Csrrs(rd, rs1, num),
+                        _ => InstCode::Csrrc(rd, rs1, num),
+                    }
+                }]);
+            }
+
+            let code = match mnemonic {
+                "lui" | "auipc" => {
+                    count(2)?;
+                    let imm = (self.imm(ops[1], -0x80000, 0xFFFFF)? << 12) as i32;
+                    if mnemonic == "lui" { InstCode::Lui(reg(ops[0])?, imm) } else { InstCode::Auipc(reg(ops[0])?, imm) }
+                },
+                "jal" => match ops.len() {
+                    1 => InstCode::Jal(RegID::X1, self.offset(ops[0], pc, 21)? as i32),
+                    _ => {
+                        count(2)?;
+                        InstCode::Jal(reg(ops[0])?, self.offset(ops[1], pc, 21)? as i32)
+                    },
+                },
+                "jalr" => match ops.len() {
+                    1 => InstCode::Jalr(RegID::X1, reg(ops[0])?, 0),
+                    2 if ops[1].ends_with(')') => {
+                        let (base, ofs) = self.mem(ops[1])?;
+                        InstCode::Jalr(reg(ops[0])?, base, ofs)
+                    },
+                    2 => InstCode::Jalr(reg(ops[0])?, reg(ops[1])?, 0),
+                    _ => {
+                        count(3)?;
+                        InstCode::Jalr(reg(ops[0])?, reg(ops[1])?, self.imm12(ops[2])?)
+                    },
+                },
+                "j" => {
+                    count(1)?;
+                    InstCode::Jal(zero, self.offset(ops[0], pc, 21)? as i32)
+                },
+                "jr" => {
+                    count(1)?;
+                    InstCode::Jalr(zero, reg(ops[0])?, 0)
+                },
+                "ret" => {
+                    count(0)?;
+                    InstCode::Jalr(zero, RegID::X1, 0)
+                },
+                "call" | "tail" => {
+                    count(1)?;
+                    let link = if mnemonic == "call" { RegID::X1 } else { RegID::X6 };
+                    let (hi, lo) = self.pcrel(ops[0], pc)?;
+                    let rd = if mnemonic == "call" { RegID::X1 } else { zero };
+                    return Ok(vec![InstCode::Auipc(link, hi), InstCode::Jalr(rd, link, lo)]);
+                },
+                "la" | "lla" => {
+                    count(2)?;
+                    let rd = reg(ops[0])?;
+                    let (hi, lo) = self.pcrel(ops[1], pc)?;
+                    return Ok(vec![InstCode::Auipc(rd, hi), InstCode::Addi(rd, rd, lo)]);
+                },
+                "li" => {
+                    count(2)?;
+                    // The value must be known when laying out
+                    return Ok(li(reg(ops[0])?, self.eval(ops[1], false)?, self.xlen));
+                },
+                "nop" => {
+                    count(0)?;
+                    InstCode::Addi(zero, zero, 0)
+                },
+                "mv" | "not" | "neg" | "negw" | "sext.w" | "zext.b" | "zext.w" | "seqz" | "snez" | "sltz" | "sgtz" => {
+                    count(2)?;
+                    let (rd, rs) = (reg(ops[0])?, reg(ops[1])?);
+                    match mnemonic {
+                        "mv" => InstCode::Addi(rd, rs, 0),
+                        "not" => InstCode::Xori(rd, rs, -1),
+                        "neg" => InstCode::Sub(rd, zero, rs),
+                        "negw" => InstCode::Subw(rd, zero, rs),
+                        "sext.w" => InstCode::Addiw(rd, rs, 0),
+                        "zext.b" => InstCode::Andi(rd, rs, 255),
+                        "zext.w" => InstCode::AddUw(rd, rs, zero),
+                        "seqz" => InstCode::Sltiu(rd, rs, 1),
+                        "snez" => InstCode::Sltu(rd, zero, rs),
+                        "sltz" => InstCode::Slt(rd, rs, zero),
+                        _ => InstCode::Slt(rd, zero, rs),
+                    }
+                },
+                "csrr" => {
+                    count(2)?;
+                    InstCode::Csrrs(reg(ops[0])?, zero, csr_number(ops[1])?)
+                },
+                "csrw" | "csrs" | "csrc" => {
+                    count(2)?;
+                    let (num, rs) = (csr_number(ops[0])?, reg(ops[1])?);
+                    match mnemonic {
+                        "csrw" => InstCode::Csrrw(zero, rs, num),
+                        "csrs" => InstCode::Csrrs(zero, rs, num),
+                        _ => InstCode::Csrrc(zero, rs, num),
+                    }
+                },
+                "csrwi" | "csrsi" | "csrci" => {
+                    count(2)?;
+                    let (num, uimm) = (csr_number(ops[0])?, self.imm(ops[1], 0, 31)? as u8);
+                    match mnemonic {
+                        "csrwi" => InstCode::Csrrwi(zero, num, uimm),
+                        "csrsi" => InstCode::Csrrsi(zero, num, uimm),
+                        _ => InstCode::Csrrci(zero, num, uimm),
+                    }
+                },
+                "rdcycle" | "rdtime" | "rdinstret" | "rdcycleh" | "rdtimeh" | "rdinstreth" => {
+                    count(1)?;
+                    let num = counter_number(&mnemonic[2..]).unwrap();
+                    InstCode::Csrrs(reg(ops[0])?, zero, num)
+                },
+                "fence" => match ops.len() {
+                    0 => InstCode::Fence(0b1111, 0b1111),
+                    _ => {
+                        count(2)?;
+                        InstCode::Fence(fence_set(ops[0])?, fence_set(ops[1])?)
+                    },
+                },
+                "fence.i" => InstCode::FenceI,
+                "ecall" => InstCode::Ecall,
+                "ebreak" => InstCode::Ebreak,
+                "uret" => InstCode::Uret,
+                "sret" => InstCode::Sret,
+                "mret" => InstCode::Mret,
+                "wfi" => InstCode::Wfi,
+                "sfence.vma" => match ops.len() {
+                    0 => InstCode::SfenceVma(zero, zero),
+                    1 => InstCode::SfenceVma(reg(ops[0])?, zero),
+                    _ => {
+                        count(2)?;
+                        InstCode::SfenceVma(reg(ops[0])?, reg(ops[1])?)
+                    },
+                },
+                _ => return Err(format!("unknown instruction `{}`", mnemonic)),
+            };
+            if matches!(code, InstCode::FenceI | InstCode::Ecall | InstCode::Ebreak | InstCode::Uret |
+                InstCode::Sret | InstCode::Mret | InstCode::Wfi) {
+                count(0)?;
+            }
+            Ok(vec![code])
+        }
+
+        /// Assemble a vector instruction or pseudo-instruction.
+        fn vector(&self, mnemonic: &str, ops: &[&str]) -> Result<InstCode, String> {
+            let (ops, vm) = match ops.split_last() {
+                Some((last, rest)) if last.trim() == "v0.t" => (rest, false),
+                _ => (ops, true),
+            };
+            let count = |n: usize| -> Result<(), String> {
+                if ops.len() != n {
+                    return Err(format!("`{}` expects {} operands, found {}", mnemonic, n, ops.len()));
+                }
+                Ok(())
+            };
+            let unmasked = || -> Result<(), String> {
+                if !vm {
+                    return Err(format!("`{}` cannot be masked", mnemonic));
+                }
+                Ok(())
+            };
+            let zero = RegID::X0;
+
+            match mnemonic {
+                "vsetvli" | "vsetivli" => {
+                    if ops.len() < 3 {
+                        return Err(format!("`{}` expects a vtype", mnemonic));
+                    }
+                    unmasked()?;
+                    let rd = reg(ops[0])?;
+                    return Ok(if mnemonic == "vsetvli" {
+                        InstCode::Vsetvli(rd, reg(ops[1])?, self.vtype(&ops[2..], 0x7ff)?)
+                    }
+                    else {
+                        InstCode::Vsetivli(rd, self.imm(ops[1], 0, 31)? as u8, self.vtype(&ops[2..], 0x3ff)?)
+                    });
+                },
+                "vsetvl" => {
+                    count(3)?;
+                    unmasked()?;
+                    return Ok(InstCode::Vsetvl(reg(ops[0])?, reg(ops[1])?, reg(ops[2])?));
+                },
+                "vlm.v" | "vsm.v" => {
+                    count(2)?;
+                    unmasked()?;
+                    let (vd, base) = (vreg(ops[0])?, self.vaddr(ops[1])?);
+                    return Ok(if mnemonic == "vlm.v" { InstCode::Vlm(vd, base) } else { InstCode::Vsm(vd, base) });
+                },
+                "vmv.v.v" | "vmv.v.x" | "vmv.v.i" => {
+                    count(2)?;
+                    unmasked()?;
+                    return Ok(InstCode::Vmv(vreg(ops[0])?, 0, self.vsrc(&mnemonic[6..], ops[1], false)?, true));
+                },
+                "vmv.x.s" => {
+                    count(2)?;
+                    unmasked()?;
+                    return Ok(InstCode::VmvXS(reg(ops[0])?, vreg(ops[1])?));
+                },
+                "vmv.s.x" => {
+                    count(2)?;
+                    unmasked()?;
+                    return Ok(InstCode::VmvSX(vreg(ops[0])?, reg(ops[1])?));
+                },
+                "vcpop.m" | "vfirst.m" => {
+                    count(2)?;
+                    let (rd, vs2) = (reg(ops[0])?, vreg(ops[1])?);
+                    return Ok(if mnemonic == "vcpop.m" { InstCode::Vcpop(rd, vs2, vm) } else { InstCode::Vfirst(rd, vs2, vm) });
+                },
+                "vid.v" => {
+                    count(1)?;
+                    return Ok(InstCode::Vid(vreg(ops[0])?, vm));
+                },
+                "vneg.v" | "vnot.v" => {
+                    count(2)?;
+                    let (vd, vs) = (vreg(ops[0])?, vreg(ops[1])?);
+                    return Ok(if mnemonic == "vneg.v" { InstCode::Vrsub(vd, vs, VSrc::X(zero), vm) } else { InstCode::Vxor(vd, vs, VSrc::I(-1), vm) });
+                },
+                "vmmv.m" | "vmnot.m" => {
+                    count(2)?;
+                    unmasked()?;
+                    let (vd, vs) = (vreg(ops[0])?, vreg(ops[1])?);
+                    return Ok(if mnemonic == "vmmv.m" { InstCode::Vmand(vd, vs, vs) } else { InstCode::Vmnand(vd, vs, vs) });
+                },
+                "vmclr.m" | "vmset.m" => {
+                    count(1)?;
+                    unmasked()?;
+                    let vd = vreg(ops[0])?;
+                    return Ok(if mnemonic == "vmclr.m" { InstCode::Vmxor(vd, vd, vd) } else { InstCode::Vmxnor(vd, vd, vd) });
+                },
+                _ => {},
+            }
+
+            // Loads and stores, with the EEW in the mnemonic
+            if let Some(name) = mnemonic.strip_suffix(".v") {
+                let eew = |digits: &str| match digits {
+                    "8" | "16" | "32" | "64" => Ok(digits.parse::<u8>().unwrap()),
+                    _ => Err(format!("unknown instruction `{}`", mnemonic)),
+                };
+                let nregs = |digits: &str| match digits {
+                    "1" | "2" | "4" | "8" => Ok(digits.parse::<u8>().unwrap()),
+                    _ => Err(format!("unknown instruction `{}`", mnemonic)),
+                };
+                for prefix in ["vluxei", "vloxei", "vsuxei", "vsoxei"] {
+                    if let Some(digits) = name.strip_prefix(prefix) {
+                        count(3)?;
+                        let (vd, base, vs2, eew) = (vreg(ops[0])?, self.vaddr(ops[1])?, vreg(ops[2])?, eew(digits)?);
+                        return Ok(match prefix {
+                            "vluxei" => InstCode::Vluxei(vd, base, vs2, eew, vm),
+                            "vloxei" => InstCode::Vloxei(vd, base, vs2, eew, vm),
+                            "vsuxei" => InstCode::Vsuxei(vd, base, vs2, eew, vm),
+                            _ => InstCode::Vsoxei(vd, base, vs2, eew, vm),
+                        });
+                    }
+                }
+                for prefix in ["vlse", "vsse"] {
+                    if let Some(digits) = name.strip_prefix(prefix) {
+                        count(3)?;
+                        let (vd, base, rs2, eew) = (vreg(ops[0])?, self.vaddr(ops[1])?, reg(ops[2])?, eew(digits)?);
+                        return Ok(if prefix == "vlse" { InstCode::Vlse(vd, base, rs2, eew, vm) } else { InstCode::Vsse(vd, base, rs2, eew, vm) });
+                    }
+                }
+                for prefix in ["vle", "vse"] {
+                    if let Some(digits) = name.strip_prefix(prefix) {
+                        count(2)?;
+                        let (vd, base, eew) = (vreg(ops[0])?, self.vaddr(ops[1])?, eew(digits)?);
+                        return Ok(if prefix == "vle" { InstCode::Vle(vd, base, eew, vm) } else { InstCode::Vse(vd, base, eew, vm) });
+                    }
+                }
+                // vl<n>re<eew>.v, vl<n>r.v, vs<n>r.v and vmv<n>r.v
+                if let Some(rest) = name.strip_prefix("vmv").and_then(|rest| rest.strip_suffix('r')) {
+                    count(2)?;
+                    unmasked()?;
+                    let nr = nregs(rest)?;
+                    return Ok(InstCode::VmvNr(vreg(ops[0])?, vreg(ops[1])?, nr));
+                }
+                if let Some(rest) = name.strip_prefix("vl") {
+                    let (nf, eew) = match rest.split_once("re") {
+                        Some((nf, digits)) => (nregs(nf)?, eew(digits)?),
+                        None => (nregs(rest.strip_suffix('r').unwrap_or(""))?, 8),
+                    };
+                    count(2)?;
+                    unmasked()?;
+                    return Ok(InstCode::Vlre(vreg(ops[0])?, self.vaddr(ops[1])?, nf, eew));
+                }
+                if let Some(rest) = name.strip_prefix("vs").and_then(|rest| rest.strip_suffix('r')) {
+                    count(2)?;
+                    unmasked()?;
+                    let nf = nregs(rest)?;
+                    return Ok(InstCode::Vsr(vreg(ops[0])?, self.vaddr(ops[1])?, nf));
+                }
+            }
+
+            let (name, form) = mnemonic.split_once('.').ok_or(format!("unknown instruction `{}`", mnemonic))?;
+            if form == "vs" {
+                if let Some((_, _, op)) = REDUCTIONS.iter().find(|(red, _, _)| *red == name) {
+                    count(3)?;
+                    return Ok(op(vreg(ops[0])?, vreg(ops[1])?, vreg(ops[2])?, vm));
+                }
+            }
+            if form == "mm" {
+                if let Some((_, _, op)) = MASK_LOGICAL.iter().find(|(logical, _, _)| *logical == name) {
+                    count(3)?;
+                    unmasked()?;
+                    return Ok(op(vreg(ops[0])?, vreg(ops[1])?, vreg(ops[2])?));
+                }
+            }
+            let (_, _, _, forms, op) = ARITH.iter()
+                .filter(|(arith, _, _, _, _)| *arith == name && name != "vmv")
+                .find(|(_, _, _, forms, _)| match form.as_bytes() {
+                    [b'v', kind] if name != "vmerge" => forms.contains(*kind as char) || (*kind == b'i' && forms.contains('u')),
+                    [b'v', kind, b'm'] if name == "vmerge" => forms.contains(*kind as char),
+                    _ => false,
+                })
+                .ok_or(format!("unknown instruction `{}`", mnemonic))?;
+            if name == "vmerge" {
+                count(4)?;
+                if ops[3].trim() != "v0" || !vm {
+                    return Err(format!("`{}` expects v0 as its mask", mnemonic));
+                }
+                return Ok(op(vreg(ops[0])?, vreg(ops[1])?, self.vsrc(&form[1..2], ops[2], false)?, false));
+            }
+            count(3)?;
+            let src = |op: &str| self.vsrc(&form[1..], op, forms.contains('u'));
+            Ok(match name {
+                // vd, vs1/rs1, vs2
+                "vmacc" | "vnmsac" | "vmadd" | "vnmsub" => op(vreg(ops[0])?, vreg(ops[2])?, src(ops[1])?, vm),
+                _ => op(vreg(ops[0])?, vreg(ops[1])?, src(ops[2])?, vm),
+            })
+        }
+
+        /// The last source of vector arithmetic, of the form `v`, `x` or `i`.
+        fn vsrc(&self, form: &str, src: &str, unsigned: bool) -> Result<VSrc, String> {
+            match form {
+                "v" => Ok(VSrc::V(vreg(src)?)),
+                "x" => Ok(VSrc::X(reg(src)?)),
+                _ if unsigned => Ok(VSrc::I(self.imm(src, 0, 31)? as i8)),
+                _ => Ok(VSrc::I(self.imm(src, -16, 15)? as i8)),
+            }
+        }
+
+        /// `(rs1)` of vector loads and stores.
+        fn vaddr(&self, src: &str) -> Result<RegID, String> {
+            match self.mem(src)? {
+                (base, 0) => Ok(base),
+                _ => Err(String::from("vector loads and stores take no offset")),
+            }
+        }
+
+        /// A vtype as `e32,m2,ta,ma`, or as a number up to `max`. LMUL and
+        /// the policies default to `m1,tu,mu`.
+        fn vtype(&self, ops: &[&str], max: i64) -> Result<u16, String> {
+            if let [num] = ops {
+                if num.trim().starts_with(|c: char| c.is_ascii_digit()) {
+                    return Ok(self.imm(num, 0, max)? as u16);
+                }
+            }
+            let (mut sew, mut lmul, mut policy) = (None, 0u16, 0u16);
+            for op in ops {
+                match op.trim() {
+                    "e8" => sew = Some(0),
+                    "e16" => sew = Some(1),
+                    "e32" => sew = Some(2),
+                    "e64" => sew = Some(3),
+                    "m1" => lmul = 0,
+                    "m2" => lmul = 1,
+                    "m4" => lmul = 2,
+                    "m8" => lmul = 3,
+                    "mf8" => lmul = 5,
+                    "mf4" => lmul = 6,
+                    "mf2" => lmul = 7,
+                    "ta" => policy |= 0x40,
+                    "ma" => policy |= 0x80,
+                    "tu" | "mu" => {},
+                    op => return Err(format!("bad vtype `{}`", op)),
+                }
+            }
+            match sew {
+                Some(sew) => Ok(sew << 3 | lmul | policy),
+                None => Err(String::from("vtype expects the SEW")),
+            }
+        }
+
+        /// Assemble the base instruction of the compressed instruction `c.<base>`.
+        fn compressed(&self, base: &str, ops: &[&str]) -> Result<Vec<InstCode>, String> {
+            let mut ops = ops.to_vec();
+            let base = match base {
+                // Two operand forms of the register-register and immediate operations
+                "add" | "addw" | "sub" | "subw" | "and" | "or" | "xor" |
+                "addi" | "addiw" | "slli" | "srli" | "srai" | "andi" if ops.len() == 2 => {
+                    ops.insert(0, ops[0]);
+                    base
+                },
+                "addi16sp" => {
+                    ops.insert(0, "sp");
+                    "addi"
+                },
+                "addi4spn" => "addi",
+                "lwsp" | "ldsp" | "swsp" | "sdsp" => &base[..2],
+                // c.mv is an add, unlike mv
+                "mv" if ops.len() == 2 => {
+                    ops.insert(1, "zero");
+                    "add"
+                },
+                _ => base,
+            };
+            self.instruction(base, &ops)
+        }
+    }
+
+    /// The shortest sequence loading `val` into `rd`: `addi`, `lui` and
+    /// `addiw` (`addi` on RV32, where `val` is taken modulo 2^32), or else
+    /// the upper bits loaded recursively, shifted left and topped up with
+    /// an `addi`.
+    fn li(rd: RegID, val: i64, xlen: u32) -> Vec<InstCode> {
+        let val = if xlen == 32 { val as i32 as i64 } else { val };
+        if (-2048..2048).contains(&val) {
+            return vec![InstCode::Addi(rd, RegID::X0, val as i16)];
+        }
+        if val as i32 as i64 == val {
+            let hi = (val.wrapping_add(0x800) >> 12) << 12;
+            let lo = val - hi;
+            let mut codes = vec![InstCode::Lui(rd, hi as i32)];
+            if lo != 0 && xlen == 32 {
+                codes.push(InstCode::Addi(rd, rd, lo as i16));
+            }
+            else if lo != 0 {
+                codes.push(InstCode::Addiw(rd, rd, lo as i16));
+            }
+            return codes;
+        }
+        let lo = lo(val);
+        let hi = val.wrapping_sub(lo) >> 12;
+        let shift = hi.trailing_zeros() + 12;
+        let mut codes = li(rd, hi >> (shift - 12), xlen);
+        codes.push(InstCode::Slli(rd, rd, shift as i16));
+        if lo != 0 {
+            codes.push(InstCode::Addi(rd, rd, lo as i16));
+        }
+        codes
+    }
+
+    fn hi(val: i64) -> i64 {
+        (val.wrapping_add(0x800) >> 12) & 0xFFFFF
+    }
+
+    fn lo(val: i64) -> i64 {
+        ((val & 0xFFF) ^ 0x800) - 0x800
+    }
+
+    fn r_type(mnemonic: &str) -> Option<RType> {
+        Some(match mnemonic {
+            "add" => InstCode::Add,
+            "sub" => InstCode::Sub,
+            "sll" => InstCode::Sll,
+            "slt" => InstCode::Slt,
+            "sltu" => InstCode::Sltu,
+            "xor" => InstCode::Xor,
+            "srl" => InstCode::Srl,
+            "sra" => InstCode::Sra,
+            "or" => InstCode::Or,
+            "and" => InstCode::And,
+            "addw" => InstCode::Addw,
+            "subw" => InstCode::Subw,
+            "sllw" => InstCode::Sllw,
+            "srlw" => InstCode::Srlw,
+            "sraw" => InstCode::Sraw,
+            "mul" => InstCode::Mul,
+            "mulh" => InstCode::Mulh,
+            "mulhsu" => InstCode::Mulhsu,
+            "mulhu" => InstCode::Mulhu,
+            "div" => InstCode::Div,
+            "divu" => InstCode::Divu,
+            "rem" => InstCode::Rem,
+            "remu" => InstCode::Remu,
+            "mulw" => InstCode::Mulw,
+            "divw" => InstCode::Divw,
+            "divuw" => InstCode::Divuw,
+            "remw" => InstCode::Remw,
+            "remuw" => InstCode::Remuw,
+            "sh1add" => InstCode::Sh1add,
+            "sh2add" => InstCode::Sh2add,
+            "sh3add" => InstCode::Sh3add,
+            "add.uw" => InstCode::AddUw,
+            "sh1add.uw" => InstCode::Sh1addUw,
+            "sh2add.uw" => InstCode::Sh2addUw,
+            "sh3add.uw" => InstCode::Sh3addUw,
+            "andn" => InstCode::Andn,
+            "orn" => InstCode::Orn,
+            "xnor" => InstCode::Xnor,
+            "max" => InstCode::Max,
+            "maxu" => InstCode::Maxu,
+            "min" => InstCode::Min,
+            "minu" => InstCode::Minu,
+            "rol" => InstCode::Rol,
+            "ror" => InstCode::Ror,
+            "rolw" => InstCode::Rolw,
+            "rorw" => InstCode::Rorw,
+            "clmul" => InstCode::Clmul,
+            "clmulh" => InstCode::Clmulh,
+            "clmulr" => InstCode::Clmulr,
+            "bclr" => InstCode::Bclr,
+            "bext" => InstCode::Bext,
+            "binv" => InstCode::Binv,
+            "bset" => InstCode::Bset,
+            "czero.eqz" => InstCode::CzeroEqz,
+            "czero.nez" => InstCode::CzeroNez,
+            _ => return None,
+        })
+    }
+
+    fn i_type(mnemonic: &str) -> Option<IType> {
+        Some(match mnemonic {
+            "addi" => InstCode::Addi,
+            "slti" => InstCode::Slti,
+            "sltiu" => InstCode::Sltiu,
+            "xori" => InstCode::Xori,
+            "ori" => InstCode::Ori,
+            "andi" => InstCode::Andi,
+            "addiw" => InstCode::Addiw,
+            _ => return None,
+        })
+    }
+
+    /// Shifts by an immediate: (op, max shift amount, function bits)
+    fn shift(mnemonic: &str) -> Option<(IType, i64, i16)> {
+        Some(match mnemonic {
+            "slli" => (InstCode::Slli, 63, 0),
+            "srli" => (InstCode::Srli, 63, 0),
+            "srai" => (InstCode::Srai, 63, 0x400),
+            "slliw" => (InstCode::Slliw, 31, 0),
+            "srliw" => (InstCode::Srliw, 31, 0),
+            "sraiw" => (InstCode::Sraiw, 31, 0x400),
+            "slli.uw" => (InstCode::SlliUw, 63, 0x080),
+            "rori" => (InstCode::Rori, 63, 0x600),
+            "roriw" => (InstCode::Roriw, 31, 0x600),
+            "bclri" => (InstCode::Bclri, 63, 0x480),
+            "bexti" => (InstCode::Bexti, 63, 0x480),
+            "binvi" => (InstCode::Binvi, 63, 0x680),
+            "bseti" => (InstCode::Bseti, 63, 0x280),
+            _ => return None,
+        })
+    }
+
+    /// Operations on a single register
+    fn unary(mnemonic: &str) -> Option<Unary> {
+        Some(match mnemonic {
+            "clz" => InstCode::Clz,
+            "ctz" => InstCode::Ctz,
+            "cpop" => InstCode::Cpop,
+            "clzw" => InstCode::Clzw,
+            "ctzw" => InstCode::Ctzw,
+            "cpopw" => InstCode::Cpopw,
+            "sext.b" => InstCode::SextB,
+            "sext.h" => InstCode::SextH,
+            "zext.h" => InstCode::ZextH,
+            "orc.b" => InstCode::OrcB,
+            "rev8" => InstCode::Rev8,
+            _ => return None,
+        })
+    }
+
+    fn load(mnemonic: &str) -> Option<IType> {
+        Some(match mnemonic {
+            "lb" => InstCode::Lb,
+            "lh" => InstCode::Lh,
+            "lw" => InstCode::Lw,
+            "ld" => InstCode::Ld,
+            "lbu" => InstCode::Lbu,
+            "lhu" => InstCode::Lhu,
+            "lwu" => InstCode::Lwu,
+            _ => return None,
+        })
+    }
+
+    fn store(mnemonic: &str) -> Option<IType> {
+        Some(match mnemonic {
+            "sb" => InstCode::Sb,
+            "sh" => InstCode::Sh,
+            "sw" => InstCode::Sw,
+            "sd" => InstCode::Sd,
+            _ => return None,
+        })
+    }
+
+    fn branch(mnemonic: &str) -> Option<IType> {
+        Some(match mnemonic {
+            "beq" => InstCode::Beq,
+            "bne" => InstCode::Bne,
+            "blt" => InstCode::Blt,
+            "bge" => InstCode::Bge,
+            "bltu" => InstCode::Bltu,
+            "bgeu" => InstCode::Bgeu,
+            _ => return None,
+        })
+    }
+
+    /// Branches comparing against zero: (op, whether zero comes first)
+    fn branch_zero(mnemonic: &str) -> Option<(IType, bool)> {
+        Some(match mnemonic {
+            "beqz" => (InstCode::Beq, false),
+            "bnez" => (InstCode::Bne, false),
+            "bltz" => (InstCode::Blt, false),
+            "bgez" => (InstCode::Bge, false),
+            "bgtz" => (InstCode::Blt, true),
+            "blez" => (InstCode::Bge, true),
+            _ => return None,
+        })
+    }
+
+    /// Branches with the operands swapped.
+    fn branch_swapped(mnemonic: &str) -> Option<IType> {
+        Some(match mnemonic {
+            "bgt" => InstCode::Blt,
+            "ble" => InstCode::Bge,
+            "bgtu" => InstCode::Bltu,
+            "bleu" => InstCode::Bgeu,
+            _ => return None,
+        })
+    }
+
+    /// A instructions: (operation, width in bytes, aq/rl bits)
+    fn atomic(mnemonic: &str) -> Option<(Atomic, u8, u8)> {
+        let (name, order) = match mnemonic.rsplit_once('.') {
+            Some((name, "aq")) => (name, 0b10),
+            Some((name, "rl")) => (name, 0b01),
+            Some((name, "aqrl")) => (name, 0b11),
+            _ => (mnemonic, 0b00),
+        };
+        let (op, width) = name.rsplit_once('.')?;
+        let width = match width {
+            "w" => 4,
+            "d" => 8,
+            _ => return None,
+        };
+        let op: Atomic = match op {
+            "lr" => |rd, rs1, _, width, order| InstCode::Lr(rd, rs1, width, order),
+            "sc" => InstCode::Sc,
+            "amoswap" => InstCode::Amoswap,
+            "amoadd" => InstCode::Amoadd,
+            "amoxor" => InstCode::Amoxor,
+            "amoand" => InstCode::Amoand,
+            "amoor" => InstCode::Amoor,
+            "amomin" => InstCode::Amomin,
+            "amomax" => InstCode::Amomax,
+            "amominu" => InstCode::Amominu,
+            "amomaxu" => InstCode::Amomaxu,
+            _ => return None,
+        };
+        Some((op, width, order))
+    }
+
+    /// CSR instructions: (operation, whether it takes an immediate)
+    fn csr(mnemonic: &str) -> Option<(&'static str, bool)> {
+        match mnemonic {
+            "csrrw" => Some(("rw", false)),
+            "csrrs" => Some(("rs", false)),
+            "csrrc" => Some(("rc", false)),
+            "csrrwi" => Some(("rw", true)),
+            "csrrsi" => Some(("rs", true)),
+            "csrrci" => Some(("rc", true)),
+            _ => None,
+        }
+    }
+
+    fn csr_number(src: &str) -> Result<u16, String> {
+        let src = src.trim();
+        if let Some((num, _)) = CSRS.iter().find(|(_, name)| *name == src) {
+            return Ok(*num);
+        }
+        if let Some(num) = counter_number(src) {
+            return Ok(num);
+        }
+        if let Some(num) = vector_csr_number(src) {
+            return Ok(num);
+        }
+        match number(src) {
+            Ok(num) if (0..0x1000).contains(&num) => Ok(num as u16),
+            _ => Err(format!("unknown CSR `{}`", src)),
+        }
+    }
+
+    /// The `iorw` sets of a fence.
+    fn fence_set(src: &str) -> Result<u8, String> {
+        let mut set = 0u8;
+        for c in src.trim().chars() {
+            set |= match c {
+                'i' => 0b1000,
+                'o' => 0b0100,
+                'r' => 0b0010,
+                'w' => 0b0001,
+                _ => return Err(format!("bad fence set `{}`", src.trim())),
+            };
+        }
+        Ok(set)
+    }
+
+    fn reg(src: &str) -> Result<RegID, String> {
+        let src = src.trim();
+        if src == "fp" {
+            return Ok(RegID::X8);
+        }
+        if let Some(num) = src.strip_prefix('x').and_then(|num| num.parse::<u8>().ok()) {
+            if num < 32 {
+                return Ok(RegID::decode(num));
+            }
+        }
+        (0..32u8).map(RegID::decode)
+            .find(|id| id.abi_name() == src)
+            .ok_or(format!("bad register `{}`", src))
+    }
+
+    /// A vector register `v0` to `v31`.
+    fn vreg(src: &str) -> Result<u8, String> {
+        let src = src.trim();
+        match src.strip_prefix('v').and_then(|num| num.parse::<u8>().ok()) {
+            Some(num) if num < 32 => Ok(num),
+            _ => Err(format!("bad vector register `{}`", src)),
+        }
+    }
+
+    fn number(src: &str) -> Result<i64, String> {
+        let (digits, radix) = if let Some(hex) = src.strip_prefix("0x").or(src.strip_prefix("0X")) {
+            (hex, 16)
+        }
+        else if let Some(bin) = src.strip_prefix("0b").or(src.strip_prefix("0B")) {
+            (bin, 2)
+        }
+        else {
+            (src, 10)
+        };
+        u64::from_str_radix(digits, radix)
+            .map(|val| val as i64)
+            .map_err(|_| format!("bad number `{}`", src))
+    }
+
+    fn is_symbol_char(c: char) -> bool {
+        c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$'
+    }
+
+    /// Whether `name` appears as a symbol in `source`.
+    fn mentions(source: &str, name: &str) -> bool {
+        source.split(|c: char| !is_symbol_char(c)).any(|word| word == name)
+    }
+
+    /// Index of the parenthesis closing an already opened one in `src`.
+    fn closing(src: &str) -> Result<usize, String> {
+        let mut depth = 0;
+        for (idx, c) in src.char_indices() {
+            match c {
+                '(' => depth += 1,
+                ')' if depth == 0 => return Ok(idx),
+                ')' => depth -= 1,
+                _ => {}
+            }
+        }
+        Err(String::from("unbalanced parentheses"))
+    }
+
+    /// The statements of `source` with their line numbers, without
+    /// comments, split at `;`.
+    fn statements(source: &str) -> Vec<(usize, String)> {
+        let mut statements = Vec::new();
+        for (idx, line) in source.lines().enumerate() {
+            let mut current = String::new();
+            let mut quote = None;
+            let mut escaped = false;
+            for c in line.chars() {
+                match quote {
+                    Some(q) => {
+                        if escaped {
+                            escaped = false;
+                        }
+                        else if c == '\\' {
+                            escaped = true;
+                        }
+                        else if c == q {
+                            quote = None;
+                        }
+                    },
+                    None if c == '#' => break,
+                    None if c == ';' => {
+                        statements.push((idx + 1, current.clone()));
+                        current.clear();
+                        continue;
+                    },
+                    None if c == '"' || c == '\'' => quote = Some(c),
+                    None => {}
+                }
+                current.push(c);
+            }
+            statements.push((idx + 1, current));
+        }
+        statements
+    }
+
+    /// Split operands at the commas outside of parentheses and quotes.
+    fn split_operands(src: &str) -> Vec<&str> {
+        let mut ops = Vec::new();
+        let mut depth = 0;
+        let mut quote = None;
+        let mut escaped = false;
+        let mut start = 0;
+        for (idx, c) in src.char_indices() {
+            if let Some(q) = quote {
+                if escaped {
+                    escaped = false;
+                }
+                else if c == '\\' {
+                    escaped = true;
+                }
+                else if c == q {
+                    quote = None;
+                }
+                continue;
+            }
+            match c {
+                '"' | '\'' => quote = Some(c),
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                ',' if depth == 0 => {
+                    ops.push(src[start..idx].trim());
+                    start = idx + 1;
+                },
+                _ => {}
+            }
+        }
+        if !src[start..].trim().is_empty() || !ops.is_empty() {
+            ops.push(src[start..].trim());
+        }
+        ops
+    }
+
+    /// The bytes of a string literal.
+    fn string(src: &str) -> Result<Vec<u8>, String> {
+        let src = src.trim();
+        match src.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+            Some(body) if src.len() >= 2 => unescape(body),
+            _ => Err(format!("expected a string, found `{}`", src)),
+        }
+    }
+
+    /// Resolve the C escapes in `src`.
+    fn unescape(src: &str) -> Result<Vec<u8>, String> {
+        let mut bytes = Vec::new();
+        let mut chars = src.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                let mut buf = [0u8; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                continue;
+            }
+            match chars.next() {
+                Some('n') => bytes.push(b'\n'),
+                Some('t') => bytes.push(b'\t'),
+                Some('r') => bytes.push(b'\r'),
+                Some('x') => {
+                    let mut val = 0u32;
+                    let mut len = 0;
+                    while let Some(digit) = chars.peek().and_then(|c| c.to_digit(16)) {
+                        if len == 2 {
+                            break;
+                        }
+                        val = val * 16 + digit;
+                        len += 1;
+                        chars.next();
+                    }
+                    if len == 0 {
+                        return Err(String::from("bad escape `\\x`"));
+                    }
+                    bytes.push(val as u8);
+                },
+                Some(c) if c.is_digit(8) => {
+                    let mut val = c.to_digit(8).unwrap();
+                    for _ in 0..2 {
+                        match chars.peek().and_then(|c| c.to_digit(8)) {
+                            Some(digit) => {
+                                val = val * 8 + digit;
+                                chars.next();
+                            },
+                            None => break,
+                        }
+                    }
+                    bytes.push(val as u8);
+                },
+                Some(c) if c == '\\' || c == '"' || c == '\'' => bytes.push(c as u8),
+                Some(c) => return Err(format!("bad escape `\\{}`", c)),
+                None => return Err(String::from("unterminated escape")),
+            }
+        }
+        Ok(bytes)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        /// The bytes of `source` assembled at `addr`.
+        fn asm(source: &str, addr: u64, symbols: &[(u64, usize, String)]) -> Vec<u8> {
+            Assembler::new(source).assemble_at(addr, symbols).unwrap()
+        }
+
+        #[test]
+        fn pseudo_instructions() {
+            assert_eq!(asm("addi a0, a0, 1\nret", 0, &[]), [0x13, 0x05, 0x15, 0x00, 0x67, 0x80, 0x00, 0x00]);
+            let cases = [
+                ("li a0, 5", "addi a0, zero, 5"),
+                ("li a0, 0x12345678", "lui a0, 0x12345\naddiw a0, a0, 0x678"),
+                ("li a0, 0x800", "lui a0, 1\naddiw a0, a0, -2048"),
+                ("li a0, 0x100000000", "addi a0, zero, 1\nslli a0, a0, 32"),
+                ("li a0, -1", "addi a0, zero, -1"),
+                ("mv a1, a0", "addi a1, a0, 0"),
+                ("not a1, a0", "xori a1, a0, -1"),
+                ("neg a1, a0", "sub a1, zero, a0"),
+                ("seqz a1, a0", "sltiu a1, a0, 1"),
+                ("snez a1, a0", "sltu a1, zero, a0"),
+                ("nop", "addi zero, zero, 0"),
+                ("ret", "jalr zero, 0(ra)"),
+                ("jr a0", "jalr zero, 0(a0)"),
+                ("csrr a0, fflags", "csrrs a0, fflags, zero"),
+                ("bgt a0, a1, 0x1000", "blt a1, a0, 0x1000"),
+                ("beqz a0, 0x1000", "beq a0, zero, 0x1000"),
+            ];
+            for (pseudo, expansion) in cases {
+                assert_eq!(asm(pseudo, 0x1000, &[]), asm(expansion, 0x1000, &[]), "{}", pseudo);
+            }
+            // With RV32, li sign-extends the low word
+            let rv32 = Assembler::new("li a0, 0xFFFFF800").with_xlen(32).assemble_at(0, &[]).unwrap();
+            assert_eq!(rv32, asm("addi a0, zero, -2048", 0, &[]));
+        }
+
+        #[test]
+        fn labels_and_relocations() {
+            let func = [(0x3800, 4, String::from("func"))];
+            let source = "
+                start:  beqz a0, 1f
+                        call func
+                1:      lui a0, %hi(func)
+                        addi a0, a0, %lo(func)
+                        bnez a0, 1b
+                        j start";
+            let expected = "
+                        beq a0, zero, 0x100c
+                        auipc ra, 2
+                        jalr ra, 0x7fc(ra)
+                        lui a0, 4
+                        addi a0, a0, -2048
+                        bne a0, zero, 0x100c
+                        jal zero, 0x1000";
+            assert_eq!(asm(source, 0x1000, &func), asm(expected, 0x1000, &[]));
+
+            assert!(Assembler::new("j nowhere").assemble_at(0x1000, &[]).is_err());
+            assert!(Assembler::new("a: nop\na: nop").assemble_at(0x1000, &[]).is_err());
+            assert!(Assembler::new("beqz a0, 0x1ffe").assemble_at(0x1000, &[]).is_ok());
+            assert!(Assembler::new("beqz a0, 0x1ffe").assemble_at(0, &[]).is_err());
+            assert!(Assembler::new(".data").assemble_at(0x1000, &[]).is_err());
+        }
+
+        #[test]
+        fn directives() {
+            let prog = Assembler::new("
+                        .data
+                msg:    .asciz \"Hi\\n\"
+                        .align 3
+                val:    .dword msg
+                        .equ N, 3
+                        .half N + 1, -1
+                        .byte 'a', 0x7f
+                        .zero 2, 0xAA
+                        .text
+                helper: ret
+                main:   la a0, msg
+                        call puts
+                        j helper").load().unwrap();
+
+            let data: Vec<u8> = [b"Hi\n\0".as_slice(), &[0; 4], &0x11000u64.to_le_bytes(), &[4, 0, 0xff, 0xff, b'a', 0x7f, 0xAA, 0xAA]].concat();
+            let vma = prog.vmas.iter().find(|vma| vma.lower_bound == 0x11000).unwrap();
+            assert_eq!(vma.memory, data);
+            assert!(vma.writeble && !vma.executable);
+
+            // puts gets a stub after the code
+            let text = asm("ret\nauipc a0, 1\naddi a0, a0, -4\ncall 0x10018\nj 0x10000\nret", 0x10000, &[]);
+            let vma = prog.vmas.iter().find(|vma| vma.lower_bound == Assembler::TEXT_BASE).unwrap();
+            assert_eq!(vma.memory, text);
+            assert_eq!(prog.entry_point, 0x10004);
+            assert_eq!(prog.simulated_library_funcs.get(&0x10018).map(String::as_str), Some("puts"));
+            assert!(prog.funcs.contains(&(0x10004, 0x14, String::from("main"))));
+
+            for bad in [".byte 256", ".half 0x10000", ".align 13", ".balign 3", ".equ N, undefined", ".fill 1", ".asciz abc"] {
+                assert!(Assembler::new(bad).load().is_err(), "{}", bad);
+            }
+        }
+    }
+}
@@ -0,0 +1,265 @@
+#![allow(clippy::module_inception)]
+#[allow(dead_code)]
+pub mod counters {
+    //! This module implements the unprivileged counters of Zicntr and
+    //! Zihpm, read by guests with `rdcycle`, `rdtime`, `rdinstret` and
+    //! `csrr a0, hpmcounterN`. rsim has no timing model, so cycles are
+    //! derived from the retired instructions: one per instruction, plus
+    //! a flush penalty whenever the fetch of the fall-through instruction
+    //! is discarded by a taken branch or a jump. The time counter ticks
+    //! at the same rate. Each of hpmcounter3..31 counts one selectable
+    //! `Event`, and reads as zero otherwise. rsim has no cache model
+    //! either, so there is no cache-miss event.
+
+    use std::fmt;
+    use std::str::FromStr;
+    use crate::{InstCode, InstCategory, Extension};
+
+    /// Cycles lost to discarding the fall-through fetch on a redirect.
+    pub const FLUSH_PENALTY: u64 = 2;
+
+    /// Events the hpm counters can count.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Event {
+        Loads,
+        Stores,
+        /// Conditional branches, taken or not
+        Branches,
+        /// Taken conditional branches, as rsim always predicts not taken
+        BranchMisses,
+        /// Jal and Jalr
+        Jumps,
+        MulDiv,
+        /// Cycles lost to flushes
+        FlushCycles,
+    }
+
+    impl Event {
+        pub const ALL: [Event; 7] = [
+            Event::Loads, Event::Stores, Event::Branches, Event::BranchMisses,
+            Event::Jumps, Event::MulDiv, Event::FlushCycles,
+        ];
+    }
+
+    impl fmt::Display for Event {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                Event::Loads => write!(f, "loads"),
+                Event::Stores => write!(f, "stores"),
+                Event::Branches => write!(f, "branches"),
+                Event::BranchMisses => write!(f, "branch-misses"),
+                Event::Jumps => write!(f, "jumps"),
+                Event::MulDiv => write!(f, "muldiv"),
+                Event::FlushCycles => write!(f, "flush-cycles"),
+            }
+        }
+    }
+
+    impl FromStr for Event {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            Event::ALL.into_iter()
+                .find(|event| event.to_string() == s)
+                .ok_or(format!("unknown event `{}`, expected one of: {}", s,
+                    Event::ALL.map(|event| event.to_string()).join(", ")))
+        }
+    }
+
+    /// Number of the `cycle` CSR. The counters are `cycle + index`, and
+    /// their upper halves on RV32 are `cycleh + index`.
+    const CYCLE: u16 = 0xc00;
+    const CYCLEH: u16 = 0xc80;
+    /// Index of the first hpm counter.
+    const HPM: usize = 3;
+
+    /// The counters of the hart.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Counters {
+        /// Indexed as the CSRs: cycle, time, instret, hpmcounter3..31
+        pub values: [u64; 32],
+        /// Events of the hpm counters, None if not counting
+        pub events: [Option<Event>; 32],
+    }
+
+    impl Counters {
+        pub fn new() -> Self {
+            Counters { values: [0; 32], events: [None; 32] }
+        }
+
+        /// Make hpmcounter`idx` count `event`, with `idx` in 3..=31.
+        pub fn select(&mut self, idx: usize, event: Event) {
+            assert!((HPM..32).contains(&idx), "hpmcounter{} does not exist", idx);
+            self.events[idx] = Some(event);
+        }
+
+        /// Account one retired instruction.
+        pub fn retire(&mut self, pc: u64, code: InstCode, next_pc: u64) {
+            let taken = match code {
+                InstCode::Beq(_,_,ofs) | InstCode::Bne(_,_,ofs) |
+                InstCode::Blt(_,_,ofs) | InstCode::Bge(_,_,ofs) |
+                InstCode::Bltu(_,_,ofs) | InstCode::Bgeu(_,_,ofs) => next_pc == (pc as i64 + ofs as i64) as u64,
+                _ => false,
+            };
+            let category = code.category();
+            let flush = if taken || category == InstCategory::Jump { FLUSH_PENALTY } else { 0 };
+
+            self.values[0] = self.values[0].wrapping_add(1 + flush);
+            self.values[1] = self.values[1].wrapping_add(1 + flush);
+            self.values[2] = self.values[2].wrapping_add(1);
+            for idx in HPM..32 {
+                let delta = match self.events[idx] {
+                    Some(Event::Loads) => (category == InstCategory::Load) as u64,
+                    Some(Event::Stores) => (category == InstCategory::Store) as u64,
+                    Some(Event::Branches) => (category == InstCategory::Branch) as u64,
+                    Some(Event::BranchMisses) => taken as u64,
+                    Some(Event::Jumps) => (category == InstCategory::Jump) as u64,
+                    Some(Event::MulDiv) => (category == InstCategory::MulDiv) as u64,
+                    Some(Event::FlushCycles) => flush,
+                    None => 0,
+                };
+                self.values[idx] = self.values[idx].wrapping_add(delta);
+            }
+        }
+
+        /// The value of counter CSR `csr`, or None if it is not a counter.
+        /// The upper halves only exist on RV32.
+        pub fn read(&self, csr: u16, xlen: u32) -> Option<u64> {
+            match csr {
+                CYCLE..=0xc1f => Some(self.values[(csr - CYCLE) as usize]),
+                CYCLEH..=0xc9f if xlen == 32 => Some(self.values[(csr - CYCLEH) as usize] >> 32),
+                _ => None,
+            }
+        }
+
+        /// The extension providing counter CSR `csr`.
+        pub fn extension(csr: u16) -> Extension {
+            if ((csr & 0x1f) as usize) < HPM { Extension::Zicntr } else { Extension::Zihpm }
+        }
+    }
+
+    /// The name of counter CSR `csr`, as in `cycle` or `hpmcounter3h`.
+    pub fn counter_name(csr: u16) -> Option<String> {
+        let (idx, suffix) = match csr {
+            CYCLE..=0xc1f => (csr - CYCLE, ""),
+            CYCLEH..=0xc9f => (csr - CYCLEH, "h"),
+            _ => return None,
+        };
+        let name = match idx {
+            0 => String::from("cycle"),
+            1 => String::from("time"),
+            2 => String::from("instret"),
+            _ => format!("hpmcounter{}", idx),
+        };
+        Some(name + suffix)
+    }
+
+    /// The number of the counter CSR named `name`.
+    pub fn counter_number(name: &str) -> Option<u16> {
+        (CYCLE..=0xc1f).chain(CYCLEH..=0xc9f)
+            .find(|csr| counter_name(*csr).as_deref() == Some(name))
+    }
+
+    impl Default for Counters {
+        fn default() -> Self {
+            Counters::new()
+        }
+    }
+
+    impl fmt::Display for Counters {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            for idx in 0..32 {
+                if idx >= HPM && self.events[idx].is_none() {
+                    continue;
+                }
+                let name = counter_name(CYCLE + idx as u16).unwrap();
+                write!(f, "{:<14}{:>16}", name, self.values[idx])?;
+                if let Some(event) = self.events[idx] {
+                    write!(f, "  ({})", event)?;
+                }
+                writeln!(f)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::{Assembler, Inst, Isa, RegID, HLT_ADDR};
+
+        /// The counters read by a loop of three loads, multiplications,
+        /// stores and branches, two of them taken.
+        #[test]
+        fn known_mix() {
+            let mut prog = Assembler::new("
+                        .data
+                buf:    .dword 1
+                        .text
+                main:   la a0, buf
+                        li t0, 3
+                .Lloop: ld t1, 0(a0)
+                        mul t1, t1, t0
+                        sd t1, 0(a0)
+                        addi t0, t0, -1
+                        bnez t0, .Lloop
+                        rdinstret s1
+                        rdcycle s2
+                        csrr s3, hpmcounter3
+                        csrr s4, hpmcounter4
+                        csrr s5, hpmcounter5
+                        csrr s6, hpmcounter6
+                        ret").load().unwrap();
+            prog.isa = Isa::parse("rv64gc").unwrap().with_counters();
+            let events = [Event::Loads, Event::BranchMisses, Event::FlushCycles, Event::Stores, Event::MulDiv, Event::Jumps, Event::Branches];
+            for (idx, event) in [3, 4, 5, 7, 8, 9, 10].into_iter().zip(events) {
+                prog.counters.select(idx, event);
+            }
+
+            while prog.program_counter != HLT_ADDR {
+                let pc = prog.program_counter;
+                let mut inst = Inst::new().advance(&mut prog).unwrap();
+                let code = inst.code();
+                prog.program_counter = loop {
+                    match inst.advance(&mut prog) {
+                        Ok(next) => inst = next,
+                        Err(next_pc) => break next_pc,
+                    }
+                };
+                prog.counters.retire(pc, code, prog.program_counter);
+            }
+
+            // Reads see the instructions retired before them
+            let read = |id| prog.registers.read(id).unwrap();
+            assert_eq!(read(RegID::X9), 18);
+            assert_eq!(read(RegID::X18), 19 + 2 * FLUSH_PENALTY);
+            assert_eq!([RegID::X19, RegID::X20, RegID::X21, RegID::X22].map(read), [3, 2, 4, 0]);
+            assert_eq!(prog.counters.to_string(), "\
+cycle                       31
+time                        31
+instret                     25
+hpmcounter3                  3  (loads)
+hpmcounter4                  2  (branch-misses)
+hpmcounter5                  6  (flush-cycles)
+hpmcounter7                  3  (stores)
+hpmcounter8                  3  (muldiv)
+hpmcounter9                  1  (jumps)
+hpmcounter10                 3  (branches)
+");
+        }
+
+        /// The upper halves only exist on RV32.
+        #[test]
+        fn read() {
+            let mut counters = Counters::new();
+            counters.values[2] = 0x1_0000_0002;
+            assert_eq!(counters.read(0xc02, 64), Some(0x1_0000_0002));
+            assert_eq!(counters.read(0xc82, 32), Some(1));
+            assert_eq!(counters.read(0xc82, 64), None);
+            assert_eq!(counters.read(0xc1f, 64), Some(0));
+            assert_eq!(counters.read(0xc20, 64), None);
+            assert_eq!(counter_number("hpmcounter31h").and_then(counter_name).as_deref(), Some("hpmcounter31h"));
+            assert_eq!(Counters::extension(0xc83), Extension::Zihpm);
+        }
+    }
+}
@@ -0,0 +1,258 @@
+#![allow(clippy::module_inception)]
+#[allow(dead_code)]
+pub mod coverage {
+    //! This module implements code coverage over the retired instruction
+    //! stream. Executed PCs and branch directions are recorded, then
+    //! mapped to the functions in `Program::funcs` and, when line
+    //! information is available, to source lines in lcov `.info` format.
+
+    use std::collections::{BTreeMap, HashMap};
+    use std::io::{self, Write};
+    use crate::{Program, InstCode, InstCategory};
+
+    /// Coverage of one program run.
+    #[derive(Default)]
+    pub struct Coverage {
+        /// PC => times executed.
+        hits: HashMap<u64, u64>,
+        /// Branch PC => (taken, not taken).
+        branches: HashMap<u64, (u64, u64)>,
+    }
+
+    impl Coverage {
+        pub fn new() -> Self {
+            Coverage::default()
+        }
+
+        /// Account one retired instruction.
+        pub fn retire(&mut self, pc: u64, code: InstCode, next_pc: u64) {
+            *self.hits.entry(pc).or_insert(0) += 1;
+
+            match code {
+                InstCode::Beq(_,_,ofs) | InstCode::Bne(_,_,ofs) |
+                InstCode::Blt(_,_,ofs) | InstCode::Bge(_,_,ofs) |
+                InstCode::Bltu(_,_,ofs) | InstCode::Bgeu(_,_,ofs) => {
+                    let counts = self.branches.entry(pc).or_insert((0, 0));
+                    if next_pc == (pc as i64 + ofs as i64) as u64 {
+                        counts.0 += 1;
+                    }
+                    else {
+                        counts.1 += 1;
+                    }
+                },
+                _ => {}
+            }
+        }
+
+        /// Times the instruction at `pc` was executed.
+        pub fn hits(&self, pc: u64) -> u64 {
+            *self.hits.get(&pc).unwrap_or(&0)
+        }
+
+        /// Decode every instruction of every function in `prog.funcs`.
+        /// Returns PC => (instruction, function, offset into function).
+        fn instructions(prog: &Program) -> BTreeMap<u64, (InstCode, &str, u64)> {
+            let mut insts = BTreeMap::new();
+            for (start, sz, name) in prog.funcs.iter() {
+                let mut pc = *start;
+                while pc < *start + *sz as u64 {
+                    let code = match prog.mem_load(pc, 4, true) {
+                        Some((data, 0)) => {
+                            let raw = u32::from_le_bytes(data.try_into().unwrap());
+                            InstCode::parse((raw & 0xFFFF) as u16, (raw >> 16) as u16, prog.isa.xlen)
+                        },
+                        _ => break,
+                    };
+                    insts.entry(pc).or_insert((code.0, name.as_str(), pc - *start));
+                    pc += code.1 as u64 / 8;
+                }
+            }
+            insts
+        }
+
+        /// Returns (instructions executed, instructions in `prog.funcs`).
+        pub fn summary(&self, prog: &Program) -> (usize, usize) {
+            let insts = Coverage::instructions(prog);
+            let hit = insts.keys().filter(|pc| self.hits.contains_key(pc)).count();
+            (hit, insts.len())
+        }
+
+        /// Write the per-instruction hit map: one line per instruction in
+        /// `prog.funcs`, with its hit count and, for branches, the number
+        /// of times it was taken and not taken.
+        pub fn write_hit_map<W: Write>(&self, prog: &Program, out: &mut W) -> io::Result<()> {
+            writeln!(out, "# address\thits\ttaken\tnot_taken\tsymbol")?;
+            for (pc, (code, name, ofs)) in Coverage::instructions(prog) {
+                let (taken, not_taken) = match (code.category(), self.branches.get(&pc)) {
+                    (InstCategory::Branch, Some((t, n))) => (t.to_string(), n.to_string()),
+                    (InstCategory::Branch, None) => (String::from("0"), String::from("0")),
+                    _ => (String::from("-"), String::from("-")),
+                };
+                writeln!(out, "{:#x}\t{}\t{}\t{}\t<{}+{:#x}>", pc, self.hits(pc), taken, not_taken, name, ofs)?;
+            }
+            Ok(())
+        }
+
+        /// Write an lcov tracefile. `line_of` maps a PC to its (source file,
+        /// line); instructions without line information are only accounted
+        /// in the function records, which are then filed under `default_file`.
+        pub fn write_lcov<W, F>(&self, prog: &Program, default_file: &str, line_of: F, out: &mut W) -> io::Result<()>
+        where
+            W: Write,
+            F: Fn(u64) -> Option<(String, u32)>,
+        {
+            /// Per source file records.
+            #[derive(Default)]
+            struct Record {
+                /// (line, name, hits)
+                funcs: Vec<(u32, String, u64)>,
+                /// line => hits
+                lines: BTreeMap<u32, u64>,
+                /// (line, taken, not taken, executed)
+                branches: Vec<(u32, u64, u64, bool)>,
+            }
+            let mut records: BTreeMap<String, Record> = BTreeMap::new();
+
+            let mut seen = Vec::new();
+            for (start, sz, name) in prog.funcs.iter() {
+                if *sz == 0 || seen.contains(start) {
+                    continue;
+                }
+                seen.push(*start);
+                let (file, line) = line_of(*start).unwrap_or((String::from(default_file), 0));
+                records.entry(file).or_default().funcs.push((line, name.clone(), self.hits(*start)));
+            }
+
+            for (pc, (code, _, _)) in Coverage::instructions(prog) {
+                if let Some((file, line)) = line_of(pc) {
+                    let record = records.entry(file).or_default();
+                    let hits = record.lines.entry(line).or_insert(0);
+                    // A line is executed as often as its most executed instruction
+                    *hits = std::cmp::max(*hits, self.hits(pc));
+
+                    if let InstCategory::Branch = code.category() {
+                        let (taken, not_taken) = *self.branches.get(&pc).unwrap_or(&(0, 0));
+                        record.branches.push((line, taken, not_taken, self.hits.contains_key(&pc)));
+                    }
+                }
+            }
+
+            writeln!(out, "TN:")?;
+            for (file, record) in records {
+                writeln!(out, "SF:{}", file)?;
+                for (line, name, _) in record.funcs.iter() {
+                    writeln!(out, "FN:{},{}", line, name)?;
+                }
+                for (_, name, hits) in record.funcs.iter() {
+                    writeln!(out, "FNDA:{},{}", hits, name)?;
+                }
+                writeln!(out, "FNF:{}", record.funcs.len())?;
+                writeln!(out, "FNH:{}", record.funcs.iter().filter(|(_, _, hits)| *hits > 0).count())?;
+
+                for (block, (line, taken, not_taken, executed)) in record.branches.iter().enumerate() {
+                    let count = |cnt: &u64| if *executed { cnt.to_string() } else { String::from("-") };
+                    writeln!(out, "BRDA:{},{},0,{}", line, block, count(taken))?;
+                    writeln!(out, "BRDA:{},{},1,{}", line, block, count(not_taken))?;
+                }
+                writeln!(out, "BRF:{}", record.branches.len() * 2)?;
+                writeln!(out, "BRH:{}", record.branches.iter()
+                    .map(|(_, t, n, _)| (*t > 0) as usize + (*n > 0) as usize)
+                    .sum::<usize>())?;
+
+                for (line, hits) in record.lines.iter() {
+                    writeln!(out, "DA:{},{}", line, hits)?;
+                }
+                writeln!(out, "LF:{}", record.lines.len())?;
+                writeln!(out, "LH:{}", record.lines.values().filter(|hits| **hits > 0).count())?;
+                writeln!(out, "end_of_record")?;
+            }
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::{Assembler, Inst, HLT_ADDR};
+
+        #[test]
+        fn lcov() {
+            let mut prog = Assembler::new("
+                main:   li t0, 2
+                .Lloop: addi t0, t0, -1
+                        bnez t0, .Lloop
+                        beqz t0, .Ldone
+                .Ldone: ret
+                unused: beqz a0, unused
+                nolines: ret").load().unwrap();
+            let mut coverage = Coverage::new();
+            while prog.program_counter != HLT_ADDR {
+                let pc = prog.program_counter;
+                let mut inst = Inst::new().advance(&mut prog).unwrap();
+                let code = inst.code();
+                prog.program_counter = loop {
+                    match inst.advance(&mut prog) {
+                        Ok(next) => inst = next,
+                        Err(next_pc) => break next_pc,
+                    }
+                };
+                coverage.retire(pc, code, prog.program_counter);
+            }
+            assert_eq!(coverage.summary(&prog), (5, 7));
+
+            // main is lines 1 to 5 of a.s, unused line 10 of b.s
+            let line_of = |pc: u64| match pc {
+                0x10000..=0x10010 => Some((String::from("a.s"), (pc - 0x10000) as u32 / 4 + 1)),
+                0x10014 => Some((String::from("b.s"), 10)),
+                _ => None,
+            };
+            let mut out = Vec::new();
+            coverage.write_lcov(&prog, "a.out", line_of, &mut out).unwrap();
+            assert_eq!(String::from_utf8(out).unwrap(), "\
+TN:
+SF:a.out
+FN:0,nolines
+FNDA:0,nolines
+FNF:1
+FNH:0
+BRF:0
+BRH:0
+LF:0
+LH:0
+end_of_record
+SF:a.s
+FN:1,main
+FNDA:1,main
+FNF:1
+FNH:1
+BRDA:3,0,0,1
+BRDA:3,0,1,1
+BRDA:4,1,0,1
+BRDA:4,1,1,0
+BRF:4
+BRH:3
+DA:1,1
+DA:2,2
+DA:3,2
+DA:4,1
+DA:5,1
+LF:5
+LH:5
+end_of_record
+SF:b.s
+FN:10,unused
+FNDA:0,unused
+FNF:1
+FNH:0
+BRDA:10,0,0,-
+BRDA:10,0,1,-
+BRF:2
+BRH:0
+DA:10,0
+LF:1
+LH:0
+end_of_record
+");
+        }
+    }
+}
@@ -0,0 +1,464 @@
+#![allow(clippy::module_inception)]
+#[allow(dead_code)]
+pub mod disasm {
+    //! This module formats instructions as GNU assembly, the way `objdump
+    //! -d` prints them: canonical operand syntax (`addi sp,sp,-16`, `sd
+    //! ra,8(sp)`), the common pseudo-instructions (`li`, `mv`, `ret`, `j`,
+    //! `nop`, `beqz`, ...), and absolute branch targets annotated with the
+    //! enclosing symbol. When disassembling a range, `auipc`+`jalr` pairs
+    //! are fused into `call` or `tail`, and other uses of an `auipc` are
+    //! annotated with the address they compute.
+
+    use std::fmt;
+    use crate::{InstCode, Program, RegID};
+    use crate::counters::counters::counter_name;
+    use crate::vector::vector::{vector_csr_name, vtype_name};
+    use crate::float::float::{freg_name, rm_name};
+
+    /// Names of the CSRs known to the disassembler.
+    pub(crate) const CSRS: [(u16, &str); 21] = [
+        (0x001, "fflags"), (0x002, "frm"), (0x003, "fcsr"),
+        (0x100, "sstatus"), (0x104, "sie"), (0x105, "stvec"), (0x140, "sscratch"),
+        (0x141, "sepc"), (0x142, "scause"), (0x143, "stval"), (0x144, "sip"), (0x180, "satp"),
+        (0x300, "mstatus"), (0x301, "misa"), (0x304, "mie"), (0x305, "mtvec"), (0x340, "mscratch"),
+        (0x341, "mepc"), (0x342, "mcause"), (0x343, "mtval"), (0x344, "mip"),
+    ];
+
+    /// One line of disassembly.
+    #[derive(Debug, Clone)]
+    pub struct Disasm {
+        pub addr: u64,
+        /// Encodings of the instructions: (raw, length in bytes)
+        pub raw: Vec<(u32, usize)>,
+        /// The first instruction of the line
+        pub code: InstCode,
+        pub mnemonic: String,
+        pub operands: String,
+        /// `# ...` annotation, if any
+        pub comment: Option<String>,
+    }
+
+    impl Disasm {
+        /// Number of bytes covered by the line.
+        pub fn size(&self) -> usize {
+            self.raw.iter().map(|(_, len)| len).sum()
+        }
+
+        /// The encodings, as halfwords or words in hex.
+        pub fn raw_hex(&self) -> String {
+            self.raw.iter()
+                .map(|(raw, len)| if *len == 2 { format!("{:04x}", raw) } else { format!("{:08x}", raw) })
+                .collect::<Vec<_>>()
+                .join(" ")
+        }
+
+        /// The assembly text, with the mnemonic padded to a column.
+        pub fn asm(&self) -> String {
+            let mut text = format!("{:<7} {}", self.mnemonic, self.operands);
+            if let Some(comment) = self.comment.as_ref() {
+                text += &format!(" # {}", comment);
+            }
+            String::from(text.trim_end())
+        }
+    }
+
+    impl fmt::Display for Disasm {
+        /// Same layout as `objdump -d`.
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{:>8x}:\t{:<18}\t{}", self.addr, self.raw_hex(), self.mnemonic)?;
+            if !self.operands.is_empty() {
+                write!(f, "\t{}", self.operands)?;
+            }
+            if let Some(comment) = self.comment.as_ref() {
+                write!(f, " # {}", comment)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// The symbol `addr` is in, as `<func>` or `<func+0x..>`, or an empty
+    /// string if none.
+    pub fn symbolize(prog: &Program, addr: u64) -> String {
+        match prog.func_at(addr) {
+            Some((start, _, name)) if addr == *start => format!("<{}>", name),
+            Some((start, _, name)) => format!("<{}+{:#x}>", name, addr - start),
+            None => String::new(),
+        }
+    }
+
+    /// An absolute code address, with its symbol if known.
+    fn target(prog: &Program, addr: u64) -> String {
+        format!("{:x} {}", addr, symbolize(prog, addr)).trim_end().to_string()
+    }
+
+    fn csr_name(csr: u16) -> String {
+        match CSRS.iter().find(|(num, _)| *num == csr & 0xfff) {
+            Some((_, name)) => String::from(*name),
+            None => counter_name(csr & 0xfff)
+                .or(vector_csr_name(csr & 0xfff).map(String::from))
+                .unwrap_or(format!("{:#x}", csr & 0xfff)),
+        }
+    }
+
+    /// The `,v0.t` operand of masked vector instructions.
+    fn vmask(vm: bool) -> &'static str {
+        if vm { "" } else { ",v0.t" }
+    }
+
+    /// Format vector instruction `code` as (mnemonic, operands).
+    fn format_vector(code: InstCode) -> (&'static str, String) {
+        use crate::VSrc;
+        if let Some((vd, vs2, src, vm)) = code.varith() {
+            return match code {
+                InstCode::Vrsub(_, _, VSrc::X(RegID::X0), _) => ("vneg.v", format!("v{},v{}{}", vd, vs2, vmask(vm))),
+                InstCode::Vxor(_, _, VSrc::I(-1), _) => ("vnot.v", format!("v{},v{}{}", vd, vs2, vmask(vm))),
+                InstCode::Vmerge(_, _, _, _) => (code.mnemonic(), format!("v{},v{},{},v0", vd, vs2, src)),
+                InstCode::Vmv(_, _, _, _) => (code.mnemonic(), format!("v{},{}", vd, src)),
+                InstCode::Vmacc(_, _, _, _) | InstCode::Vnmsac(_, _, _, _) | InstCode::Vmadd(_, _, _, _) |
+                InstCode::Vnmsub(_, _, _, _) => (code.mnemonic(), format!("v{},{},v{}{}", vd, src, vs2, vmask(vm))),
+                _ => (code.mnemonic(), format!("v{},v{},{}{}", vd, vs2, src, vmask(vm))),
+            };
+        }
+        if let Some((vd, vs2, vs1, vm)) = code.vreduction() {
+            return (code.mnemonic(), format!("v{},v{},v{}{}", vd, vs2, vs1, vmask(vm)));
+        }
+        if let Some((vd, vs2, vs1)) = code.vmask_logical() {
+            return match code {
+                InstCode::Vmand(_, _, _) if vs1 == vs2 => ("vmmv.m", format!("v{},v{}", vd, vs2)),
+                InstCode::Vmnand(_, _, _) if vs1 == vs2 => ("vmnot.m", format!("v{},v{}", vd, vs2)),
+                InstCode::Vmxor(_, _, _) if vs1 == vd && vs2 == vd => ("vmclr.m", format!("v{}", vd)),
+                InstCode::Vmxnor(_, _, _) if vs1 == vd && vs2 == vd => ("vmset.m", format!("v{}", vd)),
+                _ => (code.mnemonic(), format!("v{},v{},v{}", vd, vs2, vs1)),
+            };
+        }
+        let operands = match code {
+            InstCode::Vsetvli(rd, rs1, vtype) => format!("{},{},{}", rd, rs1, vtype_name(vtype as u64)),
+            InstCode::Vsetivli(rd, uimm, vtype) => format!("{},{},{}", rd, uimm, vtype_name(vtype as u64)),
+            InstCode::Vsetvl(rd, rs1, rs2) => format!("{},{},{}", rd, rs1, rs2),
+            InstCode::Vle(vd, rs1, _, vm) | InstCode::Vse(vd, rs1, _, vm) => format!("v{},({}){}", vd, rs1, vmask(vm)),
+            InstCode::Vlse(vd, rs1, rs2, _, vm) | InstCode::Vsse(vd, rs1, rs2, _, vm) => format!("v{},({}),{}{}", vd, rs1, rs2, vmask(vm)),
+            InstCode::Vluxei(vd, rs1, vs2, _, vm) | InstCode::Vloxei(vd, rs1, vs2, _, vm) |
+            InstCode::Vsuxei(vd, rs1, vs2, _, vm) | InstCode::Vsoxei(vd, rs1, vs2, _, vm) => format!("v{},({}),v{}{}", vd, rs1, vs2, vmask(vm)),
+            InstCode::Vlm(vd, rs1) | InstCode::Vsm(vd, rs1) | InstCode::Vlre(vd, rs1, _, _) |
+            InstCode::Vsr(vd, rs1, _) => format!("v{},({})", vd, rs1),
+            InstCode::VmvNr(vd, vs2, _) => format!("v{},v{}", vd, vs2),
+            InstCode::VmvXS(rd, vs2) => format!("{},v{}", rd, vs2),
+            InstCode::VmvSX(vd, rs1) => format!("v{},{}", vd, rs1),
+            InstCode::Vcpop(rd, vs2, vm) | InstCode::Vfirst(rd, vs2, vm) => format!("{},v{}{}", rd, vs2, vmask(vm)),
+            InstCode::Vid(vd, vm) => format!("v{}{}", vd, vmask(vm)),
+            _ => unreachable!(),
+        };
+        (code.mnemonic(), operands)
+    }
+
+    /// The `,rtz` operand of floating-point instructions with a static
+    /// rounding mode.
+    fn rounding(rm: u8) -> String {
+        match rm_name(rm) {
+            "" => String::new(),
+            name => format!(",{}", name),
+        }
+    }
+
+    /// Format F or D instruction `code` as (mnemonic, operands).
+    fn format_float(code: InstCode) -> (&'static str, String) {
+        let f = freg_name;
+        let pick = |width: u8, s: &'static str, d: &'static str| if width == 4 { s } else { d };
+        let operands = match code {
+            InstCode::Fl(rd, rs1, imm, _) => format!("{},{}({})", f(rd), imm, rs1),
+            InstCode::Fs(rs1, rs2, imm, _) => format!("{},{}({})", f(rs2), imm, rs1),
+            InstCode::Fmadd(rd, rs1, rs2, rs3, rm, _) | InstCode::Fmsub(rd, rs1, rs2, rs3, rm, _) |
+            InstCode::Fnmsub(rd, rs1, rs2, rs3, rm, _) |
+            InstCode::Fnmadd(rd, rs1, rs2, rs3, rm, _) => format!("{},{},{},{}{}", f(rd), f(rs1), f(rs2), f(rs3), rounding(rm)),
+            InstCode::Fadd(rd, rs1, rs2, rm, _) | InstCode::Fsub(rd, rs1, rs2, rm, _) |
+            InstCode::Fmul(rd, rs1, rs2, rm, _) |
+            InstCode::Fdiv(rd, rs1, rs2, rm, _) => format!("{},{},{}{}", f(rd), f(rs1), f(rs2), rounding(rm)),
+            InstCode::Fsqrt(rd, rs1, rm, _) | InstCode::FcvtFF(rd, rs1, rm, _) => format!("{},{}{}", f(rd), f(rs1), rounding(rm)),
+            InstCode::Fsgnj(rd, rs1, rs2, width) if rs1 == rs2 => return (pick(width, "fmv.s", "fmv.d"), format!("{},{}", f(rd), f(rs1))),
+            InstCode::Fsgnjn(rd, rs1, rs2, width) if rs1 == rs2 => return (pick(width, "fneg.s", "fneg.d"), format!("{},{}", f(rd), f(rs1))),
+            InstCode::Fsgnjx(rd, rs1, rs2, width) if rs1 == rs2 => return (pick(width, "fabs.s", "fabs.d"), format!("{},{}", f(rd), f(rs1))),
+            InstCode::Fsgnj(rd, rs1, rs2, _) | InstCode::Fsgnjn(rd, rs1, rs2, _) | InstCode::Fsgnjx(rd, rs1, rs2, _) |
+            InstCode::Fmin(rd, rs1, rs2, _) | InstCode::Fmax(rd, rs1, rs2, _) => format!("{},{},{}", f(rd), f(rs1), f(rs2)),
+            InstCode::Feq(rd, rs1, rs2, _) | InstCode::Flt(rd, rs1, rs2, _) |
+            InstCode::Fle(rd, rs1, rs2, _) => format!("{},{},{}", rd, f(rs1), f(rs2)),
+            InstCode::Fclass(rd, rs1, _) | InstCode::FmvXF(rd, rs1, _) => format!("{},{}", rd, f(rs1)),
+            InstCode::FcvtW(rd, rs1, rm, _) | InstCode::FcvtWu(rd, rs1, rm, _) | InstCode::FcvtL(rd, rs1, rm, _) |
+            InstCode::FcvtLu(rd, rs1, rm, _) => format!("{},{}{}", rd, f(rs1), rounding(rm)),
+            InstCode::FcvtFW(rd, rs1, rm, _) | InstCode::FcvtFWu(rd, rs1, rm, _) | InstCode::FcvtFL(rd, rs1, rm, _) |
+            InstCode::FcvtFLu(rd, rs1, rm, _) => format!("{},{}{}", f(rd), rs1, rounding(rm)),
+            InstCode::FmvFX(rd, rs1, _) => format!("{},{}", f(rd), rs1),
+            _ => unreachable!(),
+        };
+        (code.mnemonic(), operands)
+    }
+
+    /// The `iorw` set of a fence.
+    fn fence_set(bits: u8) -> String {
+        "iorw".chars().enumerate()
+            .filter(|(idx, _)| bits & (8 >> idx) != 0)
+            .map(|(_, ch)| ch)
+            .collect()
+    }
+
+    /// The suffix of an A instruction for its aq/rl bits.
+    fn ordering(order: u8) -> &'static str {
+        ["", ".rl", ".aq", ".aqrl"][order as usize & 0b11]
+    }
+
+    /// Format `code` located at `pc` as (mnemonic, operands).
+    pub fn format(code: InstCode, pc: u64, prog: &Program) -> (String, String) {
+        use RegID::X0 as ZERO;
+        use RegID::X1 as RA;
+        let branch = |ofs: i16| target(prog, pc.wrapping_add(ofs as i64 as u64));
+        let (mnemonic, operands) = match code {
+            // Pseudo-instructions first
+            InstCode::Addi(ZERO, ZERO, 0) => ("nop", String::new()),
+            InstCode::Addi(rd, ZERO, imm) => ("li", format!("{},{}", rd, imm)),
+            InstCode::Addi(rd, rs1, 0) => ("mv", format!("{},{}", rd, rs1)),
+            InstCode::Addiw(rd, rs1, 0) => ("sext.w", format!("{},{}", rd, rs1)),
+            InstCode::Xori(rd, rs1, -1) => ("not", format!("{},{}", rd, rs1)),
+            InstCode::Sltiu(rd, rs1, 1) => ("seqz", format!("{},{}", rd, rs1)),
+            InstCode::Sub(rd, ZERO, rs2) => ("neg", format!("{},{}", rd, rs2)),
+            InstCode::Subw(rd, ZERO, rs2) => ("negw", format!("{},{}", rd, rs2)),
+            InstCode::Sltu(rd, ZERO, rs2) => ("snez", format!("{},{}", rd, rs2)),
+            InstCode::Slt(rd, rs1, ZERO) => ("sltz", format!("{},{}", rd, rs1)),
+            InstCode::Slt(rd, ZERO, rs2) => ("sgtz", format!("{},{}", rd, rs2)),
+            InstCode::Beq(rs1, ZERO, ofs) => ("beqz", format!("{},{}", rs1, branch(ofs))),
+            InstCode::Bne(rs1, ZERO, ofs) => ("bnez", format!("{},{}", rs1, branch(ofs))),
+            InstCode::Blt(rs1, ZERO, ofs) => ("bltz", format!("{},{}", rs1, branch(ofs))),
+            InstCode::Bge(rs1, ZERO, ofs) => ("bgez", format!("{},{}", rs1, branch(ofs))),
+            InstCode::Blt(ZERO, rs2, ofs) => ("bgtz", format!("{},{}", rs2, branch(ofs))),
+            InstCode::Bge(ZERO, rs2, ofs) => ("blez", format!("{},{}", rs2, branch(ofs))),
+            InstCode::Jal(ZERO, ofs) => ("j", target(prog, pc.wrapping_add(ofs as i64 as u64))),
+            InstCode::Jal(RA, ofs) => ("jal", target(prog, pc.wrapping_add(ofs as i64 as u64))),
+            InstCode::Jalr(ZERO, RA, 0) => ("ret", String::new()),
+            InstCode::Jalr(ZERO, rs1, 0) => ("jr", format!("{}", rs1)),
+            InstCode::Jalr(RA, rs1, 0) => ("jalr", format!("{}", rs1)),
+            InstCode::Csrrs(rd, ZERO, 0xc00) => ("rdcycle", format!("{}", rd)),
+            InstCode::Csrrs(rd, ZERO, 0xc01) => ("rdtime", format!("{}", rd)),
+            InstCode::Csrrs(rd, ZERO, 0xc02) => ("rdinstret", format!("{}", rd)),
+            InstCode::Csrrs(rd, ZERO, 0xc80) => ("rdcycleh", format!("{}", rd)),
+            InstCode::Csrrs(rd, ZERO, 0xc81) => ("rdtimeh", format!("{}", rd)),
+            InstCode::Csrrs(rd, ZERO, 0xc82) => ("rdinstreth", format!("{}", rd)),
+            InstCode::Csrrs(rd, ZERO, csr) => ("csrr", format!("{},{}", rd, csr_name(csr))),
+            InstCode::Csrrw(ZERO, rs1, csr) => ("csrw", format!("{},{}", csr_name(csr), rs1)),
+            InstCode::Csrrs(ZERO, rs1, csr) => ("csrs", format!("{},{}", csr_name(csr), rs1)),
+            InstCode::Csrrc(ZERO, rs1, csr) => ("csrc", format!("{},{}", csr_name(csr), rs1)),
+            InstCode::Csrrwi(ZERO, csr, imm) => ("csrwi", format!("{},{}", csr_name(csr), imm)),
+            InstCode::Csrrsi(ZERO, csr, imm) => ("csrsi", format!("{},{}", csr_name(csr), imm)),
+            InstCode::Csrrci(ZERO, csr, imm) => ("csrci", format!("{},{}", csr_name(csr), imm)),
+            InstCode::Fence(0b1111, 0b1111) => ("fence", String::new()),
+            InstCode::AddUw(rd, rs1, ZERO) => ("zext.w", format!("{},{}", rd, rs1)),
+
+            // rd, rs1, rs2
+            InstCode::Add(rd, rs1, rs2) | InstCode::Mul(rd, rs1, rs2) | InstCode::Sub(rd, rs1, rs2) |
+            InstCode::Sll(rd, rs1, rs2) | InstCode::Mulh(rd, rs1, rs2) | InstCode::Slt(rd, rs1, rs2) |
+            InstCode::Sltu(rd, rs1, rs2) | InstCode::Xor(rd, rs1, rs2) | InstCode::Div(rd, rs1, rs2) |
+            InstCode::Srl(rd, rs1, rs2) | InstCode::Sra(rd, rs1, rs2) | InstCode::Or(rd, rs1, rs2) |
+            InstCode::Rem(rd, rs1, rs2) | InstCode::And(rd, rs1, rs2) | InstCode::Addw(rd, rs1, rs2) |
+            InstCode::Subw(rd, rs1, rs2) | InstCode::Mulw(rd, rs1, rs2) | InstCode::Divw(rd, rs1, rs2) |
+            InstCode::Sllw(rd, rs1, rs2) | InstCode::Srlw(rd, rs1, rs2) | InstCode::Sraw(rd, rs1, rs2) |
+            InstCode::Remw(rd, rs1, rs2) | InstCode::Mulhsu(rd, rs1, rs2) | InstCode::Mulhu(rd, rs1, rs2) |
+            InstCode::Divu(rd, rs1, rs2) | InstCode::Remu(rd, rs1, rs2) | InstCode::Divuw(rd, rs1, rs2) |
+            InstCode::Remuw(rd, rs1, rs2) | InstCode::Sh1add(rd, rs1, rs2) | InstCode::Sh2add(rd, rs1, rs2) |
+            InstCode::Sh3add(rd, rs1, rs2) | InstCode::AddUw(rd, rs1, rs2) | InstCode::Sh1addUw(rd, rs1, rs2) |
+            InstCode::Sh2addUw(rd, rs1, rs2) | InstCode::Sh3addUw(rd, rs1, rs2) | InstCode::Andn(rd, rs1, rs2) |
+            InstCode::Orn(rd, rs1, rs2) | InstCode::Xnor(rd, rs1, rs2) | InstCode::Max(rd, rs1, rs2) |
+            InstCode::Maxu(rd, rs1, rs2) | InstCode::Min(rd, rs1, rs2) | InstCode::Minu(rd, rs1, rs2) |
+            InstCode::Rol(rd, rs1, rs2) | InstCode::Ror(rd, rs1, rs2) | InstCode::Rolw(rd, rs1, rs2) |
+            InstCode::Rorw(rd, rs1, rs2) | InstCode::Clmul(rd, rs1, rs2) | InstCode::Clmulh(rd, rs1, rs2) |
+            InstCode::Clmulr(rd, rs1, rs2) | InstCode::Bclr(rd, rs1, rs2) | InstCode::Bext(rd, rs1, rs2) |
+            InstCode::Binv(rd, rs1, rs2) | InstCode::Bset(rd, rs1, rs2) |
+            InstCode::CzeroEqz(rd, rs1, rs2) | InstCode::CzeroNez(rd, rs1, rs2) => (code.mnemonic(), format!("{},{},{}", rd, rs1, rs2)),
+            // rd, rs1
+            InstCode::Clz(rd, rs1) | InstCode::Ctz(rd, rs1) | InstCode::Cpop(rd, rs1) | InstCode::Clzw(rd, rs1) |
+            InstCode::Ctzw(rd, rs1) | InstCode::Cpopw(rd, rs1) | InstCode::SextB(rd, rs1) | InstCode::SextH(rd, rs1) |
+            InstCode::ZextH(rd, rs1) | InstCode::OrcB(rd, rs1) | InstCode::Rev8(rd, rs1) => (code.mnemonic(), format!("{},{}", rd, rs1)),
+            // rd, imm(rs1)
+            InstCode::Lb(rd, rs1, imm) | InstCode::Lbu(rd, rs1, imm) | InstCode::Lh(rd, rs1, imm) |
+            InstCode::Lhu(rd, rs1, imm) | InstCode::Lw(rd, rs1, imm) | InstCode::Lwu(rd, rs1, imm) |
+            InstCode::Ld(rd, rs1, imm) | InstCode::Jalr(rd, rs1, imm) => (code.mnemonic(), format!("{},{}({})", rd, imm, rs1)),
+            // rs2, imm(rs1)
+            InstCode::Sb(rs1, rs2, imm) | InstCode::Sh(rs1, rs2, imm) | InstCode::Sw(rs1, rs2, imm) |
+            InstCode::Sd(rs1, rs2, imm) => (code.mnemonic(), format!("{},{}({})", rs2, imm, rs1)),
+            // Shift amounts, without the function bits
+            InstCode::Slli(rd, rs1, imm) | InstCode::Srli(rd, rs1, imm) | InstCode::Srai(rd, rs1, imm) |
+            InstCode::Slliw(rd, rs1, imm) | InstCode::Srliw(rd, rs1, imm) |
+            InstCode::Sraiw(rd, rs1, imm) | InstCode::SlliUw(rd, rs1, imm) | InstCode::Rori(rd, rs1, imm) |
+            InstCode::Roriw(rd, rs1, imm) | InstCode::Bclri(rd, rs1, imm) | InstCode::Bexti(rd, rs1, imm) |
+            InstCode::Binvi(rd, rs1, imm) | InstCode::Bseti(rd, rs1, imm) => (code.mnemonic(), format!("{},{},{}", rd, rs1, imm & 0x3f)),
+            // rd, rs1, imm
+            InstCode::Addi(rd, rs1, imm) | InstCode::Slti(rd, rs1, imm) | InstCode::Sltiu(rd, rs1, imm) |
+            InstCode::Xori(rd, rs1, imm) | InstCode::Ori(rd, rs1, imm) | InstCode::Andi(rd, rs1, imm) |
+            InstCode::Addiw(rd, rs1, imm) => (code.mnemonic(), format!("{},{},{}", rd, rs1, imm)),
+            // rs1, rs2, target
+            InstCode::Beq(rs1, rs2, ofs) | InstCode::Bne(rs1, rs2, ofs) | InstCode::Blt(rs1, rs2, ofs) |
+            InstCode::Bge(rs1, rs2, ofs) | InstCode::Bltu(rs1, rs2, ofs) |
+            InstCode::Bgeu(rs1, rs2, ofs) => (code.mnemonic(), format!("{},{},{}", rs1, rs2, branch(ofs))),
+            InstCode::Auipc(rd, imm) | InstCode::Lui(rd, imm) => (code.mnemonic(), format!("{},{:#x}", rd, (imm as u32) >> 12)),
+            InstCode::Jal(rd, ofs) => ("jal", format!("{},{}", rd, target(prog, pc.wrapping_add(ofs as i64 as u64)))),
+            InstCode::Fence(pred, succ) => ("fence", format!("{},{}", fence_set(pred), fence_set(succ))),
+            InstCode::Csrrw(rd, rs1, csr) | InstCode::Csrrs(rd, rs1, csr) |
+            InstCode::Csrrc(rd, rs1, csr) => (code.mnemonic(), format!("{},{},{}", rd, csr_name(csr), rs1)),
+            InstCode::Csrrwi(rd, csr, imm) | InstCode::Csrrsi(rd, csr, imm) |
+            InstCode::Csrrci(rd, csr, imm) => (code.mnemonic(), format!("{},{},{}", rd, csr_name(csr), imm)),
+            InstCode::SfenceVma(rs1, rs2) => ("sfence.vma", format!("{},{}", rs1, rs2)),
+            // rd(, rs2), (rs1), with the aq/rl bits in the mnemonic
+            InstCode::Lr(rd, rs1, _, order) => {
+                return (format!("{}{}", code.mnemonic(), ordering(order)), format!("{},({})", rd, rs1));
+            },
+            InstCode::Sc(rd, rs1, rs2, _, order) => {
+                return (format!("{}{}", code.mnemonic(), ordering(order)), format!("{},{},({})", rd, rs2, rs1));
+            },
+            _ if code.amo().is_some() => {
+                let (rd, rs1, rs2, _, order) = code.amo().unwrap();
+                return (format!("{}{}", code.mnemonic(), ordering(order)), format!("{},{},({})", rd, rs2, rs1));
+            },
+            InstCode::Unknown(raw) => (".4byte", format!("{:#x}", raw)),
+            InstCode::UnknownC(raw) => (".2byte", format!("{:#x}", raw)),
+            InstCode::Ecall | InstCode::Ebreak | InstCode::FenceI | InstCode::Uret | InstCode::Sret |
+            InstCode::Mret | InstCode::Wfi | InstCode::IllegalProlonged => (code.mnemonic(), String::new()),
+            _ if code.is_float() => format_float(code),
+            _ => format_vector(code),
+        };
+        (String::from(mnemonic), operands)
+    }
+
+    /// Decode the instruction at `addr` from executable memory. Returns
+    /// the instruction, its encoding and length in bytes.
+    pub fn decode_at(prog: &Program, addr: u64) -> Option<(InstCode, u32, usize)> {
+        let first = match prog.mem_load(addr, 2, true) {
+            Some((data, 0)) => u16::from_le_bytes(data.try_into().unwrap()),
+            _ => return None,
+        };
+        // The second halfword may lie past the end of the segment for a
+        // compressed instruction
+        let second = match prog.mem_load(addr + 2, 2, true) {
+            Some((data, 0)) => u16::from_le_bytes(data.try_into().unwrap()),
+            _ if first & 0b11 != 0b11 => 0,
+            _ => return None,
+        };
+        let (code, len) = InstCode::parse(first, second, prog.isa.xlen);
+        let raw = if len == 16 { first as u32 } else { ((second as u32) << 16) | first as u32 };
+        Some((code, raw, len / 8))
+    }
+
+    /// Disassemble the instruction at `addr` on its own.
+    pub fn disassemble_one(prog: &Program, addr: u64) -> Option<Disasm> {
+        let (code, raw, len) = decode_at(prog, addr)?;
+        let (mnemonic, operands) = format(code, addr, prog);
+        Some(Disasm { addr, raw: vec![(raw, len)], code, mnemonic, operands, comment: None })
+    }
+
+    /// Disassemble `[start, end)`, stopping early at unreadable memory.
+    pub fn disassemble(prog: &Program, start: u64, end: u64) -> Vec<Disasm> {
+        let mut lines: Vec<Disasm> = Vec::new();
+        let mut addr = start;
+        while addr < end {
+            let line = match disassemble_one(prog, addr) {
+                Some(line) => line,
+                None => break,
+            };
+            addr += line.size() as u64;
+
+            // Pair up with a preceding auipc of the same register
+            let hi = match lines.last() {
+                Some(Disasm { code: InstCode::Auipc(rd, imm), raw, .. }) if raw.len() == 1 => Some((*rd, *imm, lines.last().unwrap().addr)),
+                _ => None,
+            };
+            if let Some((base, imm, pc)) = hi {
+                let absolute = |lo: i16| pc.wrapping_add(imm as i64 as u64).wrapping_add(lo as i64 as u64);
+                match line.code {
+                    InstCode::Jalr(rd @ (RegID::X0 | RegID::X1), rs1, lo) if rs1 == base &&
+                        (rd == RegID::X1 || base == RegID::X6) =>
+                    {
+                        let prev = lines.last_mut().unwrap();
+                        prev.raw.extend(line.raw);
+                        prev.code = line.code;
+                        prev.mnemonic = String::from(if rd == RegID::X1 { "call" } else { "tail" });
+                        prev.operands = target(prog, absolute(lo));
+                        continue;
+                    },
+                    InstCode::Addi(_, rs1, lo) | InstCode::Ld(_, rs1, lo) | InstCode::Lw(_, rs1, lo) |
+                    InstCode::Lwu(_, rs1, lo) | InstCode::Lh(_, rs1, lo) | InstCode::Lhu(_, rs1, lo) |
+                    InstCode::Lb(_, rs1, lo) | InstCode::Lbu(_, rs1, lo) | InstCode::Jalr(_, rs1, lo) |
+                    InstCode::Sd(rs1, _, lo) | InstCode::Sw(rs1, _, lo) | InstCode::Sh(rs1, _, lo) |
+                    InstCode::Sb(rs1, _, lo) if rs1 == base => {
+                        let mut line = line;
+                        line.comment = Some(target(prog, absolute(lo)));
+                        lines.push(line);
+                        continue;
+                    },
+                    _ => {},
+                }
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::Assembler;
+
+        #[test]
+        fn objdump() {
+            let prog = Assembler::new("
+                        .data
+                val:    .dword 0
+                        .text
+                main:   addi sp, sp, -16
+                        sd ra, 8(sp)
+                        la a0, val
+                        li a1, 5
+                        beqz a1, .Lout
+                        blt a0, a1, helper
+                .Lout:  call helper
+                        ld ra, 8(sp)
+                        addi sp, sp, 16
+                        j helper
+                        ret
+                helper: mv a1, a0
+                        .half 0x8082
+                        .half 0x0001").load().unwrap();
+            let text: Vec<String> = disassemble(&prog, 0x10000, 0x1003c).iter().map(|line| line.to_string()).collect();
+            assert_eq!(text, [
+                "   10000:\tff010113          \taddi\tsp,sp,-16",
+                "   10004:\t00113423          \tsd\tra,8(sp)",
+                "   10008:\t00001517          \tauipc\ta0,0x1",
+                "   1000c:\tff850513          \taddi\ta0,a0,-8 # 11000",
+                "   10010:\t00500593          \tli\ta1,5",
+                "   10014:\t00058463          \tbeqz\ta1,1001c <main+0x1c>",
+                "   10018:\t00b54e63          \tblt\ta0,a1,10034 <helper>",
+                "   1001c:\t00000097 018080e7 \tcall\t10034 <helper>",
+                "   10024:\t00813083          \tld\tra,8(sp)",
+                "   10028:\t01010113          \taddi\tsp,sp,16",
+                "   1002c:\t0080006f          \tj\t10034 <helper>",
+                "   10030:\t00008067          \tret",
+                "   10034:\t00050593          \tmv\ta1,a0",
+                "   10038:\t8082              \tret",
+                "   1003a:\t0001              \tnop",
+            ]);
+
+            // auipc and jalr on their own are not fused
+            assert_eq!(disassemble_one(&prog, 0x1001c).unwrap().asm(), "auipc   ra,0x0");
+            assert_eq!(disassemble(&prog, 0x10020, 0x10024)[0].asm(), "jalr    ra,24(ra)");
+            assert_eq!(disassemble_one(&prog, 0x1003c).map(|line| line.asm()), None);
+
+            for (code, asm) in [
+                (InstCode::Jal(RegID::X5, -0x28), "jal     t0,1000c <main+0xc>"),
+                (InstCode::Jal(RegID::X1, 0x100000), "jal     110034"),
+                (InstCode::Csrrs(RegID::X10, RegID::X0, 0xc00), "rdcycle a0"),
+                (InstCode::Csrrw(RegID::X0, RegID::X11, 0x300), "csrw    mstatus,a1"),
+                (InstCode::Csrrs(RegID::X12, RegID::X0, 0x7c0), "csrr    a2,0x7c0"),
+                (InstCode::Fence(0b0011, 0b0001), "fence   rw,w"),
+                (InstCode::Unknown(0xffffffff), ".4byte  0xffffffff"),
+            ] {
+                let (mnemonic, operands) = format(code, 0x10034, &prog);
+                let line = Disasm { addr: 0x10034, raw: vec![], code, mnemonic, operands, comment: None };
+                assert_eq!(line.asm(), asm);
+            }
+        }
+    }
+}
@@ -0,0 +1,1126 @@
+#![allow(clippy::module_inception)]
+#[allow(dead_code)]
+pub mod dwarf {
+    //! This module implements a minimal reader of the DWARF debugging
+    //! information embedded in an ELF executable. We only care about
+    //! `.debug_line` for mapping PCs to source lines, and `.debug_info`
+    //! for the names of functions and variables, so that the debugger
+    //! can work at source level. DWARF versions 2 to 5 are supported.
+
+    use std::collections::HashMap;
+
+    /// Returns the sections of an ELF32 or ELF64 little-endian file, by
+    /// name.
+    pub fn elf_sections(data: &[u8]) -> HashMap<String, &[u8]> {
+        let mut sections = HashMap::new();
+        let mut hdr = Reader::new(data);
+        if data.len() < 0x34 || &data[..4] != b"\x7fELF" || !matches!(data[4], 1 | 2) || data[5] != 1 {
+            return sections;
+        }
+        // Addresses and offsets are words of the ELF class
+        let elf64 = data[4] == 2;
+        let word = |reader: &mut Reader| if elf64 { reader.u64() } else { reader.u32().map(u64::from) };
+
+        let headers = (|| {
+            hdr.seek(if elf64 { 0x28 } else { 0x20 })?;
+            let shoff = word(&mut hdr)? as usize;
+            hdr.seek(if elf64 { 0x3A } else { 0x2E })?;
+            let shentsize = hdr.u16()? as usize;
+            let shnum = hdr.u16()? as usize;
+            let shstrndx = hdr.u16()? as usize;
+
+            // (name, offset, size)
+            let mut headers = Vec::new();
+            for idx in 0..shnum {
+                let mut sh = Reader::new(data);
+                sh.seek(idx.checked_mul(shentsize)?.checked_add(shoff)?)?;
+                let name = sh.u32()? as usize;
+                let kind = sh.u32()?;
+                sh.skip(if elf64 { 16 } else { 8 })?;
+                let offset = word(&mut sh)? as usize;
+                let size = word(&mut sh)? as usize;
+                // SHT_NOBITS occupies no file space
+                let size = if kind == 8 { 0 } else { size };
+                headers.push((name, offset, size));
+            }
+            let &(_, strtab, _) = headers.get(shstrndx)?;
+            Some((headers, strtab))
+        })();
+
+        if let Some((headers, strtab)) = headers {
+            for (name, offset, size) in headers {
+                let mut names = Reader::new(data);
+                let name = strtab.checked_add(name).and_then(|pos| names.seek(pos)).and_then(|_| names.cstr());
+                let content = offset.checked_add(size).and_then(|end| data.get(offset..end));
+                if let (Some(name), Some(content)) = (name, content) {
+                    sections.insert(name, content);
+                }
+            }
+        }
+        sections
+    }
+
+    /// A cursor over little-endian DWARF data. All reads return None
+    /// when running past the end, so malformed input is simply dropped.
+    #[derive(Clone)]
+    struct Reader<'a> {
+        data: &'a [u8],
+        pos: usize,
+    }
+
+    impl<'a> Reader<'a> {
+        fn new(data: &'a [u8]) -> Self {
+            Reader { data, pos: 0 }
+        }
+
+        fn eof(&self) -> bool {
+            self.pos >= self.data.len()
+        }
+
+        fn seek(&mut self, pos: usize) -> Option<()> {
+            if pos > self.data.len() {
+                return None;
+            }
+            self.pos = pos;
+            Some(())
+        }
+
+        fn bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+            let bytes = self.data.get(self.pos..self.pos.checked_add(n)?)?;
+            self.pos += n;
+            Some(bytes)
+        }
+
+        fn skip(&mut self, n: usize) -> Option<()> {
+            self.bytes(n).map(|_| ())
+        }
+
+        fn uint(&mut self, n: usize) -> Option<u64> {
+            let bytes = self.bytes(n)?;
+            Some(bytes.iter().rev().fold(0u64, |acc, &b| (acc << 8) | b as u64))
+        }
+
+        fn u8(&mut self) -> Option<u8> {
+            self.uint(1).map(|v| v as u8)
+        }
+
+        fn u16(&mut self) -> Option<u16> {
+            self.uint(2).map(|v| v as u16)
+        }
+
+        fn u32(&mut self) -> Option<u32> {
+            self.uint(4).map(|v| v as u32)
+        }
+
+        fn u64(&mut self) -> Option<u64> {
+            self.uint(8)
+        }
+
+        fn uleb(&mut self) -> Option<u64> {
+            let mut result = 0u64;
+            let mut shift = 0;
+            loop {
+                let byte = self.u8()?;
+                if shift < 64 {
+                    result |= ((byte & 0x7f) as u64) << shift;
+                }
+                shift += 7;
+                if byte & 0x80 == 0 {
+                    return Some(result);
+                }
+            }
+        }
+
+        fn sleb(&mut self) -> Option<i64> {
+            let mut result = 0i64;
+            let mut shift = 0;
+            loop {
+                let byte = self.u8()?;
+                if shift < 64 {
+                    result |= ((byte & 0x7f) as i64) << shift;
+                }
+                shift += 7;
+                if byte & 0x80 == 0 {
+                    if shift < 64 && byte & 0x40 != 0 {
+                        result |= -1i64 << shift;
+                    }
+                    return Some(result);
+                }
+            }
+        }
+
+        fn cstr(&mut self) -> Option<String> {
+            let rest = self.data.get(self.pos..)?;
+            let len = rest.iter().position(|&b| b == 0)?;
+            self.pos += len + 1;
+            Some(String::from_utf8_lossy(&rest[..len]).into_owned())
+        }
+
+        /// Read the initial length of a unit. Returns (length, is_dwarf64).
+        fn unit_length(&mut self) -> Option<(usize, bool)> {
+            match self.u32()? {
+                0xffffffff => Some((self.u64()? as usize, true)),
+                len => Some((len as usize, false)),
+            }
+        }
+
+        /// Read a section offset.
+        fn offset(&mut self, is_dwarf64: bool) -> Option<u64> {
+            if is_dwarf64 { self.u64() } else { self.u32().map(|v| v as u64) }
+        }
+    }
+
+    /// Read a NUL-terminated string at `offset` in a string section.
+    fn str_at(section: &[u8], offset: u64) -> Option<String> {
+        let mut reader = Reader::new(section);
+        reader.seek(offset as usize)?;
+        reader.cstr()
+    }
+
+    /// One row of the line number table.
+    #[derive(Debug, Clone, Copy)]
+    struct Row {
+        addr: u64,
+        /// Index into `DebugInfo::files`
+        file: usize,
+        line: u32,
+        is_stmt: bool,
+        /// First address after a sequence
+        end: bool,
+    }
+
+    /// Where a variable lives.
+    #[derive(Debug, Clone, Copy)]
+    pub enum Location {
+        /// Static storage at this address.
+        Address(u64),
+        /// Offset from the frame base (the CFA) of the enclosing function.
+        FrameOffset(i64),
+        Unknown,
+    }
+
+    /// A function from `.debug_info`.
+    #[derive(Debug, Clone)]
+    pub struct Function {
+        pub name: String,
+        pub low_pc: u64,
+        pub high_pc: u64,
+        /// Declaration site, (index into `DebugInfo::files`, line)
+        pub decl: Option<(usize, u32)>,
+    }
+
+    /// A variable (or parameter) from `.debug_info`.
+    #[derive(Debug, Clone)]
+    pub struct Variable {
+        pub name: String,
+        /// Index into `DebugInfo::functions` of the enclosing function;
+        /// None for globals.
+        pub function: Option<usize>,
+        pub location: Location,
+        /// Size of the variable in bytes, if known.
+        pub size: Option<u64>,
+    }
+
+    /// Source-level debugging information of a program.
+    #[derive(Debug, Default)]
+    pub struct DebugInfo {
+        /// Paths of all source files.
+        pub files: Vec<String>,
+        pub functions: Vec<Function>,
+        pub variables: Vec<Variable>,
+        /// Line table rows of all sequences, sorted by address.
+        rows: Vec<Row>,
+    }
+
+    // DW_FORM_* values
+    const FORM_ADDR: u64 = 0x01;
+    const FORM_BLOCK2: u64 = 0x03;
+    const FORM_BLOCK4: u64 = 0x04;
+    const FORM_DATA2: u64 = 0x05;
+    const FORM_DATA4: u64 = 0x06;
+    const FORM_DATA8: u64 = 0x07;
+    const FORM_STRING: u64 = 0x08;
+    const FORM_BLOCK: u64 = 0x09;
+    const FORM_BLOCK1: u64 = 0x0a;
+    const FORM_DATA1: u64 = 0x0b;
+    const FORM_FLAG: u64 = 0x0c;
+    const FORM_SDATA: u64 = 0x0d;
+    const FORM_STRP: u64 = 0x0e;
+    const FORM_UDATA: u64 = 0x0f;
+    const FORM_REF_ADDR: u64 = 0x10;
+    const FORM_REF1: u64 = 0x11;
+    const FORM_REF2: u64 = 0x12;
+    const FORM_REF4: u64 = 0x13;
+    const FORM_REF8: u64 = 0x14;
+    const FORM_REF_UDATA: u64 = 0x15;
+    const FORM_INDIRECT: u64 = 0x16;
+    const FORM_SEC_OFFSET: u64 = 0x17;
+    const FORM_EXPRLOC: u64 = 0x18;
+    const FORM_FLAG_PRESENT: u64 = 0x19;
+    const FORM_STRX: u64 = 0x1a;
+    const FORM_ADDRX: u64 = 0x1b;
+    const FORM_REF_SUP4: u64 = 0x1c;
+    const FORM_STRP_SUP: u64 = 0x1d;
+    const FORM_DATA16: u64 = 0x1e;
+    const FORM_LINE_STRP: u64 = 0x1f;
+    const FORM_REF_SIG8: u64 = 0x20;
+    const FORM_IMPLICIT_CONST: u64 = 0x21;
+    const FORM_LOCLISTX: u64 = 0x22;
+    const FORM_RNGLISTX: u64 = 0x23;
+    const FORM_REF_SUP8: u64 = 0x24;
+    const FORM_STRX1: u64 = 0x25;
+    const FORM_STRX4: u64 = 0x28;
+    const FORM_ADDRX1: u64 = 0x29;
+    const FORM_ADDRX4: u64 = 0x2c;
+
+    // DW_TAG_* values
+    const TAG_ARRAY_TYPE: u64 = 0x01;
+    const TAG_FORMAL_PARAMETER: u64 = 0x05;
+    const TAG_POINTER_TYPE: u64 = 0x0f;
+    const TAG_COMPILE_UNIT: u64 = 0x11;
+    const TAG_TYPEDEF: u64 = 0x16;
+    const TAG_CONST_TYPE: u64 = 0x26;
+    const TAG_SUBRANGE_TYPE: u64 = 0x21;
+    const TAG_SUBPROGRAM: u64 = 0x2e;
+    const TAG_VARIABLE: u64 = 0x34;
+    const TAG_VOLATILE_TYPE: u64 = 0x35;
+
+    // DW_AT_* values
+    const AT_LOCATION: u64 = 0x02;
+    const AT_NAME: u64 = 0x03;
+    const AT_BYTE_SIZE: u64 = 0x0b;
+    const AT_STMT_LIST: u64 = 0x10;
+    const AT_LOW_PC: u64 = 0x11;
+    const AT_HIGH_PC: u64 = 0x12;
+    const AT_COMP_DIR: u64 = 0x1b;
+    const AT_UPPER_BOUND: u64 = 0x2f;
+    const AT_COUNT: u64 = 0x37;
+    const AT_DECL_FILE: u64 = 0x3a;
+    const AT_DECL_LINE: u64 = 0x3b;
+    const AT_TYPE: u64 = 0x49;
+    const AT_STR_OFFSETS_BASE: u64 = 0x72;
+    const AT_ADDR_BASE: u64 = 0x73;
+
+    /// An attribute value, before resolving indirections.
+    #[derive(Debug, Clone)]
+    enum Value {
+        Addr(u64),
+        AddrX(u64),
+        Udata(u64),
+        Sdata(i64),
+        Str(String),
+        StrX(u64),
+        Block(Vec<u8>),
+        /// Offset of a DIE in `.debug_info`
+        Ref(u64),
+        Other,
+    }
+
+    impl Value {
+        fn udata(&self) -> Option<u64> {
+            match self {
+                Value::Udata(v) => Some(*v),
+                Value::Sdata(v) => Some(*v as u64),
+                _ => None,
+            }
+        }
+    }
+
+    /// A parsed debugging information entry.
+    struct Die {
+        offset: u64,
+        tag: u64,
+        attrs: Vec<(u64, Value)>,
+        /// Index of the parent DIE in the unit
+        parent: Option<usize>,
+    }
+
+    impl Die {
+        fn attr(&self, name: u64) -> Option<&Value> {
+            self.attrs.iter().find(|(n, _)| *n == name).map(|(_, v)| v)
+        }
+    }
+
+    /// Abbreviation: (tag, has_children, [(attribute, form, implicit_const)])
+    type Abbrev = (u64, bool, Vec<(u64, u64, i64)>);
+
+    /// The sections we read, with the encoding of the current unit.
+    struct Sections<'a> {
+        info: &'a [u8],
+        abbrev: &'a [u8],
+        line: &'a [u8],
+        str: &'a [u8],
+        line_str: &'a [u8],
+        str_offsets: &'a [u8],
+        addr: &'a [u8],
+    }
+
+    impl DebugInfo {
+        /// Parse the debugging information of an ELF file. Returns None if
+        /// the file has no line table.
+        pub fn parse(elf: &[u8]) -> Option<DebugInfo> {
+            let sections = elf_sections(elf);
+            let get = |name: &str| sections.get(name).copied().unwrap_or(&[]);
+            let sections = Sections {
+                info: get(".debug_info"),
+                abbrev: get(".debug_abbrev"),
+                line: get(".debug_line"),
+                str: get(".debug_str"),
+                line_str: get(".debug_line_str"),
+                str_offsets: get(".debug_str_offsets"),
+                addr: get(".debug_addr"),
+            };
+            if sections.line.is_empty() {
+                return None;
+            }
+
+            let mut info = DebugInfo::default();
+            let mut file_ids: HashMap<String, usize> = HashMap::new();
+            let mut parsed_lines: Vec<u64> = Vec::new();
+
+            // Walk the compilation units, reading the line program of each.
+            let mut units = Reader::new(sections.info);
+            while !units.eof() {
+                let start = units.pos;
+                let parsed = info.parse_unit(&sections, &mut units, &mut file_ids, &mut parsed_lines);
+                match parsed {
+                    Some(end) if end > start => { let _ = units.seek(end); },
+                    _ => break,
+                }
+            }
+
+            // Line programs not referenced by any unit
+            let mut lines = Reader::new(sections.line);
+            while !lines.eof() {
+                let offset = lines.pos as u64;
+                let end = match lines.unit_length().and_then(|(len, _)| lines.pos.checked_add(len)) {
+                    Some(end) => end,
+                    None => break,
+                };
+                if !parsed_lines.contains(&offset) {
+                    info.parse_line_program(&sections, offset, "", &mut file_ids);
+                }
+                if lines.seek(end).is_none() {
+                    break;
+                }
+            }
+
+            // End markers sort before rows starting at the same address
+            info.rows.sort_by(|a, b| a.addr.cmp(&b.addr).then(b.end.cmp(&a.end)));
+            Some(info)
+        }
+
+        /// Parse one unit of `.debug_info` starting at the reader position.
+        /// Returns the offset of the next unit.
+        fn parse_unit(
+            &mut self,
+            sections: &Sections,
+            units: &mut Reader,
+            file_ids: &mut HashMap<String, usize>,
+            parsed_lines: &mut Vec<u64>,
+        ) -> Option<usize> {
+            let unit_offset = units.pos as u64;
+            let (len, is64) = units.unit_length()?;
+            let end = units.pos.checked_add(len)?;
+            let version = units.u16()?;
+            let (abbrev_offset, addr_size) = if version >= 5 {
+                let unit_type = units.u8()?;
+                let addr_size = units.u8()?;
+                let abbrev_offset = units.offset(is64)?;
+                match unit_type {
+                    // DW_UT_compile, DW_UT_partial
+                    1 | 3 => {},
+                    // DW_UT_skeleton, DW_UT_split_compile
+                    4 | 5 => units.skip(8)?,
+                    // Type units are of no interest
+                    _ => return Some(end),
+                }
+                (abbrev_offset, addr_size as usize)
+            }
+            else {
+                let abbrev_offset = units.offset(is64)?;
+                (abbrev_offset, units.u8()? as usize)
+            };
+
+            let abbrevs = DebugInfo::parse_abbrevs(sections.abbrev, abbrev_offset)?;
+
+            // Read all DIEs of the unit
+            let mut dies: Vec<Die> = Vec::new();
+            let mut parents: Vec<usize> = Vec::new();
+            while units.pos < end {
+                let offset = units.pos as u64;
+                let code = units.uleb()?;
+                if code == 0 {
+                    parents.pop();
+                    continue;
+                }
+                let (tag, has_children, specs) = abbrevs.get(&code)?;
+                let mut attrs = Vec::new();
+                for &(name, form, implicit) in specs.iter() {
+                    let value = DebugInfo::read_value(sections, units, form, implicit, addr_size, is64, version, unit_offset)?;
+                    attrs.push((name, value));
+                }
+                dies.push(Die { offset, tag: *tag, attrs, parent: parents.last().copied() });
+                if *has_children {
+                    parents.push(dies.len() - 1);
+                }
+            }
+
+            // Unit attributes
+            let unit = dies.iter().find(|die| die.tag == TAG_COMPILE_UNIT);
+            let str_offsets_base = unit.and_then(|u| u.attr(AT_STR_OFFSETS_BASE)).and_then(Value::udata)
+                .unwrap_or(if is64 { 16 } else { 8 });
+            let addr_base = unit.and_then(|u| u.attr(AT_ADDR_BASE)).and_then(Value::udata).unwrap_or(8);
+            let resolve_str = |value: &Value| -> Option<String> {
+                match value {
+                    Value::Str(s) => Some(s.clone()),
+                    Value::StrX(idx) => {
+                        let size = if is64 { 8 } else { 4 };
+                        let mut offsets = Reader::new(sections.str_offsets);
+                        offsets.seek(idx.checked_mul(size)?.checked_add(str_offsets_base)? as usize)?;
+                        str_at(sections.str, offsets.offset(is64)?)
+                    },
+                    _ => None,
+                }
+            };
+            let resolve_addr = |value: &Value| -> Option<u64> {
+                match value {
+                    Value::Addr(addr) => Some(*addr),
+                    Value::AddrX(idx) => {
+                        let mut addrs = Reader::new(sections.addr);
+                        addrs.seek(idx.checked_mul(addr_size as u64)?.checked_add(addr_base)? as usize)?;
+                        addrs.uint(addr_size)
+                    },
+                    _ => None,
+                }
+            };
+
+            // Line program of the unit, giving the meaning of DW_AT_decl_file
+            let comp_dir = unit.and_then(|u| u.attr(AT_COMP_DIR)).and_then(resolve_str).unwrap_or_default();
+            let unit_files = match unit.and_then(|u| u.attr(AT_STMT_LIST)).and_then(Value::udata) {
+                Some(offset) => {
+                    parsed_lines.push(offset);
+                    self.parse_line_program(sections, offset, &comp_dir, file_ids).unwrap_or_default()
+                },
+                None => Vec::new(),
+            };
+
+            // Byte sizes of types, following typedefs and qualifiers
+            let by_offset: HashMap<u64, usize> = dies.iter().enumerate()
+                .map(|(idx, die)| (die.offset, idx))
+                .collect();
+            fn type_size(dies: &[Die], by_offset: &HashMap<u64, usize>, die: &Die, addr_size: usize, depth: usize) -> Option<u64> {
+                let idx = match die.attr(AT_TYPE) {
+                    Some(Value::Ref(ofs)) if depth < 16 => *by_offset.get(ofs)?,
+                    _ => return None,
+                };
+                let ty = &dies[idx];
+                if let Some(size) = ty.attr(AT_BYTE_SIZE).and_then(Value::udata) {
+                    return Some(size);
+                }
+                match ty.tag {
+                    TAG_POINTER_TYPE => Some(addr_size as u64),
+                    TAG_TYPEDEF | TAG_CONST_TYPE | TAG_VOLATILE_TYPE => type_size(dies, by_offset, ty, addr_size, depth + 1),
+                    TAG_ARRAY_TYPE => {
+                        // Element size times the length of each dimension
+                        let mut size = type_size(dies, by_offset, ty, addr_size, depth + 1)?;
+                        for sub in dies.iter().filter(|d| d.tag == TAG_SUBRANGE_TYPE && d.parent == Some(idx)) {
+                            let len = match (sub.attr(AT_COUNT), sub.attr(AT_UPPER_BOUND)) {
+                                (Some(count), _) => count.udata()?,
+                                (None, Some(upper)) => upper.udata()?.checked_add(1)?,
+                                _ => return None,
+                            };
+                            size = size.checked_mul(len)?;
+                        }
+                        Some(size)
+                    },
+                    _ => None,
+                }
+            }
+
+            // Collect functions, then variables
+            let mut functions: HashMap<usize, usize> = HashMap::new();
+            for (idx, die) in dies.iter().enumerate() {
+                if die.tag != TAG_SUBPROGRAM {
+                    continue;
+                }
+                let name = die.attr(AT_NAME).and_then(resolve_str);
+                let low_pc = die.attr(AT_LOW_PC).and_then(resolve_addr);
+                if let (Some(name), Some(low_pc)) = (name, low_pc) {
+                    let high_pc = match die.attr(AT_HIGH_PC) {
+                        Some(Value::Udata(len)) => low_pc.saturating_add(*len),
+                        Some(value) => resolve_addr(value).unwrap_or(low_pc),
+                        None => low_pc,
+                    };
+                    let decl_file = die.attr(AT_DECL_FILE).and_then(Value::udata)
+                        .and_then(|file| unit_files.get(file as usize).copied().flatten());
+                    let decl_line = die.attr(AT_DECL_LINE).and_then(Value::udata).unwrap_or(0);
+                    functions.insert(idx, self.functions.len());
+                    self.functions.push(Function {
+                        name,
+                        low_pc,
+                        high_pc,
+                        decl: decl_file.map(|file| (file, decl_line as u32)),
+                    });
+                }
+            }
+
+            for die in dies.iter() {
+                if die.tag != TAG_VARIABLE && die.tag != TAG_FORMAL_PARAMETER {
+                    continue;
+                }
+                let name = match die.attr(AT_NAME).and_then(resolve_str) {
+                    Some(name) => name,
+                    None => continue,
+                };
+                let location = match die.attr(AT_LOCATION) {
+                    Some(Value::Block(expr)) => DebugInfo::parse_location(expr, addr_size, &resolve_addr),
+                    _ => Location::Unknown,
+                };
+                // Nearest enclosing function
+                let mut parent = die.parent;
+                let mut function = None;
+                while let Some(idx) = parent {
+                    if dies[idx].tag == TAG_SUBPROGRAM {
+                        function = functions.get(&idx).copied();
+                        break;
+                    }
+                    parent = dies[idx].parent;
+                }
+                // Declarations nested in a function without code are useless
+                if function.is_none() && die.parent.is_some_and(|p| dies[p].tag == TAG_SUBPROGRAM) {
+                    continue;
+                }
+                let size = type_size(&dies, &by_offset, die, addr_size, 0);
+                self.variables.push(Variable { name, function, location, size });
+            }
+
+            Some(end)
+        }
+
+        /// Decode the simple location expressions we support.
+        fn parse_location(expr: &[u8], addr_size: usize, resolve_addr: &dyn Fn(&Value) -> Option<u64>) -> Location {
+            let mut reader = Reader::new(expr);
+            let location = match reader.u8() {
+                // DW_OP_addr
+                Some(0x03) => reader.uint(addr_size).map(Location::Address),
+                // DW_OP_addrx
+                Some(0xa1) => reader.uleb().and_then(|idx| resolve_addr(&Value::AddrX(idx))).map(Location::Address),
+                // DW_OP_fbreg
+                Some(0x91) => reader.sleb().map(Location::FrameOffset),
+                _ => None,
+            };
+            match location {
+                Some(location) if reader.eof() => location,
+                _ => Location::Unknown,
+            }
+        }
+
+        /// Parse the abbreviation table at `offset`.
+        fn parse_abbrevs(section: &[u8], offset: u64) -> Option<HashMap<u64, Abbrev>> {
+            let mut reader = Reader::new(section);
+            reader.seek(offset as usize)?;
+            let mut abbrevs = HashMap::new();
+            loop {
+                let code = reader.uleb()?;
+                if code == 0 {
+                    return Some(abbrevs);
+                }
+                let tag = reader.uleb()?;
+                let has_children = reader.u8()? != 0;
+                let mut specs = Vec::new();
+                loop {
+                    let name = reader.uleb()?;
+                    let form = reader.uleb()?;
+                    if name == 0 && form == 0 {
+                        break;
+                    }
+                    let implicit = if form == FORM_IMPLICIT_CONST { reader.sleb()? } else { 0 };
+                    specs.push((name, form, implicit));
+                }
+                abbrevs.insert(code, (tag, has_children, specs));
+            }
+        }
+
+        /// Read an attribute value of the given form.
+        #[allow(clippy::too_many_arguments)]
+        fn read_value(
+            sections: &Sections,
+            reader: &mut Reader,
+            form: u64,
+            implicit: i64,
+            addr_size: usize,
+            is64: bool,
+            version: u16,
+            unit_offset: u64,
+        ) -> Option<Value> {
+            let offset_size = if is64 { 8 } else { 4 };
+            let value = match form {
+                FORM_ADDR => Value::Addr(reader.uint(addr_size)?),
+                FORM_BLOCK1 => { let len = reader.u8()? as usize; Value::Block(reader.bytes(len)?.to_vec()) },
+                FORM_BLOCK2 => { let len = reader.u16()? as usize; Value::Block(reader.bytes(len)?.to_vec()) },
+                FORM_BLOCK4 => { let len = reader.u32()? as usize; Value::Block(reader.bytes(len)?.to_vec()) },
+                FORM_BLOCK | FORM_EXPRLOC => { let len = reader.uleb()? as usize; Value::Block(reader.bytes(len)?.to_vec()) },
+                FORM_DATA1 | FORM_FLAG => Value::Udata(reader.u8()? as u64),
+                FORM_DATA2 => Value::Udata(reader.u16()? as u64),
+                FORM_DATA4 => Value::Udata(reader.u32()? as u64),
+                FORM_DATA8 => Value::Udata(reader.u64()?),
+                FORM_DATA16 => { reader.skip(16)?; Value::Other },
+                FORM_SDATA => Value::Sdata(reader.sleb()?),
+                FORM_UDATA => Value::Udata(reader.uleb()?),
+                FORM_STRING => Value::Str(reader.cstr()?),
+                FORM_STRP => Value::Str(str_at(sections.str, reader.offset(is64)?)?),
+                FORM_LINE_STRP => Value::Str(str_at(sections.line_str, reader.offset(is64)?)?),
+                FORM_STRP_SUP => { reader.skip(offset_size)?; Value::Other },
+                FORM_STRX | FORM_STRX1..=FORM_STRX4 => Value::StrX(match form {
+                    FORM_STRX => reader.uleb()?,
+                    _ => reader.uint((form - FORM_STRX1 + 1) as usize)?,
+                }),
+                FORM_ADDRX | FORM_ADDRX1..=FORM_ADDRX4 => Value::AddrX(match form {
+                    FORM_ADDRX => reader.uleb()?,
+                    _ => reader.uint((form - FORM_ADDRX1 + 1) as usize)?,
+                }),
+                FORM_REF_ADDR => Value::Ref(reader.uint(if version <= 2 { addr_size } else { offset_size })?),
+                FORM_REF1 => Value::Ref(unit_offset.checked_add(reader.u8()? as u64)?),
+                FORM_REF2 => Value::Ref(unit_offset.checked_add(reader.u16()? as u64)?),
+                FORM_REF4 => Value::Ref(unit_offset.checked_add(reader.u32()? as u64)?),
+                FORM_REF8 => Value::Ref(unit_offset.checked_add(reader.u64()?)?),
+                FORM_REF_UDATA => Value::Ref(unit_offset.checked_add(reader.uleb()?)?),
+                FORM_REF_SIG8 | FORM_REF_SUP8 => { reader.skip(8)?; Value::Other },
+                FORM_REF_SUP4 => { reader.skip(4)?; Value::Other },
+                FORM_SEC_OFFSET => Value::Udata(reader.offset(is64)?),
+                FORM_FLAG_PRESENT => Value::Udata(1),
+                FORM_IMPLICIT_CONST => Value::Sdata(implicit),
+                FORM_LOCLISTX | FORM_RNGLISTX => { reader.uleb()?; Value::Other },
+                FORM_INDIRECT => {
+                    let form = reader.uleb()?;
+                    DebugInfo::read_value(sections, reader, form, implicit, addr_size, is64, version, unit_offset)?
+                },
+                // Unknown form, cannot continue
+                _ => return None,
+            };
+            Some(value)
+        }
+
+        /// Intern a file path into `files`.
+        fn file_id(&mut self, path: String, file_ids: &mut HashMap<String, usize>) -> usize {
+            if let Some(&id) = file_ids.get(&path) {
+                return id;
+            }
+            self.files.push(path.clone());
+            file_ids.insert(path, self.files.len() - 1);
+            self.files.len() - 1
+        }
+
+        /// Read an entry of a DWARF 5 directory/file table.
+        /// Returns (path, directory index).
+        fn read_entry(
+            sections: &Sections,
+            reader: &mut Reader,
+            formats: &[(u64, u64)],
+            addr_size: usize,
+            is64: bool,
+        ) -> Option<(String, u64)> {
+            let mut path = String::new();
+            let mut dir = 0;
+            for &(content, form) in formats {
+                let value = DebugInfo::read_value(sections, reader, form, 0, addr_size, is64, 5, 0)?;
+                match (content, value) {
+                    // DW_LNCT_path
+                    (1, Value::Str(s)) => path = s,
+                    // DW_LNCT_directory_index
+                    (2, value) => dir = value.udata().unwrap_or(0),
+                    _ => {}
+                }
+            }
+            Some((path, dir))
+        }
+
+        /// Run the line number program at `offset` of `.debug_line`, appending
+        /// rows to `self.rows`. Returns the program's file table, mapping
+        /// the program's file numbers to indices in `self.files`.
+        fn parse_line_program(
+            &mut self,
+            sections: &Sections,
+            offset: u64,
+            comp_dir: &str,
+            file_ids: &mut HashMap<String, usize>,
+        ) -> Option<Vec<Option<usize>>> {
+            let mut reader = Reader::new(sections.line);
+            reader.seek(offset as usize)?;
+            let (len, is64) = reader.unit_length()?;
+            let end = reader.pos.checked_add(len)?;
+            let version = reader.u16()?;
+            let mut addr_size = 8;
+            if version >= 5 {
+                addr_size = reader.u8()? as usize;
+                let _seg_sel_size = reader.u8()?;
+            }
+            let header_len = reader.offset(is64)? as usize;
+            let program = reader.pos.checked_add(header_len)?;
+            let min_inst_len = reader.u8()? as u64;
+            if version >= 4 {
+                let _max_ops = reader.u8()?;
+            }
+            let default_is_stmt = reader.u8()? != 0;
+            let line_base = reader.u8()? as i8 as i64;
+            let line_range = reader.u8()? as u64;
+            let opcode_base = reader.u8()?;
+            let mut opcode_lengths = Vec::new();
+            for _ in 1..opcode_base {
+                opcode_lengths.push(reader.u8()?);
+            }
+            if line_range == 0 {
+                return None;
+            }
+
+            let join = |dir: &str, name: &str| -> String {
+                if name.starts_with('/') || dir.is_empty() {
+                    String::from(name)
+                }
+                else {
+                    format!("{}/{}", dir.trim_end_matches('/'), name)
+                }
+            };
+
+            // File table, indexed by the program's file numbers
+            let mut files: Vec<Option<usize>> = Vec::new();
+            if version >= 5 {
+                let mut dirs: Vec<String> = Vec::new();
+                for table in 0..2 {
+                    let format_count = reader.u8()?;
+                    let mut formats = Vec::new();
+                    for _ in 0..format_count {
+                        formats.push((reader.uleb()?, reader.uleb()?));
+                    }
+                    let count = reader.uleb()?;
+                    for _ in 0..count {
+                        let (path, dir) = DebugInfo::read_entry(sections, &mut reader, &formats, addr_size, is64)?;
+                        if table == 0 {
+                            let path = if dirs.is_empty() { path } else { join(&dirs[0], &path) };
+                            dirs.push(path);
+                        }
+                        else {
+                            let dir = dirs.get(dir as usize).cloned().unwrap_or_default();
+                            files.push(Some(self.file_id(join(&dir, &path), file_ids)));
+                        }
+                    }
+                }
+            }
+            else {
+                let mut dirs = vec![String::from(comp_dir)];
+                loop {
+                    let dir = reader.cstr()?;
+                    if dir.is_empty() {
+                        break;
+                    }
+                    dirs.push(join(comp_dir, &dir));
+                }
+                // File numbers start at 1
+                files.push(None);
+                loop {
+                    let name = reader.cstr()?;
+                    if name.is_empty() {
+                        break;
+                    }
+                    let dir = reader.uleb()?;
+                    let _mtime = reader.uleb()?;
+                    let _len = reader.uleb()?;
+                    let dir = dirs.get(dir as usize).cloned().unwrap_or_default();
+                    files.push(Some(self.file_id(join(&dir, &name), file_ids)));
+                }
+            }
+
+            // The state machine
+            reader.seek(program)?;
+            let initial_file = if version >= 5 { 0 } else { 1 };
+            let mut addr = 0u64;
+            let mut file = initial_file;
+            let mut line = 1i64;
+            let mut is_stmt = default_is_stmt;
+            let emit = |rows: &mut Vec<Row>, addr: u64, file: u64, line: i64, is_stmt: bool, end: bool| {
+                if let Some(Some(file)) = files.get(file as usize) {
+                    rows.push(Row { addr, file: *file, line: line as u32, is_stmt, end });
+                }
+            };
+
+            while reader.pos < end {
+                let opcode = reader.u8()?;
+                if opcode >= opcode_base {
+                    // Special opcode
+                    // Addresses and lines wrap around rather than overflow
+                    let adjusted = (opcode - opcode_base) as u64;
+                    addr = addr.wrapping_add((adjusted / line_range) * min_inst_len);
+                    line = line.wrapping_add(line_base + (adjusted % line_range) as i64);
+                    emit(&mut self.rows, addr, file, line, is_stmt, false);
+                    continue;
+                }
+                match opcode {
+                    // Extended opcodes
+                    0 => {
+                        let len = reader.uleb()? as usize;
+                        let next = reader.pos.checked_add(len)?;
+                        match reader.u8()? {
+                            // DW_LNE_end_sequence
+                            1 => {
+                                emit(&mut self.rows, addr, file, line, is_stmt, true);
+                                addr = 0;
+                                file = initial_file;
+                                line = 1;
+                                is_stmt = default_is_stmt;
+                            },
+                            // DW_LNE_set_address
+                            2 => addr = reader.uint(len.checked_sub(1)?)?,
+                            _ => {},
+                        }
+                        reader.seek(next)?;
+                    },
+                    // DW_LNS_copy
+                    1 => emit(&mut self.rows, addr, file, line, is_stmt, false),
+                    // DW_LNS_advance_pc
+                    2 => addr = addr.wrapping_add(reader.uleb()?.wrapping_mul(min_inst_len)),
+                    // DW_LNS_advance_line
+                    3 => line = line.wrapping_add(reader.sleb()?),
+                    // DW_LNS_set_file
+                    4 => file = reader.uleb()?,
+                    // DW_LNS_set_column
+                    5 => { reader.uleb()?; },
+                    // DW_LNS_negate_stmt
+                    6 => is_stmt = !is_stmt,
+                    // DW_LNS_const_add_pc
+                    8 => addr = addr.wrapping_add(((255 - opcode_base) as u64 / line_range) * min_inst_len),
+                    // DW_LNS_fixed_advance_pc
+                    9 => addr = addr.wrapping_add(reader.u16()? as u64),
+                    // Others carry ULEB operands we do not need
+                    _ => {
+                        for _ in 0..opcode_lengths[opcode as usize - 1] {
+                            reader.uleb()?;
+                        }
+                    },
+                }
+            }
+            Some(files)
+        }
+
+        /// Index of the row covering `pc`.
+        fn row_at(&self, pc: u64) -> Option<usize> {
+            let idx = self.rows.partition_point(|row| row.addr <= pc);
+            if idx == 0 || self.rows[idx - 1].end {
+                return None;
+            }
+            Some(idx - 1)
+        }
+
+        /// Shift all addresses by `bias`, for an executable loaded at a
+        /// different address than it was linked at.
+        pub fn relocate(&mut self, bias: u64) {
+            for func in self.functions.iter_mut() {
+                func.low_pc = func.low_pc.wrapping_add(bias);
+                func.high_pc = func.high_pc.wrapping_add(bias);
+            }
+            for var in self.variables.iter_mut() {
+                if let Location::Address(addr) = var.location {
+                    var.location = Location::Address(addr.wrapping_add(bias));
+                }
+            }
+            for row in self.rows.iter_mut() {
+                row.addr = row.addr.wrapping_add(bias);
+            }
+        }
+
+        /// Source (file, line) of the code at `pc`.
+        pub fn line_at(&self, pc: u64) -> Option<(&str, u32)> {
+            self.row_at(pc).map(|idx| {
+                let row = &self.rows[idx];
+                (self.files[row.file].as_str(), row.line)
+            })
+        }
+
+        /// Whether `pc` is the first instruction of a statement, i.e.
+        /// where a debugger stops when stepping by source line.
+        pub fn is_stmt_start(&self, pc: u64) -> bool {
+            self.rows.iter()
+                .skip(self.rows.partition_point(|row| row.addr < pc))
+                .take_while(|row| row.addr == pc)
+                .any(|row| row.is_stmt && !row.end)
+        }
+
+        /// Files whose path matches `name`, either exactly or by its trailing
+        /// path components (so `mm.c` matches `/home/user/mm.c`).
+        pub fn find_files(&self, name: &str) -> Vec<usize> {
+            self.files.iter().enumerate()
+                .filter(|(_, path)| {
+                    path.as_str() == name || path.ends_with(&format!("/{}", name.trim_start_matches("./")))
+                })
+                .map(|(idx, _)| idx)
+                .collect()
+        }
+
+        /// Address to break at for `file:line`. If no code is generated for
+        /// the line, the next line with code is used, as gdb does.
+        /// Returns the address and the actual line.
+        pub fn addr_of_line(&self, file: &str, line: u32) -> Option<(u64, u32)> {
+            let files = self.find_files(file);
+            let best = self.rows.iter()
+                .filter(|row| !row.end && row.is_stmt && files.contains(&row.file) && row.line >= line)
+                .map(|row| row.line)
+                .min()?;
+            self.rows.iter()
+                .filter(|row| !row.end && row.is_stmt && files.contains(&row.file) && row.line == best)
+                .map(|row| (row.addr, best))
+                .min()
+        }
+
+        /// The function containing `pc`.
+        pub fn function_at(&self, pc: u64) -> Option<(usize, &Function)> {
+            self.functions.iter().enumerate()
+                .find(|(_, func)| func.low_pc <= pc && pc < func.high_pc)
+        }
+
+        /// Look up a variable visible at `pc`: locals of the function
+        /// containing `pc` first, then globals.
+        pub fn variable(&self, name: &str, pc: u64) -> Option<&Variable> {
+            let function = self.function_at(pc).map(|(idx, _)| idx);
+            self.variables.iter()
+                .filter(|var| var.name == name)
+                .find(|var| function.is_some() && var.function == function)
+                .or_else(|| self.variables.iter().find(|var| var.name == name && var.function.is_none()))
+        }
+
+        /// Variables local to the function containing `pc`.
+        pub fn locals(&self, pc: u64) -> Vec<&Variable> {
+            match self.function_at(pc) {
+                Some((idx, _)) => self.variables.iter().filter(|var| var.function == Some(idx)).collect(),
+                None => Vec::new(),
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        /// A little-endian ELF64 file holding only `sections`.
+        fn elf(sections: &[(&str, &[u8])]) -> Vec<u8> {
+            let mut data = vec![0u8; 0x40];
+            data[..6].copy_from_slice(b"\x7fELF\x02\x01");
+            let mut names = vec![0u8];
+            let mut headers = vec![(0, 0, 0)];
+            for (name, content) in sections.iter().copied().chain([(".shstrtab", &[][..])]) {
+                headers.push((names.len(), data.len(), content.len()));
+                names.extend_from_slice(name.as_bytes());
+                names.push(0);
+                data.extend_from_slice(content);
+            }
+            // The names go last, once they are all known
+            let last = headers.last_mut().unwrap();
+            *last = (last.0, data.len(), names.len());
+            data.extend_from_slice(&names);
+
+            let shoff = data.len() as u64;
+            data[0x28..0x30].copy_from_slice(&shoff.to_le_bytes());
+            data[0x3A..0x3C].copy_from_slice(&64u16.to_le_bytes());
+            data[0x3C..0x3E].copy_from_slice(&(headers.len() as u16).to_le_bytes());
+            data[0x3E..0x40].copy_from_slice(&(headers.len() as u16 - 1).to_le_bytes());
+            for (name, offset, size) in headers {
+                let mut header = [0u8; 64];
+                header[..4].copy_from_slice(&(name as u32).to_le_bytes());
+                header[4..8].copy_from_slice(&1u32.to_le_bytes());
+                header[0x18..0x20].copy_from_slice(&(offset as u64).to_le_bytes());
+                header[0x20..0x28].copy_from_slice(&(size as u64).to_le_bytes());
+                data.extend_from_slice(&header);
+            }
+            data
+        }
+
+        /// A version 4 line program for src/main.c and util.h:
+        ///
+        /// | address | file   | line | is_stmt |
+        /// |---------|--------|------|---------|
+        /// | 0x10000 | main.c | 10   | yes     |
+        /// | 0x10002 | main.c | 11   | yes     |
+        /// | 0x10008 | util.h | 3    | no      |
+        /// | 0x1000c | end of sequence         |
+        fn line_program(dwarf64: bool) -> Vec<u8> {
+            // min_inst_len 2, max_ops 1, default_is_stmt, line_base -5,
+            // line_range 14, opcode_base 13 and the standard lengths
+            let mut header = vec![2, 1, 1, (-5i8) as u8, 14, 13, 0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1];
+            header.extend_from_slice(b"src\0\0main.c\0\x01\0\0util.h\0\0\0\0\0");
+            let mut program = vec![0, 9, 2];
+            program.extend_from_slice(&0x10000u64.to_le_bytes());
+            program.extend_from_slice(&[
+                3, 9, 1,        // line 10, copy
+                33,             // special: address += 2, line += 1
+                4, 2, 2, 3,     // file util.h, address += 6
+                3, 0x78, 6, 1,  // line -= 8, negate_stmt, copy
+                6, 2, 2,        // negate_stmt, address += 4
+                0, 1, 1,        // end_sequence
+            ]);
+
+            let mut unit = 4u16.to_le_bytes().to_vec();
+            if dwarf64 {
+                unit.extend_from_slice(&(header.len() as u64).to_le_bytes());
+            }
+            else {
+                unit.extend_from_slice(&(header.len() as u32).to_le_bytes());
+            }
+            unit.extend(header);
+            unit.extend(program);
+            let mut data = Vec::new();
+            if dwarf64 {
+                data.extend_from_slice(&0xffffffffu32.to_le_bytes());
+                data.extend_from_slice(&(unit.len() as u64).to_le_bytes());
+            }
+            else {
+                data.extend_from_slice(&(unit.len() as u32).to_le_bytes());
+            }
+            data.extend(unit);
+            data
+        }
+
+        fn parse(line: &[u8]) -> DebugInfo {
+            DebugInfo::parse(&elf(&[(".text", &[0; 16]), (".debug_line", line)])).unwrap()
+        }
+
+        #[test]
+        fn line_table() {
+            for dwarf64 in [false, true] {
+                let info = parse(&line_program(dwarf64));
+                assert_eq!(info.line_at(0xffff), None);
+                assert_eq!(info.line_at(0x10000), Some(("src/main.c", 10)));
+                assert_eq!(info.line_at(0x10001), Some(("src/main.c", 10)));
+                assert_eq!(info.line_at(0x10002), Some(("src/main.c", 11)));
+                assert_eq!(info.line_at(0x1000b), Some(("util.h", 3)));
+                assert_eq!(info.line_at(0x1000c), None);
+                assert!(info.is_stmt_start(0x10002));
+                assert!(!info.is_stmt_start(0x10008));
+                assert_eq!(info.addr_of_line("main.c", 11), Some((0x10002, 11)));
+                // Lines without code move to the next one that has some
+                assert_eq!(info.addr_of_line("src/main.c", 5), Some((0x10000, 10)));
+                // The only row of util.h is not a statement
+                assert_eq!(info.addr_of_line("util.h", 1), None);
+                assert_eq!(info.find_files("main.c"), [0]);
+            }
+        }
+
+        /// Truncated sections keep the rows decoded up to the cut, and
+        /// lengths running past the end are dropped.
+        #[test]
+        fn truncated() {
+            let line = line_program(false);
+            let info = parse(&line[..line.len() - 3]);
+            assert_eq!(info.line_at(0x10002), Some(("src/main.c", 11)));
+            assert_eq!(info.line_at(0x10008), Some(("util.h", 3)));
+
+            for len in [2, 10, 30] {
+                assert_eq!(parse(&line[..len]).line_at(0x10000), None);
+            }
+            let mut huge = 0xffffffffu32.to_le_bytes().to_vec();
+            huge.extend_from_slice(&u64::MAX.to_le_bytes());
+            huge.extend_from_slice(&line[4..]);
+            assert_eq!(parse(&huge).line_at(0x10000), None);
+            assert!(DebugInfo::parse(&elf(&[(".text", &[0; 16])])).is_none());
+            assert!(elf_sections(&elf(&[(".debug_line", &line)])[..0x40]).is_empty());
+        }
+    }
+}
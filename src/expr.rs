@@ -0,0 +1,398 @@
+#![allow(clippy::module_inception)]
+#[allow(dead_code)]
+pub mod expr {
+    //! This module implements the C-like expressions of the debugger, as
+    //! used by conditional breakpoints. Operands are numbers, registers by
+    //! ABI name (optionally prefixed with `$`) or `pc`, variables from the
+    //! debugging information, and function symbols, which evaluate to their
+    //! address. Memory is dereferenced with `*addr`, which reads 8 bytes,
+    //! or with a width given by a pointer cast as in `*(u32*)addr`.
+    //!
+    //! Values are 64-bit; relational operators compare them as signed.
+    //! Numbers are decimal unless prefixed with `0x`, as in gdb, both in
+    //! values and in the addresses of the debugger commands.
+
+    use crate::Program;
+
+    /// Binary operators, by increasing precedence groups.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum BinOp {
+        Or, And,
+        BitOr, BitXor, BitAnd,
+        Eq, Ne,
+        Lt, Le, Gt, Ge,
+        Shl, Shr,
+        Add, Sub,
+        Mul, Div, Rem,
+    }
+
+    /// Precedence table, loosest first.
+    const PRECEDENCE: [&[(&str, BinOp)]; 10] = [
+        &[("||", BinOp::Or)],
+        &[("&&", BinOp::And)],
+        &[("|", BinOp::BitOr)],
+        &[("^", BinOp::BitXor)],
+        &[("&", BinOp::BitAnd)],
+        &[("==", BinOp::Eq), ("!=", BinOp::Ne)],
+        &[("<=", BinOp::Le), (">=", BinOp::Ge), ("<", BinOp::Lt), (">", BinOp::Gt)],
+        &[("<<", BinOp::Shl), (">>", BinOp::Shr)],
+        &[("+", BinOp::Add), ("-", BinOp::Sub)],
+        &[("*", BinOp::Mul), ("/", BinOp::Div), ("%", BinOp::Rem)],
+    ];
+
+    /// Pointee types of pointer casts: (name, width, signed).
+    const TYPES: [(&str, usize, bool); 12] = [
+        ("u8", 1, false), ("u16", 2, false), ("u32", 4, false), ("u64", 8, false),
+        ("i8", 1, true), ("i16", 2, true), ("i32", 4, true), ("i64", 8, true),
+        ("char", 1, true), ("short", 2, true), ("int", 4, true), ("long", 8, true),
+    ];
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Expr {
+        Num(u64),
+        /// Register, `pc`, variable or symbol, resolved on evaluation.
+        Ident(String),
+        Neg(Box<Expr>),
+        Not(Box<Expr>),
+        BitNot(Box<Expr>),
+        /// Pointer cast: (width, signed, pointer)
+        Cast(usize, bool, Box<Expr>),
+        /// Memory read: (width, signed, address)
+        Deref(usize, bool, Box<Expr>),
+        Binary(BinOp, Box<Expr>, Box<Expr>),
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Token {
+        Num(u64),
+        Ident(String),
+        Op(&'static str),
+    }
+
+    /// All operators, longest first.
+    const OPS: [&str; 22] = [
+        "||", "&&", "==", "!=", "<=", ">=", "<<", ">>",
+        "|", "^", "&", "<", ">", "+", "-", "*", "/", "%", "!", "~", "(", ")",
+    ];
+
+    /// Split `src` into tokens.
+    fn tokenize(src: &str) -> Result<Vec<Token>, String> {
+        let mut tokens = Vec::new();
+        let mut rest = src.trim_start();
+        while !rest.is_empty() {
+            let ch = rest.chars().next().unwrap();
+            if ch.is_ascii_digit() {
+                let end = rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+                let text = &rest[..end];
+                let num = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+                    Some(hex) => u64::from_str_radix(hex, 16),
+                    None => text.parse::<u64>(),
+                };
+                tokens.push(Token::Num(num.map_err(|_| format!("Bad number \"{}\"", text))?));
+                rest = &rest[end..];
+            }
+            else if ch.is_ascii_alphabetic() || ch == '_' || ch == '$' || ch == '.' {
+                let end = rest[1..].find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+                    .map_or(rest.len(), |end| end + 1);
+                tokens.push(Token::Ident(String::from(rest[..end].trim_start_matches('$'))));
+                rest = &rest[end..];
+            }
+            else {
+                match OPS.iter().find(|op| rest.starts_with(*op)) {
+                    Some(op) => {
+                        tokens.push(Token::Op(op));
+                        rest = &rest[op.len()..];
+                    },
+                    None => return Err(format!("Unexpected '{}'", ch)),
+                }
+            }
+            rest = rest.trim_start();
+        }
+        Ok(tokens)
+    }
+
+    struct Parser {
+        tokens: Vec<Token>,
+        pos: usize,
+    }
+
+    impl Parser {
+        fn peek(&self) -> Option<&Token> {
+            self.tokens.get(self.pos)
+        }
+
+        fn eat(&mut self, op: &str) -> bool {
+            if matches!(self.peek(), Some(Token::Op(o)) if *o == op) {
+                self.pos += 1;
+                true
+            }
+            else {
+                false
+            }
+        }
+
+        fn binary(&mut self, level: usize) -> Result<Expr, String> {
+            if level == PRECEDENCE.len() {
+                return self.unary();
+            }
+            let mut lhs = self.binary(level + 1)?;
+            'outer: loop {
+                for &(op, bin) in PRECEDENCE[level] {
+                    if self.eat(op) {
+                        let rhs = self.binary(level + 1)?;
+                        lhs = Expr::Binary(bin, Box::new(lhs), Box::new(rhs));
+                        continue 'outer;
+                    }
+                }
+                return Ok(lhs);
+            }
+        }
+
+        fn unary(&mut self) -> Result<Expr, String> {
+            if self.eat("-") {
+                return Ok(Expr::Neg(Box::new(self.unary()?)));
+            }
+            if self.eat("!") {
+                return Ok(Expr::Not(Box::new(self.unary()?)));
+            }
+            if self.eat("~") {
+                return Ok(Expr::BitNot(Box::new(self.unary()?)));
+            }
+            if self.eat("*") {
+                return Ok(match self.unary()? {
+                    Expr::Cast(width, signed, ptr) => Expr::Deref(width, signed, ptr),
+                    ptr => Expr::Deref(8, false, Box::new(ptr)),
+                });
+            }
+            self.primary()
+        }
+
+        fn primary(&mut self) -> Result<Expr, String> {
+            match self.peek().cloned() {
+                Some(Token::Num(num)) => {
+                    self.pos += 1;
+                    Ok(Expr::Num(num))
+                },
+                Some(Token::Ident(name)) => {
+                    self.pos += 1;
+                    Ok(Expr::Ident(name))
+                },
+                Some(Token::Op("(")) => {
+                    self.pos += 1;
+                    // (type*) cast
+                    if let Some(Token::Ident(name)) = self.peek().cloned() {
+                        if let Some(&(_, width, signed)) = TYPES.iter().find(|(ty, _, _)| *ty == name) {
+                            self.pos += 1;
+                            if !self.eat("*") || !self.eat(")") {
+                                return Err(format!("Expected \"({}*)\"", name));
+                            }
+                            return Ok(Expr::Cast(width, signed, Box::new(self.unary()?)));
+                        }
+                    }
+                    let expr = self.binary(0)?;
+                    if !self.eat(")") {
+                        return Err(String::from("Expected ')'"));
+                    }
+                    Ok(expr)
+                },
+                Some(Token::Op(op)) => Err(format!("Unexpected '{}'", op)),
+                None => Err(String::from("Unexpected end of expression")),
+            }
+        }
+    }
+
+    /// Sign or zero extend the little-endian `data` to 64 bits.
+    fn extend(data: &[u8], signed: bool) -> u64 {
+        let sz = data.len();
+        let mut bytes = [0u8; 8];
+        bytes[..sz].copy_from_slice(data);
+        let val = u64::from_le_bytes(bytes);
+        if signed && sz < 8 {
+            (((val << (64 - 8 * sz)) as i64) >> (64 - 8 * sz)) as u64
+        }
+        else {
+            val
+        }
+    }
+
+    impl Expr {
+        /// Parse `src` as a whole expression.
+        pub fn parse(src: &str) -> Result<Expr, String> {
+            let mut parser = Parser { tokens: tokenize(src)?, pos: 0 };
+            let expr = parser.binary(0)?;
+            match parser.peek() {
+                None => Ok(expr),
+                Some(token) => Err(format!("Unexpected {:?}", token)),
+            }
+        }
+
+        /// Look up a register, `pc`, variable or function symbol.
+        fn resolve(name: &str, prog: &Program) -> Result<u64, String> {
+            if name == "pc" {
+                return Ok(prog.program_counter);
+            }
+            if let Some(reg) = prog.registers.registers.iter()
+                .find(|reg| name.eq_ignore_ascii_case(reg.id.abi_name().as_str()))
+            {
+                return Ok(reg.value);
+            }
+            if let Some(var) = prog.debug_info.as_ref().and_then(|info| info.variable(name, prog.program_counter)) {
+                let addr = prog.variable_addr(var).ok_or(format!("\"{}\" is optimized out", name))?;
+                // Aggregates evaluate to their address, as arrays do in C
+                return match var.size.unwrap_or(8) as usize {
+                    sz @ (1 | 2 | 4 | 8) => match prog.mem_load(addr, sz, false) {
+                        Some((data, 0)) => Ok(extend(data, true)),
+                        _ => Err(format!("Cannot access memory at {:#x}", addr)),
+                    },
+                    _ => Ok(addr),
+                };
+            }
+            match prog.funcs.iter().find(|(_, _, func)| func == name) {
+                Some((addr, _, _)) => Ok(*addr),
+                None => Err(format!("No symbol \"{}\"", name)),
+            }
+        }
+
+        /// Evaluate against the current state of `prog`.
+        pub fn eval(&self, prog: &Program) -> Result<u64, String> {
+            Ok(match self {
+                Expr::Num(num) => *num,
+                Expr::Ident(name) => Expr::resolve(name, prog)?,
+                Expr::Neg(e) => e.eval(prog)?.wrapping_neg(),
+                Expr::Not(e) => (e.eval(prog)? == 0) as u64,
+                Expr::BitNot(e) => !e.eval(prog)?,
+                Expr::Cast(_, _, e) => e.eval(prog)?,
+                Expr::Deref(width, signed, e) => {
+                    let addr = e.eval(prog)?;
+                    match prog.mem_load(addr, *width, false) {
+                        Some((data, 0)) => extend(data, *signed),
+                        _ => return Err(format!("Cannot access memory at {:#x}", addr)),
+                    }
+                },
+                Expr::Binary(op, lhs, rhs) => {
+                    let lhs = lhs.eval(prog)?;
+                    // Short circuit
+                    match op {
+                        BinOp::Or if lhs != 0 => return Ok(1),
+                        BinOp::And if lhs == 0 => return Ok(0),
+                        _ => {}
+                    }
+                    let rhs = rhs.eval(prog)?;
+                    match op {
+                        BinOp::Or | BinOp::And => (rhs != 0) as u64,
+                        BinOp::BitOr => lhs | rhs,
+                        BinOp::BitXor => lhs ^ rhs,
+                        BinOp::BitAnd => lhs & rhs,
+                        BinOp::Eq => (lhs == rhs) as u64,
+                        BinOp::Ne => (lhs != rhs) as u64,
+                        BinOp::Lt => ((lhs as i64) < (rhs as i64)) as u64,
+                        BinOp::Le => ((lhs as i64) <= (rhs as i64)) as u64,
+                        BinOp::Gt => ((lhs as i64) > (rhs as i64)) as u64,
+                        BinOp::Ge => ((lhs as i64) >= (rhs as i64)) as u64,
+                        BinOp::Shl => lhs.wrapping_shl(rhs as u32),
+                        BinOp::Shr => lhs.wrapping_shr(rhs as u32),
+                        BinOp::Add => lhs.wrapping_add(rhs),
+                        BinOp::Sub => lhs.wrapping_sub(rhs),
+                        BinOp::Mul => lhs.wrapping_mul(rhs),
+                        BinOp::Div | BinOp::Rem if rhs == 0 => return Err(String::from("Division by zero")),
+                        BinOp::Div => (lhs as i64).wrapping_div(rhs as i64) as u64,
+                        BinOp::Rem => (lhs as i64).wrapping_rem(rhs as i64) as u64,
+                    }
+                },
+            })
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::{Assembler, RegID};
+
+        /// A program with a function `main` at 0x10000 and the doubleword
+        /// 0x80000000fffffffe at 0x11000, which a0 points to.
+        fn program() -> Program {
+            let mut prog = Assembler::new("main: ret\n.data\n.dword 0x80000000fffffffe").load().unwrap();
+            prog.registers.write(RegID::X10, 0x11000);
+            prog
+        }
+
+        fn eval(src: &str, prog: &Program) -> Result<u64, String> {
+            Expr::parse(src)?.eval(prog)
+        }
+
+        #[test]
+        fn precedence() {
+            let prog = program();
+            let num = |num| Box::new(Expr::Num(num));
+            assert_eq!(Expr::parse("1 + 2 * 3"), Ok(Expr::Binary(BinOp::Add, num(1), Box::new(Expr::Binary(BinOp::Mul, num(2), num(3))))));
+            assert_eq!(Expr::parse("7 - 2 - 1"), Ok(Expr::Binary(BinOp::Sub, Box::new(Expr::Binary(BinOp::Sub, num(7), num(2))), num(1))));
+            let cases = [
+                ("1 + 2 * 3", 7),
+                ("(1 + 2) * 3", 9),
+                ("7 - 2 - 1", 4),
+                ("10 % 4 / 2", 1),
+                ("1 << 2 + 1", 8),
+                ("1 | 2 == 2", 1),
+                ("6 & 3 ^ 1", 3),
+                ("-1 < 0", 1),
+                ("0xffffffffffffffff > 0", 0),
+                ("-7 / 2", -3i64 as u64),
+                ("~0", u64::MAX),
+                ("!0 && 3 > 2 || 0", 1),
+                ("1 || 1 / 0", 1),
+                ("0 && nosuch", 0),
+            ];
+            for (src, val) in cases {
+                assert_eq!(eval(src, &prog), Ok(val), "{}", src);
+            }
+        }
+
+        #[test]
+        fn operands() {
+            let prog = program();
+            let cases = [
+                ("a0", 0x11000),
+                ("$A0 + 8", 0x11008),
+                ("pc", 0x10000),
+                ("main", 0x10000),
+                ("*a0", 0x80000000fffffffe),
+                ("*(u32*)a0", 0xfffffffe),
+                ("*(int*)a0", -2i64 as u64),
+                ("*(u16*)(a0 + 2)", 0xffff),
+                ("*(char*)(a0 + 7)", -128i64 as u64),
+                ("*(u8*)(a0 + 7)", 0x80),
+                // A cast alone is no pointer arithmetic
+                ("(u32*)a0 + 1", 0x11001),
+            ];
+            for (src, val) in cases {
+                assert_eq!(eval(src, &prog), Ok(val), "{}", src);
+            }
+            // Decimal unless prefixed with 0x, in addresses too
+            assert_eq!(eval("10 + 0x10", &prog), Ok(26));
+            assert_eq!(eval("*(u8*)69639", &prog), eval("*(u8*)0x11007", &prog));
+            assert_eq!(Expr::parse("12ab"), Err(String::from("Bad number \"12ab\"")));
+        }
+
+        #[test]
+        fn errors() {
+            let prog = program();
+            let cases = [
+                ("", "Unexpected end of expression"),
+                ("1 +", "Unexpected end of expression"),
+                ("(1", "Expected ')'"),
+                ("1)", "Unexpected Op(\")\")"),
+                ("1 2", "Unexpected Num(2)"),
+                ("1 @ 2", "Unexpected '@'"),
+                ("0x", "Bad number \"0x\""),
+                ("(u8) 1", "Expected \"(u8*)\""),
+                ("1 / 0", "Division by zero"),
+                ("5 % (1 - 1)", "Division by zero"),
+                ("nosuch", "No symbol \"nosuch\""),
+                ("*0", "Cannot access memory at 0x0"),
+            ];
+            for (src, e) in cases {
+                assert_eq!(eval(src, &prog), Err(String::from(e)), "{}", src);
+            }
+        }
+    }
+}
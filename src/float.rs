@@ -0,0 +1,1058 @@
+#![allow(clippy::module_inception)]
+#[allow(dead_code)]
+pub mod float {
+    //! This module implements the F and D extensions: the floating-point
+    //! register file with NaN-boxing of single-precision values, the
+    //! fflags, frm and fcsr CSRs, loads and stores, arithmetic, fused
+    //! multiply-add, square root, sign injection, min/max, comparisons,
+    //! classification, conversions and moves.
+    //!
+    //! The host FPU is not used, as it only rounds to nearest and does not
+    //! report the exception flags. Instead, every operation is computed
+    //! exactly on integers and rounded once, in any of the five rounding
+    //! modes, detecting tininess after rounding as RISC-V does. NaN results
+    //! are always the canonical NaN.
+
+    use std::cmp::{self, Ordering};
+    use std::fmt;
+    use crate::{InstCode, Program, RegID};
+
+    /// The floating-point CSRs.
+    const CSRS: [(u16, &str); 3] = [(0x001, "fflags"), (0x002, "frm"), (0x003, "fcsr")];
+
+    /// ABI names of the floating-point registers.
+    const NAMES: [&str; 32] = [
+        "ft0", "ft1", "ft2", "ft3", "ft4", "ft5", "ft6", "ft7",
+        "fs0", "fs1", "fa0", "fa1", "fa2", "fa3", "fa4", "fa5",
+        "fa6", "fa7", "fs2", "fs3", "fs4", "fs5", "fs6", "fs7",
+        "fs8", "fs9", "fs10", "fs11", "ft8", "ft9", "ft10", "ft11",
+    ];
+
+    /// Exception flags, as in fflags.
+    pub const NV: u8 = 0b10000;
+    pub const DZ: u8 = 0b01000;
+    pub const OF: u8 = 0b00100;
+    pub const UF: u8 = 0b00010;
+    pub const NX: u8 = 0b00001;
+
+    /// Rounding modes, as in frm and the rm field.
+    pub const RNE: u8 = 0;
+    pub const RTZ: u8 = 1;
+    pub const RDN: u8 = 2;
+    pub const RUP: u8 = 3;
+    pub const RMM: u8 = 4;
+    /// The rm field selecting frm
+    pub const DYN: u8 = 7;
+
+    /// Name of floating-point CSR `csr`, None if it is not one.
+    pub fn float_csr_name(csr: u16) -> Option<&'static str> {
+        CSRS.iter().find(|(num, _)| *num == csr).map(|(_, name)| *name)
+    }
+
+    /// ABI name of floating-point register `reg`.
+    pub fn freg_name(reg: u8) -> &'static str {
+        NAMES[reg as usize & 0b11111]
+    }
+
+    /// Floating-point register numbered by its ABI name, or as `f0` to `f31`.
+    pub fn freg_number(name: &str) -> Option<u8> {
+        if let Some(reg) = NAMES.iter().position(|abi| *abi == name) {
+            return Some(reg as u8);
+        }
+        name.strip_prefix('f')
+            .and_then(|num| num.parse::<u8>().ok())
+            .filter(|reg| *reg < 32)
+    }
+
+    /// The rounding mode operand of the disassembly, empty for DYN.
+    pub fn rm_name(rm: u8) -> &'static str {
+        ["rne", "rtz", "rdn", "rup", "rmm", "", "", ""][rm as usize & 0b111]
+    }
+
+    /// An unpacked floating-point value. Finite values, zeros included,
+    /// are `(-1)^sign * mant * 2^exp`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Value {
+        Nan { signaling: bool },
+        Inf(bool),
+        Finite(bool, i32, u128),
+    }
+
+    /// An IEEE 754 binary interchange format.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct Format {
+        /// Number of exponent bits
+        exp: u32,
+        /// Number of fraction bits, the significand lacking its leading one
+        frac: u32,
+    }
+
+    const SINGLE: Format = Format { exp: 8, frac: 23 };
+    const DOUBLE: Format = Format { exp: 11, frac: 52 };
+
+    impl Format {
+        /// The format of a `width`-byte value.
+        fn of(width: u8) -> Format {
+            if width == 4 { SINGLE } else { DOUBLE }
+        }
+
+        fn bias(&self) -> i32 {
+            (1 << (self.exp - 1)) - 1
+        }
+
+        /// Exponent of the smallest normal numbers.
+        fn emin(&self) -> i32 {
+            1 - self.bias()
+        }
+
+        /// The biased exponent of infinities and NaNs.
+        fn max_exp(&self) -> u64 {
+            (1 << self.exp) - 1
+        }
+
+        fn sign_bit(&self) -> u64 {
+            1 << (self.exp + self.frac)
+        }
+
+        fn zero(&self, sign: bool) -> u64 {
+            if sign { self.sign_bit() } else { 0 }
+        }
+
+        fn inf(&self, sign: bool) -> u64 {
+            self.zero(sign) | (self.max_exp() << self.frac)
+        }
+
+        /// The finite number of largest magnitude.
+        fn largest(&self, sign: bool) -> u64 {
+            self.inf(sign) - 1
+        }
+
+        fn canonical_nan(&self) -> u64 {
+            (self.max_exp() << self.frac) | (1 << (self.frac - 1))
+        }
+
+        fn unpack(&self, bits: u64) -> Value {
+            let sign = bits & self.sign_bit() != 0;
+            let biased = (bits >> self.frac) & self.max_exp();
+            let fraction = (bits & ((1 << self.frac) - 1)) as u128;
+            match biased {
+                _ if biased == self.max_exp() && fraction == 0 => Value::Inf(sign),
+                _ if biased == self.max_exp() => Value::Nan { signaling: fraction >> (self.frac - 1) == 0 },
+                0 => Value::Finite(sign, self.emin() - self.frac as i32, fraction),
+                _ => Value::Finite(sign, biased as i32 - self.bias() - self.frac as i32, fraction | (1 << self.frac)),
+            }
+        }
+
+        fn is_nan(&self, bits: u64) -> bool {
+            matches!(self.unpack(bits), Value::Nan { .. })
+        }
+
+        /// The canonical NaN if any of `ops` is a NaN, raising NV for the
+        /// signaling ones.
+        fn propagate(&self, ops: &[u64], flags: &mut u8) -> Option<u64> {
+            let mut nan = false;
+            for op in ops {
+                if let Value::Nan { signaling } = self.unpack(*op) {
+                    nan = true;
+                    if signaling {
+                        *flags |= NV;
+                    }
+                }
+            }
+            if nan { Some(self.canonical_nan()) } else { None }
+        }
+
+        /// The invalid operation: raise NV, giving the canonical NaN.
+        fn invalid(&self, flags: &mut u8) -> u64 {
+            *flags |= NV;
+            self.canonical_nan()
+        }
+
+        /// Round `(-1)^sign * mant * 2^exp` to the format.
+        fn round(&self, sign: bool, exp: i32, mant: u128, rm: u8, flags: &mut u8) -> u64 {
+            if mant == 0 {
+                return self.zero(sign);
+            }
+            // The value is in [2^top, 2^(top+1)), and its last bit kept
+            // is worth 2^quantum, that of subnormals at the least
+            let top = exp + 127 - mant.leading_zeros() as i32;
+            let mut quantum = cmp::max(top, self.emin()) - self.frac as i32;
+            let (mut kept, inexact) = shift_round(sign, mant, quantum - exp, rm);
+            if kept >> (self.frac + 1) != 0 {
+                kept >>= 1;
+                quantum += 1;
+            }
+            if inexact {
+                *flags |= NX;
+                // Tiny unless rounding with an unbounded exponent range
+                // carries into the smallest normal number
+                if top < self.emin() {
+                    let (unbounded, _) = shift_round(sign, mant, top - self.frac as i32 - exp, rm);
+                    if unbounded >> (self.frac + 1) == 0 || top + 1 < self.emin() {
+                        *flags |= UF;
+                    }
+                }
+            }
+
+            if kept >> self.frac == 0 {
+                return self.zero(sign) | kept as u64;
+            }
+            let biased = quantum + self.frac as i32 + self.bias();
+            if biased >= self.max_exp() as i32 {
+                *flags |= OF | NX;
+                let to_inf = match rm {
+                    RTZ => false,
+                    RDN => sign,
+                    RUP => !sign,
+                    _ => true,
+                };
+                return if to_inf { self.inf(sign) } else { self.largest(sign) };
+            }
+            self.zero(sign) | ((biased as u64) << self.frac) | (kept as u64 & ((1 << self.frac) - 1))
+        }
+
+        fn add(&self, a: u64, b: u64, rm: u8, flags: &mut u8) -> u64 {
+            if let Some(nan) = self.propagate(&[a, b], flags) {
+                return nan;
+            }
+            match (self.unpack(a), self.unpack(b)) {
+                (Value::Inf(sa), Value::Inf(sb)) if sa != sb => self.invalid(flags),
+                (Value::Inf(sign), _) | (_, Value::Inf(sign)) => self.inf(sign),
+                (Value::Finite(sa, ea, ma), Value::Finite(sb, eb, mb)) => {
+                    let (sign, exp, mant) = exact_sum((sa, ea, ma), (sb, eb, mb), rm);
+                    self.round(sign, exp, mant, rm, flags)
+                },
+                _ => unreachable!(),
+            }
+        }
+
+        fn mul(&self, a: u64, b: u64, rm: u8, flags: &mut u8) -> u64 {
+            if let Some(nan) = self.propagate(&[a, b], flags) {
+                return nan;
+            }
+            let sign = (a ^ b) & self.sign_bit() != 0;
+            match (self.unpack(a), self.unpack(b)) {
+                (Value::Inf(_), Value::Finite(_, _, 0)) | (Value::Finite(_, _, 0), Value::Inf(_)) => self.invalid(flags),
+                (Value::Inf(_), _) | (_, Value::Inf(_)) => self.inf(sign),
+                (Value::Finite(_, ea, ma), Value::Finite(_, eb, mb)) => self.round(sign, ea + eb, ma * mb, rm, flags),
+                _ => unreachable!(),
+            }
+        }
+
+        /// `a * b + c`, with the product and the addend negated as asked,
+        /// rounded once.
+        fn fma(&self, a: u64, b: u64, c: u64, negate: (bool, bool), rm: u8, flags: &mut u8) -> u64 {
+            let (va, vb, vc) = (self.unpack(a), self.unpack(b), self.unpack(c));
+            // Invalid even if the addend is a quiet NaN
+            if let (Value::Inf(_), Value::Finite(_, _, 0)) | (Value::Finite(_, _, 0), Value::Inf(_)) = (va, vb) {
+                return self.invalid(flags);
+            }
+            if let Some(nan) = self.propagate(&[a, b, c], flags) {
+                return nan;
+            }
+            let sp = ((a ^ b) & self.sign_bit() != 0) ^ negate.0;
+            let sc = (c & self.sign_bit() != 0) ^ negate.1;
+            let product_inf = matches!(va, Value::Inf(_)) || matches!(vb, Value::Inf(_));
+            match (product_inf, vc) {
+                (true, Value::Inf(_)) if sp != sc => self.invalid(flags),
+                (true, _) => self.inf(sp),
+                (false, Value::Inf(_)) => self.inf(sc),
+                (false, Value::Finite(_, ec, mc)) => {
+                    let (Value::Finite(_, ea, ma), Value::Finite(_, eb, mb)) = (va, vb) else { unreachable!() };
+                    let (sign, exp, mant) = exact_sum((sp, ea + eb, ma * mb), (sc, ec, mc), rm);
+                    self.round(sign, exp, mant, rm, flags)
+                },
+                _ => unreachable!(),
+            }
+        }
+
+        fn div(&self, a: u64, b: u64, rm: u8, flags: &mut u8) -> u64 {
+            if let Some(nan) = self.propagate(&[a, b], flags) {
+                return nan;
+            }
+            let sign = (a ^ b) & self.sign_bit() != 0;
+            match (self.unpack(a), self.unpack(b)) {
+                (Value::Inf(_), Value::Inf(_)) | (Value::Finite(_, _, 0), Value::Finite(_, _, 0)) => self.invalid(flags),
+                (Value::Inf(_), _) => self.inf(sign),
+                (_, Value::Inf(_)) | (Value::Finite(_, _, 0), _) => self.zero(sign),
+                (_, Value::Finite(_, _, 0)) => {
+                    *flags |= DZ;
+                    self.inf(sign)
+                },
+                (Value::Finite(_, ea, ma), Value::Finite(_, eb, mb)) => {
+                    // Enough quotient bits to round, and a sticky bit for the remainder
+                    let shift = ma.leading_zeros() as i32 - 1;
+                    let num = ma << shift;
+                    let quot = (num / mb) | !num.is_multiple_of(mb) as u128;
+                    self.round(sign, ea - shift - eb, quot, rm, flags)
+                },
+                _ => unreachable!(),
+            }
+        }
+
+        fn sqrt(&self, a: u64, rm: u8, flags: &mut u8) -> u64 {
+            if let Some(nan) = self.propagate(&[a], flags) {
+                return nan;
+            }
+            match self.unpack(a) {
+                Value::Finite(_, _, 0) => a,
+                Value::Inf(true) | Value::Finite(true, _, _) => self.invalid(flags),
+                Value::Inf(false) => a,
+                Value::Finite(false, exp, mant) => {
+                    // An even exponent, and enough root bits to round
+                    let (exp, mant) = if exp % 2 != 0 { (exp - 1, mant << 1) } else { (exp, mant) };
+                    let shift = (mant.leading_zeros() as i32 - 2) & !1;
+                    let mant = mant << shift;
+                    let root = mant.isqrt();
+                    self.round(false, (exp - shift) / 2, root | (root * root != mant) as u128, rm, flags)
+                },
+                _ => unreachable!(),
+            }
+        }
+
+        /// Ordering of `a` and `b`, None if either is a NaN. Zeros are equal.
+        fn compare(&self, a: u64, b: u64) -> Option<Ordering> {
+            if self.is_nan(a) || self.is_nan(b) {
+                return None;
+            }
+            let key = |bits: u64| {
+                let magnitude = (bits & (self.sign_bit() - 1)) as i128;
+                if bits & self.sign_bit() != 0 { -magnitude } else { magnitude }
+            };
+            Some(key(a).cmp(&key(b)))
+        }
+
+        /// fmin or fmax: a NaN operand gives the other one, and -0 is
+        /// less than +0.
+        fn min_max(&self, a: u64, b: u64, max: bool, flags: &mut u8) -> u64 {
+            for op in [a, b] {
+                if let Value::Nan { signaling: true } = self.unpack(op) {
+                    *flags |= NV;
+                }
+            }
+            match (self.is_nan(a), self.is_nan(b)) {
+                (true, true) => return self.canonical_nan(),
+                (true, false) => return b,
+                (false, true) => return a,
+                _ => {}
+            }
+            let a_first = match self.compare(a, b).unwrap() {
+                Ordering::Less => !max,
+                Ordering::Greater => max,
+                Ordering::Equal => (a & self.sign_bit() != 0) != max,
+            };
+            if a_first { a } else { b }
+        }
+
+        /// The fclass mask of `a`.
+        fn class(&self, a: u64) -> u64 {
+            let bit = match self.unpack(a) {
+                Value::Inf(true) => 0,
+                Value::Finite(true, _, 0) => 3,
+                Value::Finite(true, _, mant) if mant >> self.frac == 0 => 2,
+                Value::Finite(true, _, _) => 1,
+                Value::Finite(false, _, 0) => 4,
+                Value::Finite(false, _, mant) if mant >> self.frac == 0 => 5,
+                Value::Finite(false, _, _) => 6,
+                Value::Inf(false) => 7,
+                Value::Nan { signaling: true } => 8,
+                Value::Nan { signaling: false } => 9,
+            };
+            1 << bit
+        }
+
+        /// Convert `a` to a `bits`-bit integer, saturating out of range
+        /// values and NaNs. The 32-bit results are sign-extended.
+        fn to_int(self, a: u64, signed: bool, bits: u32, rm: u8, flags: &mut u8) -> u64 {
+            let (min, max): (i128, i128) = if signed {
+                (-(1 << (bits - 1)), (1 << (bits - 1)) - 1)
+            } else {
+                (0, (1 << bits) - 1)
+            };
+            let (val, inexact) = match self.unpack(a) {
+                Value::Nan { .. } => (max + 1, false),
+                Value::Inf(sign) => (if sign { min - 1 } else { max + 1 }, false),
+                // Too large for any integer
+                Value::Finite(sign, exp, _) if exp > 64 => (if sign { min - 1 } else { max + 1 }, false),
+                Value::Finite(sign, exp, mant) => {
+                    let (magnitude, inexact) = shift_round(sign, mant, -exp, rm);
+                    let magnitude = magnitude as i128;
+                    (if sign { -magnitude } else { magnitude }, inexact)
+                },
+            };
+            let val = if val < min || val > max {
+                *flags |= NV;
+                if val < min { min } else { max }
+            } else {
+                if inexact {
+                    *flags |= NX;
+                }
+                val
+            };
+            if bits == 32 { val as u32 as i32 as i64 as u64 } else { val as u64 }
+        }
+
+        /// Convert the `bits`-bit integer `val` to the format.
+        fn convert_int(&self, val: u64, signed: bool, bits: u32, rm: u8, flags: &mut u8) -> u64 {
+            let val = match (bits, signed) {
+                (32, true) => val as i32 as i64 as u64,
+                (32, false) => val as u32 as u64,
+                _ => val,
+            };
+            let sign = signed && (val as i64) < 0;
+            let magnitude = if sign { (val as i64).unsigned_abs() } else { val };
+            self.round(sign, 0, magnitude as u128, rm, flags)
+        }
+
+        /// Convert `a` of format `from` to the format.
+        fn convert(&self, from: Format, a: u64, rm: u8, flags: &mut u8) -> u64 {
+            match from.unpack(a) {
+                Value::Nan { signaling } => {
+                    if signaling {
+                        *flags |= NV;
+                    }
+                    self.canonical_nan()
+                },
+                Value::Inf(sign) => self.inf(sign),
+                Value::Finite(sign, exp, mant) => self.round(sign, exp, mant, rm, flags),
+            }
+        }
+
+        /// fsgnj, fsgnjn or fsgnjx, by `op` 0 to 2.
+        fn sign_inject(&self, a: u64, b: u64, op: u8) -> u64 {
+            let sign = self.sign_bit();
+            match op {
+                0 => (a & !sign) | (b & sign),
+                1 => (a & !sign) | (!b & sign),
+                _ => a ^ (b & sign),
+            }
+        }
+    }
+
+    /// `mant >> shift`, rounded by `rm` for a value of `sign`, and whether
+    /// bits were lost. Shifts left for non-positive shifts.
+    fn shift_round(sign: bool, mant: u128, shift: i32, rm: u8) -> (u128, bool) {
+        if shift <= 0 {
+            return (mant << -shift, false);
+        }
+        let (kept, rest) = if shift >= 128 { (0, mant) } else { (mant >> shift, mant & ((1 << shift) - 1)) };
+        let half = match shift {
+            1..=128 => rest.cmp(&(1 << (shift - 1))),
+            _ => Ordering::Less,
+        };
+        let up = match rm {
+            RNE => half == Ordering::Greater || (half == Ordering::Equal && kept & 1 == 1),
+            RTZ => false,
+            RDN => sign && rest != 0,
+            RUP => !sign && rest != 0,
+            _ => half != Ordering::Less,
+        };
+        (kept + up as u128, rest != 0)
+    }
+
+    /// The sum of two finite values `(sign, exp, mant)`, exact but for a
+    /// sticky bit far below the rounding position. Exact zero sums are
+    /// negative only when rounding down, or when adding two negative zeros.
+    fn exact_sum(a: (bool, i32, u128), b: (bool, i32, u128), rm: u8) -> (bool, i32, u128) {
+        let top = |(_, exp, mant): (bool, i32, u128)| exp + 127 - mant.leading_zeros() as i32;
+        match (a.2, b.2) {
+            (0, 0) => return (if a.0 == b.0 { a.0 } else { rm == RDN }, 0, 0),
+            (_, 0) => return a,
+            (0, _) => return b,
+            _ => {}
+        }
+        let (a, b) = if top(a) >= top(b) { (a, b) } else { (b, a) };
+
+        // The larger one up to bit 125, so that the sum fits
+        let shift = a.2.leading_zeros() as i32 - 2;
+        let (exp, ma) = (a.1 - shift, a.2 << shift);
+        let mb = match exp - b.1 {
+            ofs if ofs <= 0 => b.2 << -ofs,
+            ofs if ofs >= 128 => 1,
+            ofs => (b.2 >> ofs) | (b.2 & ((1 << ofs) - 1) != 0) as u128,
+        };
+        match (a.0 == b.0, ma.cmp(&mb)) {
+            (true, _) => (a.0, exp, ma + mb),
+            (false, Ordering::Equal) => (rm == RDN, exp, 0),
+            (false, Ordering::Greater) => (a.0, exp, ma - mb),
+            (false, Ordering::Less) => (b.0, exp, mb - ma),
+        }
+    }
+
+    /// The floating-point registers and CSRs.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct FloatUnit {
+        /// The 32 registers. Single-precision values are NaN-boxed, with
+        /// the upper 32 bits set.
+        pub regs: [u64; 32],
+        /// Accrued exceptions
+        pub fflags: u8,
+        /// Dynamic rounding mode
+        pub frm: u8,
+    }
+
+    impl FloatUnit {
+        pub fn new() -> Self {
+            FloatUnit::default()
+        }
+
+        /// Register `reg` as a `width`-byte value. Single-precision values
+        /// that are not properly NaN-boxed read as the canonical NaN.
+        pub fn read(&self, reg: u8, width: u8) -> u64 {
+            let val = self.regs[reg as usize];
+            match width {
+                4 if val >> 32 == 0xFFFFFFFF => val & 0xFFFFFFFF,
+                4 => SINGLE.canonical_nan(),
+                _ => val,
+            }
+        }
+
+        /// Write the `width`-byte value `val` to register `reg`.
+        pub fn write(&mut self, reg: u8, width: u8, val: u64) {
+            self.regs[reg as usize] = if width == 4 { val | 0xFFFFFFFF00000000 } else { val };
+        }
+
+        pub fn fcsr(&self) -> u64 {
+            ((self.frm as u64) << 5) | self.fflags as u64
+        }
+
+        /// Value of floating-point CSR `csr`, None if it is not one.
+        pub fn read_csr(&self, csr: u16) -> Option<u64> {
+            match csr {
+                0x001 => Some(self.fflags as u64),
+                0x002 => Some(self.frm as u64),
+                0x003 => Some(self.fcsr()),
+                _ => None,
+            }
+        }
+
+        /// Write floating-point CSR `csr`, ignoring the reserved bits.
+        pub fn write_csr(&mut self, csr: u16, val: u64) {
+            match csr {
+                0x001 => self.fflags = (val & 0b11111) as u8,
+                0x002 => self.frm = (val & 0b111) as u8,
+                _ => {
+                    self.fflags = (val & 0b11111) as u8;
+                    self.frm = ((val >> 5) & 0b111) as u8;
+                },
+            }
+        }
+
+        /// Register `reg` in hex, and as a number of the format it holds.
+        pub fn format_reg(&self, reg: u8) -> String {
+            let val = self.regs[reg as usize];
+            if val >> 32 == 0xFFFFFFFF {
+                format!("{:#018x} {}", val, f32::from_bits(val as u32))
+            } else {
+                format!("{:#018x} {}", val, f64::from_bits(val))
+            }
+        }
+    }
+
+    impl fmt::Display for FloatUnit {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            writeln!(f, "fflags = {:#x}, frm = {}", self.fflags, self.frm)?;
+            for reg in 0..32u8 {
+                writeln!(f, "{:<5}: {}", freg_name(reg), self.format_reg(reg))?;
+            }
+            Ok(())
+        }
+    }
+
+    /// The `.s` or `.d` mnemonic of a `width`-byte operation.
+    fn pick(width: u8, s: &'static str, d: &'static str) -> &'static str {
+        if width == 4 { s } else { d }
+    }
+
+    impl InstCode {
+        /// Parse an F or D instruction, from the LOAD-FP and STORE-FP
+        /// major opcodes with widths 2 and 3, OP-FP and the fused
+        /// multiply-add opcodes. Other formats, such as Q, are unknown.
+        pub(crate) fn parse_float(raw: u32) -> Self {
+            let opcode = raw & 0x7f;
+            let rd = ((raw >> 7) & 0b11111) as u8;
+            let rm = ((raw >> 12) & 0b111) as u8;
+            let rs1 = ((raw >> 15) & 0b11111) as u8;
+            let rs2 = ((raw >> 20) & 0b11111) as u8;
+            let funct5 = raw >> 27;
+            let (xd, xs1) = (RegID::decode(rd), RegID::decode(rs1));
+
+            match opcode {
+                0x07 => return InstCode::Fl(rd, xs1, ((raw as i32) >> 20) as i16, if rm == 2 { 4 } else { 8 }),
+                0x27 => {
+                    let imm = (((raw as i32) >> 25) << 5) | rd as i32;
+                    return InstCode::Fs(xs1, rs2, imm as i16, if rm == 2 { 4 } else { 8 });
+                },
+                _ => {}
+            }
+            let width = match (raw >> 25) & 0b11 {
+                0 => 4,
+                1 => 8,
+                _ => return InstCode::Unknown(raw),
+            };
+            match opcode {
+                0x43 => InstCode::Fmadd(rd, rs1, rs2, funct5 as u8, rm, width),
+                0x47 => InstCode::Fmsub(rd, rs1, rs2, funct5 as u8, rm, width),
+                0x4b => InstCode::Fnmsub(rd, rs1, rs2, funct5 as u8, rm, width),
+                0x4f => InstCode::Fnmadd(rd, rs1, rs2, funct5 as u8, rm, width),
+                _ => match (funct5, rs2, rm) {
+                    (0b00000, _, _) => InstCode::Fadd(rd, rs1, rs2, rm, width),
+                    (0b00001, _, _) => InstCode::Fsub(rd, rs1, rs2, rm, width),
+                    (0b00010, _, _) => InstCode::Fmul(rd, rs1, rs2, rm, width),
+                    (0b00011, _, _) => InstCode::Fdiv(rd, rs1, rs2, rm, width),
+                    (0b01011, 0, _) => InstCode::Fsqrt(rd, rs1, rm, width),
+                    (0b00100, _, 0) => InstCode::Fsgnj(rd, rs1, rs2, width),
+                    (0b00100, _, 1) => InstCode::Fsgnjn(rd, rs1, rs2, width),
+                    (0b00100, _, 2) => InstCode::Fsgnjx(rd, rs1, rs2, width),
+                    (0b00101, _, 0) => InstCode::Fmin(rd, rs1, rs2, width),
+                    (0b00101, _, 1) => InstCode::Fmax(rd, rs1, rs2, width),
+                    // fcvt.s.d and fcvt.d.s
+                    (0b01000, 1, _) if width == 4 => InstCode::FcvtFF(rd, rs1, rm, width),
+                    (0b01000, 0, _) if width == 8 => InstCode::FcvtFF(rd, rs1, rm, width),
+                    (0b10100, _, 2) => InstCode::Feq(xd, rs1, rs2, width),
+                    (0b10100, _, 1) => InstCode::Flt(xd, rs1, rs2, width),
+                    (0b10100, _, 0) => InstCode::Fle(xd, rs1, rs2, width),
+                    (0b11100, 0, 0) => InstCode::FmvXF(xd, rs1, width),
+                    (0b11100, 0, 1) => InstCode::Fclass(xd, rs1, width),
+                    (0b11000, 0, _) => InstCode::FcvtW(xd, rs1, rm, width),
+                    (0b11000, 1, _) => InstCode::FcvtWu(xd, rs1, rm, width),
+                    (0b11000, 2, _) => InstCode::FcvtL(xd, rs1, rm, width),
+                    (0b11000, 3, _) => InstCode::FcvtLu(xd, rs1, rm, width),
+                    (0b11010, 0, _) => InstCode::FcvtFW(rd, xs1, rm, width),
+                    (0b11010, 1, _) => InstCode::FcvtFWu(rd, xs1, rm, width),
+                    (0b11010, 2, _) => InstCode::FcvtFL(rd, xs1, rm, width),
+                    (0b11010, 3, _) => InstCode::FcvtFLu(rd, xs1, rm, width),
+                    (0b11110, 0, 0) => InstCode::FmvFX(rd, xs1, width),
+                    _ => InstCode::Unknown(raw),
+                },
+            }
+        }
+
+        pub(crate) fn encode_float(&self) -> u32 {
+            let fmt = |width: u8| if width == 4 { 0 } else { 1 };
+            let op = |funct5: u32, width: u8, rs2: u32, rs1: u32, rm: u8, rd: u32| {
+                (funct5<<27) | (fmt(width)<<25) | (rs2<<20) | (rs1<<15) | ((rm as u32)<<12) | (rd<<7) | 0x53
+            };
+            let r4 = |rd: u8, rs1: u8, rs2: u8, rs3: u8, rm: u8, width: u8, opcode: u32| {
+                ((rs3 as u32)<<27) | (fmt(width)<<25) | ((rs2 as u32)<<20) | ((rs1 as u32)<<15) |
+                ((rm as u32)<<12) | ((rd as u32)<<7) | opcode
+            };
+            let x = |reg: RegID| reg.encode() as u32;
+            let f = |reg: u8| reg as u32;
+
+            match *self {
+                InstCode::Fl(rd, rs1, imm, width) => {
+                    (((imm as u32) & 0xFFF)<<20) | (x(rs1)<<15) | ((if width == 4 { 2 } else { 3 })<<12) | (f(rd)<<7) | 0x07
+                },
+                InstCode::Fs(rs1, rs2, imm, width) => {
+                    let imm = imm as u32;
+                    (((imm>>5) & 0b1111111)<<25) | (f(rs2)<<20) | (x(rs1)<<15) |
+                    ((if width == 4 { 2 } else { 3 })<<12) | ((imm & 0b11111)<<7) | 0x27
+                },
+                InstCode::Fmadd(rd, rs1, rs2, rs3, rm, width) => r4(rd, rs1, rs2, rs3, rm, width, 0x43),
+                InstCode::Fmsub(rd, rs1, rs2, rs3, rm, width) => r4(rd, rs1, rs2, rs3, rm, width, 0x47),
+                InstCode::Fnmsub(rd, rs1, rs2, rs3, rm, width) => r4(rd, rs1, rs2, rs3, rm, width, 0x4b),
+                InstCode::Fnmadd(rd, rs1, rs2, rs3, rm, width) => r4(rd, rs1, rs2, rs3, rm, width, 0x4f),
+                InstCode::Fadd(rd, rs1, rs2, rm, width) => op(0b00000, width, f(rs2), f(rs1), rm, f(rd)),
+                InstCode::Fsub(rd, rs1, rs2, rm, width) => op(0b00001, width, f(rs2), f(rs1), rm, f(rd)),
+                InstCode::Fmul(rd, rs1, rs2, rm, width) => op(0b00010, width, f(rs2), f(rs1), rm, f(rd)),
+                InstCode::Fdiv(rd, rs1, rs2, rm, width) => op(0b00011, width, f(rs2), f(rs1), rm, f(rd)),
+                InstCode::Fsqrt(rd, rs1, rm, width) => op(0b01011, width, 0, f(rs1), rm, f(rd)),
+                InstCode::Fsgnj(rd, rs1, rs2, width) => op(0b00100, width, f(rs2), f(rs1), 0, f(rd)),
+                InstCode::Fsgnjn(rd, rs1, rs2, width) => op(0b00100, width, f(rs2), f(rs1), 1, f(rd)),
+                InstCode::Fsgnjx(rd, rs1, rs2, width) => op(0b00100, width, f(rs2), f(rs1), 2, f(rd)),
+                InstCode::Fmin(rd, rs1, rs2, width) => op(0b00101, width, f(rs2), f(rs1), 0, f(rd)),
+                InstCode::Fmax(rd, rs1, rs2, width) => op(0b00101, width, f(rs2), f(rs1), 1, f(rd)),
+                InstCode::FcvtFF(rd, rs1, rm, width) => op(0b01000, width, fmt(12 - width), f(rs1), rm, f(rd)),
+                InstCode::Feq(rd, rs1, rs2, width) => op(0b10100, width, f(rs2), f(rs1), 2, x(rd)),
+                InstCode::Flt(rd, rs1, rs2, width) => op(0b10100, width, f(rs2), f(rs1), 1, x(rd)),
+                InstCode::Fle(rd, rs1, rs2, width) => op(0b10100, width, f(rs2), f(rs1), 0, x(rd)),
+                InstCode::FmvXF(rd, rs1, width) => op(0b11100, width, 0, f(rs1), 0, x(rd)),
+                InstCode::Fclass(rd, rs1, width) => op(0b11100, width, 0, f(rs1), 1, x(rd)),
+                InstCode::FcvtW(rd, rs1, rm, width) => op(0b11000, width, 0, f(rs1), rm, x(rd)),
+                InstCode::FcvtWu(rd, rs1, rm, width) => op(0b11000, width, 1, f(rs1), rm, x(rd)),
+                InstCode::FcvtL(rd, rs1, rm, width) => op(0b11000, width, 2, f(rs1), rm, x(rd)),
+                InstCode::FcvtLu(rd, rs1, rm, width) => op(0b11000, width, 3, f(rs1), rm, x(rd)),
+                InstCode::FcvtFW(rd, rs1, rm, width) => op(0b11010, width, 0, x(rs1), rm, f(rd)),
+                InstCode::FcvtFWu(rd, rs1, rm, width) => op(0b11010, width, 1, x(rs1), rm, f(rd)),
+                InstCode::FcvtFL(rd, rs1, rm, width) => op(0b11010, width, 2, x(rs1), rm, f(rd)),
+                InstCode::FcvtFLu(rd, rs1, rm, width) => op(0b11010, width, 3, x(rs1), rm, f(rd)),
+                InstCode::FmvFX(rd, rs1, width) => op(0b11110, width, 0, x(rs1), 0, f(rd)),
+                _ => unreachable!(),
+            }
+        }
+
+        pub(crate) fn float_mnemonic(&self) -> &'static str {
+            match *self {
+                InstCode::Fl(_,_,_,width) => pick(width, "flw", "fld"),
+                InstCode::Fs(_,_,_,width) => pick(width, "fsw", "fsd"),
+                InstCode::Fmadd(_,_,_,_,_,width) => pick(width, "fmadd.s", "fmadd.d"),
+                InstCode::Fmsub(_,_,_,_,_,width) => pick(width, "fmsub.s", "fmsub.d"),
+                InstCode::Fnmsub(_,_,_,_,_,width) => pick(width, "fnmsub.s", "fnmsub.d"),
+                InstCode::Fnmadd(_,_,_,_,_,width) => pick(width, "fnmadd.s", "fnmadd.d"),
+                InstCode::Fadd(_,_,_,_,width) => pick(width, "fadd.s", "fadd.d"),
+                InstCode::Fsub(_,_,_,_,width) => pick(width, "fsub.s", "fsub.d"),
+                InstCode::Fmul(_,_,_,_,width) => pick(width, "fmul.s", "fmul.d"),
+                InstCode::Fdiv(_,_,_,_,width) => pick(width, "fdiv.s", "fdiv.d"),
+                InstCode::Fsqrt(_,_,_,width) => pick(width, "fsqrt.s", "fsqrt.d"),
+                InstCode::Fsgnj(_,_,_,width) => pick(width, "fsgnj.s", "fsgnj.d"),
+                InstCode::Fsgnjn(_,_,_,width) => pick(width, "fsgnjn.s", "fsgnjn.d"),
+                InstCode::Fsgnjx(_,_,_,width) => pick(width, "fsgnjx.s", "fsgnjx.d"),
+                InstCode::Fmin(_,_,_,width) => pick(width, "fmin.s", "fmin.d"),
+                InstCode::Fmax(_,_,_,width) => pick(width, "fmax.s", "fmax.d"),
+                InstCode::FcvtFF(_,_,_,width) => pick(width, "fcvt.s.d", "fcvt.d.s"),
+                InstCode::Feq(_,_,_,width) => pick(width, "feq.s", "feq.d"),
+                InstCode::Flt(_,_,_,width) => pick(width, "flt.s", "flt.d"),
+                InstCode::Fle(_,_,_,width) => pick(width, "fle.s", "fle.d"),
+                InstCode::FmvXF(_,_,width) => pick(width, "fmv.x.w", "fmv.x.d"),
+                InstCode::Fclass(_,_,width) => pick(width, "fclass.s", "fclass.d"),
+                InstCode::FcvtW(_,_,_,width) => pick(width, "fcvt.w.s", "fcvt.w.d"),
+                InstCode::FcvtWu(_,_,_,width) => pick(width, "fcvt.wu.s", "fcvt.wu.d"),
+                InstCode::FcvtL(_,_,_,width) => pick(width, "fcvt.l.s", "fcvt.l.d"),
+                InstCode::FcvtLu(_,_,_,width) => pick(width, "fcvt.lu.s", "fcvt.lu.d"),
+                InstCode::FcvtFW(_,_,_,width) => pick(width, "fcvt.s.w", "fcvt.d.w"),
+                InstCode::FcvtFWu(_,_,_,width) => pick(width, "fcvt.s.wu", "fcvt.d.wu"),
+                InstCode::FcvtFL(_,_,_,width) => pick(width, "fcvt.s.l", "fcvt.d.l"),
+                InstCode::FcvtFLu(_,_,_,width) => pick(width, "fcvt.s.lu", "fcvt.d.lu"),
+                InstCode::FmvFX(_,_,width) => pick(width, "fmv.w.x", "fmv.d.x"),
+                _ => unreachable!(),
+            }
+        }
+
+        /// The width in bytes of the floating-point operands, None if
+        /// not an F or D instruction. fcvt.s.d and fcvt.d.s give that of
+        /// the result.
+        pub fn float_width(&self) -> Option<u8> {
+            match *self {
+                InstCode::Fl(_,_,_,width) | InstCode::Fs(_,_,_,width) |
+                InstCode::Fmadd(_,_,_,_,_,width) | InstCode::Fmsub(_,_,_,_,_,width) |
+                InstCode::Fnmsub(_,_,_,_,_,width) | InstCode::Fnmadd(_,_,_,_,_,width) |
+                InstCode::Fadd(_,_,_,_,width) | InstCode::Fsub(_,_,_,_,width) |
+                InstCode::Fmul(_,_,_,_,width) | InstCode::Fdiv(_,_,_,_,width) |
+                InstCode::Fsqrt(_,_,_,width) | InstCode::Fsgnj(_,_,_,width) |
+                InstCode::Fsgnjn(_,_,_,width) | InstCode::Fsgnjx(_,_,_,width) |
+                InstCode::Fmin(_,_,_,width) | InstCode::Fmax(_,_,_,width) |
+                InstCode::FcvtFF(_,_,_,width) | InstCode::Feq(_,_,_,width) |
+                InstCode::Flt(_,_,_,width) | InstCode::Fle(_,_,_,width) |
+                InstCode::FmvXF(_,_,width) | InstCode::Fclass(_,_,width) |
+                InstCode::FcvtW(_,_,_,width) | InstCode::FcvtWu(_,_,_,width) |
+                InstCode::FcvtL(_,_,_,width) | InstCode::FcvtLu(_,_,_,width) |
+                InstCode::FcvtFW(_,_,_,width) | InstCode::FcvtFWu(_,_,_,width) |
+                InstCode::FcvtFL(_,_,_,width) | InstCode::FcvtFLu(_,_,_,width) |
+                InstCode::FmvFX(_,_,width) => Some(width),
+                _ => None,
+            }
+        }
+
+        pub fn is_float(&self) -> bool {
+            self.float_width().is_some()
+        }
+
+        /// The integer register read by an F or D instruction.
+        pub fn float_source(&self) -> Option<RegID> {
+            match *self {
+                InstCode::Fl(_, rs1, _, _) | InstCode::Fs(rs1, _, _, _) |
+                InstCode::FcvtFW(_, rs1, _, _) | InstCode::FcvtFWu(_, rs1, _, _) |
+                InstCode::FcvtFL(_, rs1, _, _) | InstCode::FcvtFLu(_, rs1, _, _) |
+                InstCode::FmvFX(_, rs1, _) => Some(rs1),
+                _ => None,
+            }
+        }
+
+        /// The integer register written by an F or D instruction.
+        pub fn float_rd(&self) -> Option<RegID> {
+            match *self {
+                InstCode::Feq(rd,_,_,_) | InstCode::Flt(rd,_,_,_) | InstCode::Fle(rd,_,_,_) |
+                InstCode::FmvXF(rd,_,_) | InstCode::Fclass(rd,_,_) |
+                InstCode::FcvtW(rd,_,_,_) | InstCode::FcvtWu(rd,_,_,_) |
+                InstCode::FcvtL(rd,_,_,_) | InstCode::FcvtLu(rd,_,_,_) => Some(rd),
+                _ => None,
+            }
+        }
+    }
+
+    impl Program {
+        /// Execute F or D instruction `code` at `pc`, with R[rs1] `x1`.
+        /// Returns the value for the integer rd, or the address of a
+        /// load or store.
+        pub fn float_execute(&mut self, code: InstCode, pc: u64, x1: u64) -> Result<u64, String> {
+            let unit = &mut self.float;
+            let mut flags = 0;
+            // The rounding mode of the rm field, or of frm for DYN
+            let frm = unit.frm;
+            let rounding = |rm: u8| match if rm == DYN { frm } else { rm } {
+                rm @ RNE..=RMM => Ok(rm),
+                _ => Err(format!("Illegal rounding mode of {} at {:x}", code.mnemonic(), pc)),
+            };
+
+            let result = match code {
+                InstCode::Fl(_, _, imm, _) | InstCode::Fs(_, _, imm, _) => return Ok(x1.wrapping_add(imm as i64 as u64)),
+                InstCode::Fmadd(rd, rs1, rs2, rs3, rm, width) | InstCode::Fmsub(rd, rs1, rs2, rs3, rm, width) |
+                InstCode::Fnmsub(rd, rs1, rs2, rs3, rm, width) | InstCode::Fnmadd(rd, rs1, rs2, rs3, rm, width) => {
+                    let negate = match code {
+                        InstCode::Fmadd(_,_,_,_,_,_) => (false, false),
+                        InstCode::Fmsub(_,_,_,_,_,_) => (false, true),
+                        InstCode::Fnmsub(_,_,_,_,_,_) => (true, false),
+                        _ => (true, true),
+                    };
+                    let rm = rounding(rm)?;
+                    let (a, b, c) = (unit.read(rs1, width), unit.read(rs2, width), unit.read(rs3, width));
+                    unit.write(rd, width, Format::of(width).fma(a, b, c, negate, rm, &mut flags));
+                    0
+                },
+                InstCode::Fadd(rd, rs1, rs2, rm, width) | InstCode::Fsub(rd, rs1, rs2, rm, width) |
+                InstCode::Fmul(rd, rs1, rs2, rm, width) | InstCode::Fdiv(rd, rs1, rs2, rm, width) => {
+                    let fmt = Format::of(width);
+                    let rm = rounding(rm)?;
+                    let (a, b) = (unit.read(rs1, width), unit.read(rs2, width));
+                    let val = match code {
+                        InstCode::Fadd(_,_,_,_,_) => fmt.add(a, b, rm, &mut flags),
+                        InstCode::Fsub(_,_,_,_,_) => fmt.add(a, b ^ fmt.sign_bit(), rm, &mut flags),
+                        InstCode::Fmul(_,_,_,_,_) => fmt.mul(a, b, rm, &mut flags),
+                        _ => fmt.div(a, b, rm, &mut flags),
+                    };
+                    unit.write(rd, width, val);
+                    0
+                },
+                InstCode::Fsqrt(rd, rs1, rm, width) => {
+                    let rm = rounding(rm)?;
+                    let val = Format::of(width).sqrt(unit.read(rs1, width), rm, &mut flags);
+                    unit.write(rd, width, val);
+                    0
+                },
+                InstCode::Fsgnj(rd, rs1, rs2, width) | InstCode::Fsgnjn(rd, rs1, rs2, width) |
+                InstCode::Fsgnjx(rd, rs1, rs2, width) => {
+                    let op = match code {
+                        InstCode::Fsgnj(_,_,_,_) => 0,
+                        InstCode::Fsgnjn(_,_,_,_) => 1,
+                        _ => 2,
+                    };
+                    let val = Format::of(width).sign_inject(unit.read(rs1, width), unit.read(rs2, width), op);
+                    unit.write(rd, width, val);
+                    0
+                },
+                InstCode::Fmin(rd, rs1, rs2, width) | InstCode::Fmax(rd, rs1, rs2, width) => {
+                    let max = matches!(code, InstCode::Fmax(_,_,_,_));
+                    let val = Format::of(width).min_max(unit.read(rs1, width), unit.read(rs2, width), max, &mut flags);
+                    unit.write(rd, width, val);
+                    0
+                },
+                InstCode::FcvtFF(rd, rs1, rm, width) => {
+                    let rm = rounding(rm)?;
+                    let from = 12 - width;
+                    let val = Format::of(width).convert(Format::of(from), unit.read(rs1, from), rm, &mut flags);
+                    unit.write(rd, width, val);
+                    0
+                },
+                InstCode::Feq(_, rs1, rs2, width) | InstCode::Flt(_, rs1, rs2, width) |
+                InstCode::Fle(_, rs1, rs2, width) => {
+                    let fmt = Format::of(width);
+                    let (a, b) = (unit.read(rs1, width), unit.read(rs2, width));
+                    // feq is a quiet comparison, flt and fle signaling ones
+                    let order = fmt.compare(a, b);
+                    if order.is_none() && !matches!(code, InstCode::Feq(_,_,_,_)) {
+                        flags |= NV;
+                    }
+                    else if order.is_none() {
+                        fmt.propagate(&[a, b], &mut flags);
+                    }
+                    match (code, order) {
+                        (InstCode::Feq(_,_,_,_), Some(order)) => (order == Ordering::Equal) as u64,
+                        (InstCode::Flt(_,_,_,_), Some(order)) => (order == Ordering::Less) as u64,
+                        (InstCode::Fle(_,_,_,_), Some(order)) => (order != Ordering::Greater) as u64,
+                        _ => 0,
+                    }
+                },
+                InstCode::FmvXF(_, rs1, width) => {
+                    let val = unit.regs[rs1 as usize];
+                    if width == 4 { val as i32 as i64 as u64 } else { val }
+                },
+                InstCode::Fclass(_, rs1, width) => Format::of(width).class(unit.read(rs1, width)),
+                InstCode::FcvtW(_, rs1, rm, width) | InstCode::FcvtWu(_, rs1, rm, width) |
+                InstCode::FcvtL(_, rs1, rm, width) | InstCode::FcvtLu(_, rs1, rm, width) => {
+                    let (signed, bits) = match code {
+                        InstCode::FcvtW(_,_,_,_) => (true, 32),
+                        InstCode::FcvtWu(_,_,_,_) => (false, 32),
+                        InstCode::FcvtL(_,_,_,_) => (true, 64),
+                        _ => (false, 64),
+                    };
+                    let rm = rounding(rm)?;
+                    Format::of(width).to_int(unit.read(rs1, width), signed, bits, rm, &mut flags)
+                },
+                InstCode::FcvtFW(rd, _, rm, width) | InstCode::FcvtFWu(rd, _, rm, width) |
+                InstCode::FcvtFL(rd, _, rm, width) | InstCode::FcvtFLu(rd, _, rm, width) => {
+                    let (signed, bits) = match code {
+                        InstCode::FcvtFW(_,_,_,_) => (true, 32),
+                        InstCode::FcvtFWu(_,_,_,_) => (false, 32),
+                        InstCode::FcvtFL(_,_,_,_) => (true, 64),
+                        _ => (false, 64),
+                    };
+                    let rm = rounding(rm)?;
+                    let val = Format::of(width).convert_int(x1, signed, bits, rm, &mut flags);
+                    unit.write(rd, width, val);
+                    0
+                },
+                InstCode::FmvFX(rd, _, width) => {
+                    unit.write(rd, width, if width == 4 { x1 & 0xFFFFFFFF } else { x1 });
+                    0
+                },
+                _ => unreachable!(),
+            };
+            unit.fflags |= flags;
+            Ok(result)
+        }
+
+        /// Perform floating-point load or store `code` at `addr`. Stores
+        /// write the register bits as they are, NaN-boxed or not.
+        pub fn float_access(&mut self, code: InstCode, addr: u64) -> Result<(), String> {
+            match code {
+                InstCode::Fl(rd, _, _, width) => match self.mem_load(addr, width as usize, false) {
+                    Some((data, 0)) => {
+                        let mut bytes = [0u8; 8];
+                        bytes[..width as usize].copy_from_slice(data);
+                        self.float.write(rd, width, u64::from_le_bytes(bytes));
+                        Ok(())
+                    },
+                    _ => Err(format!("Cannot access memory at {:x}", addr)),
+                },
+                InstCode::Fs(_, rs2, _, width) => {
+                    let val = self.float.regs[rs2 as usize];
+                    if self.mem_store(addr, &val.to_le_bytes()[..width as usize]) {
+                        Ok(())
+                    } else {
+                        Err(format!("Cannot access memory at {:x}", addr))
+                    }
+                },
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn s(val: f32) -> u64 {
+            val.to_bits() as u64
+        }
+
+        fn d(val: f64) -> u64 {
+            val.to_bits()
+        }
+
+        /// Results of the round-to-nearest operations agree with the host.
+        #[test]
+        fn nearest() {
+            let vals = [0.0, -0.0, 1.0, -1.5, 3.0, 0.1, 1e-310, 2.5e-308, 1e300, -7.25, f64::MAX, f64::MIN_POSITIVE];
+            for a in vals {
+                for b in vals {
+                    let mut flags = 0;
+                    assert_eq!(DOUBLE.add(d(a), d(b), RNE, &mut flags), d(a + b), "{} + {}", a, b);
+                    assert_eq!(DOUBLE.mul(d(a), d(b), RNE, &mut flags), d(a * b), "{} * {}", a, b);
+                    if b != 0.0 {
+                        assert_eq!(DOUBLE.div(d(a), d(b), RNE, &mut flags), d(a / b), "{} / {}", a, b);
+                    }
+                    assert_eq!(DOUBLE.fma(d(a), d(b), d(1.0), (false, false), RNE, &mut flags),
+                        d(a.mul_add(b, 1.0)), "{} * {} + 1", a, b);
+                    let (a, b) = (a as f32, b as f32);
+                    assert_eq!(SINGLE.add(s(a), s(b), RNE, &mut flags), s(a + b), "{} + {}", a, b);
+                }
+                let mut flags = 0;
+                assert_eq!(DOUBLE.sqrt(d(a.abs()), RNE, &mut flags), d(a.abs().sqrt()), "sqrt {}", a);
+                assert_eq!(SINGLE.convert(DOUBLE, d(a), RNE, &mut flags), s(a as f32), "{} as f32", a);
+            }
+        }
+
+        #[test]
+        fn rounding_modes() {
+            let third = |rm| {
+                let mut flags = 0;
+                let val = SINGLE.div(s(1.0), s(3.0), rm, &mut flags);
+                assert_eq!(flags, NX);
+                val
+            };
+            // The nearest single is above 1/3
+            assert_eq!(third(RNE), s(1.0 / 3.0));
+            assert_eq!(third(RMM), s(1.0 / 3.0));
+            assert_eq!(third(RUP), s(1.0 / 3.0));
+            assert_eq!(third(RTZ), s(1.0 / 3.0) - 1);
+            assert_eq!(third(RDN), s(1.0 / 3.0) - 1);
+
+            // 2.5 to integers, and ties away from zero
+            let mut flags = 0;
+            let int = |rm, flags: &mut u8| DOUBLE.to_int(d(2.5), true, 32, rm, flags);
+            assert_eq!([RNE, RTZ, RDN, RUP, RMM].map(|rm| int(rm, &mut flags)), [2, 2, 2, 3, 3]);
+            assert_eq!(flags, NX);
+            assert_eq!(DOUBLE.to_int(d(-2.5), true, 64, RDN, &mut flags) as i64, -3);
+
+            // x - x is -0 only when rounding down
+            assert_eq!(DOUBLE.add(d(1.0), d(-1.0), RNE, &mut flags), d(0.0));
+            assert_eq!(DOUBLE.add(d(1.0), d(-1.0), RDN, &mut flags), d(-0.0));
+        }
+
+        #[test]
+        fn exceptions() {
+            let mut flags = 0;
+            assert_eq!(DOUBLE.div(d(1.0), d(0.0), RNE, &mut flags), d(f64::INFINITY));
+            assert_eq!(flags, DZ);
+
+            let mut flags = 0;
+            assert_eq!(DOUBLE.add(d(f64::INFINITY), d(f64::NEG_INFINITY), RNE, &mut flags), DOUBLE.canonical_nan());
+            assert_eq!(flags, NV);
+
+            // Overflow rounds to the largest finite number towards zero
+            let mut flags = 0;
+            assert_eq!(DOUBLE.mul(d(f64::MAX), d(2.0), RTZ, &mut flags), d(f64::MAX));
+            assert_eq!(flags, OF | NX);
+
+            let mut flags = 0;
+            assert_eq!(DOUBLE.mul(d(f64::MIN_POSITIVE), d(0.75), RNE, &mut flags), d(f64::MIN_POSITIVE * 0.75));
+            assert_eq!(flags, 0);
+            DOUBLE.mul(d(f64::MIN_POSITIVE), d(1.0 / 3.0), RNE, &mut flags);
+            assert_eq!(flags, UF | NX);
+
+            // Signaling NaNs are invalid even in quiet comparisons
+            let mut flags = 0;
+            let snan = SINGLE.canonical_nan() ^ 0x00600000;
+            assert!(SINGLE.compare(snan, s(1.0)).is_none());
+            assert_eq!(SINGLE.propagate(&[s(1.0), snan], &mut flags), Some(SINGLE.canonical_nan()));
+            assert_eq!(flags, NV);
+        }
+
+        #[test]
+        fn conversions() {
+            let mut flags = 0;
+            assert_eq!(SINGLE.to_int(s(f32::NAN), true, 32, RNE, &mut flags), i32::MAX as u64);
+            assert_eq!(SINGLE.to_int(s(-1e10), true, 32, RNE, &mut flags), i32::MIN as i64 as u64);
+            assert_eq!(SINGLE.to_int(s(-1.0), false, 64, RNE, &mut flags), 0);
+            assert_eq!(flags, NV);
+            // Unsigned words are sign-extended too
+            assert_eq!(SINGLE.to_int(s(4e9), false, 32, RNE, &mut flags), 4_000_000_000u32 as i32 as i64 as u64);
+            assert_eq!(DOUBLE.convert_int(u64::MAX, false, 64, RNE, &mut flags), d(u64::MAX as f64));
+            assert_eq!(SINGLE.convert_int(-7i64 as u64, true, 32, RNE, &mut flags), s(-7.0));
+        }
+
+        #[test]
+        fn min_max_and_class() {
+            let mut flags = 0;
+            assert_eq!(DOUBLE.min_max(d(-0.0), d(0.0), false, &mut flags), d(-0.0));
+            assert_eq!(DOUBLE.min_max(d(-0.0), d(0.0), true, &mut flags), d(0.0));
+            assert_eq!(DOUBLE.min_max(d(f64::NAN), d(2.0), false, &mut flags), d(2.0));
+            assert_eq!(flags, 0);
+            assert_eq!(DOUBLE.class(d(f64::NEG_INFINITY)), 1 << 0);
+            assert_eq!(DOUBLE.class(d(-0.0)), 1 << 3);
+            assert_eq!(DOUBLE.class(d(1e-310)), 1 << 5);
+            assert_eq!(DOUBLE.class(DOUBLE.canonical_nan()), 1 << 9);
+        }
+
+        #[test]
+        fn nan_boxing() {
+            let mut unit = FloatUnit::new();
+            unit.write(1, 4, s(1.5));
+            assert_eq!(unit.regs[1], 0xFFFFFFFF00000000 | s(1.5));
+            assert_eq!(unit.read(1, 4), s(1.5));
+            unit.write(2, 8, d(1.5));
+            assert_eq!(unit.read(2, 4), SINGLE.canonical_nan());
+        }
+    }
+}
@@ -0,0 +1,437 @@
+#![allow(clippy::module_inception)]
+#[allow(dead_code)]
+pub mod history {
+    //! This module implements the execution history used for reverse
+    //! debugging. Every retired instruction appends an undo entry holding
+    //! the PC, and the old values of the registers, counters, vector and
+    //! floating-point registers and memory it wrote, to a bounded ring
+    //! buffer. Snapshots of the whole machine are also taken periodically,
+    //! so that instructions that fell off the ring can still be reached by
+    //! restoring a snapshot and replaying forward.
+    //!
+    //! System calls of the Linux process are not undone, as they act on
+    //! the host and change the mappings: the history is cut after each,
+    //! so that reverse execution stops there, and replaying never runs a
+    //! system call twice.
+    //!
+    //! There is no GDB remote stub, so reverse execution is only exposed
+    //! through the `rsi` and `rc` commands of the built-in debugger.
+
+    use std::collections::VecDeque;
+    use crate::{FloatUnit, Program, Snapshot};
+
+    /// The changes of one retired instruction.
+    #[derive(Debug)]
+    struct Entry {
+        /// PC of the instruction
+        pc: u64,
+        /// (register index, old value)
+        registers: Vec<(usize, u64)>,
+        /// (counter index, old value)
+        counters: Vec<(usize, u64)>,
+        /// (address, old bytes), in the order of the writes
+        memory: Vec<(u64, Vec<u8>)>,
+        /// The old depth of the call stack and the frames popped from
+        /// it, if changed
+        call_stack: Option<(usize, Vec<(u64, u64)>)>,
+        /// (byte offset in the vector register file, old bytes)
+        vregs: Vec<(usize, Vec<u8>)>,
+        /// The old vl, vtype and vill, if changed
+        vconfig: Option<(u64, u64, bool)>,
+        /// The old floating-point registers and CSRs, if changed
+        float: Option<FloatUnit>,
+        /// The old LR/SC reservation, if changed
+        reservation: Option<Option<u64>>,
+    }
+
+    /// (pc, registers, counters, call stack depth, vector configuration,
+    /// floating-point registers and CSRs, reservation)
+    type State = (u64, [u64; 32], [u64; 32], usize, (u64, u64, bool), FloatUnit, Option<u64>);
+
+    /// Execution history of a `Program`.
+    #[derive(Debug)]
+    pub struct History {
+        /// Maximal number of entries in `entries`.
+        capacity: usize,
+        entries: VecDeque<Entry>,
+        /// Take a snapshot every `interval` instructions.
+        interval: u64,
+        /// Maximal number of snapshots kept.
+        max_snapshots: usize,
+        /// (instruction count, snapshot), oldest first
+        snapshots: VecDeque<(u64, Snapshot)>,
+        /// Number of instructions retired.
+        icount: u64,
+        /// State before the instruction being executed.
+        before: Option<State>,
+        /// Memory writes of the instruction being executed.
+        writes: Vec<(u64, Vec<u8>)>,
+        /// Vector register writes of the instruction being executed.
+        vregs: Vec<(usize, Vec<u8>)>,
+        /// Call stack frames popped by the instruction being executed.
+        popped: Vec<(u64, u64)>,
+        /// Whether the instruction being executed made a system call.
+        syscall: bool,
+        /// (instruction count, PC) after the last system call, before
+        /// which there is no history.
+        barrier: Option<(u64, u64)>,
+    }
+
+    impl History {
+        pub fn new(capacity: usize, interval: u64, max_snapshots: usize) -> Self {
+            History {
+                capacity,
+                entries: VecDeque::new(),
+                interval: std::cmp::max(interval, 1),
+                max_snapshots,
+                snapshots: VecDeque::new(),
+                icount: 0,
+                before: None,
+                writes: Vec::new(),
+                vregs: Vec::new(),
+                popped: Vec::new(),
+                syscall: false,
+                barrier: None,
+            }
+        }
+
+        /// Number of instructions retired.
+        pub fn icount(&self) -> u64 {
+            self.icount
+        }
+
+        /// Record the old content of memory about to be overwritten.
+        pub fn record_write(&mut self, addr: u64, old: &[u8]) {
+            if self.before.is_some() {
+                self.writes.push((addr, old.to_vec()));
+            }
+        }
+
+        /// Record the frames popped from the top of the call stack.
+        pub fn record_pop(&mut self, frames: &[(u64, u64)]) {
+            if self.before.is_some() {
+                self.popped.splice(0..0, frames.iter().copied());
+            }
+        }
+
+        /// Record that the instruction being executed makes a system call.
+        pub fn record_syscall(&mut self) {
+            if self.before.is_some() {
+                self.syscall = true;
+            }
+        }
+
+        /// PC of the system call that ends the history, if it is reached.
+        pub fn barrier(&self) -> Option<u64> {
+            match self.barrier {
+                Some((icount, pc)) if icount == self.icount => Some(pc),
+                _ => None,
+            }
+        }
+
+        /// Record the old content of the vector registers about to be overwritten.
+        fn record_vregs(&mut self, offset: usize, old: &[u8]) {
+            if self.before.is_some() {
+                self.vregs.push((offset, old.to_vec()));
+            }
+        }
+
+        /// The newest snapshot taken at or before `icount`.
+        pub fn snapshot_before(&self, icount: u64) -> Option<(u64, Snapshot)> {
+            self.snapshots.iter().rev()
+                .find(|(at, _)| *at <= icount)
+                .map(|(at, snapshot)| (*at, snapshot.clone()))
+        }
+    }
+
+    impl Program {
+        /// Start recording the instruction at the PC.
+        pub fn record_begin(&mut self) {
+            let mut registers = [0u64; 32];
+            for (idx, reg) in self.registers.registers.iter().enumerate() {
+                registers[idx] = reg.value;
+            }
+            // Also right after a system call, where the history restarts
+            let snapshot = match self.history.as_ref() {
+                Some(history) if (history.icount % history.interval == 0 || history.snapshots.is_empty()) &&
+                    !history.snapshots.iter().any(|(at, _)| *at == history.icount) => Some(self.snapshot()),
+                Some(_) => None,
+                None => return,
+            };
+
+            let history = self.history.as_mut().unwrap();
+            if let Some(snapshot) = snapshot {
+                history.snapshots.push_back((history.icount, snapshot));
+                if history.snapshots.len() > history.max_snapshots {
+                    history.snapshots.pop_front();
+                }
+            }
+            let vconfig = (self.vector.vl, self.vector.vtype, self.vector.vill);
+            history.before = Some((self.program_counter, registers, self.counters.values, self.call_stack.len(), vconfig, self.float, self.reservation));
+            history.writes.clear();
+            history.vregs.clear();
+            history.popped.clear();
+            history.syscall = false;
+        }
+
+        /// Record vector registers `first` to `first + count - 1` before
+        /// they are written.
+        pub fn record_vregs(&mut self, first: u8, count: usize) {
+            if let Some(history) = self.history.as_mut() {
+                let vlenb = self.vector.vlenb();
+                let start = first as usize * vlenb;
+                let end = std::cmp::min(start + count * vlenb, self.vector.regs.len());
+                history.record_vregs(start, &self.vector.regs[start..end]);
+            }
+        }
+
+        /// Finish recording the instruction started by `record_begin`.
+        pub fn record_commit(&mut self) {
+            let history = match self.history.as_mut() {
+                Some(history) => history,
+                None => return,
+            };
+            let (pc, before, counters, depth, vconfig, float, reservation) = match history.before.take() {
+                Some(before) => before,
+                None => return,
+            };
+            history.icount += 1;
+            if history.syscall {
+                // Nothing before the system call can be undone or replayed
+                history.entries.clear();
+                history.snapshots.clear();
+                history.barrier = Some((history.icount, pc));
+                return;
+            }
+
+            let registers = self.registers.registers.iter().enumerate()
+                .filter(|(idx, reg)| reg.value != before[*idx])
+                .map(|(idx, _)| (idx, before[idx]))
+                .collect();
+            let counters = self.counters.values.iter().enumerate()
+                .filter(|(idx, val)| **val != counters[*idx])
+                .map(|(idx, _)| (idx, counters[idx]))
+                .collect();
+            let call_stack = if depth != self.call_stack.len() || !history.popped.is_empty() {
+                Some((depth, std::mem::take(&mut history.popped)))
+            } else {
+                None
+            };
+            let vconfig = if vconfig != (self.vector.vl, self.vector.vtype, self.vector.vill) { Some(vconfig) } else { None };
+            let float = if float != self.float { Some(float) } else { None };
+            let reservation = if reservation != self.reservation { Some(reservation) } else { None };
+
+            history.entries.push_back(Entry {
+                pc,
+                registers,
+                counters,
+                memory: std::mem::take(&mut history.writes),
+                call_stack,
+                vregs: std::mem::take(&mut history.vregs),
+                vconfig,
+                float,
+                reservation,
+            });
+            if history.entries.len() > history.capacity {
+                history.entries.pop_front();
+            }
+        }
+
+        /// Undo the last retired instruction. Returns false if it is no
+        /// longer in the ring buffer.
+        pub fn step_back(&mut self) -> bool {
+            let entry = match self.history.as_mut().and_then(|history| history.entries.pop_back()) {
+                Some(entry) => entry,
+                None => return false,
+            };
+
+            self.program_counter = entry.pc;
+            for (idx, val) in entry.registers {
+                self.registers.registers[idx].write(val);
+            }
+            for (idx, val) in entry.counters {
+                self.counters.values[idx] = val;
+            }
+            // Undo the writes newest first, bypassing permission checks
+            for (addr, old) in entry.memory.iter().rev() {
+                if let Some(vma) = self.vmas.iter_mut()
+                    .find(|v| v.lower_bound <= *addr && v.lower_bound + v.size > *addr)
+                {
+                    let start = (addr - vma.lower_bound) as usize;
+                    vma.memory[start..start + old.len()].copy_from_slice(old);
+                }
+            }
+            // Drop the pushed frames and push back the popped ones
+            if let Some((depth, popped)) = entry.call_stack {
+                self.call_stack.truncate(depth.saturating_sub(popped.len()));
+                self.call_stack.extend(popped);
+            }
+            for (offset, old) in entry.vregs.iter().rev() {
+                self.vector.regs[*offset..*offset + old.len()].copy_from_slice(old);
+            }
+            if let Some((vl, vtype, vill)) = entry.vconfig {
+                self.vector.vl = vl;
+                self.vector.vtype = vtype;
+                self.vector.vill = vill;
+            }
+            if let Some(float) = entry.float {
+                self.float = float;
+            }
+            if let Some(reservation) = entry.reservation {
+                self.reservation = reservation;
+            }
+
+            // Snapshots of the undone future are stale once the state is changed
+            let history = self.history.as_mut().unwrap();
+            history.icount -= 1;
+            let icount = history.icount;
+            history.snapshots.retain(|(at, _)| *at <= icount);
+            true
+        }
+
+        /// Restore the newest snapshot at or before `icount`, keeping the
+        /// current breakpoints and watchpoints. The ring buffer is discarded, as it will be
+        /// filled again by replaying. Returns the instruction count of the
+        /// snapshot.
+        pub fn rewind_to_snapshot(&mut self, icount: u64) -> Option<u64> {
+            let (at, snapshot) = self.history.as_ref()?.snapshot_before(icount)?;
+            let breakpoints = self.breakpoints.clone();
+            let watchpoints = self.watchpoints.clone();
+            self.restore(&snapshot).ok()?;
+            self.breakpoints = breakpoints;
+            self.watchpoints = watchpoints;
+
+            let history = self.history.as_mut().unwrap();
+            history.entries.clear();
+            history.snapshots.retain(|(icount, _)| *icount <= at);
+            history.icount = at;
+            Some(at)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::{Assembler, Inst, Linux};
+
+        /// Stores, register writes, a call and a return, then a system
+        /// call.
+        const SOURCE: &str = "
+                    .data
+            val:    .dword 7
+                    .text
+            main:   la a0, val
+                    li t0, 42
+                    sd t0, 0(a0)
+                    call helper
+                    li a7, 172
+                    ecall
+                    addi a1, a1, 1
+            helper: sb t0, 1(a0)
+                    li a1, 5
+                    ret";
+
+        /// (pc, registers, the doubleword at val, call stack)
+        type Observed = (u64, Vec<u64>, Vec<u8>, Vec<(u64, u64)>);
+
+        fn observe(prog: &Program) -> Observed {
+            let registers = prog.registers.registers.iter().map(|reg| reg.value).collect();
+            let val = prog.mem_load(0x11000, 8, false).unwrap().0.to_vec();
+            (prog.program_counter, registers, val, prog.call_stack.clone())
+        }
+
+        fn program(capacity: usize, interval: u64) -> Program {
+            let mut prog = Assembler::new(SOURCE).load().unwrap();
+            prog.linux = Some(Linux::new("/", "a.out", 0x10000, 0x3ff0000000));
+            prog.history = Some(History::new(capacity, interval, 16));
+            prog
+        }
+
+        /// Run one instruction while recording it.
+        fn step(prog: &mut Program) {
+            prog.record_begin();
+            let mut inst = Inst::new();
+            prog.program_counter = loop {
+                match inst.advance(prog) {
+                    Ok(next) => inst = next,
+                    Err(next_pc) => break next_pc,
+                }
+            };
+            prog.record_commit();
+        }
+
+        /// The states before each of the first `count` instructions, and
+        /// the one after them.
+        fn run(prog: &mut Program, count: usize) -> Vec<Observed> {
+            let mut states = vec![observe(prog)];
+            for _ in 0..count {
+                step(prog);
+                states.push(observe(prog));
+            }
+            states
+        }
+
+        fn icount(prog: &Program) -> u64 {
+            prog.history.as_ref().unwrap().icount()
+        }
+
+        #[test]
+        fn step_back() {
+            // Up to the return from helper
+            let mut prog = program(100, 1000);
+            let mut states = run(&mut prog, 9);
+            assert_eq!(prog.program_counter, 0x10018);
+            assert_eq!(states[6].3.len(), 2);
+            assert_eq!(states[8].2, [42, 42, 0, 0, 0, 0, 0, 0]);
+
+            states.pop();
+            while let Some(state) = states.pop() {
+                assert!(prog.step_back());
+                assert_eq!(observe(&prog), state);
+                assert_eq!(icount(&prog), states.len() as u64);
+            }
+            assert!(!prog.step_back());
+        }
+
+        /// Instructions that fell off the ring buffer are reached from a
+        /// snapshot, by replaying forward.
+        #[test]
+        fn rewind_to_snapshot() {
+            let mut prog = program(2, 4);
+            let states = run(&mut prog, 9);
+            assert!(prog.step_back() && prog.step_back());
+            assert!(!prog.step_back());
+            assert_eq!(observe(&prog), states[7]);
+
+            assert_eq!(prog.rewind_to_snapshot(6), Some(4));
+            assert_eq!(observe(&prog), states[4]);
+            assert_eq!(icount(&prog), 4);
+            step(&mut prog);
+            step(&mut prog);
+            assert_eq!(observe(&prog), states[6]);
+
+            // Snapshots after a rewound instruction are dropped
+            assert!(prog.step_back());
+            assert_eq!(prog.rewind_to_snapshot(100), Some(4));
+            assert_eq!(prog.rewind_to_snapshot(3), Some(0));
+            assert_eq!(observe(&prog), states[0]);
+        }
+
+        /// Nothing before a system call is undone.
+        #[test]
+        fn syscall_barrier() {
+            let mut prog = program(100, 4);
+            let states = run(&mut prog, 12);
+            assert_eq!(prog.history.as_ref().unwrap().barrier(), None);
+            assert!(prog.step_back());
+            assert_eq!(observe(&prog), states[11]);
+            assert_eq!(prog.history.as_ref().unwrap().barrier(), Some(0x1001c));
+            assert!(!prog.step_back());
+            assert_eq!(prog.rewind_to_snapshot(10), None);
+
+            // The history restarts after the system call
+            assert_eq!(prog.rewind_to_snapshot(11), Some(11));
+            assert_eq!(observe(&prog), states[11]);
+        }
+    }
+}
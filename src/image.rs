@@ -0,0 +1,418 @@
+#![allow(clippy::module_inception)]
+#[allow(dead_code)]
+pub mod image {
+    //! This module loads the program images that are not ELF executables,
+    //! as shipped for firmware and bare-metal targets: raw binaries placed
+    //! at a base address, Intel HEX and Motorola S-record files. Such
+    //! images carry no symbols and at most a start address, so the
+    //! permissions of their memory and the entry point are up to the user.
+    //! ELF executables and assembly sources are dispatched to `Loader`
+    //! and `Assembler`.
+
+    use std::{fmt, collections::HashMap};
+    use std::str::FromStr;
+    use crate::{SimError, VMA, Program, Loader, Assembler, Isa, HLT_ADDR};
+
+    /// Data to load: (address, data)
+    pub type Segments = Vec<(u64, Vec<u8>)>;
+
+    /// Format of a program image
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ImageFormat {
+        Elf,
+        /// Raw binary, loaded at the base address
+        Binary,
+        IntelHex,
+        SRecord,
+        /// Assembly source
+        Asm,
+    }
+
+    impl ImageFormat {
+        /// Guess the format from the extension of `path`, defaulting to ELF.
+        pub fn guess(path: &str) -> Self {
+            let ext = path.rsplit_once('.').map_or("", |(_, ext)| ext);
+            match ext.to_lowercase().as_str() {
+                "bin" | "img" => ImageFormat::Binary,
+                "hex" | "ihex" | "ihx" => ImageFormat::IntelHex,
+                "srec" | "s19" | "s28" | "s37" | "mot" => ImageFormat::SRecord,
+                // Not `.s`, which is the disassembly of the benchmarks
+                "asm" => ImageFormat::Asm,
+                _ if ext == "S" => ImageFormat::Asm,
+                _ => ImageFormat::Elf,
+            }
+        }
+    }
+
+    impl FromStr for ImageFormat {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "elf" => Ok(ImageFormat::Elf),
+                "bin" => Ok(ImageFormat::Binary),
+                "ihex" => Ok(ImageFormat::IntelHex),
+                "srec" => Ok(ImageFormat::SRecord),
+                "asm" => Ok(ImageFormat::Asm),
+                _ => Err(format!("unknown format `{}`, expected elf, bin, ihex, srec or asm", s)),
+            }
+        }
+    }
+
+    impl fmt::Display for ImageFormat {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                ImageFormat::Elf => write!(f, "elf"),
+                ImageFormat::Binary => write!(f, "bin"),
+                ImageFormat::IntelHex => write!(f, "ihex"),
+                ImageFormat::SRecord => write!(f, "srec"),
+                ImageFormat::Asm => write!(f, "asm"),
+            }
+        }
+    }
+
+    /// Permissions of the memory of an image, as in `rwx` or `r-x`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Permissions {
+        pub readable: bool,
+        pub writeble: bool,
+        pub executable: bool,
+    }
+
+    impl FromStr for Permissions {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let mut perms = Permissions { readable: false, writeble: false, executable: false };
+            for c in s.chars() {
+                match c {
+                    'r' => perms.readable = true,
+                    'w' => perms.writeble = true,
+                    'x' => perms.executable = true,
+                    '-' => {},
+                    _ => return Err(format!("bad permissions `{}`, expected a combination of r, w and x", s)),
+                }
+            }
+            Ok(perms)
+        }
+    }
+
+    impl fmt::Display for Permissions {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{}{}{}",
+                if self.readable { 'r' } else { '-' },
+                if self.writeble { 'w' } else { '-' },
+                if self.executable { 'x' } else { '-' })
+        }
+    }
+
+    /// Pseudo-struct for loading a program image of any format.
+    pub struct ImageLoader {
+        format: ImageFormat,
+        path: String,
+        /// Load address of raw binaries
+        base: u64,
+        perms: Permissions,
+        /// Overrides the entry point of the image
+        entry: Option<u64>,
+        /// Root of the target filesystem, for dynamically linked ELF executables
+        sysroot: Option<String>,
+        /// ISA of the hart, else detected from ELF executables
+        isa: Option<Isa>,
+    }
+
+    impl ImageLoader {
+        /// Where raw binaries go by default, the start of DRAM on the
+        /// common RISC-V boards and emulators.
+        pub const DEFAULT_BASE: u64 = 0x80000000u64;
+        /// Images whose segments are closer than this are loaded as one VMA.
+        pub const MAX_GAP: u64 = 0x1000u64;
+
+        pub fn new(format: ImageFormat, path: &str, base: u64, perms: Permissions, entry: Option<u64>,
+            sysroot: Option<&str>, isa: Option<Isa>) -> Self {
+            ImageLoader {
+                format,
+                path: String::from(path),
+                base,
+                perms,
+                entry,
+                sysroot: sysroot.map(String::from),
+                isa,
+            }
+        }
+
+        pub fn load(&self) -> Result<Program, SimError> {
+            let mut prog = match self.format {
+                ImageFormat::Elf => {
+                    let loader = Loader::new(self.isa.clone(), &self.path);
+                    match &self.sysroot {
+                        Some(sysroot) => loader.with_sysroot(sysroot).load()?,
+                        None => loader.load()?,
+                    }
+                },
+                ImageFormat::Asm => {
+                    let source = std::fs::read_to_string(&self.path).map_err(SimError::IOError)?;
+                    let xlen = self.isa.as_ref().map_or(Isa::default().xlen, |isa| isa.xlen);
+                    Assembler::new(&source).with_xlen(xlen).load()?
+                },
+                _ => {
+                    let (segments, start) = match self.format {
+                        ImageFormat::Binary => {
+                            let data = std::fs::read(&self.path).map_err(SimError::IOError)?;
+                            (vec![(self.base, data)], None)
+                        },
+                        ImageFormat::IntelHex => parse_ihex(&std::fs::read_to_string(&self.path).map_err(SimError::IOError)?)?,
+                        _ => parse_srec(&std::fs::read_to_string(&self.path).map_err(SimError::IOError)?)?,
+                    };
+                    self.program(segments, start)?
+                },
+            };
+
+            if let Some(entry) = self.entry {
+                prog.entry_point = entry;
+                prog.program_counter = entry;
+                prog.call_stack = vec![(entry, HLT_ADDR)];
+            }
+            if let Some(isa) = &self.isa {
+                prog.isa = isa.clone();
+            }
+            Ok(prog)
+        }
+
+        /// Map `segments` with the permissions of the image,
+        /// starting at the entry point given, else at `start`, else at the
+        /// lowest address.
+        fn program(&self, segments: Segments, start: Option<u64>) -> Result<Program, SimError> {
+            let stack = Loader::STACK_BOTTOM - Loader::STACK_SIZE..Loader::STACK_BOTTOM;
+            let mut vmas: Vec<VMA> = Vec::new();
+            for (addr, data) in coalesce(segments) {
+                let end = addr.checked_add(data.len() as u64)
+                    .ok_or(SimError::ImageError(format!("segment at {:#x} wraps around", addr)))?;
+                if addr < stack.end && stack.start < end {
+                    return Err(SimError::ImageError(format!("segment {:#x} ~ {:#x} overlaps the stack", addr, end)));
+                }
+                vmas.push(VMA {
+                    lower_bound: addr,
+                    size: data.len() as u64,
+                    readable: self.perms.readable,
+                    writeble: self.perms.writeble,
+                    executable: self.perms.executable,
+                    memory: data,
+                });
+            }
+            if vmas.is_empty() {
+                return Err(SimError::ImageError(String::from("empty image")));
+            }
+
+            let entry_point = self.entry.or(start).unwrap_or(vmas[0].lower_bound);
+            Ok(Loader::program(entry_point, vmas, HashMap::new(), Vec::new(), None))
+        }
+    }
+
+    /// Sort `segments` and merge the ones closer than `MAX_GAP`, filling
+    /// the gaps with zeros. Later data wins where segments overlap.
+    fn coalesce(mut segments: Segments) -> Segments {
+        segments.retain(|(_, data)| !data.is_empty());
+        segments.sort_by_key(|(addr, _)| *addr);
+        let mut merged: Segments = Vec::new();
+        for (addr, data) in segments {
+            match merged.last_mut() {
+                Some((start, memory)) if addr <= *start + memory.len() as u64 + ImageLoader::MAX_GAP => {
+                    let ofs = (addr - *start) as usize;
+                    if memory.len() < ofs + data.len() {
+                        memory.resize(ofs + data.len(), 0);
+                    }
+                    memory[ofs..ofs + data.len()].copy_from_slice(&data);
+                },
+                _ => merged.push((addr, data)),
+            }
+        }
+        merged
+    }
+
+    /// The bytes of the hex digits in `src`.
+    fn hex_bytes(src: &str) -> Result<Vec<u8>, String> {
+        if !src.len().is_multiple_of(2) || !src.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("bad hex digits `{}`", src));
+        }
+        Ok((0..src.len()).step_by(2)
+            .map(|idx| u8::from_str_radix(&src[idx..idx+2], 16).unwrap())
+            .collect())
+    }
+
+    fn be(bytes: &[u8]) -> u64 {
+        bytes.iter().fold(0u64, |val, &byte| (val << 8) | byte as u64)
+    }
+
+    /// Parse Intel HEX records into segments and the start address, if any.
+    pub fn parse_ihex(text: &str) -> Result<(Segments, Option<u64>), SimError> {
+        let mut segments: Segments = Vec::new();
+        let mut start = None;
+        let mut upper = 0u64;
+
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |e: String| SimError::ImageError(format!("line {}: {}", idx + 1, e));
+            let record = line.strip_prefix(':').ok_or(error(String::from("expected `:`")))?;
+            let bytes = hex_bytes(record).map_err(error)?;
+            if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+                return Err(error(String::from("bad record length")));
+            }
+            if bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) != 0 {
+                return Err(error(String::from("bad checksum")));
+            }
+            let addr = be(&bytes[1..3]);
+            let data = &bytes[4..bytes.len()-1];
+            let expect = |len: usize| if data.len() == len { Ok(()) } else { Err(error(String::from("bad record length"))) };
+
+            match bytes[3] {
+                0x00 => {
+                    // Extend the last segment if contiguous
+                    match segments.last_mut() {
+                        Some((start, memory)) if *start + memory.len() as u64 == upper + addr => memory.extend_from_slice(data),
+                        _ => segments.push((upper + addr, data.to_vec())),
+                    }
+                },
+                0x01 => break,
+                0x02 => {
+                    expect(2)?;
+                    upper = be(data) << 4;
+                },
+                0x03 => {
+                    expect(4)?;
+                    start = Some((be(&data[..2]) << 4) + be(&data[2..]));
+                },
+                0x04 => {
+                    expect(2)?;
+                    upper = be(data) << 16;
+                },
+                0x05 => {
+                    expect(4)?;
+                    start = Some(be(data));
+                },
+                kind => return Err(error(format!("unknown record type {:02x}", kind))),
+            }
+        }
+        Ok((segments, start))
+    }
+
+    /// Parse Motorola S-records into segments and the start address, if any.
+    pub fn parse_srec(text: &str) -> Result<(Segments, Option<u64>), SimError> {
+        let mut segments: Segments = Vec::new();
+        let mut start = None;
+
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |e: String| SimError::ImageError(format!("line {}: {}", idx + 1, e));
+            let record = line.strip_prefix('S').ok_or(error(String::from("expected `S`")))?;
+            let kind = record.chars().next().ok_or(error(String::from("missing record type")))?;
+            if !kind.is_ascii_digit() {
+                return Err(error(format!("unknown record type S{}", kind)));
+            }
+            let bytes = hex_bytes(&record[1..]).map_err(error)?;
+            if bytes.is_empty() || bytes.len() != bytes[0] as usize + 1 {
+                return Err(error(String::from("bad record length")));
+            }
+            if bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) != 0xFF {
+                return Err(error(String::from("bad checksum")));
+            }
+            let addr_len = match kind {
+                '0' | '1' | '5' | '9' => 2,
+                '2' | '6' | '8' => 3,
+                '3' | '7' => 4,
+                _ => return Err(error(format!("unknown record type S{}", kind))),
+            };
+            if bytes.len() < addr_len + 2 {
+                return Err(error(String::from("bad record length")));
+            }
+            let addr = be(&bytes[1..1+addr_len]);
+            let data = &bytes[1+addr_len..bytes.len()-1];
+
+            match kind {
+                '1' | '2' | '3' => {
+                    match segments.last_mut() {
+                        Some((start, memory)) if *start + memory.len() as u64 == addr => memory.extend_from_slice(data),
+                        _ => segments.push((addr, data.to_vec())),
+                    }
+                },
+                '7' | '8' | '9' => start = Some(addr),
+                // Header and record counts
+                _ => {},
+            }
+        }
+        Ok((segments, start))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        /// The message of the error `result` fails with.
+        fn error<T>(result: Result<T, SimError>) -> String {
+            match result {
+                Err(SimError::ImageError(e)) => e,
+                Err(e) => panic!("unexpected error {:?}", e),
+                Ok(_) => panic!("no error"),
+            }
+        }
+
+        #[test]
+        fn ihex() {
+            let text = "
+                :020000040800F2
+                :0400000013050500DF
+                :02000400678013
+                :020000021000EC
+                :01001000AA45
+                :0400000508000004EB
+                :00000001FF
+                garbage after the end";
+            let (segments, start) = parse_ihex(text).unwrap();
+            assert_eq!(segments, vec![(0x08000000, vec![0x13, 0x05, 0x05, 0x00, 0x67, 0x80]), (0x10010, vec![0xAA])]);
+            assert_eq!(start, Some(0x08000004));
+
+            // Segment start address, CS:IP
+            assert_eq!(parse_ihex(":0400000310000100E8").unwrap().1, Some(0x10100));
+
+            assert_eq!(error(parse_ihex(":0400000013050500DE")), "line 1: bad checksum");
+            assert_eq!(error(parse_ihex(":0100000013050500DF")), "line 1: bad record length");
+            assert_eq!(error(parse_ihex(":0100000313E9")), "line 1: bad record length");
+            assert_eq!(error(parse_ihex("\n:00000006FA")), "line 2: unknown record type 06");
+            assert_eq!(error(parse_ihex("0400000013050500DF")), "line 1: expected `:`");
+            assert!(error(parse_ihex(":0é000000FF")).starts_with("line 1: bad hex digits"));
+        }
+
+        #[test]
+        fn srec() {
+            let text = "
+                S0030000FC
+                S107100013050500CB
+                S30680000000AACF
+                S705800000007A";
+            let (segments, start) = parse_srec(text).unwrap();
+            assert_eq!(segments, vec![(0x1000, vec![0x13, 0x05, 0x05, 0x00]), (0x80000000, vec![0xAA])]);
+            assert_eq!(start, Some(0x80000000));
+
+            assert_eq!(error(parse_srec("S107100013050500CC")), "line 1: bad checksum");
+            assert_eq!(error(parse_srec("S108100013050500CB")), "line 1: bad record length");
+            assert_eq!(error(parse_srec("S4030000FC")), "line 1: unknown record type S4");
+            assert_eq!(error(parse_srec("Sé030000FC")), "line 1: unknown record type Sé");
+            assert_eq!(error(parse_srec("107100013050500CB")), "line 1: expected `S`");
+        }
+
+        #[test]
+        fn merge() {
+            let segments = vec![(0x1010, vec![3]), (0x1000, vec![1, 2]), (0x1001, vec![4]), (0x100000, vec![5])];
+            let mut expected = vec![0; 0x11];
+            expected[..2].copy_from_slice(&[1, 4]);
+            expected[0x10] = 3;
+            assert_eq!(coalesce(segments), vec![(0x1000, expected), (0x100000, vec![5])]);
+        }
+    }
+}
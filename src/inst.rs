@@ -7,16 +7,19 @@ pub mod inst {
     //! This module implements all supported RV64I instructions, 
     //! including their parsing and executing logic.
     
-    use crate::{SimError, RegID, Program, HLT_ADDR};
+    use crate::{SimError, RegID, Program, Extension, Counters, VSrc, HLT_ADDR};
+    use crate::counters::counters::counter_name;
+    use crate::vector::vector::vector_csr_name;
+    use crate::float::float::float_csr_name;
     use quark::Signs;
     use std::convert::Into;
     use std::fmt;
     use colored::Colorize;
 
     /// All RV64I instructions. RV32/64C instructions are expanded at parsing.
-    /// Floating-point related instructions are NOT supported, and will be
-    /// treated as illegal.
-    #[derive(Debug, Copy, Clone)]
+    /// Floating-point registers are numbered as u8, and the F and D
+    /// instructions carry the width of their operands in bytes, 4 or 8.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
     pub enum InstCode {
         // R-type: rd, rs1, rs2
         Add(RegID, RegID, RegID),
@@ -25,14 +28,18 @@ pub mod inst {
         Sll(RegID, RegID, RegID),
         Mulh(RegID, RegID, RegID),
         Slt(RegID, RegID, RegID),
+        Mulhsu(RegID, RegID, RegID),
         Sltu(RegID, RegID, RegID),
+        Mulhu(RegID, RegID, RegID),
         Xor(RegID, RegID, RegID),
         Div(RegID, RegID, RegID),
         Srl(RegID, RegID, RegID),
         Sra(RegID, RegID, RegID),
+        Divu(RegID, RegID, RegID),
         Or(RegID, RegID, RegID),
         Rem(RegID, RegID, RegID),
         And(RegID, RegID, RegID),
+        Remu(RegID, RegID, RegID),
         Addw(RegID, RegID, RegID),
         Subw(RegID, RegID, RegID),
         Mulw(RegID, RegID, RegID),
@@ -40,7 +47,50 @@ pub mod inst {
         Sllw(RegID, RegID, RegID),
         Srlw(RegID, RegID, RegID),
         Sraw(RegID, RegID, RegID),
+        Divuw(RegID, RegID, RegID),
         Remw(RegID, RegID, RegID),
+        Remuw(RegID, RegID, RegID),
+        // Zba/Zbb/Zbc/Zbs R-type: rd, rs1, rs2
+        Sh1add(RegID, RegID, RegID),
+        Sh2add(RegID, RegID, RegID),
+        Sh3add(RegID, RegID, RegID),
+        AddUw(RegID, RegID, RegID),
+        Sh1addUw(RegID, RegID, RegID),
+        Sh2addUw(RegID, RegID, RegID),
+        Sh3addUw(RegID, RegID, RegID),
+        Andn(RegID, RegID, RegID),
+        Orn(RegID, RegID, RegID),
+        Xnor(RegID, RegID, RegID),
+        Max(RegID, RegID, RegID),
+        Maxu(RegID, RegID, RegID),
+        Min(RegID, RegID, RegID),
+        Minu(RegID, RegID, RegID),
+        Rol(RegID, RegID, RegID),
+        Ror(RegID, RegID, RegID),
+        Rolw(RegID, RegID, RegID),
+        Rorw(RegID, RegID, RegID),
+        Clmul(RegID, RegID, RegID),
+        Clmulh(RegID, RegID, RegID),
+        Clmulr(RegID, RegID, RegID),
+        Bclr(RegID, RegID, RegID),
+        Bext(RegID, RegID, RegID),
+        Binv(RegID, RegID, RegID),
+        Bset(RegID, RegID, RegID),
+        // Zicond: rd, rs1, rs2
+        CzeroEqz(RegID, RegID, RegID),
+        CzeroNez(RegID, RegID, RegID),
+        // Zalrsc/Zaamo: rd, rs1(, rs2), width in bytes, aq/rl bits
+        Lr(RegID, RegID, u8, u8),
+        Sc(RegID, RegID, RegID, u8, u8),
+        Amoswap(RegID, RegID, RegID, u8, u8),
+        Amoadd(RegID, RegID, RegID, u8, u8),
+        Amoxor(RegID, RegID, RegID, u8, u8),
+        Amoand(RegID, RegID, RegID, u8, u8),
+        Amoor(RegID, RegID, RegID, u8, u8),
+        Amomin(RegID, RegID, RegID, u8, u8),
+        Amomax(RegID, RegID, RegID, u8, u8),
+        Amominu(RegID, RegID, RegID, u8, u8),
+        Amomaxu(RegID, RegID, RegID, u8, u8),
         // I-type: rd, rs1, imm
         Lb(RegID, RegID, i16),
         Lbu(RegID, RegID, i16),
@@ -64,6 +114,26 @@ pub mod inst {
         Addiw(RegID, RegID, i16),
         Jalr(RegID, RegID, i16),
         Ecall,
+        // Zba/Zbb/Zbs shifts by an immediate: rd, rs1, imm
+        SlliUw(RegID, RegID, i16),
+        Rori(RegID, RegID, i16),
+        Roriw(RegID, RegID, i16),
+        Bclri(RegID, RegID, i16),
+        Bexti(RegID, RegID, i16),
+        Binvi(RegID, RegID, i16),
+        Bseti(RegID, RegID, i16),
+        // Zbb unary: rd, rs1
+        Clz(RegID, RegID),
+        Ctz(RegID, RegID),
+        Cpop(RegID, RegID),
+        Clzw(RegID, RegID),
+        Ctzw(RegID, RegID),
+        Cpopw(RegID, RegID),
+        SextB(RegID, RegID),
+        SextH(RegID, RegID),
+        ZextH(RegID, RegID),
+        OrcB(RegID, RegID),
+        Rev8(RegID, RegID),
         // S-type: rs1, rs2, imm
         Sb(RegID, RegID, i16),
         Sh(RegID, RegID, i16),
@@ -81,15 +151,137 @@ pub mod inst {
         Lui(RegID, i32),
         // UJ-Type: rd, imm(20b)
         Jal(RegID, i32),
-        // Not currently supported:
-        Fence(u8, u8),
-        FenceI,
+        // V configuration: rd, rs1/uimm, vtypei and rd, rs1, rs2
+        Vsetvli(RegID, RegID, u16),
+        Vsetivli(RegID, u8, u16),
+        Vsetvl(RegID, RegID, RegID),
+        // V loads and stores: vd/vs3, rs1, stride rs2 or offsets vs2, EEW,
+        // vm (false when masked by v0.t)
+        Vle(u8, RegID, u8, bool),
+        Vse(u8, RegID, u8, bool),
+        Vlse(u8, RegID, RegID, u8, bool),
+        Vsse(u8, RegID, RegID, u8, bool),
+        Vluxei(u8, RegID, u8, u8, bool),
+        Vloxei(u8, RegID, u8, u8, bool),
+        Vsuxei(u8, RegID, u8, u8, bool),
+        Vsoxei(u8, RegID, u8, u8, bool),
+        Vlm(u8, RegID),
+        Vsm(u8, RegID),
+        // Whole registers: vd/vs3, rs1, number of registers(, EEW)
+        Vlre(u8, RegID, u8, u8),
+        Vsr(u8, RegID, u8),
+        // V integer arithmetic: vd, vs2, vs1/rs1/imm, vm
+        Vadd(u8, u8, VSrc, bool),
+        Vsub(u8, u8, VSrc, bool),
+        Vrsub(u8, u8, VSrc, bool),
+        Vminu(u8, u8, VSrc, bool),
+        Vmin(u8, u8, VSrc, bool),
+        Vmaxu(u8, u8, VSrc, bool),
+        Vmax(u8, u8, VSrc, bool),
+        Vand(u8, u8, VSrc, bool),
+        Vor(u8, u8, VSrc, bool),
+        Vxor(u8, u8, VSrc, bool),
+        Vsll(u8, u8, VSrc, bool),
+        Vsrl(u8, u8, VSrc, bool),
+        Vsra(u8, u8, VSrc, bool),
+        Vmseq(u8, u8, VSrc, bool),
+        Vmsne(u8, u8, VSrc, bool),
+        Vmsltu(u8, u8, VSrc, bool),
+        Vmslt(u8, u8, VSrc, bool),
+        Vmsleu(u8, u8, VSrc, bool),
+        Vmsle(u8, u8, VSrc, bool),
+        Vmsgtu(u8, u8, VSrc, bool),
+        Vmsgt(u8, u8, VSrc, bool),
+        Vmerge(u8, u8, VSrc, bool),
+        Vmv(u8, u8, VSrc, bool),
+        Vmul(u8, u8, VSrc, bool),
+        Vmulh(u8, u8, VSrc, bool),
+        Vmulhu(u8, u8, VSrc, bool),
+        Vmulhsu(u8, u8, VSrc, bool),
+        Vdivu(u8, u8, VSrc, bool),
+        Vdiv(u8, u8, VSrc, bool),
+        Vremu(u8, u8, VSrc, bool),
+        Vrem(u8, u8, VSrc, bool),
+        Vmacc(u8, u8, VSrc, bool),
+        Vnmsac(u8, u8, VSrc, bool),
+        Vmadd(u8, u8, VSrc, bool),
+        Vnmsub(u8, u8, VSrc, bool),
+        // Whole register moves: vd, vs2, number of registers
+        VmvNr(u8, u8, u8),
+        // V reductions: vd, vs2, vs1, vm
+        Vredsum(u8, u8, u8, bool),
+        Vredand(u8, u8, u8, bool),
+        Vredor(u8, u8, u8, bool),
+        Vredxor(u8, u8, u8, bool),
+        Vredminu(u8, u8, u8, bool),
+        Vredmin(u8, u8, u8, bool),
+        Vredmaxu(u8, u8, u8, bool),
+        Vredmax(u8, u8, u8, bool),
+        // V mask logical: vd, vs2, vs1
+        Vmandn(u8, u8, u8),
+        Vmand(u8, u8, u8),
+        Vmor(u8, u8, u8),
+        Vmxor(u8, u8, u8),
+        Vmorn(u8, u8, u8),
+        Vmnand(u8, u8, u8),
+        Vmnor(u8, u8, u8),
+        Vmxnor(u8, u8, u8),
+        // V scalar moves and mask operations
+        VmvXS(RegID, u8),
+        VmvSX(u8, RegID),
+        Vcpop(RegID, u8, bool),
+        Vfirst(RegID, u8, bool),
+        Vid(u8, bool),
+        // F/D loads and stores: frd, rs1, offset and rs1, frs2, offset, then width
+        Fl(u8, RegID, i16, u8),
+        Fs(RegID, u8, i16, u8),
+        // F/D fused multiply-add: frd, frs1, frs2, frs3, rm, width
+        Fmadd(u8, u8, u8, u8, u8, u8),
+        Fmsub(u8, u8, u8, u8, u8, u8),
+        Fnmsub(u8, u8, u8, u8, u8, u8),
+        Fnmadd(u8, u8, u8, u8, u8, u8),
+        // F/D arithmetic: frd, frs1, frs2, rm, width
+        Fadd(u8, u8, u8, u8, u8),
+        Fsub(u8, u8, u8, u8, u8),
+        Fmul(u8, u8, u8, u8, u8),
+        Fdiv(u8, u8, u8, u8, u8),
+        Fsqrt(u8, u8, u8, u8),
+        // F/D sign injection, min and max: frd, frs1, frs2, width
+        Fsgnj(u8, u8, u8, u8),
+        Fsgnjn(u8, u8, u8, u8),
+        Fsgnjx(u8, u8, u8, u8),
+        Fmin(u8, u8, u8, u8),
+        Fmax(u8, u8, u8, u8),
+        // fcvt.s.d and fcvt.d.s: frd, frs1, rm, width of frd
+        FcvtFF(u8, u8, u8, u8),
+        // F/D comparisons and classification: rd, frs1(, frs2), width
+        Feq(RegID, u8, u8, u8),
+        Flt(RegID, u8, u8, u8),
+        Fle(RegID, u8, u8, u8),
+        Fclass(RegID, u8, u8),
+        // F/D conversions to integers: rd, frs1, rm, width
+        FcvtW(RegID, u8, u8, u8),
+        FcvtWu(RegID, u8, u8, u8),
+        FcvtL(RegID, u8, u8, u8),
+        FcvtLu(RegID, u8, u8, u8),
+        // F/D conversions from integers: frd, rs1, rm, width
+        FcvtFW(u8, RegID, u8, u8),
+        FcvtFWu(u8, RegID, u8, u8),
+        FcvtFL(u8, RegID, u8, u8),
+        FcvtFLu(u8, RegID, u8, u8),
+        // F/D moves: rd, frs1, width and frd, rs1, width
+        FmvXF(RegID, u8, u8),
+        FmvFX(u8, RegID, u8),
+        // Zicsr: rd, rs1, csr and rd, csr, uimm
         Csrrw(RegID, RegID, u16),
         Csrrs(RegID, RegID, u16),
         Csrrc(RegID, RegID, u16),
         Csrrwi(RegID, u16, u8),
         Csrrsi(RegID, u16, u8),
         Csrrci(RegID, u16, u8),
+        // Not currently supported:
+        Fence(u8, u8),
+        FenceI,
         Ebreak,
         Uret,
         Sret,
@@ -97,36 +289,343 @@ pub mod inst {
         Wfi,
         SfenceVma(RegID, RegID),
         // Invalid
-        IllegalCf(u16),
         IllegalProlonged,
         Unknown(u32),
         UnknownC(u16),
     }
 
     impl InstCode {
+        /// The assembly mnemonic of the instruction.
+        pub fn mnemonic(&self) -> &'static str {
+            match self {
+                InstCode::Add(_,_,_) => "add",
+                InstCode::Mul(_,_,_) => "mul",
+                InstCode::Sub(_,_,_) => "sub",
+                InstCode::Sll(_,_,_) => "sll",
+                InstCode::Mulh(_,_,_) => "mulh",
+                InstCode::Slt(_,_,_) => "slt",
+                InstCode::Mulhsu(_,_,_) => "mulhsu",
+                InstCode::Sltu(_,_,_) => "sltu",
+                InstCode::Mulhu(_,_,_) => "mulhu",
+                InstCode::Xor(_,_,_) => "xor",
+                InstCode::Div(_,_,_) => "div",
+                InstCode::Srl(_,_,_) => "srl",
+                InstCode::Sra(_,_,_) => "sra",
+                InstCode::Divu(_,_,_) => "divu",
+                InstCode::Or(_,_,_) => "or",
+                InstCode::Rem(_,_,_) => "rem",
+                InstCode::And(_,_,_) => "and",
+                InstCode::Remu(_,_,_) => "remu",
+                InstCode::Addw(_,_,_) => "addw",
+                InstCode::Subw(_,_,_) => "subw",
+                InstCode::Mulw(_,_,_) => "mulw",
+                InstCode::Divw(_,_,_) => "divw",
+                InstCode::Sllw(_,_,_) => "sllw",
+                InstCode::Srlw(_,_,_) => "srlw",
+                InstCode::Sraw(_,_,_) => "sraw",
+                InstCode::Divuw(_,_,_) => "divuw",
+                InstCode::Remw(_,_,_) => "remw",
+                InstCode::Remuw(_,_,_) => "remuw",
+                InstCode::Sh1add(_,_,_) => "sh1add",
+                InstCode::Sh2add(_,_,_) => "sh2add",
+                InstCode::Sh3add(_,_,_) => "sh3add",
+                InstCode::AddUw(_,_,_) => "add.uw",
+                InstCode::Sh1addUw(_,_,_) => "sh1add.uw",
+                InstCode::Sh2addUw(_,_,_) => "sh2add.uw",
+                InstCode::Sh3addUw(_,_,_) => "sh3add.uw",
+                InstCode::Andn(_,_,_) => "andn",
+                InstCode::Orn(_,_,_) => "orn",
+                InstCode::Xnor(_,_,_) => "xnor",
+                InstCode::Max(_,_,_) => "max",
+                InstCode::Maxu(_,_,_) => "maxu",
+                InstCode::Min(_,_,_) => "min",
+                InstCode::Minu(_,_,_) => "minu",
+                InstCode::Rol(_,_,_) => "rol",
+                InstCode::Ror(_,_,_) => "ror",
+                InstCode::Rolw(_,_,_) => "rolw",
+                InstCode::Rorw(_,_,_) => "rorw",
+                InstCode::Clmul(_,_,_) => "clmul",
+                InstCode::Clmulh(_,_,_) => "clmulh",
+                InstCode::Clmulr(_,_,_) => "clmulr",
+                InstCode::Bclr(_,_,_) => "bclr",
+                InstCode::Bext(_,_,_) => "bext",
+                InstCode::Binv(_,_,_) => "binv",
+                InstCode::Bset(_,_,_) => "bset",
+                InstCode::CzeroEqz(_,_,_) => "czero.eqz",
+                InstCode::CzeroNez(_,_,_) => "czero.nez",
+                InstCode::Lr(_,_,w,_) => if *w == 4 { "lr.w" } else { "lr.d" },
+                InstCode::Sc(_,_,_,w,_) => if *w == 4 { "sc.w" } else { "sc.d" },
+                InstCode::Amoswap(_,_,_,w,_) => if *w == 4 { "amoswap.w" } else { "amoswap.d" },
+                InstCode::Amoadd(_,_,_,w,_) => if *w == 4 { "amoadd.w" } else { "amoadd.d" },
+                InstCode::Amoxor(_,_,_,w,_) => if *w == 4 { "amoxor.w" } else { "amoxor.d" },
+                InstCode::Amoand(_,_,_,w,_) => if *w == 4 { "amoand.w" } else { "amoand.d" },
+                InstCode::Amoor(_,_,_,w,_) => if *w == 4 { "amoor.w" } else { "amoor.d" },
+                InstCode::Amomin(_,_,_,w,_) => if *w == 4 { "amomin.w" } else { "amomin.d" },
+                InstCode::Amomax(_,_,_,w,_) => if *w == 4 { "amomax.w" } else { "amomax.d" },
+                InstCode::Amominu(_,_,_,w,_) => if *w == 4 { "amominu.w" } else { "amominu.d" },
+                InstCode::Amomaxu(_,_,_,w,_) => if *w == 4 { "amomaxu.w" } else { "amomaxu.d" },
+                InstCode::Lb(_,_,_) => "lb",
+                InstCode::Lbu(_,_,_) => "lbu",
+                InstCode::Lh(_,_,_) => "lh",
+                InstCode::Lhu(_,_,_) => "lhu",
+                InstCode::Lw(_,_,_) => "lw",
+                InstCode::Lwu(_,_,_) => "lwu",
+                InstCode::Ld(_,_,_) => "ld",
+                InstCode::Addi(_,_,_) => "addi",
+                InstCode::Slli(_,_,_) => "slli",
+                InstCode::Slliw(_,_,_) => "slliw",
+                InstCode::Slti(_,_,_) => "slti",
+                InstCode::Sltiu(_,_,_) => "sltiu",
+                InstCode::Xori(_,_,_) => "xori",
+                InstCode::Srli(_,_,_) => "srli",
+                InstCode::Srliw(_,_,_) => "srliw",
+                InstCode::Srai(_,_,_) => "srai",
+                InstCode::Sraiw(_,_,_) => "sraiw",
+                InstCode::Ori(_,_,_) => "ori",
+                InstCode::Andi(_,_,_) => "andi",
+                InstCode::Addiw(_,_,_) => "addiw",
+                InstCode::Jalr(_,_,_) => "jalr",
+                InstCode::Ecall => "ecall",
+                InstCode::SlliUw(_,_,_) => "slli.uw",
+                InstCode::Rori(_,_,_) => "rori",
+                InstCode::Roriw(_,_,_) => "roriw",
+                InstCode::Bclri(_,_,_) => "bclri",
+                InstCode::Bexti(_,_,_) => "bexti",
+                InstCode::Binvi(_,_,_) => "binvi",
+                InstCode::Bseti(_,_,_) => "bseti",
+                InstCode::Clz(_,_) => "clz",
+                InstCode::Ctz(_,_) => "ctz",
+                InstCode::Cpop(_,_) => "cpop",
+                InstCode::Clzw(_,_) => "clzw",
+                InstCode::Ctzw(_,_) => "ctzw",
+                InstCode::Cpopw(_,_) => "cpopw",
+                InstCode::SextB(_,_) => "sext.b",
+                InstCode::SextH(_,_) => "sext.h",
+                InstCode::ZextH(_,_) => "zext.h",
+                InstCode::OrcB(_,_) => "orc.b",
+                InstCode::Rev8(_,_) => "rev8",
+                InstCode::Sb(_,_,_) => "sb",
+                InstCode::Sh(_,_,_) => "sh",
+                InstCode::Sw(_,_,_) => "sw",
+                InstCode::Sd(_,_,_) => "sd",
+                InstCode::Beq(_,_,_) => "beq",
+                InstCode::Bne(_,_,_) => "bne",
+                InstCode::Blt(_,_,_) => "blt",
+                InstCode::Bge(_,_,_) => "bge",
+                InstCode::Bltu(_,_,_) => "bltu",
+                InstCode::Bgeu(_,_,_) => "bgeu",
+                InstCode::Auipc(_,_) => "auipc",
+                InstCode::Lui(_,_) => "lui",
+                InstCode::Jal(_,_) => "jal",
+                InstCode::Fence(_,_) => "fence",
+                InstCode::FenceI => "fence.i",
+                InstCode::Csrrw(_,_,_) => "csrrw",
+                InstCode::Csrrs(_,_,_) => "csrrs",
+                InstCode::Csrrc(_,_,_) => "csrrc",
+                InstCode::Csrrwi(_,_,_) => "csrrwi",
+                InstCode::Csrrsi(_,_,_) => "csrrsi",
+                InstCode::Csrrci(_,_,_) => "csrrci",
+                InstCode::Ebreak => "ebreak",
+                InstCode::Uret => "uret",
+                InstCode::Sret => "sret",
+                InstCode::Mret => "mret",
+                InstCode::Wfi => "wfi",
+                InstCode::SfenceVma(_,_) => "sfence.vma",
+                InstCode::IllegalProlonged => "illegal",
+                InstCode::Unknown(_) => "unknown",
+                InstCode::UnknownC(_) => "unknown",
+                _ if self.is_float() => self.float_mnemonic(),
+                _ => self.vector_mnemonic(),
+            }
+        }
+
+        /// The category of the instruction, for statistics.
+        pub fn category(&self) -> InstCategory {
+            match self {
+                InstCode::Mul(_,_,_) | InstCode::Mulh(_,_,_) | InstCode::Mulhsu(_,_,_) |
+                InstCode::Mulhu(_,_,_) | InstCode::Div(_,_,_) | InstCode::Divu(_,_,_) |
+                InstCode::Rem(_,_,_) | InstCode::Remu(_,_,_) | InstCode::Mulw(_,_,_) |
+                InstCode::Divw(_,_,_) | InstCode::Divuw(_,_,_) | InstCode::Remw(_,_,_) |
+                InstCode::Remuw(_,_,_) => InstCategory::MulDiv,
+                InstCode::Lb(_,_,_) | InstCode::Lbu(_,_,_) | InstCode::Lh(_,_,_) |
+                InstCode::Lhu(_,_,_) | InstCode::Lw(_,_,_) | InstCode::Lwu(_,_,_) |
+                InstCode::Ld(_,_,_) | InstCode::Lr(_,_,_,_) => InstCategory::Load,
+                InstCode::Sb(_,_,_) | InstCode::Sh(_,_,_) | InstCode::Sw(_,_,_) |
+                InstCode::Sd(_,_,_) | InstCode::Sc(_,_,_,_,_) => InstCategory::Store,
+                // AMOs also read memory, but are counted with the stores
+                _ if self.amo().is_some() => InstCategory::Store,
+                InstCode::Beq(_,_,_) | InstCode::Bne(_,_,_) | InstCode::Blt(_,_,_) |
+                InstCode::Bge(_,_,_) | InstCode::Bltu(_,_,_) | InstCode::Bgeu(_,_,_) => InstCategory::Branch,
+                InstCode::Jal(_,_) | InstCode::Jalr(_,_,_) => InstCategory::Jump,
+                InstCode::Ecall | InstCode::Ebreak | InstCode::Fence(_,_) | InstCode::FenceI |
+                InstCode::Csrrw(_,_,_) | InstCode::Csrrs(_,_,_) | InstCode::Csrrc(_,_,_) |
+                InstCode::Csrrwi(_,_,_) | InstCode::Csrrsi(_,_,_) | InstCode::Csrrci(_,_,_) |
+                InstCode::Uret | InstCode::Sret | InstCode::Mret | InstCode::Wfi |
+                InstCode::SfenceVma(_,_) => InstCategory::System,
+                InstCode::IllegalProlonged |
+                InstCode::Unknown(_) | InstCode::UnknownC(_) => InstCategory::Illegal,
+                InstCode::Fl(_,_,_,_) => InstCategory::Load,
+                InstCode::Fs(_,_,_,_) => InstCategory::Store,
+                _ if self.is_float() => InstCategory::Float,
+                _ if self.is_vector_load() => InstCategory::Load,
+                _ if self.is_vector_store() => InstCategory::Store,
+                _ if self.is_vector() => InstCategory::Vector,
+                _ => InstCategory::Alu,
+            }
+        }
+
+        /// The extension of the instruction, which the hart must have for
+        /// it to be legal. Compressed instructions also need Zca.
+        pub fn extension(&self) -> Extension {
+            match self {
+                InstCode::Mul(_,_,_) | InstCode::Mulh(_,_,_) | InstCode::Mulhsu(_,_,_) |
+                InstCode::Mulhu(_,_,_) | InstCode::Mulw(_,_,_) => Extension::Zmmul,
+                InstCode::Div(_,_,_) | InstCode::Divu(_,_,_) | InstCode::Rem(_,_,_) |
+                InstCode::Remu(_,_,_) | InstCode::Divw(_,_,_) | InstCode::Divuw(_,_,_) |
+                InstCode::Remw(_,_,_) | InstCode::Remuw(_,_,_) => Extension::M,
+                InstCode::Lr(_,_,_,_) | InstCode::Sc(_,_,_,_,_) => Extension::Zalrsc,
+                _ if self.amo().is_some() => Extension::Zaamo,
+                InstCode::Csrrw(_,_,_) | InstCode::Csrrs(_,_,_) | InstCode::Csrrc(_,_,_) |
+                InstCode::Csrrwi(_,_,_) | InstCode::Csrrsi(_,_,_) | InstCode::Csrrci(_,_,_) => Extension::Zicsr,
+                InstCode::FenceI => Extension::Zifencei,
+                InstCode::Sh1add(_,_,_) | InstCode::Sh2add(_,_,_) | InstCode::Sh3add(_,_,_) | InstCode::AddUw(_,_,_) |
+                InstCode::Sh1addUw(_,_,_) | InstCode::Sh2addUw(_,_,_) | InstCode::Sh3addUw(_,_,_) |
+                InstCode::SlliUw(_,_,_) => Extension::Zba,
+                InstCode::Andn(_,_,_) | InstCode::Orn(_,_,_) | InstCode::Xnor(_,_,_) | InstCode::Max(_,_,_) |
+                InstCode::Maxu(_,_,_) | InstCode::Min(_,_,_) | InstCode::Minu(_,_,_) | InstCode::Rol(_,_,_) |
+                InstCode::Ror(_,_,_) | InstCode::Rolw(_,_,_) | InstCode::Rorw(_,_,_) | InstCode::Rori(_,_,_) |
+                InstCode::Roriw(_,_,_) | InstCode::Clz(_,_) | InstCode::Ctz(_,_) | InstCode::Cpop(_,_) |
+                InstCode::Clzw(_,_) | InstCode::Ctzw(_,_) | InstCode::Cpopw(_,_) | InstCode::SextB(_,_) |
+                InstCode::SextH(_,_) | InstCode::ZextH(_,_) | InstCode::OrcB(_,_) | InstCode::Rev8(_,_) => Extension::Zbb,
+                InstCode::Clmul(_,_,_) | InstCode::Clmulh(_,_,_) | InstCode::Clmulr(_,_,_) => Extension::Zbc,
+                InstCode::Bclr(_,_,_) | InstCode::Bext(_,_,_) | InstCode::Binv(_,_,_) | InstCode::Bset(_,_,_) |
+                InstCode::Bclri(_,_,_) | InstCode::Bexti(_,_,_) | InstCode::Binvi(_,_,_) | InstCode::Bseti(_,_,_) => Extension::Zbs,
+                InstCode::CzeroEqz(_,_,_) | InstCode::CzeroNez(_,_,_) => Extension::Zicond,
+                _ if self.is_vector() => Extension::Zve32x,
+                // fcvt.s.d and fcvt.d.s are D instructions either way
+                InstCode::FcvtFF(_,_,_,_) => Extension::D,
+                _ => match self.float_width() {
+                    Some(4) => Extension::F,
+                    Some(_) => Extension::D,
+                    None => Extension::I,
+                },
+            }
+        }
+
+        /// The destination register, for the instructions that write back.
+        pub fn rd(&self) -> Option<RegID> {
+            match *self {
+                InstCode::Add(rd,_,_) | InstCode::Sub(rd,_,_) | InstCode::Mul(rd,_,_) | InstCode::Sll(rd,_,_) |
+                InstCode::Mulh(rd,_,_) | InstCode::Slt(rd,_,_) | InstCode::Sltu(rd,_,_) | InstCode::Xor(rd,_,_) |
+                InstCode::Div(rd,_,_) | InstCode::Srl(rd,_,_) | InstCode::Sra(rd,_,_) | InstCode::Or(rd,_,_) |
+                InstCode::Rem(rd,_,_) | InstCode::And(rd,_,_) | InstCode::Addw(rd,_,_) | InstCode::Subw(rd,_,_) |
+                InstCode::Mulw(rd,_,_) | InstCode::Divw(rd,_,_) | InstCode::Sllw(rd,_,_) | InstCode::Srlw(rd,_,_) |
+                InstCode::Sraw(rd,_,_) | InstCode::Remw(rd,_,_) | InstCode::Mulhsu(rd,_,_) | InstCode::Mulhu(rd,_,_) |
+                InstCode::Divu(rd,_,_) | InstCode::Remu(rd,_,_) | InstCode::Divuw(rd,_,_) | InstCode::Remuw(rd,_,_) |
+                InstCode::Lb(rd,_,_) | InstCode::Lbu(rd,_,_) | InstCode::Lh(rd,_,_) | InstCode::Lhu(rd,_,_) |
+                InstCode::Lw(rd,_,_) | InstCode::Lwu(rd,_,_) | InstCode::Ld(rd,_,_) |
+                InstCode::Addi(rd,_,_) | InstCode::Slli(rd,_,_) | InstCode::Slliw(rd,_,_) | InstCode::Slti(rd,_,_) |
+                InstCode::Sltiu(rd,_,_) | InstCode::Xori(rd,_,_) | InstCode::Srli(rd,_,_) | InstCode::Srliw(rd,_,_) |
+                InstCode::Srai(rd,_,_) | InstCode::Sraiw(rd,_,_) | InstCode::Ori(rd,_,_) | InstCode::Andi(rd,_,_) |
+                InstCode::Addiw(rd,_,_) | InstCode::Jalr(rd,_,_) | InstCode::Jal(rd,_) | InstCode::Auipc(rd,_) |
+                InstCode::Lui(rd,_) => Some(rd),
+                InstCode::Sh1add(rd,_,_) | InstCode::Sh2add(rd,_,_) | InstCode::Sh3add(rd,_,_) | InstCode::AddUw(rd,_,_) |
+                InstCode::Sh1addUw(rd,_,_) | InstCode::Sh2addUw(rd,_,_) | InstCode::Sh3addUw(rd,_,_) | InstCode::Andn(rd,_,_) |
+                InstCode::Orn(rd,_,_) | InstCode::Xnor(rd,_,_) | InstCode::Max(rd,_,_) | InstCode::Maxu(rd,_,_) |
+                InstCode::Min(rd,_,_) | InstCode::Minu(rd,_,_) | InstCode::Rol(rd,_,_) | InstCode::Ror(rd,_,_) |
+                InstCode::Rolw(rd,_,_) | InstCode::Rorw(rd,_,_) | InstCode::Clmul(rd,_,_) | InstCode::Clmulh(rd,_,_) |
+                InstCode::Clmulr(rd,_,_) | InstCode::Bclr(rd,_,_) | InstCode::Bext(rd,_,_) | InstCode::Binv(rd,_,_) |
+                InstCode::Bset(rd,_,_) | InstCode::SlliUw(rd,_,_) | InstCode::Rori(rd,_,_) | InstCode::Roriw(rd,_,_) |
+                InstCode::Bclri(rd,_,_) | InstCode::Bexti(rd,_,_) | InstCode::Binvi(rd,_,_) | InstCode::Bseti(rd,_,_) |
+                InstCode::Clz(rd,_) | InstCode::Ctz(rd,_) | InstCode::Cpop(rd,_) | InstCode::Clzw(rd,_) |
+                InstCode::Ctzw(rd,_) | InstCode::Cpopw(rd,_) | InstCode::SextB(rd,_) | InstCode::SextH(rd,_) |
+                InstCode::ZextH(rd,_) | InstCode::OrcB(rd,_) | InstCode::Rev8(rd,_) => Some(rd),
+                InstCode::CzeroEqz(rd,_,_) | InstCode::CzeroNez(rd,_,_) |
+                InstCode::Csrrw(rd,_,_) | InstCode::Csrrs(rd,_,_) | InstCode::Csrrc(rd,_,_) |
+                InstCode::Csrrwi(rd,_,_) | InstCode::Csrrsi(rd,_,_) | InstCode::Csrrci(rd,_,_) => Some(rd),
+                InstCode::Lr(rd,_,_,_) | InstCode::Sc(rd,_,_,_,_) => Some(rd),
+                _ if self.amo().is_some() => self.amo().map(|(rd,_,_,_,_)| rd),
+                _ if self.is_float() => self.float_rd(),
+                _ => self.vector_rd(),
+            }
+        }
+
+        /// Whether the instruction only exists on RV64: the W and UW
+        /// variants, the 64-bit loads and stores, and shifts by 32 or more.
+        pub fn rv64_only(&self) -> bool {
+            match self {
+                InstCode::Addw(_,_,_) | InstCode::Subw(_,_,_) | InstCode::Mulw(_,_,_) | InstCode::Divw(_,_,_) |
+                InstCode::Sllw(_,_,_) | InstCode::Srlw(_,_,_) | InstCode::Sraw(_,_,_) | InstCode::Remw(_,_,_) |
+                InstCode::Divuw(_,_,_) | InstCode::Remuw(_,_,_) |
+                InstCode::Addiw(_,_,_) | InstCode::Slliw(_,_,_) | InstCode::Srliw(_,_,_) | InstCode::Sraiw(_,_,_) |
+                InstCode::Lwu(_,_,_) | InstCode::Ld(_,_,_) | InstCode::Sd(_,_,_) => true,
+                InstCode::AddUw(_,_,_) | InstCode::Sh1addUw(_,_,_) | InstCode::Sh2addUw(_,_,_) | InstCode::Sh3addUw(_,_,_) |
+                InstCode::SlliUw(_,_,_) | InstCode::Rolw(_,_,_) | InstCode::Rorw(_,_,_) | InstCode::Roriw(_,_,_) |
+                InstCode::Clzw(_,_) | InstCode::Ctzw(_,_) | InstCode::Cpopw(_,_) => true,
+                InstCode::Slli(_,_,imm) | InstCode::Srli(_,_,imm) | InstCode::Srai(_,_,imm) |
+                InstCode::Rori(_,_,imm) | InstCode::Bclri(_,_,imm) | InstCode::Bexti(_,_,imm) |
+                InstCode::Binvi(_,_,imm) | InstCode::Bseti(_,_,imm) => imm & 0x20 != 0,
+                InstCode::Lr(_,_,width,_) | InstCode::Sc(_,_,_,width,_) => *width == 8,
+                InstCode::FcvtL(_,_,_,_) | InstCode::FcvtLu(_,_,_,_) | InstCode::FcvtFL(_,_,_,_) |
+                InstCode::FcvtFLu(_,_,_,_) | InstCode::FmvXF(_,_,8) | InstCode::FmvFX(_,_,8) => true,
+                _ => matches!(self.amo(), Some((_,_,_,8,_))),
+            }
+        }
+
+        /// The number of bytes accessed by a load or store, or None
+        /// if the instruction does not access memory.
+        pub fn mem_width(&self) -> Option<usize> {
+            match self {
+                InstCode::Lb(_,_,_) | InstCode::Lbu(_,_,_) | InstCode::Sb(_,_,_) => Some(1),
+                InstCode::Lh(_,_,_) | InstCode::Lhu(_,_,_) | InstCode::Sh(_,_,_) => Some(2),
+                InstCode::Lw(_,_,_) | InstCode::Lwu(_,_,_) | InstCode::Sw(_,_,_) => Some(4),
+                InstCode::Ld(_,_,_) | InstCode::Sd(_,_,_) => Some(8),
+                InstCode::Lr(_,_,width,_) | InstCode::Sc(_,_,_,width,_) => Some(*width as usize),
+                InstCode::Fl(_,_,_,width) | InstCode::Fs(_,_,_,width) => Some(*width as usize),
+                _ => self.amo().map(|(_,_,_,width,_)| width as usize),
+            }
+        }
+
+        /// The operands of an AMO, as (rd, rs1, rs2, width, aq/rl bits).
+        pub fn amo(&self) -> Option<(RegID, RegID, RegID, u8, u8)> {
+            match *self {
+                InstCode::Amoswap(rd, rs1, rs2, width, order) | InstCode::Amoadd(rd, rs1, rs2, width, order) |
+                InstCode::Amoxor(rd, rs1, rs2, width, order) | InstCode::Amoand(rd, rs1, rs2, width, order) |
+                InstCode::Amoor(rd, rs1, rs2, width, order) | InstCode::Amomin(rd, rs1, rs2, width, order) |
+                InstCode::Amomax(rd, rs1, rs2, width, order) | InstCode::Amominu(rd, rs1, rs2, width, order) |
+                InstCode::Amomaxu(rd, rs1, rs2, width, order) => Some((rd, rs1, rs2, width, order)),
+                _ => None,
+            }
+        }
+
         /// Parse the instruction encoded as `raw` (does not
         /// support instructions longer than 32-bit). Returns 
         /// the `InstCode` and the length, to distinguish between
         /// normal and compressed instructions.
-        pub fn parse(first: u16, second: u16) -> (Self, usize) {
+        pub fn parse(first: u16, second: u16, xlen: u32) -> (Self, usize) {
             let is_compressed = (first & 0b11) != 0b11;
             let is_prolonged = (first & 0b11111) == 0b11111;
             if is_prolonged {
                 return (InstCode::IllegalProlonged, 32);
             }
             if is_compressed {
-                (InstCode::parse_compressed((first & 0xFFFF) as u16), 16)
+                (InstCode::parse_compressed((first & 0xFFFF) as u16, xlen), 16)
             }
             else {
                 let raw = ((second as u32) << 16) + first as u32;
                 let opcode = raw & 0b1111111;
                 match opcode {
-                    0x33 | 0x3B => (InstCode::parse_normal_r(raw), 32),
-                    0x3 | 0x13 | 0x1B | 0x67 | 0x73 => (InstCode::parse_normal_i(raw), 32),
+                    0x33 | 0x3B => (InstCode::parse_normal_r(raw, xlen), 32),
+                    0x2F => (InstCode::parse_atomic(raw), 32),
+                    0x3 | 0x13 | 0x1B | 0x67 | 0x73 => (InstCode::parse_normal_i(raw, xlen), 32),
                     0x23 => (InstCode::parse_normal_s(raw), 32),
                     0x63 => (InstCode::parse_normal_sb(raw), 32),
                     0x17 | 0x37 => (InstCode::parse_normal_u(raw), 32),
                     0x6f => (InstCode::parse_normal_uj(raw), 32),
+                    // Widths 2 and 3 of LOAD-FP and STORE-FP are flw/fsw and fld/fsd
+                    0x07 | 0x27 if matches!((raw>>12) & 0b111, 2 | 3) => (InstCode::parse_float(raw), 32),
+                    0x07 | 0x27 | 0x57 => (InstCode::parse_vector(raw), 32),
+                    0x43 | 0x47 | 0x4b | 0x4f | 0x53 => (InstCode::parse_float(raw), 32),
                     0x0f => {
                         let func3 = (raw>>12) & 0b111;
                         let succ = ((raw>>20) & 0b1111) as u8;
@@ -142,15 +641,38 @@ pub mod inst {
             }
         }
 
-        /// Parse a compressed instruction. Unfinished!
-        fn parse_compressed(raw: u16) -> Self {
+        /// Parse a compressed instruction. On RV32, the encodings of c.ld,
+        /// c.sd, c.ldsp, c.sdsp and c.addiw are c.flw, c.fsw, c.flwsp,
+        /// c.fswsp and c.jal instead.
+        fn parse_compressed(raw: u16, xlen: u32) -> Self {
             let opcode = raw & 0b11;
             let func3 = raw>>13;
+            let rv32 = xlen == 32;
             // Compressed register code
             let rd = ((raw>>2) & 0b111) as u8 + 8;
-
-            // Unfinished
-            return InstCode::UnknownC(raw);
+            let rs1 = ((raw>>7) & 0b111) as u8 + 8;
+            // Full register code
+            let rd_full = ((raw>>7) & 0b11111) as u8;
+            let rs2_full = ((raw>>2) & 0b11111) as u8;
+            // imm[5:0] of the CI format, sign-extended
+            let imm6 = ((((raw>>2)&0b11111) + (((raw>>12)&0b1)<<5)) as i16).sign_extend(10);
+            // offset[11:1] of c.j and c.jal
+            let jump_ofs = ((
+                (((raw>>12)&0b1)<<11) +
+                (((raw>>11)&0b1)<<4) +
+                (((raw>>9)&0b11)<<8) +
+                (((raw>>8)&0b1)<<10) +
+                (((raw>>7)&0b1)<<6) +
+                (((raw>>6)&0b1)<<7) +
+                (((raw>>3)&0b111)<<1) +
+                (((raw>>2)&0b1)<<5)) as i16).sign_extend(4);
+            // offset[8:1] of c.beqz and c.bnez
+            let branch_ofs = ((
+                (((raw>>12)&0b1)<<8) +
+                (((raw>>10)&0b11)<<3) +
+                (((raw>>5)&0b11)<<6) +
+                (((raw>>3)&0b11)<<1) +
+                (((raw>>2)&0b1)<<5)) as i16).sign_extend(7);
 
             match (opcode, func3) {
                 // c.addi4spn <==> addi rd',x2,nzuimm
@@ -161,73 +683,212 @@ pub mod inst {
                         ((imm>>6)<<4) +
                         ((imm & 0b10)<<1) +
                         ((imm & 0b1)<<3);
+                    if nzuimm == 0 {
+                        return InstCode::UnknownC(raw);
+                    }
                     InstCode::Addi(RegID::decode(rd), RegID::X2, nzuimm as i16)
                 },
-                // c.fld not supported
-                (0, 1) => InstCode::IllegalCf(raw),
+                // c.fld <===> fld rd', offset[7:3](rs1')
+                (0, 1) => {
+                    let uimm = 
+                        (((raw>>10)&0b111)<<3) +
+                        (((raw>>5)&0b11)<<6);
+                    InstCode::Fl(rd, RegID::decode(rs1), uimm as i16, 8)
+                },
                 // c.lw <==> lw rd',offset[6:2](rs1')
                 (0, 2) => {
-                    let rs1 = ((raw>>7) & 0b111) as u8 + 8;
                     let uimm = 
                         (((raw>>10)&0b111)<<3) +
                         (((raw>>5)&0b1)<<6) + 
                         (((raw>>6)&0b1)<<2);
                     InstCode::Lw(RegID::decode(rd), RegID::decode(rs1), uimm as i16)
                 },
+                // c.flw <===> flw rd', offset[6:2](rs1')
+                (0, 3) if rv32 => {
+                    let uimm = 
+                        (((raw>>10)&0b111)<<3) +
+                        (((raw>>5)&0b1)<<6) + 
+                        (((raw>>6)&0b1)<<2);
+                    InstCode::Fl(rd, RegID::decode(rs1), uimm as i16, 4)
+                },
                 // c.ld <===> ld rd', offset[7:3](rs1')
                 (0, 3) => {
-                    let rs1 = ((raw>>7) & 0b111) as u8 + 8;
                     let uimm = 
                         (((raw>>10)&0b111)<<3) +
                         (((raw>>5)&0b11)<<6);
                     InstCode::Ld(RegID::decode(rd), RegID::decode(rs1), uimm as i16)
                 },
-                // c.fsd not supported
-                (0, 5) => InstCode::IllegalCf(raw),
+                // c.fsd <===> fsd rs2', offset[7:3](rs1')
+                (0, 5) => {
+                    let uimm = 
+                        (((raw>>10)&0b111)<<3) +
+                        (((raw>>5)&0b11)<<6);
+                    InstCode::Fs(RegID::decode(rs1), rd, uimm as i16, 8)
+                },
                 // c.sw <===> sw rs2',offset[6:2](rs1')
                 (0, 6) => {
-                    let rs1 = ((raw>>7) & 0b111) as u8 + 8;
                     let uimm = 
                         (((raw>>10)&0b111)<<3) +
                         (((raw>>5)&0b1)<<6) + 
                         (((raw>>6)&0b1)<<2);
                     InstCode::Sw(RegID::decode(rs1), RegID::decode(rd), uimm as i16)
                 },
+                // c.fsw <===> fsw rs2', offset[6:2](rs1')
+                (0, 7) if rv32 => {
+                    let uimm = 
+                        (((raw>>10)&0b111)<<3) +
+                        (((raw>>5)&0b1)<<6) + 
+                        (((raw>>6)&0b1)<<2);
+                    InstCode::Fs(RegID::decode(rs1), rd, uimm as i16, 4)
+                },
                 // c.sd <===> sd rs2', offset[7:3](rs1')
                 (0, 7) => {
-                    let rs1 = ((raw>>7) & 0b111) as u8 + 8;
                     let uimm = 
                         (((raw>>10)&0b111)<<3) +
                         (((raw>>5)&0b11)<<6);
                     InstCode::Sd(RegID::decode(rs1), RegID::decode(rd), uimm as i16)
-                }
+                },
                 // c.nop <===> addi x0, x0, 0
                 (1, 0) if raw == 1 => InstCode::Addi(RegID::X0, RegID::X0, 0),
                 // c.addi <===> addi rs1, rs1, nzimm[5:0]
-                (1, 0) if raw != 1 => {
-                    let rs1 = ((raw>>7) & 0b11111) as u8;
-                    let nzimm = ((((raw>>2)&0b11111) + (((raw>>12)&0b1)<<5)) as i16).sign_extend(10);
-                    InstCode::Addi(RegID::decode(rs1), RegID::decode(rs1), nzimm)
-                }
+                (1, 0) => InstCode::Addi(RegID::decode(rd_full), RegID::decode(rd_full), imm6),
+                // c.jal <===> jal x1, offset[11:1]
+                (1, 1) if rv32 => InstCode::Jal(RegID::X1, jump_ofs as i32),
                 // c.addiw <===> addiw rd,rd,imm[5:0]
-                (1, 1) => {
-                    let rs1 = ((raw>>7) & 0b11111) as u8;
-                    let nzimm = ((((raw>>2)&0b11111) + (((raw>>12)&0b1)<<5)) as i16).sign_extend(10);
-                    InstCode::Addiw(RegID::decode(rs1), RegID::decode(rs1), nzimm)
-                }
+                (1, 1) if rd_full != 0 => InstCode::Addiw(RegID::decode(rd_full), RegID::decode(rd_full), imm6),
                 // c.li <===> addi rd,x0,imm[5:0]
-                (1, 2) => {
-                    let rd = ((raw>>7) & 0b11111) as u8;
-                    let imm = ((((raw>>2)&0b11111) + (((raw>>12)&0b1)<<5)) as i16).sign_extend(10);
-                    InstCode::Addi(RegID::decode(rd), RegID::X0, imm)
-                }
+                (1, 2) => InstCode::Addi(RegID::decode(rd_full), RegID::X0, imm6),
+                // c.addi16sp <===> addi x2,x2,nzimm[9:4]
+                (1, 3) if rd_full == 2 => {
+                    let nzimm = ((
+                        (((raw>>12)&0b1)<<9) +
+                        (((raw>>6)&0b1)<<4) +
+                        (((raw>>5)&0b1)<<6) +
+                        (((raw>>3)&0b11)<<7) +
+                        (((raw>>2)&0b1)<<5)) as i16).sign_extend(6);
+                    if nzimm == 0 {
+                        return InstCode::UnknownC(raw);
+                    }
+                    InstCode::Addi(RegID::X2, RegID::X2, nzimm)
+                },
+                // c.lui <===> lui rd,nzimm[17:12]
+                (1, 3) if imm6 != 0 => InstCode::Lui(RegID::decode(rd_full), (imm6 as i32) << 12),
+                (1, 4) => {
+                    let shamt = (((raw>>2)&0b11111) + (((raw>>12)&0b1)<<5)) as i16;
+                    match ((raw>>10) & 0b11, (raw>>12) & 0b1, (raw>>5) & 0b11) {
+                        // shamt[5] must be zero on RV32
+                        (0 | 1, _, _) if rv32 && shamt >= 32 => InstCode::UnknownC(raw),
+                        // c.srli <===> srli rd',rd',shamt[5:0]
+                        (0, _, _) => InstCode::Srli(RegID::decode(rs1), RegID::decode(rs1), shamt),
+                        // c.srai <===> srai rd',rd',shamt[5:0]
+                        (1, _, _) => InstCode::Srai(RegID::decode(rs1), RegID::decode(rs1), shamt | 0x400),
+                        // c.andi <===> andi rd',rd',imm[5:0]
+                        (2, _, _) => InstCode::Andi(RegID::decode(rs1), RegID::decode(rs1), imm6),
+                        // c.sub, c.xor, c.or, c.and <===> op rd',rd',rs2'
+                        (3, 0, 0) => InstCode::Sub(RegID::decode(rs1), RegID::decode(rs1), RegID::decode(rd)),
+                        (3, 0, 1) => InstCode::Xor(RegID::decode(rs1), RegID::decode(rs1), RegID::decode(rd)),
+                        (3, 0, 2) => InstCode::Or(RegID::decode(rs1), RegID::decode(rs1), RegID::decode(rd)),
+                        (3, 0, 3) => InstCode::And(RegID::decode(rs1), RegID::decode(rs1), RegID::decode(rd)),
+                        // c.subw, c.addw <===> op rd',rd',rs2'
+                        (3, 1, 0) if !rv32 => InstCode::Subw(RegID::decode(rs1), RegID::decode(rs1), RegID::decode(rd)),
+                        (3, 1, 1) if !rv32 => InstCode::Addw(RegID::decode(rs1), RegID::decode(rs1), RegID::decode(rd)),
+                        _ => InstCode::UnknownC(raw),
+                    }
+                },
+                // c.j <===> jal x0, offset[11:1]
+                (1, 5) => InstCode::Jal(RegID::X0, jump_ofs as i32),
+                // c.beqz <===> beq rs1',x0,offset[8:1]
+                (1, 6) => InstCode::Beq(RegID::decode(rs1), RegID::X0, branch_ofs),
+                // c.bnez <===> bne rs1',x0,offset[8:1]
+                (1, 7) => InstCode::Bne(RegID::decode(rs1), RegID::X0, branch_ofs),
+                // c.slli <===> slli rd,rd,shamt[5:0]
+                (2, 0) => {
+                    let shamt = (((raw>>2)&0b11111) + (((raw>>12)&0b1)<<5)) as i16;
+                    if rv32 && shamt >= 32 {
+                        return InstCode::UnknownC(raw);
+                    }
+                    InstCode::Slli(RegID::decode(rd_full), RegID::decode(rd_full), shamt)
+                },
+                // c.fldsp <===> fld rd,offset[8:3](x2)
+                (2, 1) => {
+                    let uimm = 
+                        (((raw>>12)&0b1)<<5) +
+                        (((raw>>5)&0b11)<<3) +
+                        (((raw>>2)&0b111)<<6);
+                    InstCode::Fl(rd_full, RegID::X2, uimm as i16, 8)
+                },
+                // c.lwsp <===> lw rd,offset[7:2](x2)
+                (2, 2) if rd_full != 0 => {
+                    let uimm = 
+                        (((raw>>12)&0b1)<<5) +
+                        (((raw>>4)&0b111)<<2) +
+                        (((raw>>2)&0b11)<<6);
+                    InstCode::Lw(RegID::decode(rd_full), RegID::X2, uimm as i16)
+                },
+                // c.flwsp <===> flw rd,offset[7:2](x2)
+                (2, 3) if rv32 => {
+                    let uimm = 
+                        (((raw>>12)&0b1)<<5) +
+                        (((raw>>4)&0b111)<<2) +
+                        (((raw>>2)&0b11)<<6);
+                    InstCode::Fl(rd_full, RegID::X2, uimm as i16, 4)
+                },
+                // c.ldsp <===> ld rd,offset[8:3](x2)
+                (2, 3) if rd_full != 0 => {
+                    let uimm = 
+                        (((raw>>12)&0b1)<<5) +
+                        (((raw>>5)&0b11)<<3) +
+                        (((raw>>2)&0b111)<<6);
+                    InstCode::Ld(RegID::decode(rd_full), RegID::X2, uimm as i16)
+                },
+                (2, 4) => match ((raw>>12) & 0b1, rd_full, rs2_full) {
+                    // c.jr <===> jalr x0,0(rs1)
+                    (0, 1.., 0) => InstCode::Jalr(RegID::X0, RegID::decode(rd_full), 0),
+                    // c.mv <===> add rd,x0,rs2
+                    (0, _, 1..) => InstCode::Add(RegID::decode(rd_full), RegID::X0, RegID::decode(rs2_full)),
+                    // c.ebreak
+                    (1, 0, 0) => InstCode::Ebreak,
+                    // c.jalr <===> jalr x1,0(rs1)
+                    (1, _, 0) => InstCode::Jalr(RegID::X1, RegID::decode(rd_full), 0),
+                    // c.add <===> add rd,rd,rs2
+                    (1, _, _) => InstCode::Add(RegID::decode(rd_full), RegID::decode(rd_full), RegID::decode(rs2_full)),
+                    _ => InstCode::UnknownC(raw),
+                },
+                // c.fsdsp <===> fsd rs2,offset[8:3](x2)
+                (2, 5) => {
+                    let uimm = 
+                        (((raw>>10)&0b111)<<3) +
+                        (((raw>>7)&0b111)<<6);
+                    InstCode::Fs(RegID::X2, rs2_full, uimm as i16, 8)
+                },
+                // c.swsp <===> sw rs2,offset[7:2](x2)
+                (2, 6) => {
+                    let uimm = 
+                        (((raw>>9)&0b1111)<<2) +
+                        (((raw>>7)&0b11)<<6);
+                    InstCode::Sw(RegID::X2, RegID::decode(rs2_full), uimm as i16)
+                },
+                // c.fswsp <===> fsw rs2,offset[7:2](x2)
+                (2, 7) if rv32 => {
+                    let uimm = 
+                        (((raw>>9)&0b1111)<<2) +
+                        (((raw>>7)&0b11)<<6);
+                    InstCode::Fs(RegID::X2, rs2_full, uimm as i16, 4)
+                },
+                // c.sdsp <===> sd rs2,offset[8:3](x2)
+                (2, 7) => {
+                    let uimm = 
+                        (((raw>>10)&0b111)<<3) +
+                        (((raw>>7)&0b111)<<6);
+                    InstCode::Sd(RegID::X2, RegID::decode(rs2_full), uimm as i16)
+                },
                 _ => InstCode::UnknownC(raw),
             }
-            
         }
 
-        /// Parse a R-type instruction
-        fn parse_normal_r(raw: u32) -> Self {
+        /// Parse a R-type instruction. zext.h is encoded as a W
+        /// instruction on RV64 only.
+        fn parse_normal_r(raw: u32, xlen: u32) -> Self {
             let opcode = raw & 0b1111111;
             let rd = ((raw>>7) & 0b11111) as u8;
             let func3 = (raw>>12) & 0b111;
@@ -243,14 +904,40 @@ pub mod inst {
                     (1, 0x00) => InstCode::Sll(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
                     (1, 0x01) => InstCode::Mulh(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
                     (2, 0x00) => InstCode::Slt(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (2, 0x01) => InstCode::Mulhsu(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
                     (3, 0x00) => InstCode::Sltu(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (3, 0x01) => InstCode::Mulhu(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
                     (4, 0x00) => InstCode::Xor(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
                     (4, 0x01) => InstCode::Div(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
                     (5, 0x00) => InstCode::Srl(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
                     (5, 0x20) => InstCode::Sra(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (5, 0x01) => InstCode::Divu(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
                     (6, 0x00) => InstCode::Or(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
                     (6, 0x01) => InstCode::Rem(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
                     (7, 0x00) => InstCode::And(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (7, 0x01) => InstCode::Remu(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (2, 0x10) => InstCode::Sh1add(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (4, 0x10) => InstCode::Sh2add(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (6, 0x10) => InstCode::Sh3add(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (7, 0x20) => InstCode::Andn(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (6, 0x20) => InstCode::Orn(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (4, 0x20) => InstCode::Xnor(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (6, 0x05) => InstCode::Max(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (7, 0x05) => InstCode::Maxu(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (4, 0x05) => InstCode::Min(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (5, 0x05) => InstCode::Minu(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (1, 0x30) => InstCode::Rol(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (5, 0x30) => InstCode::Ror(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (1, 0x05) => InstCode::Clmul(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (3, 0x05) => InstCode::Clmulh(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (2, 0x05) => InstCode::Clmulr(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (1, 0x24) => InstCode::Bclr(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (5, 0x24) => InstCode::Bext(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (1, 0x34) => InstCode::Binv(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (1, 0x14) => InstCode::Bset(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (5, 0x07) => InstCode::CzeroEqz(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (7, 0x07) => InstCode::CzeroNez(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (4, 0x04) if rs2 == 0 && xlen == 32 => InstCode::ZextH(RegID::decode(rd), RegID::decode(rs1)),
                     _ => InstCode::Unknown(raw),
                 },
                 0x3B => match (func3, func7) {
@@ -261,15 +948,56 @@ pub mod inst {
                     (5, 0x00) => InstCode::Srlw(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
                     (5, 0x20) => InstCode::Sraw(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
                     (4, 0x01) => InstCode::Divw(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (5, 0x01) => InstCode::Divuw(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
                     (6, 0x01) => InstCode::Remw(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (7, 0x01) => InstCode::Remuw(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (0, 0x04) => InstCode::AddUw(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (2, 0x10) => InstCode::Sh1addUw(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (4, 0x10) => InstCode::Sh2addUw(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (6, 0x10) => InstCode::Sh3addUw(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (1, 0x30) => InstCode::Rolw(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (5, 0x30) => InstCode::Rorw(RegID::decode(rd), RegID::decode(rs1), RegID::decode(rs2)),
+                    (4, 0x04) if rs2 == 0 && xlen == 64 => InstCode::ZextH(RegID::decode(rd), RegID::decode(rs1)),
                     _ => InstCode::Unknown(raw),
                 },
                 _ => unreachable!()
             }
         }
 
-        /// Parse a I-type instruction
-        fn parse_normal_i(raw: u32) -> Self {
+        /// Parse an A instruction. The aq/rl bits are kept, so that the
+        /// instruction encodes back to `raw`.
+        fn parse_atomic(raw: u32) -> Self {
+            let rd = RegID::decode(((raw>>7) & 0b11111) as u8);
+            let func3 = (raw>>12) & 0b111;
+            let rs1 = RegID::decode(((raw>>15) & 0b11111) as u8);
+            let rs2 = ((raw>>20) & 0b11111) as u8;
+            let order = ((raw>>25) & 0b11) as u8;
+            let func5 = raw>>27;
+            let width = match func3 {
+                2 => 4,
+                3 => 8,
+                _ => return InstCode::Unknown(raw),
+            };
+
+            match func5 {
+                0b00010 if rs2 == 0 => InstCode::Lr(rd, rs1, width, order),
+                0b00011 => InstCode::Sc(rd, rs1, RegID::decode(rs2), width, order),
+                0b00001 => InstCode::Amoswap(rd, rs1, RegID::decode(rs2), width, order),
+                0b00000 => InstCode::Amoadd(rd, rs1, RegID::decode(rs2), width, order),
+                0b00100 => InstCode::Amoxor(rd, rs1, RegID::decode(rs2), width, order),
+                0b01100 => InstCode::Amoand(rd, rs1, RegID::decode(rs2), width, order),
+                0b01000 => InstCode::Amoor(rd, rs1, RegID::decode(rs2), width, order),
+                0b10000 => InstCode::Amomin(rd, rs1, RegID::decode(rs2), width, order),
+                0b10100 => InstCode::Amomax(rd, rs1, RegID::decode(rs2), width, order),
+                0b11000 => InstCode::Amominu(rd, rs1, RegID::decode(rs2), width, order),
+                0b11100 => InstCode::Amomaxu(rd, rs1, RegID::decode(rs2), width, order),
+                _ => InstCode::Unknown(raw),
+            }
+        }
+
+        /// Parse a I-type instruction. rev8 has a different encoding
+        /// on RV32 and RV64.
+        fn parse_normal_i(raw: u32, xlen: u32) -> Self {
             let opcode = raw & 0b1111111;
             let rd = ((raw>>7) & 0b11111) as u8;
             let func3 = (raw>>12) & 0b111;
@@ -290,12 +1018,24 @@ pub mod inst {
                 },
                 0x13 => match func3 {
                     0 => InstCode::Addi(RegID::decode(rd), RegID::decode(rs1), imm),
-                    1 if imm & 0b111111100000 == 0 => InstCode::Slli(RegID::decode(rd), RegID::decode(rs1), imm),
+                    1 if imm & 0b111111000000 == 0 => InstCode::Slli(RegID::decode(rd), RegID::decode(rs1), imm),
+                    1 if imm & 0b111111000000 == 0b010010000000 => InstCode::Bclri(RegID::decode(rd), RegID::decode(rs1), imm),
+                    1 if imm & 0b111111000000 == 0b011010000000 => InstCode::Binvi(RegID::decode(rd), RegID::decode(rs1), imm),
+                    1 if imm & 0b111111000000 == 0b001010000000 => InstCode::Bseti(RegID::decode(rd), RegID::decode(rs1), imm),
+                    1 if imm == 0x600 => InstCode::Clz(RegID::decode(rd), RegID::decode(rs1)),
+                    1 if imm == 0x601 => InstCode::Ctz(RegID::decode(rd), RegID::decode(rs1)),
+                    1 if imm == 0x602 => InstCode::Cpop(RegID::decode(rd), RegID::decode(rs1)),
+                    1 if imm == 0x604 => InstCode::SextB(RegID::decode(rd), RegID::decode(rs1)),
+                    1 if imm == 0x605 => InstCode::SextH(RegID::decode(rd), RegID::decode(rs1)),
                     2 => InstCode::Slti(RegID::decode(rd), RegID::decode(rs1), imm),
                     3 => InstCode::Sltiu(RegID::decode(rd), RegID::decode(rs1), imm),
                     4 => InstCode::Xori(RegID::decode(rd), RegID::decode(rs1), imm),
-                    5 if imm & 0b111111100000 == 0 => InstCode::Srli(RegID::decode(rd), RegID::decode(rs1), imm),
-                    5 if imm & 0b111111100000 == 0b001000000000 => InstCode::Srai(RegID::decode(rd), RegID::decode(rs1), imm),
+                    5 if imm & 0b111111000000 == 0 => InstCode::Srli(RegID::decode(rd), RegID::decode(rs1), imm),
+                    5 if imm & 0b111111000000 == 0b010000000000 => InstCode::Srai(RegID::decode(rd), RegID::decode(rs1), imm),
+                    5 if imm & 0b111111000000 == 0b011000000000 => InstCode::Rori(RegID::decode(rd), RegID::decode(rs1), imm),
+                    5 if imm & 0b111111000000 == 0b010010000000 => InstCode::Bexti(RegID::decode(rd), RegID::decode(rs1), imm),
+                    5 if imm == 0x287 => InstCode::OrcB(RegID::decode(rd), RegID::decode(rs1)),
+                    5 if (imm == 0x6b8 && xlen == 64) || (imm == 0x698 && xlen == 32) => InstCode::Rev8(RegID::decode(rd), RegID::decode(rs1)),
                     6 => InstCode::Ori(RegID::decode(rd), RegID::decode(rs1), imm),
                     7 => InstCode::Andi(RegID::decode(rd), RegID::decode(rs1), imm),
                     _ => InstCode::Unknown(raw),
@@ -303,8 +1043,13 @@ pub mod inst {
                 0x1B => match func3 {
                     0 => InstCode::Addiw(RegID::decode(rd), RegID::decode(rs1), imm),
                     1 if imm & 0b111111100000 == 0 => InstCode::Slliw(RegID::decode(rd), RegID::decode(rs1), imm),
+                    1 if imm & 0b111111000000 == 0b000010000000 => InstCode::SlliUw(RegID::decode(rd), RegID::decode(rs1), imm),
+                    1 if imm == 0x600 => InstCode::Clzw(RegID::decode(rd), RegID::decode(rs1)),
+                    1 if imm == 0x601 => InstCode::Ctzw(RegID::decode(rd), RegID::decode(rs1)),
+                    1 if imm == 0x602 => InstCode::Cpopw(RegID::decode(rd), RegID::decode(rs1)),
                     5 if imm & 0b111111100000 == 0 => InstCode::Srliw(RegID::decode(rd), RegID::decode(rs1), imm),
-                    5 if imm & 0b111111100000 == 0b001000000000 => InstCode::Sraiw(RegID::decode(rd), RegID::decode(rs1), imm),
+                    5 if imm & 0b111111100000 == 0b010000000000 => InstCode::Sraiw(RegID::decode(rd), RegID::decode(rs1), imm),
+                    5 if imm & 0b111111100000 == 0b011000000000 => InstCode::Roriw(RegID::decode(rd), RegID::decode(rs1), imm),
                     _ => InstCode::Unknown(raw),
                 }
                 0x67 => match func3 {
@@ -420,6 +1165,412 @@ pub mod inst {
                 _ => unreachable!(),
             }
         }
+
+        /// Encode as a 32-bit instruction, such that `parse` gives back
+        /// the same `InstCode` for `xlen`. Invalid instructions are encoded
+        /// as they were parsed, in the low halfword for compressed ones.
+        pub fn encode(&self, xlen: u32) -> u32 {
+            let r = |func7: u32, rs2: RegID, rs1: RegID, func3: u32, rd: RegID, opcode: u32| {
+                (func7<<25) | ((rs2.encode() as u32)<<20) | ((rs1.encode() as u32)<<15) |
+                (func3<<12) | ((rd.encode() as u32)<<7) | opcode
+            };
+            let i = |imm: i16, rs1: RegID, func3: u32, rd: RegID, opcode: u32| {
+                (((imm as u32) & 0xFFF)<<20) | ((rs1.encode() as u32)<<15) |
+                (func3<<12) | ((rd.encode() as u32)<<7) | opcode
+            };
+            let s = |imm: i16, rs2: RegID, rs1: RegID, func3: u32| {
+                let imm = imm as u32;
+                (((imm>>5) & 0b1111111)<<25) | ((rs2.encode() as u32)<<20) | ((rs1.encode() as u32)<<15) |
+                (func3<<12) | ((imm & 0b11111)<<7) | 0x23
+            };
+            let sb = |ofs: i16, rs1: RegID, rs2: RegID, func3: u32| {
+                let ofs = ofs as u32;
+                (((ofs>>12) & 0b1)<<31) | (((ofs>>5) & 0b111111)<<25) | ((rs2.encode() as u32)<<20) |
+                ((rs1.encode() as u32)<<15) | (func3<<12) | (((ofs>>1) & 0b1111)<<8) |
+                (((ofs>>11) & 0b1)<<7) | 0x63
+            };
+            let csr = |csr: u16, rs1: u32, func3: u32, rd: RegID| {
+                (((csr as u32) & 0xFFF)<<20) | (rs1<<15) | (func3<<12) | ((rd.encode() as u32)<<7) | 0x73
+            };
+
+            match *self {
+                InstCode::Add(rd, rs1, rs2) => r(0x00, rs2, rs1, 0, rd, 0x33),
+                InstCode::Mul(rd, rs1, rs2) => r(0x01, rs2, rs1, 0, rd, 0x33),
+                InstCode::Sub(rd, rs1, rs2) => r(0x20, rs2, rs1, 0, rd, 0x33),
+                InstCode::Sll(rd, rs1, rs2) => r(0x00, rs2, rs1, 1, rd, 0x33),
+                InstCode::Mulh(rd, rs1, rs2) => r(0x01, rs2, rs1, 1, rd, 0x33),
+                InstCode::Slt(rd, rs1, rs2) => r(0x00, rs2, rs1, 2, rd, 0x33),
+                InstCode::Mulhsu(rd, rs1, rs2) => r(0x01, rs2, rs1, 2, rd, 0x33),
+                InstCode::Sltu(rd, rs1, rs2) => r(0x00, rs2, rs1, 3, rd, 0x33),
+                InstCode::Mulhu(rd, rs1, rs2) => r(0x01, rs2, rs1, 3, rd, 0x33),
+                InstCode::Xor(rd, rs1, rs2) => r(0x00, rs2, rs1, 4, rd, 0x33),
+                InstCode::Div(rd, rs1, rs2) => r(0x01, rs2, rs1, 4, rd, 0x33),
+                InstCode::Srl(rd, rs1, rs2) => r(0x00, rs2, rs1, 5, rd, 0x33),
+                InstCode::Sra(rd, rs1, rs2) => r(0x20, rs2, rs1, 5, rd, 0x33),
+                InstCode::Divu(rd, rs1, rs2) => r(0x01, rs2, rs1, 5, rd, 0x33),
+                InstCode::Or(rd, rs1, rs2) => r(0x00, rs2, rs1, 6, rd, 0x33),
+                InstCode::Rem(rd, rs1, rs2) => r(0x01, rs2, rs1, 6, rd, 0x33),
+                InstCode::And(rd, rs1, rs2) => r(0x00, rs2, rs1, 7, rd, 0x33),
+                InstCode::Remu(rd, rs1, rs2) => r(0x01, rs2, rs1, 7, rd, 0x33),
+                InstCode::Addw(rd, rs1, rs2) => r(0x00, rs2, rs1, 0, rd, 0x3B),
+                InstCode::Subw(rd, rs1, rs2) => r(0x20, rs2, rs1, 0, rd, 0x3B),
+                InstCode::Mulw(rd, rs1, rs2) => r(0x01, rs2, rs1, 0, rd, 0x3B),
+                InstCode::Divw(rd, rs1, rs2) => r(0x01, rs2, rs1, 4, rd, 0x3B),
+                InstCode::Sllw(rd, rs1, rs2) => r(0x00, rs2, rs1, 1, rd, 0x3B),
+                InstCode::Srlw(rd, rs1, rs2) => r(0x00, rs2, rs1, 5, rd, 0x3B),
+                InstCode::Sraw(rd, rs1, rs2) => r(0x20, rs2, rs1, 5, rd, 0x3B),
+                InstCode::Divuw(rd, rs1, rs2) => r(0x01, rs2, rs1, 5, rd, 0x3B),
+                InstCode::Remw(rd, rs1, rs2) => r(0x01, rs2, rs1, 6, rd, 0x3B),
+                InstCode::Remuw(rd, rs1, rs2) => r(0x01, rs2, rs1, 7, rd, 0x3B),
+                InstCode::Sh1add(rd, rs1, rs2) => r(0x10, rs2, rs1, 2, rd, 0x33),
+                InstCode::Sh2add(rd, rs1, rs2) => r(0x10, rs2, rs1, 4, rd, 0x33),
+                InstCode::Sh3add(rd, rs1, rs2) => r(0x10, rs2, rs1, 6, rd, 0x33),
+                InstCode::Andn(rd, rs1, rs2) => r(0x20, rs2, rs1, 7, rd, 0x33),
+                InstCode::Orn(rd, rs1, rs2) => r(0x20, rs2, rs1, 6, rd, 0x33),
+                InstCode::Xnor(rd, rs1, rs2) => r(0x20, rs2, rs1, 4, rd, 0x33),
+                InstCode::Max(rd, rs1, rs2) => r(0x05, rs2, rs1, 6, rd, 0x33),
+                InstCode::Maxu(rd, rs1, rs2) => r(0x05, rs2, rs1, 7, rd, 0x33),
+                InstCode::Min(rd, rs1, rs2) => r(0x05, rs2, rs1, 4, rd, 0x33),
+                InstCode::Minu(rd, rs1, rs2) => r(0x05, rs2, rs1, 5, rd, 0x33),
+                InstCode::Rol(rd, rs1, rs2) => r(0x30, rs2, rs1, 1, rd, 0x33),
+                InstCode::Ror(rd, rs1, rs2) => r(0x30, rs2, rs1, 5, rd, 0x33),
+                InstCode::Clmul(rd, rs1, rs2) => r(0x05, rs2, rs1, 1, rd, 0x33),
+                InstCode::Clmulh(rd, rs1, rs2) => r(0x05, rs2, rs1, 3, rd, 0x33),
+                InstCode::Clmulr(rd, rs1, rs2) => r(0x05, rs2, rs1, 2, rd, 0x33),
+                InstCode::Bclr(rd, rs1, rs2) => r(0x24, rs2, rs1, 1, rd, 0x33),
+                InstCode::Bext(rd, rs1, rs2) => r(0x24, rs2, rs1, 5, rd, 0x33),
+                InstCode::Binv(rd, rs1, rs2) => r(0x34, rs2, rs1, 1, rd, 0x33),
+                InstCode::Bset(rd, rs1, rs2) => r(0x14, rs2, rs1, 1, rd, 0x33),
+                InstCode::CzeroEqz(rd, rs1, rs2) => r(0x07, rs2, rs1, 5, rd, 0x33),
+                InstCode::CzeroNez(rd, rs1, rs2) => r(0x07, rs2, rs1, 7, rd, 0x33),
+                InstCode::AddUw(rd, rs1, rs2) => r(0x04, rs2, rs1, 0, rd, 0x3B),
+                InstCode::Sh1addUw(rd, rs1, rs2) => r(0x10, rs2, rs1, 2, rd, 0x3B),
+                InstCode::Sh2addUw(rd, rs1, rs2) => r(0x10, rs2, rs1, 4, rd, 0x3B),
+                InstCode::Sh3addUw(rd, rs1, rs2) => r(0x10, rs2, rs1, 6, rd, 0x3B),
+                InstCode::Rolw(rd, rs1, rs2) => r(0x30, rs2, rs1, 1, rd, 0x3B),
+                InstCode::Rorw(rd, rs1, rs2) => r(0x30, rs2, rs1, 5, rd, 0x3B),
+
+                InstCode::Lb(rd, rs1, imm) => i(imm, rs1, 0, rd, 0x03),
+                InstCode::Lh(rd, rs1, imm) => i(imm, rs1, 1, rd, 0x03),
+                InstCode::Lw(rd, rs1, imm) => i(imm, rs1, 2, rd, 0x03),
+                InstCode::Ld(rd, rs1, imm) => i(imm, rs1, 3, rd, 0x03),
+                InstCode::Lbu(rd, rs1, imm) => i(imm, rs1, 4, rd, 0x03),
+                InstCode::Lhu(rd, rs1, imm) => i(imm, rs1, 5, rd, 0x03),
+                InstCode::Lwu(rd, rs1, imm) => i(imm, rs1, 6, rd, 0x03),
+                // Shifts keep their function bits in the immediate
+                InstCode::Addi(rd, rs1, imm) => i(imm, rs1, 0, rd, 0x13),
+                InstCode::Slli(rd, rs1, imm) => i(imm, rs1, 1, rd, 0x13),
+                InstCode::Slti(rd, rs1, imm) => i(imm, rs1, 2, rd, 0x13),
+                InstCode::Sltiu(rd, rs1, imm) => i(imm, rs1, 3, rd, 0x13),
+                InstCode::Xori(rd, rs1, imm) => i(imm, rs1, 4, rd, 0x13),
+                InstCode::Srli(rd, rs1, imm) | InstCode::Srai(rd, rs1, imm) => i(imm, rs1, 5, rd, 0x13),
+                InstCode::Ori(rd, rs1, imm) => i(imm, rs1, 6, rd, 0x13),
+                InstCode::Andi(rd, rs1, imm) => i(imm, rs1, 7, rd, 0x13),
+                InstCode::Addiw(rd, rs1, imm) => i(imm, rs1, 0, rd, 0x1B),
+                InstCode::Slliw(rd, rs1, imm) => i(imm, rs1, 1, rd, 0x1B),
+                InstCode::Srliw(rd, rs1, imm) | InstCode::Sraiw(rd, rs1, imm) => i(imm, rs1, 5, rd, 0x1B),
+                InstCode::Jalr(rd, rs1, imm) => i(imm, rs1, 0, rd, 0x67),
+                InstCode::SlliUw(rd, rs1, imm) => i(imm, rs1, 1, rd, 0x1B),
+                InstCode::Bclri(rd, rs1, imm) | InstCode::Binvi(rd, rs1, imm) |
+                InstCode::Bseti(rd, rs1, imm) => i(imm, rs1, 1, rd, 0x13),
+                InstCode::Rori(rd, rs1, imm) | InstCode::Bexti(rd, rs1, imm) => i(imm, rs1, 5, rd, 0x13),
+                InstCode::Roriw(rd, rs1, imm) => i(imm, rs1, 5, rd, 0x1B),
+                // Unary operations keep their function in the immediate, and
+                // rev8 and zext.h are encoded differently on RV32
+                InstCode::Clz(rd, rs1) => i(0x600, rs1, 1, rd, 0x13),
+                InstCode::Ctz(rd, rs1) => i(0x601, rs1, 1, rd, 0x13),
+                InstCode::Cpop(rd, rs1) => i(0x602, rs1, 1, rd, 0x13),
+                InstCode::SextB(rd, rs1) => i(0x604, rs1, 1, rd, 0x13),
+                InstCode::SextH(rd, rs1) => i(0x605, rs1, 1, rd, 0x13),
+                InstCode::Clzw(rd, rs1) => i(0x600, rs1, 1, rd, 0x1B),
+                InstCode::Ctzw(rd, rs1) => i(0x601, rs1, 1, rd, 0x1B),
+                InstCode::Cpopw(rd, rs1) => i(0x602, rs1, 1, rd, 0x1B),
+                InstCode::OrcB(rd, rs1) => i(0x287, rs1, 5, rd, 0x13),
+                InstCode::Rev8(rd, rs1) if xlen == 32 => i(0x698, rs1, 5, rd, 0x13),
+                InstCode::Rev8(rd, rs1) => i(0x6b8, rs1, 5, rd, 0x13),
+                InstCode::ZextH(rd, rs1) if xlen == 32 => r(0x04, RegID::X0, rs1, 4, rd, 0x33),
+                InstCode::ZextH(rd, rs1) => r(0x04, RegID::X0, rs1, 4, rd, 0x3B),
+
+                InstCode::Lr(rd, rs1, width, order) => {
+                    r(0b0001000 | order as u32, RegID::X0, rs1, if width == 4 { 2 } else { 3 }, rd, 0x2F)
+                },
+                InstCode::Sc(rd, rs1, rs2, width, order) => {
+                    r(0b0001100 | order as u32, rs2, rs1, if width == 4 { 2 } else { 3 }, rd, 0x2F)
+                },
+                _ if self.amo().is_some() => {
+                    let (rd, rs1, rs2, width, order) = self.amo().unwrap();
+                    let func5 = match self {
+                        InstCode::Amoswap(_,_,_,_,_) => 0b00001,
+                        InstCode::Amoadd(_,_,_,_,_) => 0b00000,
+                        InstCode::Amoxor(_,_,_,_,_) => 0b00100,
+                        InstCode::Amoand(_,_,_,_,_) => 0b01100,
+                        InstCode::Amoor(_,_,_,_,_) => 0b01000,
+                        InstCode::Amomin(_,_,_,_,_) => 0b10000,
+                        InstCode::Amomax(_,_,_,_,_) => 0b10100,
+                        InstCode::Amominu(_,_,_,_,_) => 0b11000,
+                        _ => 0b11100,
+                    };
+                    r((func5<<2) | order as u32, rs2, rs1, if width == 4 { 2 } else { 3 }, rd, 0x2F)
+                },
+
+                InstCode::Ecall => 0x00000073,
+                InstCode::Ebreak => 0x00100073,
+                InstCode::Uret => 0x00200073,
+                InstCode::Sret => 0x10200073,
+                InstCode::Mret => 0x30200073,
+                InstCode::Wfi => 0x10500073,
+                InstCode::SfenceVma(rs1, rs2) => r(0b0001001, rs2, rs1, 0, RegID::X0, 0x73),
+                InstCode::Csrrw(rd, rs1, num) => csr(num, rs1.encode() as u32, 1, rd),
+                InstCode::Csrrs(rd, rs1, num) => csr(num, rs1.encode() as u32, 2, rd),
+                InstCode::Csrrc(rd, rs1, num) => csr(num, rs1.encode() as u32, 3, rd),
+                InstCode::Csrrwi(rd, num, imm) => csr(num, (imm & 0b11111) as u32, 5, rd),
+                InstCode::Csrrsi(rd, num, imm) => csr(num, (imm & 0b11111) as u32, 6, rd),
+                InstCode::Csrrci(rd, num, imm) => csr(num, (imm & 0b11111) as u32, 7, rd),
+
+                InstCode::Sb(rs1, rs2, imm) => s(imm, rs2, rs1, 0),
+                InstCode::Sh(rs1, rs2, imm) => s(imm, rs2, rs1, 1),
+                InstCode::Sw(rs1, rs2, imm) => s(imm, rs2, rs1, 2),
+                InstCode::Sd(rs1, rs2, imm) => s(imm, rs2, rs1, 3),
+
+                InstCode::Beq(rs1, rs2, ofs) => sb(ofs, rs1, rs2, 0),
+                InstCode::Bne(rs1, rs2, ofs) => sb(ofs, rs1, rs2, 1),
+                InstCode::Blt(rs1, rs2, ofs) => sb(ofs, rs1, rs2, 4),
+                InstCode::Bge(rs1, rs2, ofs) => sb(ofs, rs1, rs2, 5),
+                InstCode::Bltu(rs1, rs2, ofs) => sb(ofs, rs1, rs2, 6),
+                InstCode::Bgeu(rs1, rs2, ofs) => sb(ofs, rs1, rs2, 7),
+
+                InstCode::Auipc(rd, imm) => ((imm as u32) & 0xFFFFF000) | ((rd.encode() as u32)<<7) | 0x17,
+                InstCode::Lui(rd, imm) => ((imm as u32) & 0xFFFFF000) | ((rd.encode() as u32)<<7) | 0x37,
+                InstCode::Jal(rd, ofs) => {
+                    let ofs = ofs as u32;
+                    (((ofs>>20) & 0b1)<<31) | (((ofs>>1) & 0b1111111111)<<21) | (((ofs>>11) & 0b1)<<20) |
+                    (((ofs>>12) & 0b11111111)<<12) | ((rd.encode() as u32)<<7) | 0x6f
+                },
+
+                InstCode::Fence(pred, succ) => (((pred & 0b1111) as u32)<<24) | (((succ & 0b1111) as u32)<<20) | 0x0f,
+                InstCode::FenceI => 0x0000100f,
+
+                InstCode::UnknownC(raw) => raw as u32,
+                InstCode::IllegalProlonged => 0b11111,
+                InstCode::Unknown(raw) => raw,
+                _ if self.is_float() => self.encode_float(),
+                _ => self.encode_vector(),
+            }
+        }
+
+        /// Encode as a compressed instruction, if the instruction has one
+        /// for `xlen`.
+        pub fn encode_compressed(&self, xlen: u32) -> Option<u16> {
+            let rv32 = xlen == 32;
+            // Compressed register codes, for x8-x15 only
+            let creg = |reg: RegID| match reg.encode() {
+                reg @ 8..=15 => Some((reg - 8) as u16),
+                _ => None,
+            };
+            let reg = |reg: RegID| reg.encode() as u16;
+            // Compressed floating-point register codes, for f8-f15 only
+            let fcreg = |reg: u8| match reg {
+                8..=15 => Some((reg - 8) as u16),
+                _ => None,
+            };
+            let fits = |imm: i64, bits: u32| imm >= -(1 << (bits - 1)) && imm < (1 << (bits - 1));
+            // 6-bit immediate of CI formats: imm[5] at 12, imm[4:0] at 6:2
+            let ci = |imm: u16| (((imm>>5) & 0b1)<<12) | ((imm & 0b11111)<<2);
+            let zero = RegID::X0;
+            let sp = RegID::X2;
+
+            let raw = match *self {
+                // Quadrant 0
+                InstCode::Addi(rd, rs1, imm) if rs1 == sp && creg(rd).is_some() &&
+                    imm > 0 && imm < 1024 && imm % 4 == 0 =>
+                {
+                    let imm = imm as u16;
+                    (((imm>>4) & 0b11)<<11) | (((imm>>6) & 0b1111)<<7) | (((imm>>2) & 0b1)<<6) |
+                    (((imm>>3) & 0b1)<<5) | (creg(rd).unwrap()<<2)
+                },
+                InstCode::Lw(rd, rs1, imm) | InstCode::Sw(rs1, rd, imm) if creg(rd).is_some() && creg(rs1).is_some() &&
+                    (0..128).contains(&imm) && imm % 4 == 0 =>
+                {
+                    let func3 = if matches!(self, InstCode::Lw(_,_,_)) { 0b010 } else { 0b110 };
+                    let imm = imm as u16;
+                    (func3<<13) | (((imm>>3) & 0b111)<<10) | (creg(rs1).unwrap()<<7) |
+                    (((imm>>2) & 0b1)<<6) | (((imm>>6) & 0b1)<<5) | (creg(rd).unwrap()<<2)
+                },
+                InstCode::Ld(rd, rs1, imm) | InstCode::Sd(rs1, rd, imm) if !rv32 && creg(rd).is_some() && creg(rs1).is_some() &&
+                    (0..256).contains(&imm) && imm % 8 == 0 =>
+                {
+                    let func3 = if matches!(self, InstCode::Ld(_,_,_)) { 0b011 } else { 0b111 };
+                    let imm = imm as u16;
+                    (func3<<13) | (((imm>>3) & 0b111)<<10) | (creg(rs1).unwrap()<<7) |
+                    (((imm>>6) & 0b11)<<5) | (creg(rd).unwrap()<<2)
+                },
+                InstCode::Fl(rd, rs1, imm, 8) | InstCode::Fs(rs1, rd, imm, 8) if fcreg(rd).is_some() && creg(rs1).is_some() &&
+                    (0..256).contains(&imm) && imm % 8 == 0 =>
+                {
+                    let func3 = if matches!(self, InstCode::Fl(_,_,_,_)) { 0b001 } else { 0b101 };
+                    let imm = imm as u16;
+                    (func3<<13) | (((imm>>3) & 0b111)<<10) | (creg(rs1).unwrap()<<7) |
+                    (((imm>>6) & 0b11)<<5) | (fcreg(rd).unwrap()<<2)
+                },
+                InstCode::Fl(rd, rs1, imm, 4) | InstCode::Fs(rs1, rd, imm, 4) if rv32 && fcreg(rd).is_some() &&
+                    creg(rs1).is_some() && (0..128).contains(&imm) && imm % 4 == 0 =>
+                {
+                    let func3 = if matches!(self, InstCode::Fl(_,_,_,_)) { 0b011 } else { 0b111 };
+                    let imm = imm as u16;
+                    (func3<<13) | (((imm>>3) & 0b111)<<10) | (creg(rs1).unwrap()<<7) |
+                    (((imm>>2) & 0b1)<<6) | (((imm>>6) & 0b1)<<5) | (fcreg(rd).unwrap()<<2)
+                },
+
+                // Quadrant 1
+                InstCode::Addi(RegID::X0, RegID::X0, 0) => 0x0001,
+                InstCode::Addi(rd, rs1, imm) if rd == rs1 && rd != zero && imm != 0 && fits(imm as i64, 6) =>
+                    ci(imm as u16) | (reg(rd)<<7) | 0b01,
+                InstCode::Addiw(rd, rs1, imm) if !rv32 && rd == rs1 && rd != zero && fits(imm as i64, 6) =>
+                    (0b001<<13) | ci(imm as u16) | (reg(rd)<<7) | 0b01,
+                InstCode::Addi(rd, rs1, imm) if rs1 == zero && rd != zero && fits(imm as i64, 6) =>
+                    (0b010<<13) | ci(imm as u16) | (reg(rd)<<7) | 0b01,
+                InstCode::Addi(rd, rs1, imm) if rd == sp && rs1 == sp && imm != 0 && imm % 16 == 0 &&
+                    fits(imm as i64, 10) =>
+                {
+                    let imm = imm as u16;
+                    (0b011<<13) | (((imm>>9) & 0b1)<<12) | (reg(sp)<<7) | (((imm>>4) & 0b1)<<6) |
+                    (((imm>>6) & 0b1)<<5) | (((imm>>7) & 0b11)<<3) | (((imm>>5) & 0b1)<<2) | 0b01
+                },
+                InstCode::Lui(rd, imm) if rd != zero && rd != sp && imm != 0 && fits(imm as i64 >> 12, 6) =>
+                    (0b011<<13) | ci((imm>>12) as u16) | (reg(rd)<<7) | 0b01,
+                InstCode::Srli(rd, rs1, imm) | InstCode::Srai(rd, rs1, imm) if rd == rs1 && creg(rd).is_some() &&
+                    imm & 0x3f != 0 =>
+                {
+                    let func2 = if matches!(self, InstCode::Srli(_,_,_)) { 0b00 } else { 0b01 };
+                    (0b100<<13) | ci((imm & 0x3f) as u16) | (func2<<10) | (creg(rd).unwrap()<<7) | 0b01
+                },
+                InstCode::Andi(rd, rs1, imm) if rd == rs1 && creg(rd).is_some() && fits(imm as i64, 6) =>
+                    (0b100<<13) | ci(imm as u16) | (0b10<<10) | (creg(rd).unwrap()<<7) | 0b01,
+                InstCode::Sub(rd, rs1, rs2) | InstCode::Xor(rd, rs1, rs2) | InstCode::Or(rd, rs1, rs2) |
+                InstCode::And(rd, rs1, rs2) | InstCode::Subw(rd, rs1, rs2) | InstCode::Addw(rd, rs1, rs2)
+                    if rd == rs1 && creg(rd).is_some() && creg(rs2).is_some() &&
+                    !(rv32 && matches!(self, InstCode::Subw(_,_,_) | InstCode::Addw(_,_,_))) =>
+                {
+                    let (func6, func2) = match self {
+                        InstCode::Sub(_,_,_) => (0b100011, 0b00),
+                        InstCode::Xor(_,_,_) => (0b100011, 0b01),
+                        InstCode::Or(_,_,_) => (0b100011, 0b10),
+                        InstCode::And(_,_,_) => (0b100011, 0b11),
+                        InstCode::Subw(_,_,_) => (0b100111, 0b00),
+                        _ => (0b100111, 0b01),
+                    };
+                    (func6<<10) | (creg(rd).unwrap()<<7) | (func2<<5) | (creg(rs2).unwrap()<<2) | 0b01
+                },
+                InstCode::Jal(rd @ (RegID::X0 | RegID::X1), ofs) if (rd == zero || rv32) && ofs % 2 == 0 &&
+                    fits(ofs as i64, 12) =>
+                {
+                    let func3 = if rd == zero { 0b101 } else { 0b001 };
+                    let ofs = ofs as u16;
+                    (func3<<13) | (((ofs>>11) & 0b1)<<12) | (((ofs>>4) & 0b1)<<11) | (((ofs>>8) & 0b11)<<9) |
+                    (((ofs>>10) & 0b1)<<8) | (((ofs>>6) & 0b1)<<7) | (((ofs>>7) & 0b1)<<6) |
+                    (((ofs>>1) & 0b111)<<3) | (((ofs>>5) & 0b1)<<2) | 0b01
+                },
+                InstCode::Beq(rs1, RegID::X0, ofs) | InstCode::Bne(rs1, RegID::X0, ofs)
+                    if creg(rs1).is_some() && ofs % 2 == 0 && fits(ofs as i64, 9) =>
+                {
+                    let func3 = if matches!(self, InstCode::Beq(_,_,_)) { 0b110 } else { 0b111 };
+                    let ofs = ofs as u16;
+                    (func3<<13) | (((ofs>>8) & 0b1)<<12) | (((ofs>>3) & 0b11)<<10) | (creg(rs1).unwrap()<<7) |
+                    (((ofs>>6) & 0b11)<<5) | (((ofs>>1) & 0b11)<<3) | (((ofs>>5) & 0b1)<<2) | 0b01
+                },
+
+                // Quadrant 2
+                InstCode::Slli(rd, rs1, imm) if rd == rs1 && rd != zero && imm & 0x3f != 0 =>
+                    ci((imm & 0x3f) as u16) | (reg(rd)<<7) | 0b10,
+                InstCode::Lw(rd, rs1, imm) if rs1 == sp && rd != zero && (0..256).contains(&imm) && imm % 4 == 0 => {
+                    let imm = imm as u16;
+                    (0b010<<13) | (((imm>>5) & 0b1)<<12) | (reg(rd)<<7) | (((imm>>2) & 0b111)<<4) |
+                    (((imm>>6) & 0b11)<<2) | 0b10
+                },
+                InstCode::Ld(rd, rs1, imm) if !rv32 && rs1 == sp && rd != zero && (0..512).contains(&imm) && imm % 8 == 0 => {
+                    let imm = imm as u16;
+                    (0b011<<13) | (((imm>>5) & 0b1)<<12) | (reg(rd)<<7) | (((imm>>3) & 0b11)<<5) |
+                    (((imm>>6) & 0b111)<<2) | 0b10
+                },
+                InstCode::Jalr(RegID::X0, rs1, 0) if rs1 != zero => (0b1000<<12) | (reg(rs1)<<7) | 0b10,
+                InstCode::Add(rd, RegID::X0, rs2) if rd != zero && rs2 != zero =>
+                    (0b1000<<12) | (reg(rd)<<7) | (reg(rs2)<<2) | 0b10,
+                InstCode::Ebreak => 0x9002,
+                InstCode::Jalr(RegID::X1, rs1, 0) if rs1 != zero => (0b1001<<12) | (reg(rs1)<<7) | 0b10,
+                InstCode::Add(rd, rs1, rs2) if rd == rs1 && rd != zero && rs2 != zero =>
+                    (0b1001<<12) | (reg(rd)<<7) | (reg(rs2)<<2) | 0b10,
+                InstCode::Sw(rs1, rs2, imm) if rs1 == sp && (0..256).contains(&imm) && imm % 4 == 0 => {
+                    let imm = imm as u16;
+                    (0b110<<13) | (((imm>>2) & 0b1111)<<9) | (((imm>>6) & 0b11)<<7) | (reg(rs2)<<2) | 0b10
+                },
+                InstCode::Sd(rs1, rs2, imm) if !rv32 && rs1 == sp && (0..512).contains(&imm) && imm % 8 == 0 => {
+                    let imm = imm as u16;
+                    (0b111<<13) | (((imm>>3) & 0b111)<<10) | (((imm>>6) & 0b111)<<7) | (reg(rs2)<<2) | 0b10
+                },
+                InstCode::Fl(rd, rs1, imm, 8) if rs1 == sp && (0..512).contains(&imm) && imm % 8 == 0 => {
+                    let imm = imm as u16;
+                    (0b001<<13) | (((imm>>5) & 0b1)<<12) | ((rd as u16)<<7) | (((imm>>3) & 0b11)<<5) |
+                    (((imm>>6) & 0b111)<<2) | 0b10
+                },
+                InstCode::Fl(rd, rs1, imm, 4) if rv32 && rs1 == sp && (0..256).contains(&imm) && imm % 4 == 0 => {
+                    let imm = imm as u16;
+                    (0b011<<13) | (((imm>>5) & 0b1)<<12) | ((rd as u16)<<7) | (((imm>>2) & 0b111)<<4) |
+                    (((imm>>6) & 0b11)<<2) | 0b10
+                },
+                InstCode::Fs(rs1, rs2, imm, 8) if rs1 == sp && (0..512).contains(&imm) && imm % 8 == 0 => {
+                    let imm = imm as u16;
+                    (0b101<<13) | (((imm>>3) & 0b111)<<10) | (((imm>>6) & 0b111)<<7) | ((rs2 as u16)<<2) | 0b10
+                },
+                InstCode::Fs(rs1, rs2, imm, 4) if rv32 && rs1 == sp && (0..256).contains(&imm) && imm % 4 == 0 => {
+                    let imm = imm as u16;
+                    (0b111<<13) | (((imm>>2) & 0b1111)<<9) | (((imm>>6) & 0b11)<<7) | ((rs2 as u16)<<2) | 0b10
+                },
+                _ => return None,
+            };
+            Some(raw)
+        }
+    }
+
+    /// Instruction categories, as reported by `--stats`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+    pub enum InstCategory {
+        Alu,
+        MulDiv,
+        Load,
+        Store,
+        Branch,
+        Jump,
+        System,
+        /// Vector instructions other than loads and stores
+        Vector,
+        /// F and D instructions other than loads and stores
+        Float,
+        Illegal,
+    }
+
+    impl InstCategory {
+        pub const ALL: [InstCategory; 10] = [
+            InstCategory::Alu, InstCategory::MulDiv, InstCategory::Load, InstCategory::Store,
+            InstCategory::Branch, InstCategory::Jump, InstCategory::System, InstCategory::Vector,
+            InstCategory::Float, InstCategory::Illegal,
+        ];
+    }
+
+    impl fmt::Display for InstCategory {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                InstCategory::Alu => write!(f, "alu"),
+                InstCategory::MulDiv => write!(f, "muldiv"),
+                InstCategory::Load => write!(f, "load"),
+                InstCategory::Store => write!(f, "store"),
+                InstCategory::Branch => write!(f, "branch"),
+                InstCategory::Jump => write!(f, "jump"),
+                InstCategory::System => write!(f, "system"),
+                InstCategory::Vector => write!(f, "vector"),
+                InstCategory::Float => write!(f, "float"),
+                InstCategory::Illegal => write!(f, "illegal"),
+            }
+        }
     }
 
     /// Instruction stages.
@@ -451,6 +1602,7 @@ pub mod inst {
         /// Internal states
         code: InstCode,
         pc: u64,
+        len: u64,           // Length in bytes
         next_pc: u64,
         stage: Stage,
         progress: usize,    // Progress in current stage
@@ -468,6 +1620,7 @@ pub mod inst {
             Inst {
                 code: InstCode::Unknown(0),
                 pc: 0,
+                len: 4,
                 next_pc: 0,
                 stage: Stage::Fetch,
                 progress: 0,
@@ -491,6 +1644,145 @@ pub mod inst {
         }
 
 
+        /// On RV32, shifts see only the lower 32 bits of rs1 and the
+        /// lower 5 bits of the shift amount, and unsigned divisions see
+        /// only the lower 32 bits of their operands.
+        fn rv32_operands(&mut self) {
+            match self.code {
+                InstCode::Sll(_,_,_) | InstCode::Sra(_,_,_) => self.val2 &= 0b11111,
+                InstCode::Srl(_,_,_) => {
+                    self.val1 &= 0xFFFFFFFF;
+                    self.val2 &= 0b11111;
+                },
+                InstCode::Srli(_,_,_) => self.val1 &= 0xFFFFFFFF,
+                InstCode::Divu(_,_,_) | InstCode::Remu(_,_,_) => {
+                    self.val1 &= 0xFFFFFFFF;
+                    self.val2 &= 0xFFFFFFFF;
+                },
+                _ => {}
+            }
+        }
+
+        /// On RV32, registers hold sign-extended 32-bit values, and
+        /// addresses are 32-bit. HLT_ADDR is kept as is, being the
+        /// sign-extension of its lower 32 bits.
+        fn rv32_result(mut self, prog: &mut Program) -> Self {
+            match self.code {
+                InstCode::Mulh(_,_,_) => {
+                    self.val_e = ((self.val1 as i32 as i64 * self.val2 as i32 as i64) >> 32) as u64;
+                },
+                InstCode::Mulhsu(_,_,_) => {
+                    self.val_e = ((self.val1 as i32 as i64 * self.val2 as u32 as i64) >> 32) as u64;
+                },
+                InstCode::Mulhu(_,_,_) => {
+                    self.val_e = (self.val1 as u32 as u64 * self.val2 as u32 as u64) >> 32;
+                },
+                _ => {}
+            }
+            if self.code.mem_width().is_some() {
+                self.val_e &= 0xFFFFFFFF;
+            }
+            else if let Some(rd) = self.code.rd() {
+                self.val_e = self.val_e as i32 as i64 as u64;
+                prog.registers.forward(rd, self.val_e);
+            }
+            if self.next_pc as u32 != HLT_ADDR as u32 {
+                self.next_pc &= 0xFFFFFFFF;
+            }
+            self
+        }
+
+        /// The value an AMO stores, from the value in memory `val_m` and
+        /// rs2. The W variants compare the lower 32 bits.
+        fn amo_value(&self) -> u64 {
+            let (old, src) = (self.val_m, self.val2);
+            let (signed, unsigned) = match self.code.amo() {
+                Some((_,_,_,4,_)) => ((old as i32 as i64, src as i32 as i64), (old as u32 as u64, src as u32 as u64)),
+                _ => ((old as i64, src as i64), (old, src)),
+            };
+            match self.code {
+                InstCode::Amoswap(_,_,_,_,_) => src,
+                InstCode::Amoadd(_,_,_,_,_) => old.wrapping_add(src),
+                InstCode::Amoxor(_,_,_,_,_) => old ^ src,
+                InstCode::Amoand(_,_,_,_,_) => old & src,
+                InstCode::Amoor(_,_,_,_,_) => old | src,
+                InstCode::Amomin(_,_,_,_,_) => signed.0.min(signed.1) as u64,
+                InstCode::Amomax(_,_,_,_,_) => signed.0.max(signed.1) as u64,
+                InstCode::Amominu(_,_,_,_,_) => unsigned.0.min(unsigned.1),
+                _ => unsigned.0.max(unsigned.1),
+            }
+        }
+
+        /// The result of a Zba/Zbb/Zbc/Zbs instruction. Bit indices are
+        /// taken modulo XLEN, and on RV32 rotations, counts and byte
+        /// reversal only see the lower 32 bits of rs1.
+        fn bitmanip(&self, xlen: u32) -> u64 {
+            let (val1, val2) = (self.val1, self.val2);
+            let rv32 = xlen == 32;
+            let index = |val: u64| (val & (xlen as u64 - 1)) as u32;
+            let uw = |val: u64| val & 0xFFFFFFFF;
+            // Carry-less product of the lower XLEN bits
+            let clmul = || {
+                let mask = if rv32 { 0xFFFFFFFF } else { u64::MAX };
+                (0..xlen).filter(|bit| ((val2 & mask) >> bit) & 1 == 1)
+                    .fold(0u128, |acc, bit| acc ^ (((val1 & mask) as u128) << bit))
+            };
+
+            match self.code {
+                InstCode::Sh1add(_,_,_) => (val1 << 1).wrapping_add(val2),
+                InstCode::Sh2add(_,_,_) => (val1 << 2).wrapping_add(val2),
+                InstCode::Sh3add(_,_,_) => (val1 << 3).wrapping_add(val2),
+                InstCode::AddUw(_,_,_) => uw(val1).wrapping_add(val2),
+                InstCode::Sh1addUw(_,_,_) => (uw(val1) << 1).wrapping_add(val2),
+                InstCode::Sh2addUw(_,_,_) => (uw(val1) << 2).wrapping_add(val2),
+                InstCode::Sh3addUw(_,_,_) => (uw(val1) << 3).wrapping_add(val2),
+                InstCode::SlliUw(_,_,imm) => uw(val1) << index(imm as u64),
+                InstCode::Andn(_,_,_) => val1 & !val2,
+                InstCode::Orn(_,_,_) => val1 | !val2,
+                InstCode::Xnor(_,_,_) => !(val1 ^ val2),
+                InstCode::Max(_,_,_) => i64::max(val1 as i64, val2 as i64) as u64,
+                InstCode::Maxu(_,_,_) => u64::max(val1, val2),
+                InstCode::Min(_,_,_) => i64::min(val1 as i64, val2 as i64) as u64,
+                InstCode::Minu(_,_,_) => u64::min(val1, val2),
+                InstCode::Rol(_,_,_) if rv32 => (val1 as u32).rotate_left(index(val2)) as u64,
+                InstCode::Rol(_,_,_) => val1.rotate_left(index(val2)),
+                InstCode::Ror(_,_,_) if rv32 => (val1 as u32).rotate_right(index(val2)) as u64,
+                InstCode::Ror(_,_,_) => val1.rotate_right(index(val2)),
+                InstCode::Rori(_,_,imm) if rv32 => (val1 as u32).rotate_right(index(imm as u64)) as u64,
+                InstCode::Rori(_,_,imm) => val1.rotate_right(index(imm as u64)),
+                InstCode::Rolw(_,_,_) => (val1 as u32).rotate_left((val2 & 0b11111) as u32) as i32 as i64 as u64,
+                InstCode::Rorw(_,_,_) => (val1 as u32).rotate_right((val2 & 0b11111) as u32) as i32 as i64 as u64,
+                InstCode::Roriw(_,_,imm) => (val1 as u32).rotate_right((imm & 0b11111) as u32) as i32 as i64 as u64,
+                InstCode::Clmul(_,_,_) => clmul() as u64,
+                InstCode::Clmulh(_,_,_) => (clmul() >> xlen) as u64,
+                InstCode::Clmulr(_,_,_) => (clmul() >> (xlen - 1)) as u64,
+                InstCode::Bclr(_,_,_) => val1 & !(1 << index(val2)),
+                InstCode::Bext(_,_,_) => (val1 >> index(val2)) & 1,
+                InstCode::Binv(_,_,_) => val1 ^ (1 << index(val2)),
+                InstCode::Bset(_,_,_) => val1 | (1 << index(val2)),
+                InstCode::Bclri(_,_,imm) => val1 & !(1 << index(imm as u64)),
+                InstCode::Bexti(_,_,imm) => (val1 >> index(imm as u64)) & 1,
+                InstCode::Binvi(_,_,imm) => val1 ^ (1 << index(imm as u64)),
+                InstCode::Bseti(_,_,imm) => val1 | (1 << index(imm as u64)),
+                InstCode::Clz(_,_) if rv32 => (val1 as u32).leading_zeros() as u64,
+                InstCode::Clz(_,_) => val1.leading_zeros() as u64,
+                InstCode::Ctz(_,_) if rv32 => (val1 as u32).trailing_zeros() as u64,
+                InstCode::Ctz(_,_) => val1.trailing_zeros() as u64,
+                InstCode::Cpop(_,_) if rv32 => (val1 as u32).count_ones() as u64,
+                InstCode::Cpop(_,_) => val1.count_ones() as u64,
+                InstCode::Clzw(_,_) => (val1 as u32).leading_zeros() as u64,
+                InstCode::Ctzw(_,_) => (val1 as u32).trailing_zeros() as u64,
+                InstCode::Cpopw(_,_) => (val1 as u32).count_ones() as u64,
+                InstCode::SextB(_,_) => val1 as i8 as i64 as u64,
+                InstCode::SextH(_,_) => val1 as i16 as i64 as u64,
+                InstCode::ZextH(_,_) => val1 & 0xFFFF,
+                InstCode::OrcB(_,_) => u64::from_le_bytes(val1.to_le_bytes().map(|byte| if byte != 0 { 0xFF } else { 0 })),
+                InstCode::Rev8(_,_) if rv32 => (val1 as u32).swap_bytes() as u64,
+                InstCode::Rev8(_,_) => val1.swap_bytes(),
+                _ => unreachable!(),
+            }
+        }
+
         /// Advance this instruction by one cycle. Note that this method
         /// consumes `self`, transforming or dropping the instruction as
         /// appropriate. If control hazard is detected, the correct PC
@@ -512,7 +1804,11 @@ pub mod inst {
                     match prog.mem_load(prog.program_counter, 4, true) {
                         Some((data, rem)) => {
                             bytes.extend(data);
-                            if rem != 0 {
+                            // A compressed instruction may end the VMA
+                            if rem == 2 && bytes[0] & 0b11 != 0b11 {
+                                bytes.extend([0, 0]);
+                            }
+                            else if rem != 0 {
                                 print!("{}", "[Warning] ".yellow().bold());
                                 println!("Fetching from {:x} across VMAs", prog.program_counter);
                                 return Err(HLT_ADDR);
@@ -526,10 +1822,29 @@ pub mod inst {
                     }
                     let raw = u32::from_le_bytes(bytes.as_slice().try_into().unwrap());
 
-                    let (code, len) = InstCode::parse((raw & 0xFFFF) as u16, (raw >> 16) as u16);
+                    let (code, len) = InstCode::parse((raw & 0xFFFF) as u16, (raw >> 16) as u16, prog.isa.xlen);
                     self.code = code;
                     self.pc = prog.program_counter;
-                    self.next_pc = prog.program_counter + (len as u64) / 8;
+                    self.len = (len as u64) / 8;
+                    self.next_pc = prog.program_counter + self.len;
+
+                    // Instructions of the extensions the hart lacks are illegal
+                    let ext = match code.extension() {
+                        Extension::F if len == 16 => Extension::Zcf,
+                        Extension::D if len == 16 => Extension::Zcd,
+                        _ if len == 16 => Extension::Zca,
+                        ext => ext,
+                    };
+                    if !prog.isa.has(ext) {
+                        print!("{}", "[Warning] ".yellow().bold());
+                        println!("Illegal instruction {} at {:x}, extension {} is not enabled", code.mnemonic(), self.pc, ext);
+                        return Err(HLT_ADDR);
+                    }
+                    if prog.isa.xlen == 32 && code.rv64_only() {
+                        print!("{}", "[Warning] ".yellow().bold());
+                        println!("Illegal instruction {} at {:x} on RV32", code.mnemonic(), self.pc);
+                        return Err(HLT_ADDR);
+                    }
 
                     match code {
                         InstCode::Unknown(raw) => {
@@ -537,11 +1852,6 @@ pub mod inst {
                             println!("Unknown instruction {:x}", raw);
                             return Err(HLT_ADDR);
                         },
-                        InstCode::IllegalCf(raw) => {
-                            print!("{}", "[Warning] ".yellow().bold());
-                            println!("Floating point compressed instruction not supported: {:x}", raw);
-                            return Err(HLT_ADDR);
-                        },
                         InstCode::UnknownC(raw) => {
                             print!("{}", "[Warning] ".yellow().bold());
                             println!("Unknown compressed instruction {:x}", raw);
@@ -552,8 +1862,7 @@ pub mod inst {
                             println!("Prolonged instruction not supported");
                             return Err(HLT_ADDR);
                         },
-                        InstCode::Fence(_,_) | InstCode::FenceI | InstCode::Csrrw(_,_,_) | InstCode::Csrrs(_,_,_) |
-                        InstCode::Csrrc(_,_,_) | InstCode::Csrrwi(_,_,_) | InstCode::Csrrsi(_,_,_) | InstCode::Csrrci(_,_,_) |
+                        InstCode::Fence(_,_) | InstCode::FenceI |
                         InstCode::Ebreak | InstCode::Uret | InstCode::Sret | InstCode::Mret | InstCode::Wfi |
                         InstCode::SfenceVma(_,_) => {
                             print!("{}", "[Warning] ".yellow().bold());
@@ -578,14 +1887,18 @@ pub mod inst {
                         InstCode::Sll(rd, rs1, rs2) |
                         InstCode::Mulh(rd, rs1, rs2) |
                         InstCode::Slt(rd, rs1, rs2) |
+                        InstCode::Mulhsu(rd, rs1, rs2) |
                         InstCode::Sltu(rd, rs1, rs2) |
+                        InstCode::Mulhu(rd, rs1, rs2) |
                         InstCode::Xor(rd, rs1, rs2) |
                         InstCode::Div(rd, rs1, rs2) |
                         InstCode::Srl(rd, rs1, rs2) |
                         InstCode::Sra(rd, rs1, rs2) |
+                        InstCode::Divu(rd, rs1, rs2) |
                         InstCode::Or(rd, rs1, rs2) |
                         InstCode::Rem(rd, rs1, rs2) |
                         InstCode::And(rd, rs1, rs2) |
+                        InstCode::Remu(rd, rs1, rs2) |
                         InstCode::Addw(rd, rs1, rs2) |
                         InstCode::Subw(rd, rs1, rs2) |
                         InstCode::Mulw(rd, rs1, rs2) |
@@ -593,7 +1906,46 @@ pub mod inst {
                         InstCode::Sllw(rd, rs1, rs2) |
                         InstCode::Srlw(rd, rs1, rs2) |
                         InstCode::Sraw(rd, rs1, rs2) |
-                        InstCode::Remw(rd, rs1, rs2) => {
+                        InstCode::Divuw(rd, rs1, rs2) |
+                        InstCode::Remw(rd, rs1, rs2) |
+                        InstCode::Remuw(rd, rs1, rs2) |
+                        InstCode::Sh1add(rd, rs1, rs2) |
+                        InstCode::Sh2add(rd, rs1, rs2) |
+                        InstCode::Sh3add(rd, rs1, rs2) |
+                        InstCode::AddUw(rd, rs1, rs2) |
+                        InstCode::Sh1addUw(rd, rs1, rs2) |
+                        InstCode::Sh2addUw(rd, rs1, rs2) |
+                        InstCode::Sh3addUw(rd, rs1, rs2) |
+                        InstCode::Andn(rd, rs1, rs2) |
+                        InstCode::Orn(rd, rs1, rs2) |
+                        InstCode::Xnor(rd, rs1, rs2) |
+                        InstCode::Max(rd, rs1, rs2) |
+                        InstCode::Maxu(rd, rs1, rs2) |
+                        InstCode::Min(rd, rs1, rs2) |
+                        InstCode::Minu(rd, rs1, rs2) |
+                        InstCode::Rol(rd, rs1, rs2) |
+                        InstCode::Ror(rd, rs1, rs2) |
+                        InstCode::Rolw(rd, rs1, rs2) |
+                        InstCode::Rorw(rd, rs1, rs2) |
+                        InstCode::Clmul(rd, rs1, rs2) |
+                        InstCode::Clmulh(rd, rs1, rs2) |
+                        InstCode::Clmulr(rd, rs1, rs2) |
+                        InstCode::Bclr(rd, rs1, rs2) |
+                        InstCode::Bext(rd, rs1, rs2) |
+                        InstCode::Binv(rd, rs1, rs2) |
+                        InstCode::Bset(rd, rs1, rs2) |
+                        InstCode::CzeroEqz(rd, rs1, rs2) |
+                        InstCode::CzeroNez(rd, rs1, rs2) |
+                        InstCode::Sc(rd, rs1, rs2, _, _) |
+                        InstCode::Amoswap(rd, rs1, rs2, _, _) |
+                        InstCode::Amoadd(rd, rs1, rs2, _, _) |
+                        InstCode::Amoxor(rd, rs1, rs2, _, _) |
+                        InstCode::Amoand(rd, rs1, rs2, _, _) |
+                        InstCode::Amoor(rd, rs1, rs2, _, _) |
+                        InstCode::Amomin(rd, rs1, rs2, _, _) |
+                        InstCode::Amomax(rd, rs1, rs2, _, _) |
+                        InstCode::Amominu(rd, rs1, rs2, _, _) |
+                        InstCode::Amomaxu(rd, rs1, rs2, _, _) => {
                             // Read from both rs1 and rs2. Also take the write lock
                             // of rd, to prevent data hazard.
                             match prog.registers.read(rs1) {
@@ -631,6 +1983,13 @@ pub mod inst {
                         InstCode::Ori(rd, rs1, imm) |
                         InstCode::Andi(rd, rs1, imm) |
                         InstCode::Addiw(rd, rs1, imm) |
+                        InstCode::SlliUw(rd, rs1, imm) |
+                        InstCode::Rori(rd, rs1, imm) |
+                        InstCode::Roriw(rd, rs1, imm) |
+                        InstCode::Bclri(rd, rs1, imm) |
+                        InstCode::Bexti(rd, rs1, imm) |
+                        InstCode::Binvi(rd, rs1, imm) |
+                        InstCode::Bseti(rd, rs1, imm) |
                         InstCode::Jalr(rd, rs1, imm) => {
                             match prog.registers.read(rs1) {
                                 Some(val) => self.val1 = val,
@@ -642,6 +2001,51 @@ pub mod inst {
                             self.stage = Stage::Execute;
                             Ok(self)
                         },
+                        // Unary:
+                        InstCode::Clz(rd, rs1) |
+                        InstCode::Ctz(rd, rs1) |
+                        InstCode::Cpop(rd, rs1) |
+                        InstCode::Clzw(rd, rs1) |
+                        InstCode::Ctzw(rd, rs1) |
+                        InstCode::Cpopw(rd, rs1) |
+                        InstCode::SextB(rd, rs1) |
+                        InstCode::SextH(rd, rs1) |
+                        InstCode::ZextH(rd, rs1) |
+                        InstCode::OrcB(rd, rs1) |
+                        InstCode::Rev8(rd, rs1) => {
+                            match prog.registers.read(rs1) {
+                                Some(val) => self.val1 = val,
+                                None => return Ok(self), // Stall
+                            }
+
+                            prog.registers.lock(rd);
+
+                            self.stage = Stage::Execute;
+                            Ok(self)
+                        },
+                        // Zicsr: val1 is R[rs1] or the immediate
+                        InstCode::Csrrw(rd, rs1, _) |
+                        InstCode::Csrrs(rd, rs1, _) |
+                        InstCode::Csrrc(rd, rs1, _) => {
+                            match prog.registers.read(rs1) {
+                                Some(val) => self.val1 = val,
+                                None => return Ok(self), // Stall
+                            }
+
+                            prog.registers.lock(rd);
+
+                            self.stage = Stage::Execute;
+                            Ok(self)
+                        },
+                        InstCode::Csrrwi(rd, _, imm) |
+                        InstCode::Csrrsi(rd, _, imm) |
+                        InstCode::Csrrci(rd, _, imm) => {
+                            self.val1 = imm as u64;
+                            prog.registers.lock(rd);
+
+                            self.stage = Stage::Execute;
+                            Ok(self)
+                        },
                         InstCode::Ecall => {
                             // Read A0 and A1
                             match prog.registers.read(RegID::X10) {
@@ -652,6 +2056,21 @@ pub mod inst {
                                 Some(val) => self.val2 = val,
                                 None => return Ok(self), // Stall
                             }
+                            // System calls of a Linux process return in A0
+                            if prog.linux.is_some() {
+                                prog.registers.lock(RegID::X10);
+                            }
+                            self.stage = Stage::Execute;
+                            Ok(self)
+                        },
+
+                        InstCode::Lr(rd, rs1, _, _) => {
+                            match prog.registers.read(rs1) {
+                                Some(val) => self.val1 = val,
+                                None => return Ok(self), // Stall
+                            }
+                            prog.registers.lock(rd);
+
                             self.stage = Stage::Execute;
                             Ok(self)
                         },
@@ -701,6 +2120,46 @@ pub mod inst {
                             Ok(self)
                         },
 
+                        // F/D: val1 is R[rs1] of loads, stores and moves or
+                        // conversions from integers
+                        _ if self.code.is_float() => {
+                            if let Some(rs1) = self.code.float_source() {
+                                match prog.registers.read(rs1) {
+                                    Some(val) => self.val1 = val,
+                                    None => return Ok(self), // Stall
+                                }
+                            }
+                            if let Some(rd) = self.code.float_rd() {
+                                prog.registers.lock(rd);
+                            }
+
+                            self.stage = Stage::Execute;
+                            Ok(self)
+                        },
+
+                        // V: val1 is R[rs1], val2 is R[rs2] (stride or vtype)
+                        _ if self.code.is_vector() => {
+                            let (rs1, rs2) = self.code.vector_sources();
+                            if let Some(rs1) = rs1 {
+                                match prog.registers.read(rs1) {
+                                    Some(val) => self.val1 = val,
+                                    None => return Ok(self), // Stall
+                                }
+                            }
+                            if let Some(rs2) = rs2 {
+                                match prog.registers.read(rs2) {
+                                    Some(val) => self.val2 = val,
+                                    None => return Ok(self), // Stall
+                                }
+                            }
+                            if let Some(rd) = self.code.vector_rd() {
+                                prog.registers.lock(rd);
+                            }
+
+                            self.stage = Stage::Execute;
+                            Ok(self)
+                        },
+
                         // Unsupported
                         _ => unreachable!(),
                     }
@@ -708,7 +2167,10 @@ pub mod inst {
 
                 // Instruction execution. May drop self if control hazard is detected.
                 Stage::Execute => {
-                    match self.code {
+                    if prog.isa.xlen == 32 {
+                        self.rv32_operands();
+                    }
+                    let result = match self.code {
                         InstCode::Add(rd,_,_) => {
                             self.val_e = u64::wrapping_add(self.val1, self.val2);
                             // Forward
@@ -738,7 +2200,7 @@ pub mod inst {
                             Ok(self)
                         },
                         InstCode::Mulh(rd,_,_) => {
-                            self.val_e = ((self.val1 as i128 * self.val2 as i128)>>64) as u64;
+                            self.val_e = ((self.val1 as i64 as i128 * self.val2 as i64 as i128)>>64) as u64;
                             // Forward
                             prog.registers.forward(rd, self.val_e);
                             self.stage = Stage::Memory;
@@ -886,6 +2348,168 @@ pub mod inst {
                             self.stage = Stage::Memory;
                             Ok(self)
                         },
+                        InstCode::Mulhsu(rd,_,_) => {
+                            self.val_e = ((self.val1 as i64 as i128 * self.val2 as i128)>>64) as u64;
+                            // Forward
+                            prog.registers.forward(rd, self.val_e);
+                            self.stage = Stage::Memory;
+                            Ok(self)
+                        },
+                        InstCode::Mulhu(rd,_,_) => {
+                            self.val_e = ((self.val1 as u128 * self.val2 as u128)>>64) as u64;
+                            // Forward
+                            prog.registers.forward(rd, self.val_e);
+                            self.stage = Stage::Memory;
+                            Ok(self)
+                        },
+                        InstCode::Divu(rd,_,_) => {
+                            if self.val2 == 0 {
+                                print!("{}", "[Warning] ".yellow().bold());
+                                println!("Divide by 0 Execption");
+                                return Err(HLT_ADDR);
+                            }
+                            self.val_e = self.val1 / self.val2;
+                            // Forward
+                            prog.registers.forward(rd, self.val_e);
+                            self.stage = Stage::Memory;
+                            Ok(self)
+                        },
+                        InstCode::Remu(rd,_,_) => {
+                            if self.val2 == 0 {
+                                print!("{}", "[Warning] ".yellow().bold());
+                                println!("Reminder by 0 Execption");
+                                return Err(HLT_ADDR);
+                            }
+                            self.val_e = self.val1 % self.val2;
+                            // Forward
+                            prog.registers.forward(rd, self.val_e);
+                            self.stage = Stage::Memory;
+                            Ok(self)
+                        },
+                        InstCode::Divuw(rd,_,_) => {
+                            if self.val2 as u32 == 0 {
+                                print!("{}", "[Warning] ".yellow().bold());
+                                println!("Divide by 0 Execption");
+                                return Err(HLT_ADDR);
+                            }
+                            self.val_e = (self.val1 as u32 / self.val2 as u32) as i32 as i64 as u64;
+                            // Forward
+                            prog.registers.forward(rd, self.val_e);
+                            self.stage = Stage::Memory;
+                            Ok(self)
+                        },
+                        InstCode::Remuw(rd,_,_) => {
+                            if self.val2 as u32 == 0 {
+                                print!("{}", "[Warning] ".yellow().bold());
+                                println!("Reminder by 0 Execption");
+                                return Err(HLT_ADDR);
+                            }
+                            self.val_e = (self.val1 as u32 % self.val2 as u32) as i32 as i64 as u64;
+                            // Forward
+                            prog.registers.forward(rd, self.val_e);
+                            self.stage = Stage::Memory;
+                            Ok(self)
+                        },
+
+                        // A: the address, which must be aligned
+                        InstCode::Lr(_,_,width,_) | InstCode::Sc(_,_,_,width,_) |
+                        InstCode::Amoswap(_,_,_,width,_) | InstCode::Amoadd(_,_,_,width,_) |
+                        InstCode::Amoxor(_,_,_,width,_) | InstCode::Amoand(_,_,_,width,_) |
+                        InstCode::Amoor(_,_,_,width,_) | InstCode::Amomin(_,_,_,width,_) |
+                        InstCode::Amomax(_,_,_,width,_) | InstCode::Amominu(_,_,_,width,_) |
+                        InstCode::Amomaxu(_,_,_,width,_) => {
+                            if !self.val1.is_multiple_of(width as u64) {
+                                print!("{}", "[Warning] ".yellow().bold());
+                                println!("Misaligned {} at {:x}", self.code.mnemonic(), self.val1);
+                                return Err(HLT_ADDR);
+                            }
+                            self.val_e = self.val1;
+                            self.stage = Stage::Memory;
+                            Ok(self)
+                        },
+
+                        // Bit manipulation
+                        InstCode::Sh1add(rd,_,_) | InstCode::Sh2add(rd,_,_) | InstCode::Sh3add(rd,_,_) |
+                        InstCode::AddUw(rd,_,_) | InstCode::Sh1addUw(rd,_,_) | InstCode::Sh2addUw(rd,_,_) |
+                        InstCode::Sh3addUw(rd,_,_) | InstCode::Andn(rd,_,_) | InstCode::Orn(rd,_,_) |
+                        InstCode::Xnor(rd,_,_) | InstCode::Max(rd,_,_) | InstCode::Maxu(rd,_,_) |
+                        InstCode::Min(rd,_,_) | InstCode::Minu(rd,_,_) | InstCode::Rol(rd,_,_) |
+                        InstCode::Ror(rd,_,_) | InstCode::Rolw(rd,_,_) | InstCode::Rorw(rd,_,_) |
+                        InstCode::Clmul(rd,_,_) | InstCode::Clmulh(rd,_,_) | InstCode::Clmulr(rd,_,_) |
+                        InstCode::Bclr(rd,_,_) | InstCode::Bext(rd,_,_) | InstCode::Binv(rd,_,_) |
+                        InstCode::Bset(rd,_,_) | InstCode::SlliUw(rd,_,_) | InstCode::Rori(rd,_,_) |
+                        InstCode::Roriw(rd,_,_) | InstCode::Bclri(rd,_,_) | InstCode::Bexti(rd,_,_) |
+                        InstCode::Binvi(rd,_,_) | InstCode::Bseti(rd,_,_) | InstCode::Clz(rd,_) |
+                        InstCode::Ctz(rd,_) | InstCode::Cpop(rd,_) | InstCode::Clzw(rd,_) |
+                        InstCode::Ctzw(rd,_) | InstCode::Cpopw(rd,_) | InstCode::SextB(rd,_) |
+                        InstCode::SextH(rd,_) | InstCode::ZextH(rd,_) | InstCode::OrcB(rd,_) |
+                        InstCode::Rev8(rd,_) => {
+                            self.val_e = self.bitmanip(prog.isa.xlen);
+                            // Forward
+                            prog.registers.forward(rd, self.val_e);
+                            self.stage = Stage::Memory;
+                            Ok(self)
+                        },
+                        InstCode::CzeroEqz(rd,_,_) => {
+                            self.val_e = if self.val2 == 0 { 0 } else { self.val1 };
+                            // Forward
+                            prog.registers.forward(rd, self.val_e);
+                            self.stage = Stage::Memory;
+                            Ok(self)
+                        },
+                        InstCode::CzeroNez(rd,_,_) => {
+                            self.val_e = if self.val2 != 0 { 0 } else { self.val1 };
+                            // Forward
+                            prog.registers.forward(rd, self.val_e);
+                            self.stage = Stage::Memory;
+                            Ok(self)
+                        },
+
+                        // The counters and vector CSRs are read-only, the
+                        // floating-point CSRs are also writable
+                        InstCode::Csrrw(rd,_,csr) | InstCode::Csrrs(rd,_,csr) |
+                        InstCode::Csrrc(rd,_,csr) | InstCode::Csrrwi(rd,csr,_) |
+                        InstCode::Csrrsi(rd,csr,_) | InstCode::Csrrci(rd,csr,_) => {
+                            // csrrs/csrrc with x0 or a zero immediate do not write
+                            let writes = match self.code {
+                                InstCode::Csrrw(_,_,_) | InstCode::Csrrwi(_,_,_) => true,
+                                InstCode::Csrrs(_,rs1,_) | InstCode::Csrrc(_,rs1,_) => !matches!(rs1, RegID::X0),
+                                _ => self.val1 != 0,
+                            };
+                            let float = prog.float.read_csr(csr);
+                            let (val, name, ext) = match (prog.counters.read(csr, prog.isa.xlen), prog.vector.read_csr(csr, prog.isa.xlen), float) {
+                                (Some(val), _, _) => (val, counter_name(csr).unwrap(), Counters::extension(csr)),
+                                (None, Some(val), _) => (val, String::from(vector_csr_name(csr).unwrap()), Extension::Zve32x),
+                                (None, None, Some(val)) => (val, String::from(float_csr_name(csr).unwrap()), Extension::F),
+                                (None, None, None) => {
+                                    print!("{}", "[Warning] ".yellow().bold());
+                                    println!("CSR {:#x} at {:x} is not supported", csr, self.pc);
+                                    return Err(HLT_ADDR);
+                                }
+                            };
+                            if !prog.isa.has(ext) {
+                                print!("{}", "[Warning] ".yellow().bold());
+                                println!("Illegal access to {} at {:x}, extension {} is not enabled", name, self.pc, ext);
+                                return Err(HLT_ADDR);
+                            }
+                            if writes && float.is_some() {
+                                let new = match self.code {
+                                    InstCode::Csrrw(_,_,_) | InstCode::Csrrwi(_,_,_) => self.val1,
+                                    InstCode::Csrrs(_,_,_) | InstCode::Csrrsi(_,_,_) => val | self.val1,
+                                    _ => val & !self.val1,
+                                };
+                                prog.float.write_csr(csr, new);
+                            } else if writes {
+                                print!("{}", "[Warning] ".yellow().bold());
+                                println!("Illegal write to read-only CSR {} at {:x}", name, self.pc);
+                                return Err(HLT_ADDR);
+                            }
+                            self.val_e = val;
+                            // Forward
+                            prog.registers.forward(rd, self.val_e);
+                            self.stage = Stage::Memory;
+                            Ok(self)
+                        },
 
                         InstCode::Lb(rd,_,imm) | InstCode::Lbu(rd,_,imm) |
                         InstCode::Lh(rd,_,imm) | InstCode::Lhu(rd,_,imm) |
@@ -989,7 +2613,7 @@ pub mod inst {
                             Ok(self)
                         },
                         InstCode::Jalr(rd, _, imm) => {
-                            self.val_e = self.pc + 4;
+                            self.val_e = self.pc + self.len;
                             // Forward
                             prog.registers.forward(rd, self.val_e);
                             // Set next_pc
@@ -997,6 +2621,20 @@ pub mod inst {
                             self.stage = Stage::Memory;
                             Ok(self)
                         },
+                        InstCode::Ecall if prog.linux.is_some() => {
+                            self.val_e = match prog.syscall(self.pc) {
+                                Ok(val) => val,
+                                Err(next_pc) => {
+                                    // Exited
+                                    prog.registers.unlock(RegID::X10);
+                                    return Err(next_pc);
+                                },
+                            };
+                            // Forward
+                            prog.registers.forward(RegID::X10, self.val_e);
+                            self.stage = Stage::Memory;
+                            Ok(self)
+                        },
                         InstCode::Ecall => {
                             if self.val1 == 10 {
                                 // exit()
@@ -1077,7 +2715,7 @@ pub mod inst {
                         },
 
                         InstCode::Jal(rd, imm) => {
-                            self.val_e = self.pc + 4;
+                            self.val_e = self.pc + self.len;
                             // Forward
                             prog.registers.forward(rd, self.val_e);
                             // Set next_pc
@@ -1086,13 +2724,64 @@ pub mod inst {
                             Ok(self)
                         },
 
+                        // F/D: loads and stores only compute the address here
+                        _ if self.code.is_float() => {
+                            match prog.float_execute(self.code, self.pc, self.val1) {
+                                Ok(val) => {
+                                    self.val_e = val;
+                                    if let Some(rd) = self.code.float_rd() {
+                                        // Forward
+                                        prog.registers.forward(rd, self.val_e);
+                                    }
+                                    self.stage = Stage::Memory;
+                                    Ok(self)
+                                },
+                                Err(msg) => {
+                                    print!("{}", "[Warning] ".yellow().bold());
+                                    println!("{}", msg);
+                                    Err(HLT_ADDR)
+                                }
+                            }
+                        },
+
+                        // V: loads and stores only check vtype here
+                        _ if self.code.is_vector() => {
+                            match prog.vector_execute(self.code, self.pc, self.val1, self.val2) {
+                                Ok(val) => {
+                                    if let Some(rd) = self.code.vector_rd() {
+                                        self.val_e = val;
+                                        // Forward
+                                        prog.registers.forward(rd, self.val_e);
+                                    }
+                                    self.stage = Stage::Memory;
+                                    Ok(self)
+                                },
+                                Err(msg) => {
+                                    print!("{}", "[Warning] ".yellow().bold());
+                                    println!("{}", msg);
+                                    Err(HLT_ADDR)
+                                }
+                            }
+                        },
+
                         _ => unreachable!(),
+                    };
+                    match result {
+                        Ok(inst) if prog.isa.xlen == 32 => Ok(inst.rv32_result(prog)),
+                        result => result,
                     }
                 },
 
                 // Access memory
                 Stage::Memory => {
-                    match self.code {
+                    // Watched ranges touched by this access, with their old contents
+                    let pc = self.pc;
+                    let mut watched = match self.code.mem_width() {
+                        Some(sz) => prog.watch_hits(self.val_e, sz, self.code.category() == InstCategory::Store),
+                        None => Vec::new(),
+                    };
+
+                    let result = match self.code {
                         InstCode::Lb(rd, _, _) | InstCode::Lbu(rd, _, _) => {
                             match prog.mem_load(self.val_e, 1, false) {
                                 None => {
@@ -1244,13 +2933,110 @@ pub mod inst {
                             }
                         },
 
+                        InstCode::Lr(rd, _, width, _) => {
+                            match prog.mem_load(self.val_e, width as usize, false) {
+                                Some((data, 0)) => {
+                                    self.val_m = if width == 4 {
+                                        i32::from_le_bytes(data.try_into().unwrap()) as i64 as u64
+                                    }
+                                    else {
+                                        u64::from_le_bytes(data.try_into().unwrap())
+                                    };
+                                    prog.reservation = Some(self.val_e);
+                                    // Forward
+                                    prog.registers.forward(rd, self.val_m);
+                                    self.stage = Stage::Writeback;
+                                    Ok(self)
+                                },
+                                _ => {
+                                    print!("{}", "[Warning] ".yellow().bold());
+                                    println!("Cannot access memory at {:x}", self.val_e);
+                                    Err(HLT_ADDR)
+                                }
+                            }
+                        },
+                        InstCode::Sc(rd, _, _, width, _) => {
+                            // With a single hart, only sc itself clears the reservation
+                            if prog.reservation.take() == Some(self.val_e) {
+                                if !prog.mem_store(self.val_e, &self.val2.to_le_bytes()[..width as usize]) {
+                                    print!("{}", "[Warning] ".yellow().bold());
+                                    println!("Cannot access memory at {:x}", self.val_e);
+                                    return Err(HLT_ADDR);
+                                }
+                                self.val_m = 0;
+                            }
+                            else {
+                                self.val_m = 1;
+                            }
+                            // Forward
+                            prog.registers.forward(rd, self.val_m);
+                            self.stage = Stage::Writeback;
+                            Ok(self)
+                        },
+                        _ if self.code.amo().is_some() => {
+                            let (rd, _, _, width, _) = self.code.amo().unwrap();
+                            self.val_m = match prog.mem_load(self.val_e, width as usize, false) {
+                                Some((data, 0)) if width == 4 => i32::from_le_bytes(data.try_into().unwrap()) as i64 as u64,
+                                Some((data, 0)) => u64::from_le_bytes(data.try_into().unwrap()),
+                                _ => {
+                                    print!("{}", "[Warning] ".yellow().bold());
+                                    println!("Cannot access memory at {:x}", self.val_e);
+                                    return Err(HLT_ADDR);
+                                }
+                            };
+                            let val = self.amo_value();
+                            if !prog.mem_store(self.val_e, &val.to_le_bytes()[..width as usize]) {
+                                print!("{}", "[Warning] ".yellow().bold());
+                                println!("Cannot access memory at {:x}", self.val_e);
+                                return Err(HLT_ADDR);
+                            }
+                            // Forward
+                            prog.registers.forward(rd, self.val_m);
+                            self.stage = Stage::Writeback;
+                            Ok(self)
+                        },
+
+                        InstCode::Fl(_,_,_,_) | InstCode::Fs(_,_,_,_) => {
+                            match prog.float_access(self.code, self.val_e) {
+                                Ok(()) => {
+                                    self.stage = Stage::Writeback;
+                                    Ok(self)
+                                },
+                                Err(msg) => {
+                                    print!("{}", "[Warning] ".yellow().bold());
+                                    println!("{}", msg);
+                                    Err(HLT_ADDR)
+                                }
+                            }
+                        },
+
+                        _ if self.code.is_vector_load() || self.code.is_vector_store() => {
+                            match prog.vector_access(self.code, self.pc, self.val1, self.val2) {
+                                Ok(hits) => {
+                                    watched = hits;
+                                    self.stage = Stage::Writeback;
+                                    Ok(self)
+                                },
+                                Err(msg) => {
+                                    print!("{}", "[Warning] ".yellow().bold());
+                                    println!("{}", msg);
+                                    Err(HLT_ADDR)
+                                }
+                            }
+                        },
+
                         // The rest do not access memory
                         _ => {
                             
                             self.stage = Stage::Writeback;
                             Ok(self)
                         }
+                    };
+
+                    if result.is_ok() && !watched.is_empty() {
+                        prog.watch_report(pc, watched);
                     }
+                    result
                 },
 
                 // Write value into register, *unlocks* it, then drop self.
@@ -1267,6 +3053,9 @@ pub mod inst {
                         InstCode::Mulw(rd,_,_) | InstCode::Divw(rd,_,_) |
                         InstCode::Sllw(rd,_,_) | InstCode::Srlw(rd,_,_) |
                         InstCode::Sraw(rd,_,_) | InstCode::Remw(rd,_,_) |
+                        InstCode::Mulhsu(rd,_,_) | InstCode::Mulhu(rd,_,_) |
+                        InstCode::Divu(rd,_,_) | InstCode::Remu(rd,_,_) |
+                        InstCode::Divuw(rd,_,_) | InstCode::Remuw(rd,_,_) |
                         InstCode::Addi(rd,_,_) | InstCode::Slli(rd,_,_) | 
                         InstCode::Slliw(rd,_,_) | InstCode::Slti(rd,_,_) | 
                         InstCode::Sltiu(rd,_,_) | InstCode::Xori(rd,_,_) | 
@@ -1275,21 +3064,71 @@ pub mod inst {
                         InstCode::Ori(rd,_,_) | InstCode::Andi(rd,_,_) | 
                         InstCode::Addiw(rd,_,_) | InstCode::Jalr(rd,_,_) | 
                         InstCode::Jal(rd,_) | InstCode::Auipc(rd,_) | 
-                        InstCode::Lui(rd,_) => {
+                        InstCode::Lui(rd,_) |
+                        InstCode::Sh1add(rd,_,_) | InstCode::Sh2add(rd,_,_) |
+                        InstCode::Sh3add(rd,_,_) | InstCode::AddUw(rd,_,_) |
+                        InstCode::Sh1addUw(rd,_,_) | InstCode::Sh2addUw(rd,_,_) |
+                        InstCode::Sh3addUw(rd,_,_) | InstCode::Andn(rd,_,_) |
+                        InstCode::Orn(rd,_,_) | InstCode::Xnor(rd,_,_) |
+                        InstCode::Max(rd,_,_) | InstCode::Maxu(rd,_,_) |
+                        InstCode::Min(rd,_,_) | InstCode::Minu(rd,_,_) |
+                        InstCode::Rol(rd,_,_) | InstCode::Ror(rd,_,_) |
+                        InstCode::Rolw(rd,_,_) | InstCode::Rorw(rd,_,_) |
+                        InstCode::Clmul(rd,_,_) | InstCode::Clmulh(rd,_,_) |
+                        InstCode::Clmulr(rd,_,_) | InstCode::Bclr(rd,_,_) |
+                        InstCode::Bext(rd,_,_) | InstCode::Binv(rd,_,_) |
+                        InstCode::Bset(rd,_,_) | InstCode::SlliUw(rd,_,_) |
+                        InstCode::Rori(rd,_,_) | InstCode::Roriw(rd,_,_) |
+                        InstCode::Bclri(rd,_,_) | InstCode::Bexti(rd,_,_) |
+                        InstCode::Binvi(rd,_,_) | InstCode::Bseti(rd,_,_) |
+                        InstCode::Clz(rd,_) | InstCode::Ctz(rd,_) |
+                        InstCode::Cpop(rd,_) | InstCode::Clzw(rd,_) |
+                        InstCode::Ctzw(rd,_) | InstCode::Cpopw(rd,_) |
+                        InstCode::SextB(rd,_) | InstCode::SextH(rd,_) |
+                        InstCode::ZextH(rd,_) | InstCode::OrcB(rd,_) |
+                        InstCode::Rev8(rd,_) | InstCode::CzeroEqz(rd,_,_) |
+                        InstCode::CzeroNez(rd,_,_) | InstCode::Csrrw(rd,_,_) |
+                        InstCode::Csrrs(rd,_,_) | InstCode::Csrrc(rd,_,_) |
+                        InstCode::Csrrwi(rd,_,_) | InstCode::Csrrsi(rd,_,_) |
+                        InstCode::Csrrci(rd,_,_) => {
                             prog.registers.write(rd, self.val_e);
                             prog.registers.unlock(rd);
                         },
                         InstCode::Lb(rd,_,_) | InstCode::Lbu(rd,_,_) |
                         InstCode::Lh(rd,_,_) | InstCode::Lhu(rd,_,_) |
                         InstCode::Lw(rd,_,_) | InstCode::Lwu(rd,_,_) |
-                        InstCode::Ld(rd,_,_) => {
+                        InstCode::Ld(rd,_,_) | InstCode::Lr(rd,_,_,_) |
+                        InstCode::Sc(rd,_,_,_,_) => {
                             prog.registers.write(rd, self.val_m);
                             prog.registers.unlock(rd);
                         },
+                        _ if self.code.amo().is_some() => {
+                            let (rd, _, _, _, _) = self.code.amo().unwrap();
+                            prog.registers.write(rd, self.val_m);
+                            prog.registers.unlock(rd);
+                        },
+                        InstCode::Ecall if prog.linux.is_some() => {
+                            prog.registers.write(RegID::X10, self.val_e);
+                            prog.registers.unlock(RegID::X10);
+                        },
+                        _ if self.code.float_rd().is_some() => {
+                            let rd = self.code.float_rd().unwrap();
+                            prog.registers.write(rd, self.val_e);
+                            prog.registers.unlock(rd);
+                        },
+                        _ if self.code.vector_rd().is_some() => {
+                            let rd = self.code.vector_rd().unwrap();
+                            prog.registers.write(rd, self.val_e);
+                            prog.registers.unlock(rd);
+                        },
                         // The rest do not write back
                         _ => {}
                     }
 
+                    if let InstCode::Jal(_,_) | InstCode::Jalr(_,_,_) = self.code {
+                        prog.track_call(self.code, self.val_e, self.next_pc);
+                    }
+
                     // Always drop self
                     Err(self.next_pc)
                 },
@@ -1298,5 +3137,345 @@ pub mod inst {
         }
     }
 
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::collections::HashMap;
+        use crate::{Loader, VMA, Isa};
+
+        const A0: RegID = RegID::X10;
+        const A1: RegID = RegID::X11;
+        const A2: RegID = RegID::X12;
+        /// Address of the doubleword of data in `exec`
+        const DATA: u64 = 0x2000;
+
+        /// Parse `raw` as a 32-bit word.
+        fn parse32(raw: u32, xlen: u32) -> InstCode {
+            InstCode::parse(raw as u16, (raw >> 16) as u16, xlen).0
+        }
+
+        /// `parse(encode(x)) == x` for every 32-bit word, up to the rd
+        /// field. It is a register decoded on its own, so it takes x0, x1
+        /// and x31, except in stores and branches where it holds immediate
+        /// bits, so each of them is set on its own.
+        fn round_trip(xlen: u32) {
+            for opcode in (0..0x80).filter(|opcode| opcode & 0b11 == 0b11) {
+                let rds: &[u32] = match opcode {
+                    0x23 | 0x63 => &[0, 1, 2, 4, 8, 16, 31],
+                    _ => &[0, 1, 31],
+                };
+                for upper in 0..1 << 20 {
+                    for rd in rds {
+                        let raw = (upper << 12) | (rd << 7) | opcode;
+                        let code = parse32(raw, xlen);
+                        assert_eq!(parse32(code.encode(xlen), xlen), code, "{:#010x} on RV{}", raw, xlen);
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn round_trip_rv64() {
+            round_trip(64);
+        }
+
+        #[test]
+        fn round_trip_rv32() {
+            round_trip(32);
+        }
+
+        /// Every compressed halfword parses back from its compressed form,
+        /// if it has one, else it is a HINT or invalid. Either way, it also
+        /// does from its 32-bit form.
+        fn round_trip_compressed(xlen: u32) {
+            for half in (0..=u16::MAX).filter(|half| half & 0b11 != 0b11) {
+                let code = InstCode::parse(half, 0, xlen).0;
+                match code.encode_compressed(xlen) {
+                    Some(raw) => assert_eq!(InstCode::parse(raw, 0, xlen).0, code, "{:#06x} on RV{}", half, xlen),
+                    None => assert!(match code {
+                        InstCode::Add(rd, _, _) | InstCode::Lui(rd, _) => rd == RegID::X0,
+                        InstCode::Addi(rd, _, imm) => rd == RegID::X0 || imm == 0,
+                        InstCode::Slli(rd, _, imm) | InstCode::Srli(rd, _, imm) | InstCode::Srai(rd, _, imm) =>
+                            rd == RegID::X0 || imm & 0x3f == 0,
+                        InstCode::UnknownC(_) => true,
+                        _ => false,
+                    }, "{:#06x} ({:?}) has no compressed form on RV{}", half, code, xlen),
+                }
+                assert_eq!(parse32(code.encode(xlen), xlen), code, "{:#06x} on RV{}", half, xlen);
+            }
+        }
+
+        #[test]
+        fn round_trip_compressed_rv64() {
+            round_trip_compressed(64);
+        }
+
+        #[test]
+        fn round_trip_compressed_rv32() {
+            round_trip_compressed(32);
+        }
+
+        /// Run `codes` from memory on a hart of `isa`, with a1 and a2 set
+        /// and the doubleword `data` at `DATA`, and return the hart.
+        fn exec(isa: &str, codes: &[InstCode], a1: u64, a2: u64, data: u64) -> Program {
+            let isa = Isa::parse(isa).unwrap();
+            let text = VMA {
+                lower_bound: 0x1000,
+                size: 4 * codes.len() as u64,
+                readable: true,
+                writeble: false,
+                executable: true,
+                memory: codes.iter().flat_map(|code| code.encode(isa.xlen).to_le_bytes()).collect(),
+            };
+            let data = VMA {
+                lower_bound: DATA,
+                size: 8,
+                readable: true,
+                writeble: true,
+                executable: false,
+                memory: data.to_le_bytes().to_vec(),
+            };
+            let end = text.lower_bound + text.size;
+            let mut prog = Loader::program(0x1000, vec![text, data], HashMap::new(), Vec::new(), None);
+            prog.isa = isa;
+            prog.registers.write(A1, a1);
+            prog.registers.write(A2, a2);
+            while prog.program_counter < end {
+                let mut inst = Inst::new();
+                prog.program_counter = loop {
+                    match inst.advance(&mut prog) {
+                        Ok(next) => inst = next,
+                        Err(next_pc) => break next_pc,
+                    }
+                };
+            }
+            prog
+        }
+
+        /// Run `code` on a hart of `isa`, with a1 and a2 set, and return a0.
+        fn run(isa: &str, code: InstCode, a1: u64, a2: u64) -> u64 {
+            exec(isa, &[code], a1, a2, 0).registers.read(A0).unwrap()
+        }
+
+        /// The doubleword at `DATA`.
+        fn data(prog: &Program) -> u64 {
+            u64::from_le_bytes(prog.mem_load(DATA, 8, false).unwrap().0.try_into().unwrap())
+        }
+
+        /// (instruction, a1, a2, a0 after), from the definitions of the
+        /// specification. Register indices take the low log2(XLEN) bits.
+        #[test]
+        fn bitmanip_rv64() {
+            let cases = [
+                (InstCode::Sh1add(A0, A1, A2), 0x800000f012345681, 0x1fffffffe, 0x1e22468ad00),
+                (InstCode::Sh2add(A0, A1, A2), 0x800000f012345681, 0x1fffffffe, 0x3c248d15a02),
+                (InstCode::Sh3add(A0, A1, A2), 0x800000f012345681, 0x1fffffffe, 0x78291a2b406),
+                (InstCode::AddUw(A0, A1, A2), 0x800000f012345681, 0x1fffffffe, 0x21234567f),
+                (InstCode::Sh1addUw(A0, A1, A2), 0x800000f012345681, 0x1fffffffe, 0x22468ad00),
+                (InstCode::Sh2addUw(A0, A1, A2), 0x800000f012345681, 0x1fffffffe, 0x248d15a02),
+                (InstCode::Sh3addUw(A0, A1, A2), 0x800000f012345681, 0x1fffffffe, 0x291a2b406),
+                (InstCode::Andn(A0, A1, A2), 0x800000f012345681, 0x1fffffffe, 0x800000f000000001),
+                (InstCode::Orn(A0, A1, A2), 0x800000f012345681, 0x1fffffffe, 0xfffffffe12345681),
+                (InstCode::Xnor(A0, A1, A2), 0x800000f012345681, 0x1fffffffe, 0x7fffff0e12345680),
+                (InstCode::Max(A0, A1, A2), 0x800000f012345681, 0x1fffffffe, 0x1fffffffe),
+                (InstCode::Maxu(A0, A1, A2), 0x800000f012345681, 0x1fffffffe, 0x800000f012345681),
+                (InstCode::Min(A0, A1, A2), 0x800000f012345681, 0x1fffffffe, 0x800000f012345681),
+                (InstCode::Minu(A0, A1, A2), 0x800000f012345681, 0x1fffffffe, 0x1fffffffe),
+                (InstCode::Clmul(A0, A1, A2), 0x800000f012345681, 0x1fffffffe, 0x1c279ba1e3d864fe),
+                (InstCode::Clmulh(A0, A1, A2), 0x800000f012345681, 0x1fffffffe, 0xffffff5f),
+                (InstCode::Clmulr(A0, A1, A2), 0x800000f012345681, 0x1fffffffe, 0x1fffffebe),
+                (InstCode::Rol(A0, A1, A2), 0x800000f012345681, 0x4, 0xf0123456818),
+                (InstCode::Rol(A0, A1, A2), 0x800000f012345681, 0x7f, 0xc0000078091a2b40),
+                (InstCode::Ror(A0, A1, A2), 0x800000f012345681, 0x4, 0x1800000f01234568),
+                (InstCode::Ror(A0, A1, A2), 0x800000f012345681, 0x7f, 0x1e02468ad03),
+                (InstCode::Bclr(A0, A1, A2), 0x800000f012345681, 0x4, 0x800000f012345681),
+                (InstCode::Bclr(A0, A1, A2), 0x800000f012345681, 0x7f, 0xf012345681),
+                (InstCode::Bext(A0, A1, A2), 0x800000f012345681, 0x4, 0x0),
+                (InstCode::Bext(A0, A1, A2), 0x800000f012345681, 0x7f, 0x1),
+                (InstCode::Binv(A0, A1, A2), 0x800000f012345681, 0x4, 0x800000f012345691),
+                (InstCode::Binv(A0, A1, A2), 0x800000f012345681, 0x7f, 0xf012345681),
+                (InstCode::Bset(A0, A1, A2), 0x800000f012345681, 0x4, 0x800000f012345691),
+                (InstCode::Bset(A0, A1, A2), 0x800000f012345681, 0x7f, 0x800000f012345681),
+                (InstCode::Rolw(A0, A1, A2), 0x800000f012345681, 0x24, 0x23456811),
+                (InstCode::Rorw(A0, A1, A2), 0x800000f012345681, 0x24, 0x11234568),
+                (InstCode::SlliUw(A0, A1, 0x80 | 40), 0x800000f012345681, 0, 0x3456810000000000),
+                (InstCode::Rori(A0, A1, 0x600 | 63), 0x800000f012345681, 0, 0x1e02468ad03),
+                (InstCode::Bclri(A0, A1, 0x480 | 63), 0x800000f012345681, 0, 0xf012345681),
+                (InstCode::Bexti(A0, A1, 0x480 | 63), 0x800000f012345681, 0, 0x1),
+                (InstCode::Binvi(A0, A1, 0x680 | 63), 0x800000f012345681, 0, 0xf012345681),
+                (InstCode::Bseti(A0, A1, 0x280 | 63), 0x800000f012345681, 0, 0x800000f012345681),
+                (InstCode::Roriw(A0, A1, 0x600 | 31), 0x800000f012345681, 0, 0x2468ad02),
+                (InstCode::Clz(A0, A1), 0x800000f012345681, 0, 0x0),
+                (InstCode::Ctz(A0, A1), 0x800000f012345681, 0, 0x0),
+                (InstCode::Cpop(A0, A1), 0x800000f012345681, 0, 0x10),
+                (InstCode::Clzw(A0, A1), 0x800000f012345681, 0, 0x3),
+                (InstCode::Ctzw(A0, A1), 0x800000f012345681, 0, 0x0),
+                (InstCode::Cpopw(A0, A1), 0x800000f012345681, 0, 0xb),
+                (InstCode::SextB(A0, A1), 0x800000f012345681, 0, 0xffffffffffffff81),
+                (InstCode::SextH(A0, A1), 0x800000f012345681, 0, 0x5681),
+                (InstCode::ZextH(A0, A1), 0x800000f012345681, 0, 0x5681),
+                (InstCode::OrcB(A0, A1), 0x800000f012345681, 0, 0xff0000ffffffffff),
+                (InstCode::Rev8(A0, A1), 0x800000f012345681, 0, 0x81563412f0000080),
+                (InstCode::Clz(A0, A1), 0x0, 0, 0x40),
+                (InstCode::Ctz(A0, A1), 0x0, 0, 0x40),
+            ];
+            for (code, a1, a2, a0) in cases {
+                assert_eq!(run("rv64i_zba_zbb_zbc_zbs", code, a1, a2), a0, "{:?} on RV64", code);
+            }
+        }
+
+        /// Same as `bitmanip_rv64`, on sign-extended 32-bit registers.
+        #[test]
+        fn bitmanip_rv32() {
+            let cases = [
+                (InstCode::Sh1add(A0, A1, A2), 0xffffffff80123481, 0x7ffffffe, 0xffffffff80246900),
+                (InstCode::Sh2add(A0, A1, A2), 0xffffffff80123481, 0x7ffffffe, 0xffffffff8048d202),
+                (InstCode::Sh3add(A0, A1, A2), 0xffffffff80123481, 0x7ffffffe, 0xffffffff8091a406),
+                (InstCode::Andn(A0, A1, A2), 0xffffffff80123481, 0x7ffffffe, 0xffffffff80000001),
+                (InstCode::Orn(A0, A1, A2), 0xffffffff80123481, 0x7ffffffe, 0xffffffff80123481),
+                (InstCode::Xnor(A0, A1, A2), 0xffffffff80123481, 0x7ffffffe, 0x123480),
+                (InstCode::Max(A0, A1, A2), 0xffffffff80123481, 0x7ffffffe, 0x7ffffffe),
+                (InstCode::Maxu(A0, A1, A2), 0xffffffff80123481, 0x7ffffffe, 0xffffffff80123481),
+                (InstCode::Min(A0, A1, A2), 0xffffffff80123481, 0x7ffffffe, 0xffffffff80123481),
+                (InstCode::Minu(A0, A1, A2), 0xffffffff80123481, 0x7ffffffe, 0x7ffffffe),
+                (InstCode::Clmul(A0, A1, A2), 0xffffffff80123481, 0x7ffffffe, 0x7fe3d8fe),
+                (InstCode::Clmulh(A0, A1, A2), 0xffffffff80123481, 0x7ffffffe, 0x3ff8f63f),
+                (InstCode::Clmulr(A0, A1, A2), 0xffffffff80123481, 0x7ffffffe, 0x7ff1ec7e),
+                (InstCode::Rol(A0, A1, A2), 0xffffffff80123481, 0x4, 0x1234818),
+                (InstCode::Rol(A0, A1, A2), 0xffffffff80123481, 0x3f, 0xffffffffc0091a40),
+                (InstCode::Ror(A0, A1, A2), 0xffffffff80123481, 0x4, 0x18012348),
+                (InstCode::Ror(A0, A1, A2), 0xffffffff80123481, 0x3f, 0x246903),
+                (InstCode::Bclr(A0, A1, A2), 0xffffffff80123481, 0x4, 0xffffffff80123481),
+                (InstCode::Bclr(A0, A1, A2), 0xffffffff80123481, 0x3f, 0x123481),
+                (InstCode::Bext(A0, A1, A2), 0xffffffff80123481, 0x4, 0x0),
+                (InstCode::Bext(A0, A1, A2), 0xffffffff80123481, 0x3f, 0x1),
+                (InstCode::Binv(A0, A1, A2), 0xffffffff80123481, 0x4, 0xffffffff80123491),
+                (InstCode::Binv(A0, A1, A2), 0xffffffff80123481, 0x3f, 0x123481),
+                (InstCode::Bset(A0, A1, A2), 0xffffffff80123481, 0x4, 0xffffffff80123491),
+                (InstCode::Bset(A0, A1, A2), 0xffffffff80123481, 0x3f, 0xffffffff80123481),
+                (InstCode::Rori(A0, A1, 0x600 | 31), 0xffffffff80123481, 0, 0x246903),
+                (InstCode::Bclri(A0, A1, 0x480 | 31), 0xffffffff80123481, 0, 0x123481),
+                (InstCode::Bexti(A0, A1, 0x480 | 31), 0xffffffff80123481, 0, 0x1),
+                (InstCode::Binvi(A0, A1, 0x680 | 31), 0xffffffff80123481, 0, 0x123481),
+                (InstCode::Bseti(A0, A1, 0x280 | 31), 0xffffffff80123481, 0, 0xffffffff80123481),
+                (InstCode::Clz(A0, A1), 0xffffffff80123481, 0, 0x0),
+                (InstCode::Ctz(A0, A1), 0xffffffff80123481, 0, 0x0),
+                (InstCode::Cpop(A0, A1), 0xffffffff80123481, 0, 0x8),
+                (InstCode::SextB(A0, A1), 0xffffffff80123481, 0, 0xffffffffffffff81),
+                (InstCode::SextH(A0, A1), 0xffffffff80123481, 0, 0x3481),
+                (InstCode::ZextH(A0, A1), 0xffffffff80123481, 0, 0x3481),
+                (InstCode::OrcB(A0, A1), 0xffffffff80123481, 0, 0xffffffffffffffff),
+                (InstCode::Rev8(A0, A1), 0xffffffff80123481, 0, 0xffffffff81341280),
+                (InstCode::Clz(A0, A1), 0x0, 0, 0x20),
+                (InstCode::Ctz(A0, A1), 0x0, 0, 0x20),
+            ];
+            for (code, a1, a2, a0) in cases {
+                assert_eq!(run("rv32i_zba_zbb_zbc_zbs", code, a1, a2), a0, "{:?} on RV32", code);
+            }
+        }
+
+        /// (instruction, a1, a2, a0 after) for the M instructions with
+        /// unsigned operands.
+        #[test]
+        fn muldiv_unsigned() {
+            let rv64 = [
+                (InstCode::Mulhsu(A0, A1, A2), 0xfffffffffffffffe, 0xffffffffffffffff, 0xfffffffffffffffe),
+                (InstCode::Mulhu(A0, A1, A2), 0xffffffffffffffff, 0xffffffffffffffff, 0xfffffffffffffffe),
+                (InstCode::Divu(A0, A1, A2), 0xfffffffffffffffe, 0x3, 0x5555555555555554),
+                (InstCode::Remu(A0, A1, A2), 0xfffffffffffffffe, 0x3, 0x2),
+                (InstCode::Divuw(A0, A1, A2), 0x1fffffffe, 0x100000001, 0xfffffffffffffffe),
+                (InstCode::Remuw(A0, A1, A2), 0x1fffffffe, 0x100000003, 0x2),
+            ];
+            for (code, a1, a2, a0) in rv64 {
+                assert_eq!(run("rv64im", code, a1, a2), a0, "{:?} on RV64", code);
+            }
+            let rv32 = [
+                (InstCode::Mulhsu(A0, A1, A2), 0xfffffffffffffffe, 0xffffffffffffffff, 0xfffffffffffffffe),
+                (InstCode::Mulhu(A0, A1, A2), 0xffffffffffffffff, 0xffffffffffffffff, 0xfffffffffffffffe),
+                (InstCode::Divu(A0, A1, A2), 0xfffffffffffffffe, 0x3, 0x55555554),
+                (InstCode::Divu(A0, A1, A2), 0xffffffff80000000, 0x1, 0xffffffff80000000),
+                (InstCode::Remu(A0, A1, A2), 0xfffffffffffffffe, 0x3, 0x2),
+            ];
+            for (code, a1, a2, a0) in rv32 {
+                assert_eq!(run("rv32im", code, a1, a2), a0, "{:?} on RV32", code);
+            }
+        }
+
+        /// (AMO, a2, a0 and the data after) with a1 pointing at the data.
+        /// The W variants only see its lower word.
+        #[test]
+        fn amo() {
+            const OLD: u64 = 0xffffffff80000005;
+            let cases = [
+                (InstCode::Amoswap(A0, A1, A2, 4, 0), 0x123456789abcdef0, OLD, 0xffffffff9abcdef0),
+                (InstCode::Amoadd(A0, A1, A2, 8, 0), 0x3, OLD, 0xffffffff80000008),
+                (InstCode::Amoadd(A0, A1, A2, 4, 0), 0x7ffffffb, OLD, 0xffffffff00000000),
+                (InstCode::Amoxor(A0, A1, A2, 4, 0b10), 0xf, OLD, 0xffffffff8000000a),
+                (InstCode::Amoand(A0, A1, A2, 8, 0b01), 0xff, OLD, 0x5),
+                (InstCode::Amoor(A0, A1, A2, 4, 0b11), 0x70000000, OLD, 0xfffffffff0000005),
+                (InstCode::Amomin(A0, A1, A2, 4, 0), 0x1, OLD, OLD),
+                (InstCode::Amomax(A0, A1, A2, 4, 0), 0x100000000, OLD, 0xffffffff00000000),
+                (InstCode::Amomax(A0, A1, A2, 8, 0), 0x1, OLD, 0x1),
+                (InstCode::Amominu(A0, A1, A2, 4, 0), 0x1, OLD, 0xffffffff00000001),
+                (InstCode::Amomaxu(A0, A1, A2, 8, 0), 0x1, OLD, OLD),
+            ];
+            for (code, a2, a0, after) in cases {
+                let prog = exec("rv64ia", &[code], DATA, a2, OLD);
+                assert_eq!(prog.registers.read(A0).unwrap(), a0, "{:?}", code);
+                assert_eq!(data(&prog), after, "{:?}", code);
+            }
+            let prog = exec("rv32ia", &[InstCode::Amomaxu(A0, A1, A2, 4, 0)], DATA, 0x1, OLD);
+            assert_eq!(prog.registers.read(A0).unwrap(), OLD);
+            assert_eq!(data(&prog), OLD);
+        }
+
+        /// sc succeeds only on the reservation of the last lr, once.
+        #[test]
+        fn lr_sc() {
+            let lr = InstCode::Lr(A0, A1, 8, 0);
+            let sc = InstCode::Sc(A0, A1, A2, 8, 0);
+            let prog = exec("rv64ia", &[lr, sc], DATA, 0x2a, 0x7);
+            assert_eq!((prog.registers.read(A0).unwrap(), data(&prog)), (0, 0x2a));
+            let prog = exec("rv64ia", &[sc], DATA, 0x2a, 0x7);
+            assert_eq!((prog.registers.read(A0).unwrap(), data(&prog)), (1, 0x7));
+            let prog = exec("rv64ia", &[lr, sc, sc], DATA, 0x2a, 0x7);
+            assert_eq!((prog.registers.read(A0).unwrap(), data(&prog)), (1, 0x2a));
+            let prog = exec("rv32ia", &[InstCode::Lr(A0, A1, 4, 0)], DATA, 0, 0x80000000);
+            assert_eq!(prog.registers.read(A0).unwrap(), 0xffffffff80000000);
+        }
+
+        /// fld, fcvt, fmul and fsd through the pipeline, with the flags
+        /// accrued in fflags and the dynamic rounding mode taken from frm.
+        #[test]
+        fn float() {
+            let codes = [
+                InstCode::Fl(10, A1, 0, 8),
+                InstCode::FcvtFL(11, A2, 7, 8),
+                InstCode::Fmul(12, 10, 11, 7, 8),
+                InstCode::Fs(A1, 12, 0, 8),
+                InstCode::FcvtL(A0, 12, 1, 8),
+                InstCode::Csrrs(A2, RegID::X0, 0x001),
+            ];
+            let prog = exec("rv64g", &codes, DATA, 3, 2.5f64.to_bits());
+            assert_eq!(data(&prog), 7.5f64.to_bits());
+            assert_eq!(prog.registers.read(A0).unwrap(), 7);
+            assert_eq!(prog.registers.read(A2).unwrap(), 1);
+
+            let codes = [InstCode::Fl(10, A1, 0, 8), InstCode::Csrrwi(RegID::X0, 0x002, 2), InstCode::FcvtL(A0, 10, 7, 8)];
+            let prog = exec("rv64g", &codes, DATA, 0, (-2.5f64).to_bits());
+            assert_eq!(prog.registers.read(A0).unwrap(), -3i64 as u64);
+            assert_eq!(prog.float.frm, 2);
 
-}
\ No newline at end of file
+            // fmv.x.w sign-extends the single on RV64
+            let codes = [InstCode::Fl(10, A1, 0, 4), InstCode::FmvXF(A0, 10, 4)];
+            let prog = exec("rv64g", &codes, DATA, 0, (-1.5f32).to_bits() as u64);
+            assert_eq!(prog.registers.read(A0).unwrap(), 0xffffffffbfc00000);
+            let prog = exec("rv32if", &codes, DATA, 0, 1.5f32.to_bits() as u64);
+            assert_eq!(prog.registers.read(A0).unwrap(), 0x3fc00000);
+
+            // Without F, the load halts
+            let prog = exec("rv64i", &codes, DATA, 0, 1.5f32.to_bits() as u64);
+            assert_eq!(prog.float.regs[10], 0);
+        }
+    }
+}
@@ -0,0 +1,361 @@
+#![allow(clippy::module_inception)]
+#[allow(dead_code)]
+pub mod isa {
+    //! This module parses RISC-V ISA strings, as in the `Tag_RISCV_arch`
+    //! attribute of ELF files (`rv64i2p1_m2p0_a2p1_zicsr2p0`) or on the
+    //! command line (`rv64gc`), into the set of enabled extensions. The
+    //! simulated hart runs one `Isa`, and instructions of the extensions
+    //! it lacks are illegal. Unknown extensions are ignored with a
+    //! warning, so their instructions are illegal too.
+
+    use std::fmt;
+    use std::str::FromStr;
+    use std::collections::BTreeSet;
+    use colored::Colorize;
+
+    /// An ISA extension, in the canonical order of ISA strings.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub enum Extension {
+        I,
+        M,
+        A,
+        F,
+        D,
+        C,
+        /// Bit manipulation: Zba, Zbb and Zbs
+        B,
+        /// Vector operations, for application processors
+        V,
+        /// Base counters: cycle, time and instret
+        Zicntr,
+        /// Integer conditional operations
+        Zicond,
+        Zicsr,
+        Zifencei,
+        /// Hardware performance counters
+        Zihpm,
+        /// Multiplication subset of M
+        Zmmul,
+        /// Atomic memory operations of A
+        Zaamo,
+        /// Load-reserved/store-conditional of A
+        Zalrsc,
+        /// Integer subset of C
+        Zca,
+        /// Single-precision subset of C, on RV32
+        Zcf,
+        /// Double-precision subset of C
+        Zcd,
+        /// Address generation
+        Zba,
+        /// Basic bit manipulation
+        Zbb,
+        /// Carry-less multiplication
+        Zbc,
+        /// Single-bit instructions
+        Zbs,
+        /// Vector operations on integers, for embedded processors
+        Zve32x,
+        Zve32f,
+        Zve64x,
+        Zve64f,
+        Zve64d,
+        /// Minimal VLEN of 32 to 1024 bits
+        Zvl32b,
+        Zvl64b,
+        Zvl128b,
+        Zvl256b,
+        Zvl512b,
+        Zvl1024b,
+    }
+
+    impl Extension {
+        const SINGLE: [(char, Extension); 8] = [
+            ('i', Extension::I), ('m', Extension::M), ('a', Extension::A),
+            ('f', Extension::F), ('d', Extension::D), ('c', Extension::C),
+            ('b', Extension::B), ('v', Extension::V),
+        ];
+        const MULTI: [(&'static str, Extension); 26] = [
+            ("zicntr", Extension::Zicntr), ("zicond", Extension::Zicond), ("zicsr", Extension::Zicsr),
+            ("zifencei", Extension::Zifencei), ("zihpm", Extension::Zihpm), ("zmmul", Extension::Zmmul),
+            ("zaamo", Extension::Zaamo), ("zalrsc", Extension::Zalrsc), ("zca", Extension::Zca),
+            ("zcf", Extension::Zcf), ("zcd", Extension::Zcd), ("zba", Extension::Zba),
+            ("zbb", Extension::Zbb), ("zbc", Extension::Zbc), ("zbs", Extension::Zbs),
+            ("zve32x", Extension::Zve32x), ("zve32f", Extension::Zve32f), ("zve64x", Extension::Zve64x),
+            ("zve64f", Extension::Zve64f), ("zve64d", Extension::Zve64d), ("zvl32b", Extension::Zvl32b),
+            ("zvl64b", Extension::Zvl64b), ("zvl128b", Extension::Zvl128b), ("zvl256b", Extension::Zvl256b),
+            ("zvl512b", Extension::Zvl512b), ("zvl1024b", Extension::Zvl1024b),
+        ];
+        /// The Zvl extensions and their minimal VLEN.
+        const ZVL: [(Extension, usize); 6] = [
+            (Extension::Zvl32b, 32), (Extension::Zvl64b, 64), (Extension::Zvl128b, 128),
+            (Extension::Zvl256b, 256), (Extension::Zvl512b, 512), (Extension::Zvl1024b, 1024),
+        ];
+
+        /// The extensions that `self` implies.
+        fn implies(&self) -> &'static [Extension] {
+            match self {
+                Extension::M => &[Extension::Zmmul],
+                Extension::A => &[Extension::Zaamo, Extension::Zalrsc],
+                Extension::F => &[Extension::Zicsr],
+                Extension::D => &[Extension::F],
+                Extension::C => &[Extension::Zca],
+                Extension::B => &[Extension::Zba, Extension::Zbb, Extension::Zbs],
+                Extension::V => &[Extension::Zve64d, Extension::Zvl128b],
+                Extension::Zve64d => &[Extension::Zve64f, Extension::D],
+                Extension::Zve64f => &[Extension::Zve64x, Extension::Zve32f],
+                Extension::Zve32f => &[Extension::Zve32x, Extension::F],
+                Extension::Zve64x => &[Extension::Zve32x, Extension::Zvl64b],
+                Extension::Zve32x => &[Extension::Zvl32b, Extension::Zicsr],
+                Extension::Zvl64b => &[Extension::Zvl32b],
+                Extension::Zvl128b => &[Extension::Zvl64b],
+                Extension::Zvl256b => &[Extension::Zvl128b],
+                Extension::Zvl512b => &[Extension::Zvl256b],
+                Extension::Zvl1024b => &[Extension::Zvl512b],
+                Extension::Zicntr | Extension::Zihpm => &[Extension::Zicsr],
+                _ => &[],
+            }
+        }
+    }
+
+    impl fmt::Display for Extension {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            if let Some((name, _)) = Extension::SINGLE.iter().find(|(_, ext)| ext == self) {
+                return write!(f, "{}", name);
+            }
+            let (name, _) = Extension::MULTI.iter().find(|(_, ext)| ext == self).unwrap();
+            write!(f, "{}", name)
+        }
+    }
+
+    /// The XLEN and extensions of a hart.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Isa {
+        pub xlen: u32,
+        extensions: BTreeSet<Extension>,
+    }
+
+    impl Isa {
+        /// Parse an ISA string, with or without extension versions. Version
+        /// 2.0 of I still includes Zicsr and Zifencei.
+        pub fn parse(src: &str) -> Result<Self, String> {
+            let src = src.trim().trim_matches('"').to_lowercase();
+            let (xlen, rest) = match src.get(..4) {
+                Some("rv32") => (32, &src[4..]),
+                Some("rv64") => (64, &src[4..]),
+                _ => return Err(format!("`{}` does not start with rv32 or rv64", src)),
+            };
+
+            let mut isa = Isa { xlen, extensions: BTreeSet::new() };
+            for (idx, token) in rest.split('_').enumerate() {
+                if token.is_empty() {
+                    continue;
+                }
+                if idx > 0 && (token.starts_with('z') || token.starts_with('s') || token.starts_with('x')) {
+                    let name = strip_version(token);
+                    match Extension::MULTI.iter().find(|(multi, _)| *multi == name) {
+                        Some((_, ext)) => isa.enable(*ext),
+                        None => unknown(name),
+                    }
+                    continue;
+                }
+
+                let chars: Vec<char> = token.chars().collect();
+                let mut pos = 0;
+                while pos < chars.len() {
+                    let c = chars[pos];
+                    pos += 1;
+                    // Skip the version, as in `2p1`
+                    let start = pos;
+                    let digits = |pos: usize| chars.get(pos..).map_or(0, |rest| rest.iter().take_while(|c| c.is_ascii_digit()).count());
+                    if digits(pos) > 0 {
+                        pos += digits(pos);
+                        if chars.get(pos) == Some(&'p') && digits(pos + 1) > 0 {
+                            pos += 1 + digits(pos + 1);
+                        }
+                    }
+                    let version: String = chars[start..pos].iter().collect();
+                    if isa.extensions.is_empty() && !matches!(c, 'i' | 'g') {
+                        return Err(format!("expected base i or g, found `{}`", c));
+                    }
+                    match c {
+                        'g' => for ext in [Extension::I, Extension::M, Extension::A, Extension::F,
+                            Extension::D, Extension::Zicsr, Extension::Zifencei] {
+                            isa.enable(ext);
+                        },
+                        'i' if matches!(version.as_str(), "2" | "2p0") => for ext in [Extension::I,
+                            Extension::Zicsr, Extension::Zifencei] {
+                            isa.enable(ext);
+                        },
+                        _ => match Extension::SINGLE.iter().find(|(single, _)| *single == c) {
+                            Some((_, ext)) => isa.enable(*ext),
+                            None => unknown(&c.to_string()),
+                        },
+                    }
+                }
+            }
+            if isa.extensions.is_empty() {
+                return Err(String::from("missing base ISA"));
+            }
+            if isa.has(Extension::C) && isa.has(Extension::F) && xlen == 32 {
+                isa.enable(Extension::Zcf);
+            }
+            if isa.has(Extension::C) && isa.has(Extension::D) {
+                isa.enable(Extension::Zcd);
+            }
+            Ok(isa)
+        }
+
+        /// The ISA with the counters, Zicntr and Zihpm, if it has CSR
+        /// instructions. Toolchains rarely name them in the ISA strings of
+        /// ELF files, though the code reads the cycle counter anyway.
+        pub fn with_counters(mut self) -> Self {
+            if self.has(Extension::Zicsr) {
+                self.enable(Extension::Zicntr);
+                self.enable(Extension::Zihpm);
+            }
+            self
+        }
+
+        /// Enable `ext` and the extensions it implies.
+        fn enable(&mut self, ext: Extension) {
+            if self.extensions.insert(ext) {
+                for implied in ext.implies() {
+                    self.enable(*implied);
+                }
+            }
+        }
+
+        pub fn has(&self, ext: Extension) -> bool {
+            self.extensions.contains(&ext)
+        }
+
+        /// The minimal VLEN required by the Zvl extensions, 0 without any.
+        pub fn min_vlen(&self) -> usize {
+            Extension::ZVL.iter()
+                .filter(|(ext, _)| self.has(*ext))
+                .map(|(_, vlen)| *vlen)
+                .max()
+                .unwrap_or(0)
+        }
+
+        /// ELEN, the widest vector element: 64 with Zve64x, 32 otherwise.
+        pub fn elen(&self) -> usize {
+            if self.has(Extension::Zve64x) { 64 } else { 32 }
+        }
+
+        /// Whether code for `other` runs on `self`.
+        pub fn contains(&self, other: &Isa) -> bool {
+            self.xlen == other.xlen && self.extensions.is_superset(&other.extensions)
+        }
+    }
+
+    /// Warn about unknown extension `name`, which is ignored.
+    fn unknown(name: &str) {
+        print!("{}", "[Warning] ".yellow().bold());
+        println!("Ignoring unsupported extension {}", name);
+    }
+
+    /// `name` without the trailing version, as in `zicsr2p0`.
+    fn strip_version(name: &str) -> &str {
+        let name = name.trim_end_matches(|c: char| c.is_ascii_digit());
+        match name.strip_suffix('p') {
+            Some(major) if major.ends_with(|c: char| c.is_ascii_digit()) => major.trim_end_matches(|c: char| c.is_ascii_digit()),
+            _ => name,
+        }
+    }
+
+    impl Default for Isa {
+        fn default() -> Self {
+            ELFArch::Rv64GC.isa()
+        }
+    }
+
+    impl FromStr for Isa {
+        type Err = String;
+
+        /// A profile name or an ISA string.
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match ELFArch::from_str(s) {
+                Ok(profile) => Ok(profile.isa()),
+                Err(_) => Isa::parse(s),
+            }
+        }
+    }
+
+    impl fmt::Display for Isa {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "rv{}", self.xlen)?;
+            for ext in self.extensions.iter().filter(|ext| **ext < Extension::Zicntr) {
+                write!(f, "{}", ext)?;
+            }
+            for ext in self.extensions.iter().filter(|ext| **ext >= Extension::Zicntr) {
+                write!(f, "_{}", ext)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Profiles of the simulated hart
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ELFArch {
+        Rv64I,
+        Rv64IM,
+        Rv64GC,
+        Rv32I,
+        Rv32IMAC,
+    }
+
+    impl ELFArch {
+        pub const ALL: [ELFArch; 5] = [ELFArch::Rv64I, ELFArch::Rv64IM, ELFArch::Rv64GC, ELFArch::Rv32I, ELFArch::Rv32IMAC];
+
+        /// The ISA of the profile. Profiles with CSR instructions also
+        /// provide the counters.
+        pub fn isa(&self) -> Isa {
+            Isa::parse(&self.to_string()).unwrap().with_counters()
+        }
+    }
+
+    impl FromStr for ELFArch {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            ELFArch::ALL.into_iter()
+                .find(|profile| profile.to_string().eq_ignore_ascii_case(s))
+                .ok_or(format!("unknown profile `{}`", s))
+        }
+    }
+
+    impl fmt::Display for ELFArch {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                ELFArch::Rv64I => write!(f, "RV64I"),
+                ELFArch::Rv64IM => write!(f, "RV64IM"),
+                ELFArch::Rv64GC => write!(f, "RV64GC"),
+                ELFArch::Rv32I => write!(f, "RV32I"),
+                ELFArch::Rv32IMAC => write!(f, "RV32IMAC"),
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn parse() {
+            let isa = Isa::parse("rv64i2p0_m2p0_a2p0_f2p0_d2p0_c2p0").unwrap();
+            assert!(isa.has(Extension::Zicsr) && isa.has(Extension::Zifencei) && isa.has(Extension::Zcd));
+            assert!(!Isa::parse("rv64i2p1_m2p0").unwrap().has(Extension::Zicsr));
+
+            // Unknown extensions are ignored
+            let isa = Isa::parse("rv64i2p1_m2p0_zicsr2p0_zihintpause2p0_zfh1p0_zicbom1p0_zkt1p0_xtheadba1p0").unwrap();
+            assert_eq!(isa, Isa::parse("rv64im_zicsr").unwrap());
+            assert_eq!(Isa::parse("rv32imqc").unwrap(), Isa::parse("rv32imc").unwrap());
+
+            assert!(Isa::parse("rv64mi").is_err());
+            assert!(Isa::parse("rv128i").is_err());
+            assert_eq!(Isa::parse("rv32imac").unwrap().to_string(), "rv32imac_zmmul_zaamo_zalrsc_zca");
+        }
+    }
+}
@@ -3,10 +3,41 @@
 
 pub mod loader;
 pub mod inst;
+pub mod profile;
+pub mod coverage;
+pub mod dwarf;
+pub mod snapshot;
+pub mod history;
+pub mod expr;
+pub mod tui;
+pub mod disasm;
+pub mod asm;
+pub mod image;
+pub mod isa;
+pub mod counters;
+pub mod vector;
+pub mod float;
+pub mod linux;
 
 use colored::Colorize;
-pub use loader::loader::{Loader, ELFArch};
-pub use inst::inst::{InstCode, Inst};
+pub use loader::loader::Loader;
+pub use inst::inst::{InstCode, InstCategory, Inst};
+pub use profile::profile::{FoldedStacks, InstStats};
+pub use coverage::coverage::Coverage;
+pub use dwarf::dwarf::DebugInfo;
+use dwarf::dwarf::{Location, Variable};
+pub use snapshot::snapshot::Snapshot;
+pub use history::history::History;
+pub use expr::expr::Expr;
+pub use tui::tui::Tui;
+pub use disasm::disasm::Disasm;
+pub use asm::asm::Assembler;
+pub use image::image::{ImageLoader, ImageFormat, Permissions};
+pub use isa::isa::{Isa, Extension, ELFArch};
+pub use counters::counters::{Counters, Event};
+pub use vector::vector::{VectorUnit, VSrc};
+pub use float::float::FloatUnit;
+pub use linux::linux::{Linux, LinuxState};
 use std::io;
 use std::fmt;
 use std::error::Error;
@@ -18,6 +49,9 @@ use std::collections::HashMap;
 pub enum SimError {
     IOError(io::Error),
     ArchError(String),
+    SnapshotError(String),
+    AsmError(String),
+    ImageError(String),
 }
 
 impl fmt::Display for SimError {
@@ -33,7 +67,7 @@ impl Error for SimError {}
 /// and enforcing protection. This is a logical abstraction of the memory
 /// layout, and could later be weaponized with actual MMU/caching related
 /// simulations.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct VMA {
     pub lower_bound: u64,
     pub size: u64,
@@ -43,6 +77,29 @@ pub struct VMA {
     pub memory: Vec<u8>,
 }
 
+impl VMA {
+    /// A VMA of `size` zero bytes at `lower_bound`.
+    pub fn anonymous(lower_bound: u64, size: u64, perms: Permissions) -> Self {
+        VMA {
+            lower_bound,
+            size,
+            readable: perms.readable,
+            writeble: perms.writeble,
+            executable: perms.executable,
+            memory: vec![0u8; size as usize],
+        }
+    }
+
+    /// Split off the part from `addr` on, which must be inside the VMA.
+    fn split_off(&mut self, addr: u64) -> VMA {
+        let at = addr - self.lower_bound;
+        let memory = self.memory.split_off(at as usize);
+        let upper = VMA { lower_bound: addr, size: self.size - at, memory, ..*self };
+        self.size = at;
+        upper
+    }
+}
+
 /// A set of RV64I registers, indexed by register ID (enum).
 #[derive(Debug)]
 pub struct RegisterFile {
@@ -352,6 +409,51 @@ impl fmt::Debug for RegID {
 
 pub const HLT_ADDR: u64 = 0xFFFFFFFFFFFFFFFEu64;
 
+/// A debugger breakpoint.
+#[derive(Debug, Clone)]
+pub struct Breakpoint {
+    pub addr: u64,
+    pub enabled: bool,
+    /// Only stop if the condition evaluates to non-zero.
+    pub condition: Option<(String, Expr)>, // (source, parsed)
+    /// Number of hits to ignore before stopping.
+    pub ignore: usize,
+    pub hits: usize,
+    /// Debugger commands run when stopping here.
+    pub commands: Vec<String>,
+}
+
+impl Breakpoint {
+    pub fn new(addr: u64) -> Self {
+        Breakpoint {
+            addr,
+            enabled: true,
+            condition: None,
+            ignore: 0,
+            hits: 0,
+            commands: Vec::new(),
+        }
+    }
+}
+
+/// Accesses that trigger a memory watchpoint.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Watch {
+    Write,
+    Read,
+    Access,
+}
+
+impl fmt::Display for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Watch::Write => write!(f, "watch"),
+            Watch::Read => write!(f, "rwatch"),
+            Watch::Access => write!(f, "awatch"),
+        }
+    }
+}
+
 /// A running program loaded from an ELF. We explicitly choose to not support
 /// multi-processing nor multi-threading to simplify things, hence it suffices
 /// to keep one program counter, one set of registers and one view of memory 
@@ -367,10 +469,74 @@ pub struct Program {
 
     pub funcs: Vec<(u64, usize, String)>, // (start, sz, name)
     pub pause: usize,
-    pub breakpoints: Vec<u64>,
+    pub breakpoints: Vec<Breakpoint>,
+    pub watchpoints: Vec<(u64, usize, Watch)>, // (addr, len, kind)
+    /// Shadow call stack maintained on jal/jalr.
+    pub call_stack: Vec<(u64, u64)>, // (callee, return address)
+    /// Source-level debugging information, if the ELF has any.
+    pub debug_info: Option<DebugInfo>,
+    /// Pending source line step in the debugger.
+    pub line_step: Option<(String, u32, usize)>, // (file, line, max call depth)
+    /// Pending run to an address in the debugger, which also stops when
+    /// the frame it started from returns.
+    pub run_to: Option<(u64, usize, usize)>, // (address, max call depth, min call depth)
+    /// Execution history for reverse debugging, if recorded.
+    pub history: Option<History>,
+    /// ISA of the simulated hart
+    pub isa: Isa,
+    /// Zicntr and Zihpm counters
+    pub counters: Counters,
+    /// Vector registers and configuration
+    pub vector: VectorUnit,
+    /// Floating-point registers and CSRs
+    pub float: FloatUnit,
+    /// Address reserved by the last lr, for sc
+    pub reservation: Option<u64>,
+    /// Linux process state, for programs started by a program interpreter
+    pub linux: Option<Linux>,
+    /// Hash of the image as loaded, to match snapshots with
+    pub image_hash: u64,
 }
 
 impl Program {
+    /// Look up the function in `funcs` that contains `addr`.
+    pub fn func_at(&self, addr: u64) -> Option<&(u64, usize, String)> {
+        self.funcs.iter()
+            .find(|(start, sz, _)| *start <= addr && addr < *start + *sz as u64)
+    }
+
+    /// Address of `var` in the current frame, None if optimized out.
+    pub fn variable_addr(&self, var: &Variable) -> Option<u64> {
+        match var.location {
+            Location::Address(addr) => Some(addr),
+            Location::FrameOffset(ofs) => Some((self.registers.read(RegID::X8).unwrap() as i64 + ofs) as u64),
+            Location::Unknown => None,
+        }
+    }
+
+    /// Push or pop the shadow call stack, following the RAS hints of
+    /// the RISC-V calling convention: a jal/jalr linking to ra (or t0) is
+    /// a call, and a jalr x0 through ra (or t0) is a return.
+    pub fn track_call(&mut self, code: InstCode, return_addr: u64, target: u64) {
+        let is_link = |id: RegID| matches!(id, RegID::X1 | RegID::X5);
+        match code {
+            InstCode::Jal(rd, _) | InstCode::Jalr(rd, _, _) if is_link(rd) => {
+                self.call_stack.push((target, return_addr));
+            },
+            InstCode::Jalr(RegID::X0, rs1, _) if is_link(rs1) => {
+                // Unwind to the matching frame, which also handles frames
+                // skipped by tail calls.
+                let depth = self.call_stack.iter().rposition(|&(_, ret)| ret == target)
+                    .unwrap_or(self.call_stack.len().saturating_sub(1));
+                let popped = self.call_stack.split_off(depth);
+                if let Some(history) = self.history.as_mut() {
+                    history.record_pop(&popped);
+                }
+            },
+            _ => {}
+        }
+    }
+
     /// Shim layer for memory access. Can be modified to simulate
     /// more involved traslations.
     
@@ -390,7 +556,7 @@ impl Program {
         }
         let end = std::cmp::min(
             vma.lower_bound + vma.size,
-            addr + sz as u64,
+            addr.checked_add(sz as u64)?,
         ) - vma.lower_bound;
         let start = addr - vma.lower_bound;
 
@@ -401,6 +567,10 @@ impl Program {
     pub fn mem_store(&mut self, addr: u64, data: &[u8]) -> bool {
         let sz = data.len();
         let mut cur = 0usize;
+        let last = match addr.checked_add(sz as u64) {
+            Some(last) => last,
+            None => return false,
+        };
 
         while cur < sz {
 
@@ -418,11 +588,14 @@ impl Program {
 
             let end = std::cmp::min(
                 vma.lower_bound + vma.size,
-                addr + sz as u64,
+                last,
             ) - vma.lower_bound;
             let start = addr + cur as u64 - vma.lower_bound;
             let nxt = cur + (end - start) as usize;
 
+            if let Some(history) = self.history.as_mut() {
+                history.record_write(vma.lower_bound + start, &vma.memory[start as usize..end as usize]);
+            }
             (&mut vma.memory[start as usize..end as usize]).copy_from_slice(&data[cur..nxt]);
 
             cur = nxt;
@@ -430,4 +603,125 @@ impl Program {
 
         return true;
     }
+
+    /// Store from the debugger. Unless `force`, the write permissions of
+    /// the VMAs apply as for the program.
+    pub fn mem_poke(&mut self, addr: u64, data: &[u8], force: bool) -> bool {
+        if !force {
+            return self.mem_store(addr, data);
+        }
+        let writeble: Vec<bool> = self.vmas.iter().map(|vma| vma.writeble).collect();
+        for vma in self.vmas.iter_mut() {
+            vma.writeble = true;
+        }
+        let ok = self.mem_store(addr, data);
+        for (vma, writeble) in self.vmas.iter_mut().zip(writeble) {
+            vma.writeble = writeble;
+        }
+        ok
+    }
+
+    /// Map `vma`, replacing what was mapped in its range before.
+    pub fn map(&mut self, vma: VMA) {
+        let _ = self.unmap(vma.lower_bound, vma.size);
+        self.vmas.push(vma);
+    }
+
+    /// Unmap `len` bytes at `addr`, splitting the VMAs partly in range.
+    /// Returns false if the range wraps around the address space.
+    pub fn unmap(&mut self, addr: u64, len: u64) -> bool {
+        let end = match addr.checked_add(len) {
+            Some(end) => end,
+            None => return false,
+        };
+        self.split_vmas(addr);
+        self.split_vmas(end);
+        self.vmas.retain(|vma| vma.lower_bound + vma.size <= addr || end <= vma.lower_bound);
+        true
+    }
+
+    /// Set the permissions of `len` bytes at `addr`, splitting the VMAs
+    /// partly in range. Returns false if some of the range is not mapped.
+    pub fn protect(&mut self, addr: u64, len: u64, perms: Permissions) -> bool {
+        let end = match addr.checked_add(len) {
+            Some(end) => end,
+            None => return false,
+        };
+        self.split_vmas(addr);
+        self.split_vmas(end);
+        let mut covered = 0;
+        for vma in self.vmas.iter_mut().filter(|vma| addr <= vma.lower_bound && vma.lower_bound < end) {
+            vma.readable = perms.readable;
+            vma.writeble = perms.writeble;
+            vma.executable = perms.executable;
+            covered += vma.size;
+        }
+        covered == len
+    }
+
+    /// Split the VMA containing `addr`, if any, so that one starts there.
+    fn split_vmas(&mut self, addr: u64) {
+        if let Some(vma) = self.vmas.iter_mut().find(|v| v.lower_bound < addr && addr < v.lower_bound + v.size) {
+            let upper = vma.split_off(addr);
+            self.vmas.push(upper);
+        }
+    }
+
+    /// Read the `len` bytes watched at `addr`, as far as accessible.
+    pub fn watched(&self, addr: u64, len: usize) -> Vec<u8> {
+        match self.mem_load(addr, len, false) {
+            Some((data, _)) => data.to_vec(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Watchpoints triggered by a load or store of `sz` bytes at `addr`,
+    /// along with the watched contents before the access.
+    pub fn watch_hits(&self, addr: u64, sz: usize, store: bool) -> Vec<(usize, Vec<u8>)> {
+        self.watchpoints.iter().enumerate()
+            .filter(|(_, (start, len, kind))| {
+                addr < start.saturating_add(*len as u64) && *start < addr.saturating_add(sz as u64) &&
+                match kind {
+                    Watch::Write => store,
+                    Watch::Read => !store,
+                    Watch::Access => true,
+                }
+            })
+            .map(|(idx, &(start, len, _))| (idx, self.watched(start, len)))
+            .collect()
+    }
+
+    /// Report the watchpoints in `hits`, triggered by the instruction at
+    /// `pc`, and pause the debugger after it.
+    pub fn watch_report(&mut self, pc: u64, hits: Vec<(usize, Vec<u8>)>) {
+        for (idx, old) in hits {
+            let (addr, len, kind) = self.watchpoints[idx];
+            let new = self.watched(addr, len);
+            print!("{}", "[Debug] ".green());
+            println!("Watchpoint {} ({}) at {:#x}, {} bytes", idx + 1, kind, addr, len);
+            if old != new {
+                println!("Old value = {}", format_watched(&old));
+                println!("New value = {}", format_watched(&new));
+            }
+            else {
+                println!("Value = {}", format_watched(&new));
+            }
+            let func = self.func_at(pc).map_or("??", |(_, _, name)| name.as_str());
+            println!("Accessed by the instruction at {:#x} in {}", pc, func);
+        }
+        self.pause = 0;
+    }
+}
+
+/// Format watched memory as a little-endian integer if it has the width
+/// of one, or as bytes otherwise.
+pub fn format_watched(data: &[u8]) -> String {
+    match data.len() {
+        1 | 2 | 4 | 8 => {
+            let mut bytes = [0u8; 8];
+            bytes[..data.len()].copy_from_slice(data);
+            format!("{:#x}", u64::from_le_bytes(bytes))
+        },
+        _ => data.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<String>>().join(" "),
+    }
 }
\ No newline at end of file
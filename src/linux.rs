@@ -0,0 +1,770 @@
+#![allow(clippy::module_inception)]
+#[allow(dead_code)]
+pub mod linux {
+    //! This module implements the Linux system calls of a process started
+    //! by its program interpreter, enough for the interpreter to open,
+    //! map and relocate the libraries from the sysroot, and for the
+    //! program to do simple I/O. Files are opened read-only from the
+    //! sysroot, mappings are private copies of the file contents, and
+    //! the process is alone: signals, threads and futexes are no-ops.
+    //! Snapshots save the open files as their path and offset, to be
+    //! opened again on restore, and reverse execution stops at system
+    //! calls, which it cannot undo.
+
+    use std::fs::File;
+    use std::io::{Read, Seek, SeekFrom, Write};
+    use std::os::unix::fs::MetadataExt;
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use colored::Colorize;
+    use crate::{Program, RegID, VMA, Permissions, HLT_ADDR};
+
+    const PAGE_SIZE: u64 = 0x1000;
+    /// Largest mapping and heap, as both are backed by host memory
+    const MAX_MAPPING: u64 = 1 << 30;
+    /// Most buffers in one `writev()`
+    const IOV_MAX: u64 = 1024;
+    /// Most symbolic links followed in one path
+    const MAX_LINKS: u32 = 40;
+    /// Process and thread ID of the simulated process
+    const PID: u64 = 1000;
+
+    const ENOENT: i64 = 2;
+    const EBADF: i64 = 9;
+    const ENOMEM: i64 = 12;
+    const EACCES: i64 = 13;
+    const EFAULT: i64 = 14;
+    const EEXIST: i64 = 17;
+    const ENOTDIR: i64 = 20;
+    const EINVAL: i64 = 22;
+    const ENOTTY: i64 = 25;
+    const ENOSYS: i64 = 38;
+    const ELOOP: i64 = 40;
+
+    const AT_FDCWD: i64 = -100;
+    const AT_EMPTY_PATH: u64 = 0x1000;
+    const O_ACCMODE: u64 = 3;
+    const MAP_FIXED: u64 = 0x10;
+    const MAP_ANONYMOUS: u64 = 0x20;
+    const MAP_FIXED_NOREPLACE: u64 = 0x100000;
+
+    /// State of the simulated Linux process.
+    #[derive(Debug)]
+    pub struct Linux {
+        /// Root of the target filesystem, for absolute paths
+        sysroot: String,
+        /// Path of the executable, for `/proc/self/exe`
+        exe: String,
+        /// Open files with their host path, indexed by descriptor from 3 on
+        files: Vec<Option<(String, File)>>,
+        /// Start and current end of the heap
+        brk_start: u64,
+        brk: u64,
+        /// Mappings without an address hint go below this
+        mmap_base: u64,
+        /// State of the generator behind `getrandom()`
+        random: u64,
+    }
+
+    /// The state of a `Linux` process in a snapshot, with the open files
+    /// as (host path, offset).
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct LinuxState {
+        pub sysroot: String,
+        pub exe: String,
+        pub files: Vec<Option<(String, u64)>>,
+        pub brk_start: u64,
+        pub brk: u64,
+        pub mmap_base: u64,
+        pub random: u64,
+    }
+
+    impl Linux {
+        /// The process of `exe`, with the heap after `brk_start` and
+        /// the mappings below `mmap_base`.
+        pub fn new(sysroot: &str, exe: &str, brk_start: u64, mmap_base: u64) -> Self {
+            let brk_start = brk_start.next_multiple_of(PAGE_SIZE);
+            Linux {
+                sysroot: String::from(sysroot.trim_end_matches('/')),
+                exe: String::from(exe),
+                files: Vec::new(),
+                brk_start,
+                brk: brk_start,
+                mmap_base,
+                random: 0x9e3779b97f4a7c15,
+            }
+        }
+
+        /// The host path of `path`, relative to the sysroot if absolute.
+        /// There `..` stops at the sysroot, and symbolic links are followed
+        /// within it, so that an absolute path cannot lead out of it.
+        fn host_path(&self, dirfd: u64, path: &str) -> Result<String, i64> {
+            if !path.starts_with('/') {
+                return match dirfd as i64 {
+                    AT_FDCWD => Ok(String::from(path)),
+                    _ => Err(ENOTDIR),
+                };
+            }
+            // Components resolved so far, and those left in reverse order
+            let mut parts: Vec<String> = Vec::new();
+            let mut rest: Vec<String> = path.rsplit('/').map(String::from).collect();
+            let mut links = 0;
+            while let Some(part) = rest.pop() {
+                match part.as_str() {
+                    "" | "." => {},
+                    ".." => {
+                        parts.pop();
+                    },
+                    _ => {
+                        parts.push(part);
+                        if let Ok(target) = std::fs::read_link(self.sysroot.clone() + "/" + &parts.join("/")) {
+                            links += 1;
+                            if links > MAX_LINKS {
+                                return Err(ELOOP);
+                            }
+                            let target = target.to_string_lossy();
+                            parts.pop();
+                            if target.starts_with('/') {
+                                parts.clear();
+                            }
+                            rest.extend(target.rsplit('/').map(String::from));
+                        }
+                    },
+                }
+            }
+            Ok(self.sysroot.clone() + "/" + &parts.join("/"))
+        }
+
+        /// The state to save in a snapshot.
+        pub fn state(&self) -> LinuxState {
+            let files = self.files.iter()
+                .map(|file| file.as_ref().map(|(path, file)| {
+                    let mut file = file;
+                    (path.clone(), file.stream_position().unwrap_or(0))
+                }))
+                .collect();
+            LinuxState {
+                sysroot: self.sysroot.clone(),
+                exe: self.exe.clone(),
+                files,
+                brk_start: self.brk_start,
+                brk: self.brk,
+                mmap_base: self.mmap_base,
+                random: self.random,
+            }
+        }
+
+        /// The process saved as `state`, opening its files again.
+        pub fn from_state(state: &LinuxState) -> Result<Self, String> {
+            let mut files = Vec::new();
+            for file in state.files.iter() {
+                files.push(match file {
+                    Some((path, offset)) => {
+                        let mut file = File::open(path).map_err(|e| format!("Cannot open {}: {}", path, e))?;
+                        file.seek(SeekFrom::Start(*offset)).map_err(|e| format!("Cannot seek {}: {}", path, e))?;
+                        Some((path.clone(), file))
+                    },
+                    None => None,
+                });
+            }
+            Ok(Linux {
+                sysroot: state.sysroot.clone(),
+                exe: state.exe.clone(),
+                files,
+                brk_start: state.brk_start,
+                brk: state.brk,
+                mmap_base: state.mmap_base,
+                random: state.random,
+            })
+        }
+
+        fn file(&mut self, fd: u64) -> Result<&mut File, i64> {
+            fd.checked_sub(3)
+                .and_then(|idx| self.files.get_mut(idx as usize))
+                .and_then(|file| file.as_mut())
+                .map(|(_, file)| file)
+                .ok_or(EBADF)
+        }
+
+        /// Store `file`, opened from `path`, at the lowest free descriptor.
+        fn install(&mut self, path: String, file: File) -> u64 {
+            let idx = match self.files.iter().position(|file| file.is_none()) {
+                Some(idx) => idx,
+                None => {
+                    self.files.push(None);
+                    self.files.len() - 1
+                },
+            };
+            self.files[idx] = Some((path, file));
+            idx as u64 + 3
+        }
+    }
+
+    /// `struct stat` of the generic Linux ABI, as used by RV64.
+    fn stat(meta: &std::fs::Metadata) -> Vec<u8> {
+        let mut buf = vec![0u8; 128];
+        let mut put = |ofs: usize, val: u64, sz: usize| buf[ofs..ofs+sz].copy_from_slice(&val.to_le_bytes()[..sz]);
+        put(0, meta.dev(), 8);
+        put(8, meta.ino(), 8);
+        put(16, meta.mode() as u64, 4);
+        put(20, meta.nlink(), 4);
+        put(24, meta.uid() as u64, 4);
+        put(28, meta.gid() as u64, 4);
+        put(32, meta.rdev(), 8);
+        put(48, meta.size(), 8);
+        put(56, meta.blksize(), 4);
+        put(64, meta.blocks(), 8);
+        put(72, meta.atime() as u64, 8);
+        put(80, meta.atime_nsec() as u64, 8);
+        put(88, meta.mtime() as u64, 8);
+        put(96, meta.mtime_nsec() as u64, 8);
+        put(104, meta.ctime() as u64, 8);
+        put(112, meta.ctime_nsec() as u64, 8);
+        buf
+    }
+
+    /// `struct statx`, the only stat of the RV32 ABI.
+    fn statx(meta: &std::fs::Metadata) -> Vec<u8> {
+        let mut buf = vec![0u8; 256];
+        let mut put = |ofs: usize, val: u64, sz: usize| buf[ofs..ofs+sz].copy_from_slice(&val.to_le_bytes()[..sz]);
+        put(0, 0x7ff, 4);                   // STATX_BASIC_STATS
+        put(4, meta.blksize(), 4);
+        put(16, meta.nlink(), 4);
+        put(20, meta.uid() as u64, 4);
+        put(24, meta.gid() as u64, 4);
+        put(28, meta.mode() as u64, 2);
+        put(32, meta.ino(), 8);
+        put(40, meta.size(), 8);
+        put(48, meta.blocks(), 8);
+        put(64, meta.atime() as u64, 8);
+        put(72, meta.atime_nsec() as u64, 4);
+        put(96, meta.ctime() as u64, 8);
+        put(104, meta.ctime_nsec() as u64, 4);
+        put(112, meta.mtime() as u64, 8);
+        put(120, meta.mtime_nsec() as u64, 4);
+        put(128, meta.rdev() >> 8 & 0xfff, 4);
+        put(132, meta.rdev() & 0xff, 4);
+        put(136, meta.dev() >> 8 & 0xfff, 4);
+        put(140, meta.dev() & 0xff, 4);
+        buf
+    }
+
+    /// `len` rounded up to whole pages, if the range of that many bytes
+    /// at `addr` does not wrap around the address space.
+    fn page_len(addr: u64, len: u64) -> Option<u64> {
+        let len = len.checked_next_multiple_of(PAGE_SIZE)?;
+        addr.checked_add(len).map(|_| len)
+    }
+
+    /// `PROT_*` bits as permissions.
+    fn permissions(prot: u64) -> Permissions {
+        Permissions { readable: prot & 1 != 0, writeble: prot & 2 != 0, executable: prot & 4 != 0 }
+    }
+
+    impl Program {
+        /// Read `len` bytes at `addr`, possibly across VMAs.
+        fn guest_read(&self, addr: u64, len: usize) -> Result<Vec<u8>, i64> {
+            let mut data = Vec::new();
+            while data.len() < len {
+                let cur = addr.checked_add(data.len() as u64).ok_or(EFAULT)?;
+                match self.mem_load(cur, len - data.len(), false) {
+                    Some((bytes, _)) => data.extend_from_slice(bytes),
+                    None => return Err(EFAULT),
+                }
+            }
+            Ok(data)
+        }
+
+        /// Read the NUL-terminated string at `addr`.
+        fn guest_string(&self, addr: u64) -> Result<String, i64> {
+            let mut data = Vec::new();
+            loop {
+                let cur = addr.checked_add(data.len() as u64).ok_or(EFAULT)?;
+                match self.mem_load(cur, 1, false) {
+                    Some(([0], _)) => return String::from_utf8(data).map_err(|_| ENOENT),
+                    Some((bytes, _)) => data.extend_from_slice(bytes),
+                    None => return Err(EFAULT),
+                }
+                if data.len() >= 4096 {
+                    return Err(EFAULT);
+                }
+            }
+        }
+
+        fn guest_write(&mut self, addr: u64, data: &[u8]) -> Result<(), i64> {
+            match self.mem_store(addr, data) {
+                true => Ok(()),
+                false => Err(EFAULT),
+            }
+        }
+
+        /// Whether `len` bytes at `addr` are free.
+        fn unmapped(&self, addr: u64, len: u64) -> bool {
+            !self.vmas.iter().any(|vma| vma.lower_bound < addr + len && addr < vma.lower_bound + vma.size)
+        }
+
+        /// The highest free range of `len` bytes below the mmap base.
+        fn mmap_addr(&self, len: u64) -> Option<u64> {
+            let mut addr = self.linux.as_ref()?.mmap_base.checked_sub(len)?;
+            loop {
+                let below = self.vmas.iter()
+                    .filter(|vma| vma.lower_bound < addr + len && addr < vma.lower_bound + vma.size)
+                    .map(|vma| vma.lower_bound)
+                    .min();
+                match below {
+                    Some(lower) => addr = (lower & !(PAGE_SIZE - 1)).checked_sub(len)?,
+                    None => return Some(addr),
+                }
+            }
+        }
+
+        /// Run the system call in a7 on the arguments in a0..a5, for the
+        /// `ecall` at `pc`. Returns the value of a0, or the PC to go to
+        /// when the process exits.
+        pub fn syscall(&mut self, pc: u64) -> Result<u64, u64> {
+            let xlen = self.isa.xlen;
+            let mask = if xlen == 32 { 0xFFFFFFFF } else { u64::MAX };
+            let reg = |prog: &Program, id: RegID| prog.registers.read(id).unwrap_or(0) & mask;
+            let nr = reg(self, RegID::X17);
+            if let Some(history) = self.history.as_mut() {
+                history.record_syscall();
+            }
+            let args = [RegID::X10, RegID::X11, RegID::X12, RegID::X13, RegID::X14, RegID::X15].map(|id| reg(self, id));
+
+            let ret = match nr {
+                // exit, exit_group
+                93 | 94 => {
+                    print!("{}", "[Debug] ".green());
+                    println!("exit({}), exiting...", args[0] as i32);
+                    return Err(HLT_ADDR);
+                },
+                // kill, tkill, tgkill: the only process is the caller
+                129..=131 => {
+                    let sig = if nr == 131 { args[2] } else { args[1] };
+                    print!("{}", "[Warning] ".yellow().bold());
+                    println!("Killed by signal {} at {:#x}", sig, pc);
+                    return Err(HLT_ADDR);
+                },
+                _ => self.syscall_result(nr, args, xlen),
+            };
+            let ret = match ret {
+                Ok(val) => val,
+                Err(errno) => (-errno) as u64,
+            };
+            Ok(if xlen == 32 { ret as i32 as i64 as u64 } else { ret })
+        }
+
+        fn syscall_result(&mut self, nr: u64, args: [u64; 6], xlen: u32) -> Result<u64, i64> {
+            let word = xlen as usize / 8;
+            if self.linux.is_none() {
+                return Err(ENOSYS);
+            }
+            match nr {
+                // getcwd
+                17 => {
+                    let cwd = std::env::current_dir().map_err(|_| ENOENT)?;
+                    let cwd = [cwd.to_string_lossy().as_bytes(), &[0]].concat();
+                    if cwd.len() as u64 > args[1] {
+                        return Err(34);     // ERANGE
+                    }
+                    self.guest_write(args[0], &cwd)?;
+                    Ok(cwd.len() as u64)
+                },
+                // fcntl
+                25 => Ok(0),
+                // ioctl: no terminals
+                29 => Err(ENOTTY),
+                // faccessat, faccessat2
+                48 | 439 => {
+                    let path = self.guest_string(args[1])?;
+                    let path = self.linux.as_ref().unwrap().host_path(args[0], &path)?;
+                    match std::fs::metadata(path) {
+                        Ok(_) if args[2] & 2 != 0 => Err(EACCES),
+                        Ok(_) => Ok(0),
+                        Err(_) => Err(ENOENT),
+                    }
+                },
+                // openat: read-only
+                56 => {
+                    let path = self.guest_string(args[1])?;
+                    if args[2] & O_ACCMODE != 0 {
+                        return Err(EACCES);
+                    }
+                    let linux = self.linux.as_mut().unwrap();
+                    let path = linux.host_path(args[0], &path)?;
+                    let file = File::open(&path).map_err(|_| ENOENT)?;
+                    Ok(linux.install(path, file))
+                },
+                // close
+                57 => {
+                    let linux = self.linux.as_mut().unwrap();
+                    match args[0] {
+                        0..=2 => Ok(0),
+                        fd => {
+                            linux.file(fd)?;
+                            linux.files[fd as usize - 3] = None;
+                            Ok(0)
+                        },
+                    }
+                },
+                // _llseek of RV32, with the offset split in two and the
+                // result stored
+                62 if xlen == 32 => {
+                    let offset = (args[1] << 32 | args[2]) as i64;
+                    let pos = match args[4] {
+                        0 => SeekFrom::Start(offset as u64),
+                        1 => SeekFrom::Current(offset),
+                        2 => SeekFrom::End(offset),
+                        _ => return Err(EINVAL),
+                    };
+                    let pos = self.linux.as_mut().unwrap().file(args[0])?.seek(pos).map_err(|_| EINVAL)?;
+                    self.guest_write(args[3], &pos.to_le_bytes())?;
+                    Ok(0)
+                },
+                // lseek
+                62 => {
+                    let pos = match args[2] {
+                        0 => SeekFrom::Start(args[1]),
+                        1 => SeekFrom::Current(args[1] as i64),
+                        2 => SeekFrom::End(args[1] as i64),
+                        _ => return Err(EINVAL),
+                    };
+                    self.linux.as_mut().unwrap().file(args[0])?.seek(pos).map_err(|_| EINVAL)
+                },
+                // read, pread64
+                63 | 67 => {
+                    if args[0] == 0 {
+                        return Ok(0);
+                    }
+                    let mut data = vec![0u8; args[2].min(1 << 24) as usize];
+                    // RV32 passes the offset in a register pair
+                    let offset = if xlen == 32 { args[4] << 32 | args[3] } else { args[3] };
+                    let file = self.linux.as_mut().unwrap().file(args[0])?;
+                    let len = if nr == 67 {
+                        let pos = file.stream_position().map_err(|_| EINVAL)?;
+                        file.seek(SeekFrom::Start(offset)).map_err(|_| EINVAL)?;
+                        let len = file.read(&mut data);
+                        file.seek(SeekFrom::Start(pos)).map_err(|_| EINVAL)?;
+                        len
+                    }
+                    else {
+                        file.read(&mut data)
+                    };
+                    let len = len.map_err(|_| EINVAL)?;
+                    self.guest_write(args[1], &data[..len])?;
+                    Ok(len as u64)
+                },
+                // write, writev: to stdout and stderr only
+                64 | 66 => {
+                    let data = match nr {
+                        64 => self.guest_read(args[1], args[2].min(1 << 24) as usize)?,
+                        _ => {
+                            if args[2] > IOV_MAX {
+                                return Err(EINVAL);
+                            }
+                            let mut data = Vec::new();
+                            for idx in 0..args[2] {
+                                let addr = args[1].checked_add(idx * 2 * word as u64).ok_or(EFAULT)?;
+                                let iov = self.guest_read(addr, 2 * word)?;
+                                let field = |ofs: usize| {
+                                    let mut bytes = [0u8; 8];
+                                    bytes[..word].copy_from_slice(&iov[ofs..ofs+word]);
+                                    u64::from_le_bytes(bytes)
+                                };
+                                let len = field(word).min((1 << 24) - data.len() as u64);
+                                data.extend(self.guest_read(field(0), len as usize)?);
+                            }
+                            data
+                        },
+                    };
+                    match args[0] {
+                        1 | 2 => {
+                            let mut stdout = std::io::stdout();
+                            let _ = stdout.write_all(&data);
+                            let _ = stdout.flush();
+                            Ok(data.len() as u64)
+                        },
+                        _ => Err(EBADF),
+                    }
+                },
+                // readlinkat
+                78 => {
+                    let path = self.guest_string(args[1])?;
+                    if path != "/proc/self/exe" {
+                        return Err(EINVAL);
+                    }
+                    let exe = self.linux.as_ref().unwrap().exe.clone();
+                    let len = exe.len().min(args[3] as usize);
+                    self.guest_write(args[2], &exe.as_bytes()[..len])?;
+                    Ok(len as u64)
+                },
+                // newfstatat, fstat (not on RV32), statx
+                79 | 80 | 291 if nr == 291 || xlen == 64 => {
+                    let (dirfd, path, flags, buf) = match nr {
+                        79 => (args[0], self.guest_string(args[1])?, args[3], args[2]),
+                        80 => (args[0], String::new(), AT_EMPTY_PATH, args[1]),
+                        _ => (args[0], self.guest_string(args[1])?, args[2], args[4]),
+                    };
+                    let linux = self.linux.as_mut().unwrap();
+                    let meta = if path.is_empty() && flags & AT_EMPTY_PATH != 0 {
+                        match dirfd {
+                            0..=2 => return Err(ENOTTY),
+                            fd => linux.file(fd)?.metadata(),
+                        }
+                    }
+                    else {
+                        std::fs::metadata(linux.host_path(dirfd, &path)?)
+                    };
+                    let meta = meta.map_err(|_| ENOENT)?;
+                    let data = if nr == 291 { statx(&meta) } else { stat(&meta) };
+                    self.guest_write(buf, &data)?;
+                    Ok(0)
+                },
+                // set_tid_address, gettid
+                96 | 178 => Ok(PID),
+                // futex, set_robust_list, sigaltstack, rt_sigaction, rt_sigprocmask
+                98 | 99 | 132 | 134 | 135 => Ok(0),
+                // clock_gettime, clock_gettime64 of RV32
+                113 | 403 if (nr == 403) == (xlen == 32) => {
+                    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+                    let mut data = now.as_secs().to_le_bytes().to_vec();
+                    data.extend((now.subsec_nanos() as u64).to_le_bytes());
+                    self.guest_write(args[1], &data)?;
+                    Ok(0)
+                },
+                // uname
+                160 => {
+                    let machine = if xlen == 32 { "riscv32" } else { "riscv64" };
+                    let mut data = vec![0u8; 65 * 6];
+                    for (idx, field) in ["Linux", "rsim", "6.1.0", "#1", machine].iter().enumerate() {
+                        data[65 * idx..65 * idx + field.len()].copy_from_slice(field.as_bytes());
+                    }
+                    self.guest_write(args[0], &data)?;
+                    Ok(0)
+                },
+                // getpid, getppid, getuid, geteuid, getgid, getegid
+                172 => Ok(PID),
+                173 => Ok(1),
+                174..=177 => Ok(0),
+                // brk
+                214 => {
+                    let linux = self.linux.as_ref().unwrap();
+                    let (start, old) = (linux.brk_start, linux.brk);
+                    let new = args[0];
+                    let new_end = match new.checked_next_multiple_of(PAGE_SIZE) {
+                        Some(end) if new >= start && end - start <= MAX_MAPPING => end,
+                        _ => return Ok(old),
+                    };
+                    let old_end = old.next_multiple_of(PAGE_SIZE);
+                    if new_end > old_end {
+                        if !self.unmapped(old_end, new_end - old_end) {
+                            return Ok(old);
+                        }
+                        self.map(VMA::anonymous(old_end, new_end - old_end, permissions(3)));
+                    }
+                    else if new_end < old_end {
+                        self.unmap(new_end, old_end - new_end);
+                    }
+                    self.linux.as_mut().unwrap().brk = new;
+                    Ok(new)
+                },
+                // munmap
+                215 => {
+                    if !args[0].is_multiple_of(PAGE_SIZE) {
+                        return Err(EINVAL);
+                    }
+                    match page_len(args[0], args[1]) {
+                        Some(len) if self.unmap(args[0], len) => Ok(0),
+                        _ => Err(EINVAL),
+                    }
+                },
+                // mmap, or mmap2 of RV32 with the offset in pages
+                222 => {
+                    let offset = if xlen == 32 { args[5] * PAGE_SIZE } else { args[5] };
+                    let (hint, len, prot, flags, fd) = (args[0], args[1], args[2], args[3], args[4]);
+                    if len == 0 || !hint.is_multiple_of(PAGE_SIZE) || !offset.is_multiple_of(PAGE_SIZE) {
+                        return Err(EINVAL);
+                    }
+                    let len = match len.checked_next_multiple_of(PAGE_SIZE) {
+                        Some(len) if len <= MAX_MAPPING => len,
+                        _ => return Err(ENOMEM),
+                    };
+                    let fits = hint.checked_add(len).is_some();
+                    let addr = if flags & (MAP_FIXED | MAP_FIXED_NOREPLACE) != 0 {
+                        if !fits {
+                            return Err(EINVAL);
+                        }
+                        if flags & MAP_FIXED == 0 && !self.unmapped(hint, len) {
+                            return Err(EEXIST);
+                        }
+                        hint
+                    }
+                    else if hint != 0 && fits && self.unmapped(hint, len) {
+                        hint
+                    }
+                    else {
+                        self.mmap_addr(len).ok_or(ENOMEM)?
+                    };
+                    let mut vma = VMA::anonymous(addr, len, permissions(prot));
+                    if flags & MAP_ANONYMOUS == 0 {
+                        // A private copy of the file, zero past its end
+                        let file = self.linux.as_mut().unwrap().file(fd)?;
+                        file.seek(SeekFrom::Start(offset)).map_err(|_| EINVAL)?;
+                        let mut data = Vec::new();
+                        file.take(len).read_to_end(&mut data).map_err(|_| EINVAL)?;
+                        vma.memory[..data.len()].copy_from_slice(&data);
+                    }
+                    self.map(vma);
+                    Ok(addr)
+                },
+                // mprotect
+                226 => {
+                    if !args[0].is_multiple_of(PAGE_SIZE) {
+                        return Err(EINVAL);
+                    }
+                    let len = page_len(args[0], args[1]).ok_or(EINVAL)?;
+                    match self.protect(args[0], len, permissions(args[2])) {
+                        true => Ok(0),
+                        false => Err(ENOMEM),
+                    }
+                },
+                // madvise
+                233 => Ok(0),
+                // prlimit64: 8MB of stack, nothing else limited
+                261 => {
+                    if args[3] != 0 {
+                        let cur = if args[1] == 3 { 8 << 20 } else { u64::MAX };
+                        let data = [cur.to_le_bytes(), u64::MAX.to_le_bytes()].concat();
+                        self.guest_write(args[3], &data)?;
+                    }
+                    Ok(0)
+                },
+                // getrandom: deterministic, so runs are reproducible
+                278 => {
+                    let len = args[1].min(1 << 24);
+                    let linux = self.linux.as_mut().unwrap();
+                    let data: Vec<u8> = (0..len).map(|_| {
+                        linux.random ^= linux.random << 13;
+                        linux.random ^= linux.random >> 7;
+                        linux.random ^= linux.random << 17;
+                        linux.random as u8
+                    }).collect();
+                    self.guest_write(args[0], &data)?;
+                    Ok(len)
+                },
+                // rseq: glibc goes on without it
+                293 => Err(ENOSYS),
+                _ => {
+                    print!("{}", "[Warning] ".yellow().bold());
+                    println!("System call {} is not simulated", nr);
+                    Err(ENOSYS)
+                },
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::collections::HashMap;
+        use crate::{Loader, Isa};
+
+        /// A process with nothing mapped but the stack.
+        fn process() -> Program {
+            let mut prog = Loader::program(0x1000, Vec::new(), HashMap::new(), Vec::new(), None);
+            prog.linux = Some(Linux::new("/", "a.out", 0x10000, 0x3ff0000000));
+            prog
+        }
+
+        /// Run system call `nr` on `args`, and return a0.
+        fn syscall(prog: &mut Program, nr: u64, args: &[u64]) -> i64 {
+            prog.registers.write(RegID::X17, nr);
+            for (id, arg) in [RegID::X10, RegID::X11, RegID::X12, RegID::X13, RegID::X14, RegID::X15].into_iter().zip(args) {
+                prog.registers.write(id, *arg);
+            }
+            prog.syscall(0x1000).unwrap() as i64
+        }
+
+        #[test]
+        fn huge_lengths() {
+            let mut prog = process();
+            let vmas = prog.vmas.len();
+            assert_eq!(syscall(&mut prog, 222, &[0, u64::MAX, 3, MAP_ANONYMOUS, u64::MAX, 0]), -ENOMEM);
+            assert_eq!(syscall(&mut prog, 222, &[0, MAX_MAPPING + 1, 3, MAP_ANONYMOUS, u64::MAX, 0]), -ENOMEM);
+            assert_eq!(syscall(&mut prog, 222, &[!(PAGE_SIZE - 1), PAGE_SIZE * 2, 3, MAP_ANONYMOUS | MAP_FIXED, u64::MAX, 0]), -EINVAL);
+            assert_eq!(syscall(&mut prog, 215, &[PAGE_SIZE, u64::MAX]), -EINVAL);
+            assert_eq!(syscall(&mut prog, 226, &[PAGE_SIZE, u64::MAX - PAGE_SIZE, 1]), -EINVAL);
+            assert_eq!(syscall(&mut prog, 214, &[u64::MAX]), 0x10000);
+            assert_eq!(syscall(&mut prog, 214, &[0x10000 + MAX_MAPPING + 1]), 0x10000);
+            assert_eq!(prog.vmas.len(), vmas);
+
+            // write and writev read no further than the mapping of the buffer
+            let sp = prog.registers.read(RegID::X2).unwrap();
+            assert_eq!(syscall(&mut prog, 64, &[1, sp, u64::MAX]), -EFAULT);
+            assert_eq!(syscall(&mut prog, 64, &[1, u64::MAX - 1, u64::MAX]), -EFAULT);
+            prog.mem_store(sp, &[sp.to_le_bytes(), u64::MAX.to_le_bytes()].concat());
+            assert_eq!(syscall(&mut prog, 66, &[1, sp, 1]), -EFAULT);
+            prog.mem_store(sp, &[(u64::MAX - 1).to_le_bytes(), u64::MAX.to_le_bytes()].concat());
+            assert_eq!(syscall(&mut prog, 66, &[1, sp, 1]), -EFAULT);
+            assert_eq!(syscall(&mut prog, 66, &[1, u64::MAX - 8, 1]), -EFAULT);
+
+            // Nor do they wrap around from the top of the address space
+            let top = u64::MAX - 2 * PAGE_SIZE + 1;
+            prog.vmas.push(VMA { lower_bound: top, size: PAGE_SIZE, readable: true, writeble: true, executable: false, memory: vec![0; PAGE_SIZE as usize] });
+            assert_eq!(syscall(&mut prog, 64, &[1, top, u64::MAX]), -EFAULT);
+            prog.mem_store(sp, &[top.to_le_bytes(), u64::MAX.to_le_bytes()].concat());
+            assert_eq!(syscall(&mut prog, 66, &[1, sp, 1]), -EFAULT);
+            assert!(prog.mem_load(top, usize::MAX, false).is_none());
+        }
+
+        #[test]
+        fn sysroot_paths() {
+            let root = std::env::temp_dir().join(format!("rsim-sysroot-{}", std::process::id()));
+            let lib = root.join("usr/lib");
+            std::fs::create_dir_all(&lib).unwrap();
+            let _ = std::os::unix::fs::symlink("/usr/lib", root.join("lib"));
+            let _ = std::os::unix::fs::symlink("../../..", lib.join("up"));
+            let sysroot = root.to_str().unwrap();
+            let linux = Linux::new(sysroot, "a.out", 0, 0);
+            let host = |path: &str| linux.host_path(AT_FDCWD as u64, path).unwrap();
+            assert_eq!(host("/etc/../../../etc/passwd"), String::from(sysroot) + "/etc/passwd");
+            assert_eq!(host("/lib/libc.so.6"), String::from(sysroot) + "/usr/lib/libc.so.6");
+            assert_eq!(host("/usr/lib/up/etc"), String::from(sysroot) + "/etc");
+            assert_eq!(host("./a.out"), "./a.out");
+            assert_eq!(linux.host_path(3, "a.out"), Err(ENOTDIR));
+            std::fs::remove_dir_all(root).unwrap();
+        }
+
+        /// On RV32, 62 is `_llseek()` and 222 is `mmap2()`.
+        #[test]
+        fn rv32_calls() {
+            let root = std::env::temp_dir().join(format!("rsim-rv32-{}", std::process::id()));
+            std::fs::create_dir_all(&root).unwrap();
+            std::fs::write(root.join("data"), [[1u8; 0x1000], [2u8; 0x1000]].concat()).unwrap();
+            let mut prog = process();
+            prog.isa = Isa::parse("rv32i").unwrap();
+            prog.linux = Some(Linux::new(root.to_str().unwrap(), "a.out", 0x10000, 0x80000000));
+            prog.map(VMA::anonymous(0x20000, PAGE_SIZE, permissions(3)));
+            prog.mem_store(0x20000, b"/data\0");
+            let fd = syscall(&mut prog, 56, &[AT_FDCWD as u64, 0x20000, 0]) as u64;
+            assert_eq!(syscall(&mut prog, 62, &[fd, 0, 0x1800, 0x20100, 0]), 0);
+            assert_eq!(prog.mem_load(0x20100, 8, false).unwrap().0, 0x1800u64.to_le_bytes());
+            let addr = syscall(&mut prog, 222, &[0, PAGE_SIZE, 1, 0, fd, 1]) as u64;
+            assert_eq!(prog.mem_load(addr, 1, false).unwrap().0, [2]);
+            std::fs::remove_dir_all(root).unwrap();
+        }
+
+        #[test]
+        fn mappings() {
+            let mut prog = process();
+            let addr = syscall(&mut prog, 222, &[0, 3 * PAGE_SIZE, 3, MAP_ANONYMOUS, u64::MAX, 0]) as u64;
+            assert!(addr.is_multiple_of(PAGE_SIZE) && addr < 0x3ff0000000);
+            assert_eq!(syscall(&mut prog, 226, &[addr + PAGE_SIZE, 1, 1]), 0);
+            assert!(!prog.mem_store(addr + PAGE_SIZE, &[1]));
+            assert!(prog.mem_store(addr + 2 * PAGE_SIZE, &[1]));
+            assert_eq!(syscall(&mut prog, 215, &[addr, PAGE_SIZE]), 0);
+            assert!(prog.mem_load(addr, 1, false).is_none());
+            assert_eq!(syscall(&mut prog, 214, &[0x10000 + 10]), 0x10000 + 10);
+            assert!(prog.mem_store(0x10000 + 9, &[1]));
+        }
+    }
+}
@@ -5,36 +5,84 @@ pub mod loader {
 
     //! This module implements the loading of an RV64I ELF executable. 
     //! We leverage the output of `riscv-unknown-elf-readelf --segment`
-    //! for loading instructions. 
+    //! for loading instructions. Position-independent static executables
+    //! (ET_DYN) are loaded at `ET_DYN_BASE` and relocated from the output
+    //! of `riscv-unknown-elf-readelf -r`.
+    //! For dynamically linked executables, the program interpreter named in
+    //! PT_INTERP is mapped from a sysroot at `INTERP_BASE`, and started with
+    //! the initial stack and auxiliary vector of Linux. Its system calls
+    //! are then simulated by `Linux`, as it maps the libraries and runs
+    //! the executable.
     
-    use std::{fmt, collections::HashMap};
+    use std::collections::HashMap;
     use std::io::{Read, Write};
     use std::process::Command;
     use std::fs::File;
     use colored::Colorize;
-    use crate::{SimError, VMA, Program, RegisterFile, Register, RegID, HLT_ADDR};
+    use crate::{SimError, VMA, Program, RegisterFile, Register, RegID, HLT_ADDR, DebugInfo, Isa, ELFArch, Counters, VectorUnit, FloatUnit, Linux};
+    use crate::snapshot::snapshot::image_hash;
     
     /// Pseudo-struct for the loading method.
     pub struct Loader {
-        target_arch: ELFArch,
+        /// ISA of the hart, else detected from the ELF
+        target_arch: Option<Isa>,
         path: String,
+        /// Root of the target filesystem, for the program interpreter
+        sysroot: Option<String>,
+    }
+
+    /// Symbols of an ELF file: (funcs, simulated library functions, main)
+    type Symbols = (Vec<(u64, usize, String)>, HashMap<u64, String>, Option<u64>);
+
+    /// An ELF file mapped into memory.
+    struct Image {
+        entry_point: u64,
+        bias: u64,
+        vmas: Vec<VMA>,
+        /// Program interpreter requested by PT_INTERP
+        interp: Option<String>,
+        /// ISA in the arch attribute
+        isa: Option<Isa>,
+        /// 32 for ELFCLASS32, else 64
+        xlen: u32,
+        /// Address and number of the program headers, for the auxv
+        phdr: u64,
+        phnum: u64,
+        data: Vec<u8>,
     }
 
     impl Loader {
         pub const STACK_BOTTOM: u64 = 0x4000000u64;
         pub const STACK_ALIGNMENT: usize = 16;
+        /// Use 1MB stack
+        pub const STACK_SIZE: u64 = 0x100000u64;
+        /// Load bias of ET_DYN executables, which Linux puts at two thirds
+        /// of the Sv39 user address space.
+        pub const ET_DYN_BASE: u64 = 0x2aaaaaa000u64;
+        /// Load address of the program interpreter, towards the top of the
+        /// Sv39 user address space like the mmap base of Linux.
+        pub const INTERP_BASE: u64 = 0x3ff0000000u64;
+        /// Same as `ET_DYN_BASE` and `INTERP_BASE` for ELFCLASS32, in the
+        /// Sv32 user address space.
+        pub const ET_DYN_BASE_32: u64 = 0x6a800000u64;
+        pub const INTERP_BASE_32: u64 = 0x90000000u64;
 
-        pub fn new(target_arch: ELFArch, path: &str) -> Self {
+        pub fn new(target_arch: Option<Isa>, path: &str) -> Self {
             Loader {
                 target_arch,
                 path: String::from(path),
+                sysroot: None,
             }
         }
 
-        pub fn load(&self) -> Result<Program, SimError> {
+        /// Load the program interpreter of dynamically linked executables
+        /// from `sysroot`.
+        pub fn with_sysroot(mut self, sysroot: &str) -> Self {
+            self.sysroot = Some(String::from(sysroot));
+            self
+        }
 
-            let mut entry_point: u64 = 0;
-            let mut vmas: Vec<VMA> = Vec::new();
+        pub fn load(&self) -> Result<Program, SimError> {
 
             // File for storing information parsed from ELF
             let mut debug_file = File::create(String::from(&self.path) + ".d");
@@ -45,19 +93,138 @@ pub mod loader {
             }
             let mut debug_file = debug_file.unwrap();
 
+            let image = self.map(false, &mut debug_file)?;
+            let isa = match (&self.target_arch, &image.isa) {
+                (Some(isa), _) if isa.xlen != image.xlen => {
+                    return Err(SimError::ArchError(format!("Expected arch {}, found ELFCLASS{}", isa, image.xlen)));
+                },
+                (Some(isa), _) | (None, Some(isa)) => isa.clone(),
+                (None, None) if image.xlen == 32 => ELFArch::Rv32IMAC.isa(),
+                (None, None) => Isa::default(),
+            };
+            let mut entry_point = image.entry_point;
+            let mut vmas = image.vmas;
+            let mut debug_info = DebugInfo::parse(&image.data);
+            let (mut funcs, mut simulated_library_funcs, main) = self.symbols(image.bias, &mut debug_file)?;
+            let mut auxv = Vec::new();
+            // Process of the interpreter: the heap follows the executable,
+            // and mappings go below the interpreter
+            let mut process = None;
+
+            match &image.interp {
+                Some(interp) => {
+                    // The interpreter maps the libraries, relocates and starts
+                    // at the entry point of the executable, found in the auxv.
+                    let sysroot = match &self.sysroot {
+                        Some(sysroot) => sysroot,
+                        None => return Err(SimError::ArchError(String::from("Dynamically linked against ") + interp + ", no sysroot given")),
+                    };
+                    let loader = Loader {
+                        target_arch: Some(isa.clone()),
+                        path: String::from(sysroot.trim_end_matches('/')) + interp,
+                        sysroot: None,
+                    };
+                    let ld = loader.map(true, &mut debug_file)?;
+                    let (ld_funcs, _, _) = loader.symbols(ld.bias, &mut debug_file)?;
+                    funcs.extend(ld_funcs);
+                    // The real library functions run
+                    simulated_library_funcs.clear();
+
+                    auxv = vec![
+                        (3, image.phdr),                // AT_PHDR
+                        (4, if image.xlen == 32 { 32 } else { 56 }), // AT_PHENT
+                        (5, image.phnum),               // AT_PHNUM
+                        (6, 0x1000),                    // AT_PAGESZ
+                        (7, ld.bias),                   // AT_BASE
+                        (8, 0),                         // AT_FLAGS
+                        (9, image.entry_point),         // AT_ENTRY
+                        (11, 0), (12, 0), (13, 0), (14, 0), // AT_UID, AT_EUID, AT_GID, AT_EGID
+                        (17, 100),                      // AT_CLKTCK
+                        (23, 0),                        // AT_SECURE
+                    ];
+                    entry_point = ld.entry_point;
+                    let brk_start = vmas.iter().map(|vma| vma.lower_bound + vma.size).max().unwrap_or(0);
+                    process = Some(Linux::new(sysroot, &self.path, brk_start, ld.bias));
+                    vmas.extend(ld.vmas);
+                },
+                None => {
+                    // Without a kernel to make the system calls of the C runtime, start at `main()`
+                    // instead of `_start()`, looked up as in `riscv64-unknown-elf-readelf -s`.
+                    if let Some(main) = main {
+                        entry_point = main;
+                    }
+                    if image.bias != 0 {
+                        self.relocate(&mut vmas, image.bias, image.xlen, &mut debug_file)?;
+                    }
+                },
+            }
+            if let Some(info) = debug_info.as_mut() {
+                info.relocate(image.bias);
+            }
+
+            let _ = debug_file.write_all("\n\n\n[rsim] Load Summary:\n".as_bytes());
+            let _ = debug_file.write_all(format!("entry point: {:#x} ({})\n", entry_point,
+                image.interp.as_deref().unwrap_or("main")).as_ref());
+            let _ = debug_file.write_all(format!("load bias: {:#x}\n", image.bias).as_ref());
+            let _ = debug_file.write_all(format!("isa: {}\n", isa).as_ref());
+            let _ = match &debug_info {
+                Some(info) => debug_file.write_all(format!("debug info: {} files, {} functions, {} variables\n",
+                    info.files.len(), info.functions.len(), info.variables.len()).as_ref()),
+                None => debug_file.write_all("debug info: none\n".as_bytes()),
+            };
+            let _ = debug_file.write_all("vmas:\n".as_bytes());
+            for (idx, vma) in vmas.iter().enumerate() {
+                let _ = debug_file.write_all(format!("{}: {:#x} ~ {:#x}, readable = {}, writeable = {}, executable = {}\n",
+                    idx, vma.lower_bound, vma.lower_bound+vma.size, vma.readable, vma.writeble, vma.executable).as_ref());
+            }
+            let _ = debug_file.write_all(format!("{}: {:#x} ~ {:#x} (stack)\n",
+                vmas.len(), Loader::STACK_BOTTOM - Loader::STACK_SIZE, Loader::STACK_BOTTOM).as_ref());
+
+            let mut prog = Loader::program(entry_point, vmas, simulated_library_funcs, funcs, debug_info);
+            prog.isa = isa;
+            if process.is_some() {
+                prog.linux = process;
+                Loader::initial_stack(&mut prog, &self.path, auxv, image.xlen);
+            }
+            Ok(prog)
+        }
+
+        /// Map the LOAD segments of the ELF file, ET_DYN ones at the base of
+        /// executables, or of program interpreters if `interp`.
+        fn map(&self, interp: bool, debug_file: &mut File) -> Result<Image, SimError> {
+            let mut image = Image {
+                entry_point: 0,
+                bias: 0,
+                vmas: Vec::new(),
+                interp: None,
+                isa: None,
+                xlen: 64,
+                phdr: 0,
+                phnum: 0,
+                data: Vec::new(),
+            };
+            let mut phoff = 0u64;
+
             match File::open(&self.path) {
                 Err(e) => return Err(SimError::IOError(e)),
                 Ok(mut file) => {
                     // Read all the data in
-                    let mut data = Vec::new();
-                    let _ = file.read_to_end(&mut data);
-
+                    let data = &mut image.data;
+                    let _ = file.read_to_end(data);
+                    // e_machine must be EM_RISCV
+                    if data.get(18..20) != Some(&[243, 0]) {
+                        return Err(SimError::ArchError(String::from("Not a RISC-V ELF")));
+                    }
+                    // EI_CLASS
+                    if data[4] == 1 {
+                        image.xlen = 32;
+                    }
                     match Command::new("riscv64-unknown-elf-readelf")
                     .args(["-A", self.path.as_str()])
                     .output() {
                         Ok(output) => {
-                            debug_file.write("\n[rsim] Checking for architecture...\n".as_bytes());
-                            if let Err(e) = debug_file.write(&output.stdout) {
+                            let _ = debug_file.write_all("\n[rsim] Checking for architecture...\n".as_bytes());
+                            if let Err(e) = debug_file.write_all(&output.stdout) {
                                 print!("{}", "[Warning] ".green().bold());
                                 println!("Cannot write to debug file.");
                             }
@@ -77,11 +244,15 @@ pub mod loader {
                                             }
                                         },
                                         "Tag_RISCV_arch" => {
-                                            if !self.target_arch.to_string().eq(value) {
+                                            let isa = Isa::parse(value)
+                                                .map_err(|e| SimError::ArchError(String::from("Arch ") + value + ": " + &e))?;
+                                            if let Some(target) = self.target_arch.as_ref().filter(|target| !target.contains(&isa)) {
                                                 return Err(SimError::ArchError(
-                                                    String::from("Expected arch ")+&self.target_arch.to_string()+", found " + value
+                                                    String::from("Expected arch ")+&target.to_string()+", found " + value
                                                 ));
                                             }
+                                            // The counters are implied, as for the profiles
+                                            image.isa = Some(isa.with_counters());
                                         },
                                         "Tag_RISCV_stack_align" => {
                                             if !value.starts_with(&Loader::STACK_ALIGNMENT.to_string()) {
@@ -100,8 +271,8 @@ pub mod loader {
                     .args(["--segments", self.path.as_str()])
                     .output() {
                         Ok(output) => {
-                            debug_file.write("\n[rsim] Reading program headers...\n".as_bytes());
-                            if let Err(e) = debug_file.write(&output.stdout) {
+                            let _ = debug_file.write_all("\n[rsim] Reading program headers...\n".as_bytes());
+                            if let Err(e) = debug_file.write_all(&output.stdout) {
                                 print!("{}", "[Warning] ".green().bold());
                                 println!("Cannot write to debug file.");
                             }
@@ -117,7 +288,16 @@ pub mod loader {
                             let mut memsz = 0u64;
                             let mut flags = String::new();
 
-                            for line in output.split('\n') {
+                            // Program headers of ELFCLASS32 fit on one line, split them as for ELFCLASS64
+                            let lines: Vec<String> = output.split('\n').flat_map(|line| {
+                                let args: Vec<&str> = line.split_whitespace().collect();
+                                match args.first() {
+                                    Some(&"LOAD") if args.len() > 4 => vec![args[..4].join(" "), args[4..].join(" ")],
+                                    _ => vec![String::from(line)],
+                                }
+                            }).collect();
+
+                            for line in lines.iter() {
 
                                 if seg_half {
                                     seg_half = false;
@@ -135,8 +315,11 @@ pub mod loader {
                                     // Can now make a new VMA
                                     let mut memory = Vec::from(&data[ofs as usize..(ofs+filesz) as usize]);
                                     memory.append(&mut [0u8].repeat((memsz - filesz) as usize));
-                                    vmas.push(VMA {
-                                        lower_bound: va,
+                                    if ofs <= phoff && phoff < ofs + filesz {
+                                        image.phdr = va + image.bias + phoff - ofs;
+                                    }
+                                    image.vmas.push(VMA {
+                                        lower_bound: va + image.bias,
                                         size: memsz,
                                         readable: flags.contains('R'),
                                         writeble: flags.contains('W'),
@@ -150,12 +333,29 @@ pub mod loader {
 
 
                                 if line.starts_with("Elf file type") {
-                                    if !line.starts_with("Elf file type is EXEC") {
+                                    if line.starts_with("Elf file type is DYN") {
+                                        image.bias = match (image.xlen, interp) {
+                                            (32, false) => Loader::ET_DYN_BASE_32,
+                                            (32, true) => Loader::INTERP_BASE_32,
+                                            (_, false) => Loader::ET_DYN_BASE,
+                                            (_, true) => Loader::INTERP_BASE,
+                                        };
+                                    }
+                                    else if !line.starts_with("Elf file type is EXEC") {
                                         return Err(SimError::ArchError(String::from("Non executable")));
                                     }
                                 }
                                 else if line.starts_with("Entry point") {
-                                    entry_point = u64::from_str_radix(&line[14..], 16).unwrap();
+                                    image.entry_point = u64::from_str_radix(&line[14..], 16).unwrap() + image.bias;
+                                }
+                                else if line.starts_with("There are") {
+                                    // There are N program headers, starting at offset X
+                                    let args: Vec<&str> = line.split_whitespace().collect();
+                                    image.phnum = args[2].parse().unwrap_or(0);
+                                    phoff = args.last().unwrap().parse().unwrap_or(0);
+                                }
+                                else if let Some(interp) = line.trim().strip_prefix("[Requesting program interpreter: ") {
+                                    image.interp = Some(String::from(interp.trim_end_matches(']')));
                                 }
                                 else if line.trim_start().starts_with("LOAD") {
                                     seg_half = true;
@@ -173,12 +373,15 @@ pub mod loader {
 
                 }
             }
+            Ok(image)
+        }
 
-            // For now, we do not support compressed instructions, which is pervasive in library code.
-            // To run the code properly, we must start at `main()` instead of `_start()`. We achieve this
-            // by looking up `main` in `riscv64-unknown-elf-readelf -s`.
-            // For the same reason, we must also intercept all library function calls, and provide simulated
-            // execution of these function. The addresses of library functions are registered here.
+        /// The FUNC symbols relocated by `bias`, the simulated library
+        /// functions among them, and the address of `main()`.
+        fn symbols(&self, bias: u64, debug_file: &mut File) -> Result<Symbols, SimError> {
+            // Without the dynamic linker, we must also intercept all library function calls, and provide
+            // simulated execution of these function. The addresses of library functions are registered here.
+            let mut main = None;
             let mut simulated_library_funcs = HashMap::<u64, String>::new();
             let mut funcs = Vec::new();
             match Command::new("riscv64-unknown-elf-readelf")
@@ -188,28 +391,30 @@ pub mod loader {
                     let output = String::from_utf8(output.stdout).unwrap();
                     for line in output.split('\n') {
                         let items: Vec<&str> = line.split_whitespace().collect();
-                        // Find the main() function
-                        if !items.is_empty() && items[3].eq("FUNC") {
+                        // Find the main() function, skipping the undefined symbols
+                        if !items.is_empty() && items[3].eq("FUNC") && !items[6].eq("UND") {
+                            let start = u64::from_str_radix(items[1], 16).unwrap() + bias;
                             if items.last().unwrap().trim().eq("main") {
-                                entry_point = u64::from_str_radix(items[1], 16).unwrap();
+                                main = Some(start);
                             }
                             else if items.last().unwrap().trim().eq("printf") {
-                                let addr = u64::from_str_radix(items[1], 16).unwrap();
-                                simulated_library_funcs.insert(addr, String::from("printf"));
+                                simulated_library_funcs.insert(start, String::from("printf"));
                             }
                             else if items.last().unwrap().trim().eq("puts") {
-                                let addr = u64::from_str_radix(items[1], 16).unwrap();
-                                simulated_library_funcs.insert(addr, String::from("puts"));
+                                simulated_library_funcs.insert(start, String::from("puts"));
                             }
 
-                            let start = u64::from_str_radix(items[1], 16).unwrap();
                             let sz = usize::from_str_radix(items[2], 10).unwrap();
-                            funcs.push((start, sz, String::from(items.last().unwrap().trim())));
+                            let func = (start, sz, String::from(items.last().unwrap().trim()));
+                            // Symbols exported in `.dynsym` are listed again in `.symtab`
+                            if !funcs.contains(&func) {
+                                funcs.push(func);
+                            }
                         }
                     }
 
-                    debug_file.write("\n[rsim] Parsed FUNCs (start, length, name):\n".as_bytes());
-                    if let Err(e) = debug_file.write(format!("{:#x?}", funcs).as_ref()) {
+                    let _ = debug_file.write_all("\n[rsim] Parsed FUNCs (start, length, name):\n".as_bytes());
+                    if let Err(e) = debug_file.write_all(format!("{:#x?}", funcs).as_ref()) {
                         print!("{}", "[Warning] ".green().bold());
                         println!("Cannot write to debug file.");
                     }
@@ -218,30 +423,90 @@ pub mod loader {
                     return Err(SimError::ArchError(String::from("main() not found")));
                 }
             }
+            Ok((funcs, simulated_library_funcs, main))
+        }
+
+        /// Apply the dynamic relocations of a position-independent executable
+        /// loaded at `bias`. There is nothing to link against, so only the
+        /// relocations resolved within the executable are supported.
+        fn relocate(&self, vmas: &mut [VMA], bias: u64, xlen: u32, debug_file: &mut File) -> Result<(), SimError> {
+            let word = xlen as usize / 8;
+            let output = match Command::new("riscv64-unknown-elf-readelf")
+                .args(["-r", "-W", self.path.as_str()])
+                .output() {
+                Ok(output) => output,
+                Err(e) => return Err(SimError::IOError(e)),
+            };
+            let _ = debug_file.write_all("\n[rsim] Applying relocations...\n".as_bytes());
+            if let Err(e) = debug_file.write_all(&output.stdout) {
+                print!("{}", "[Warning] ".green().bold());
+                println!("Cannot write to debug file.");
+            }
+
+            let output = String::from_utf8(output.stdout).unwrap();
+            for line in output.split('\n') {
+                // offset, info, type, then the addend, or the symbol value,
+                // name, sign and addend
+                let items: Vec<&str> = line.split_whitespace().collect();
+                if items.len() < 3 || !items[0].chars().all(|c| c.is_ascii_hexdigit()) {
+                    continue;
+                }
+                let hex = |item: &str| u64::from_str_radix(item, 16)
+                    .map_err(|_| SimError::ArchError(String::from("Bad relocation: ") + line.trim()));
+                let offset = hex(items[0])? + bias;
+                let addend = match items.len() {
+                    3 => 0,
+                    4 => hex(items[3])?,
+                    _ if items.len() >= 7 && items[5] == "-" => hex(items[6])?.wrapping_neg(),
+                    _ if items.len() >= 7 => hex(items[6])?,
+                    _ => 0,
+                };
+                let symbol = match items.len() {
+                    3 | 4 => None,
+                    _ => Some((hex(items[3])?, items[4])),
+                };
+                let value = match (items[2], symbol) {
+                    ("R_RISCV_NONE", _) => continue,
+                    ("R_RISCV_RELATIVE", _) => bias.wrapping_add(addend),
+                    ("R_RISCV_32" | "R_RISCV_64" | "R_RISCV_JUMP_SLOT", Some((0, name))) => {
+                        return Err(SimError::ArchError(String::from("Undefined symbol ") + name));
+                    },
+                    ("R_RISCV_32" | "R_RISCV_64", Some((value, _))) => (value + bias).wrapping_add(addend),
+                    ("R_RISCV_32" | "R_RISCV_64", None) => addend,
+                    ("R_RISCV_JUMP_SLOT", Some((value, _))) => value + bias,
+                    (kind, _) => return Err(SimError::ArchError(String::from("Unsupported relocation ") + kind)),
+                };
+
+                let vma = vmas.iter_mut()
+                    .find(|v| v.lower_bound <= offset && offset + word as u64 <= v.lower_bound + v.size);
+                match vma {
+                    Some(vma) => {
+                        let start = (offset - vma.lower_bound) as usize;
+                        vma.memory[start..start+word].copy_from_slice(&value.to_le_bytes()[..word]);
+                    },
+                    None => return Err(SimError::ArchError(format!("Relocation outside of the image at {:#x}", offset))),
+                }
+            }
+            Ok(())
+        }
+
+        /// Build the `Program` that starts at `entry_point` with the loaded
+        /// `vmas`, adding the stack and setting up the registers. Shared by
+        /// all the ways of getting a program into memory.
+        pub fn program(entry_point: u64, mut vmas: Vec<VMA>, simulated_library_funcs: HashMap<u64, String>,
+            funcs: Vec<(u64, usize, String)>, debug_info: Option<DebugInfo>) -> Program {
 
+            let image_hash = image_hash(&vmas);
             // Add stack to `vmas`
             vmas.push(VMA{
-                // Use 1MB stack
-                lower_bound: Loader::STACK_BOTTOM - 0x100000,
-                size: 0x100000u64,
+                lower_bound: Loader::STACK_BOTTOM - Loader::STACK_SIZE,
+                size: Loader::STACK_SIZE,
                 readable: true,
                 writeble: true,
                 executable: false,
-                memory: {
-                    let mut data = vec![0u8; 0x100000];
-                    data
-                },
+                memory: vec![0u8; Loader::STACK_SIZE as usize],
             });
 
-            debug_file.write("\n\n\n[rsim] Load Summary:\n".as_bytes());
-            debug_file.write(format!("entry point: {:#x} (main)\n", entry_point).as_ref());
-            debug_file.write("vmas:\n".as_bytes());
-            for (idx, vma) in vmas.iter().enumerate() {
-                debug_file.write(format!("{}: {:#x} ~ {:#x}, readable = {}, writeable = {}, executable = {}\n",
-                    idx, vma.lower_bound, vma.lower_bound+vma.size, vma.readable, vma.writeble, vma.executable).as_ref());
-            }
-            
-
             // Setup registers
             let mut registers: Vec<Register> = (0..32).into_iter()
                 .map(|id| Register::new(RegID::decode(id as u8)))
@@ -250,7 +515,7 @@ pub mod loader {
             // Set a special return address to ra which triggers the simulator to halt.
             registers[RegID::X1.encode() as usize].write(HLT_ADDR);
                 
-            Ok(Program {
+            Program {
                 entry_point,
                 program_counter: entry_point,
                 vmas,
@@ -259,21 +524,49 @@ pub mod loader {
                 funcs,
                 pause: 0,
                 breakpoints: Vec::new(),
-            })
+                watchpoints: Vec::new(),
+                call_stack: vec![(entry_point, HLT_ADDR)],
+                debug_info,
+                line_step: None,
+                run_to: None,
+                history: None,
+                isa: Isa::default(),
+                counters: Counters::new(),
+                vector: VectorUnit::default(),
+                float: FloatUnit::new(),
+                reservation: None,
+                linux: None,
+                image_hash,
+            }
         }
-    }
 
-    /// Arch attribute of the ELF executable
-    pub enum ELFArch {
-        Rv64I,
-    }
+        /// Lay out the initial stack of a Linux process running `execfn`,
+        /// from the top: the strings, then argc, argv, envp and `auxv`, in
+        /// words of `xlen` bits.
+        fn initial_stack(prog: &mut Program, execfn: &str, mut auxv: Vec<(u64, u64)>, xlen: u32) {
+            let word = xlen as usize / 8;
+            let mut sp = Loader::STACK_BOTTOM;
+            let mut push = |prog: &mut Program, data: &[u8]| {
+                sp -= data.len() as u64;
+                prog.mem_poke(sp, data, true);
+                sp
+            };
+            let execfn = push(prog, &[execfn.as_bytes(), &[0]].concat());
+            // Fixed bytes for AT_RANDOM, so runs are reproducible
+            let random = push(prog, b"rsim-at-random!!");
+            auxv.push((25, random));        // AT_RANDOM
+            auxv.push((31, execfn));        // AT_EXECFN
+            auxv.push((0, 0));              // AT_NULL
 
-    impl fmt::Display for ELFArch {
-        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-            match self {
-                ELFArch::Rv64I => write!(f, "\"rv64i2p0_m2p0_a2p0_f2p0_d2p0_c2p0\""),
-                _ => Ok(()),
+            let mut words = vec![1, execfn, 0, 0];    // argc, argv, envp
+            for (key, val) in auxv {
+                words.push(key);
+                words.push(val);
             }
+            sp = (sp - (word * words.len()) as u64) & !(Loader::STACK_ALIGNMENT as u64 - 1);
+            let data: Vec<u8> = words.iter().flat_map(|val| val.to_le_bytes()[..word].to_vec()).collect();
+            prog.mem_poke(sp, &data, true);
+            prog.registers.write(RegID::X2, sp);
         }
     }
-}
\ No newline at end of file
+}
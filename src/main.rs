@@ -1,19 +1,77 @@
 #[allow(dead_code)]
 #[allow(unused)]
 
-use rsim_seq::{Loader, ELFArch, SimError, RegID, InstCode, Inst, HLT_ADDR, Program};
+use rsim_seq::{Loader, Isa, Event, SimError, RegID, InstCode, Inst, HLT_ADDR, Program, FoldedStacks, InstStats, Coverage, Snapshot, History, Watch, Breakpoint, Expr, Tui, Assembler, ImageLoader, ImageFormat, Permissions, VectorUnit};
+use rsim_seq::inst::inst::Stage;
+use rsim_seq::disasm::disasm::{self, symbolize};
+use rsim_seq::dwarf::dwarf::{Location, Variable};
+use rsim_seq::vector::vector::DEFAULT_VLEN;
+use rsim_seq::float::float::{freg_name, freg_number};
 use colored::Colorize;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use std::io;
 use std::io::Write;
+use std::fs::File;
 use std::process;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use rustyline::{Editor, Helper, Context};
+use rustyline::completion::Completer;
+use rustyline::hint::Hinter;
+use rustyline::highlight::Highlighter;
+use rustyline::validate::Validator;
+use rustyline::history::DefaultHistory;
+use rustyline::error::ReadlineError;
 
 /// Clap command line settings
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
-    /// Target ELF file
-    elf: String,
+    #[command(subcommand)]
+    tool: Option<Tool>,
+
+    /// Target ELF file, or program image in another format
+    #[arg(required = true)]
+    elf: Option<String>,
+
+    /// Format of the program image: elf, bin, ihex, srec or asm [default:
+    /// guessed from the extension, else elf]
+    #[arg(long, value_name = "FORMAT")]
+    format: Option<ImageFormat>,
+
+    /// Load address (hex) of raw binaries
+    #[arg(long, value_name = "ADDR", value_parser = parse_addr, default_value = "80000000")]
+    base: u64,
+
+    /// Permissions of the memory of bin, ihex and srec images
+    #[arg(long, value_name = "PERMS", default_value = "rwx")]
+    perms: Permissions,
+
+    /// Start execution at (hex) ADDR instead of the entry point of the image
+    #[arg(long, value_name = "ADDR", value_parser = parse_addr)]
+    entry: Option<u64>,
+
+    /// Load the program interpreter of dynamically linked executables
+    /// from DIR
+    #[arg(long, value_name = "DIR")]
+    sysroot: Option<String>,
+
+    /// ISA of the simulated hart: a profile (RV64I, RV64IM, RV64GC, RV32I,
+    /// RV32IMAC) or an ISA string like rv64imac_zicsr [default: from the
+    /// ELF, else RV64GC]
+    #[arg(long, value_name = "ISA")]
+    isa: Option<Isa>,
+
+    /// Make hpmcounterN count EVENT: loads, stores, branches,
+    /// branch-misses, jumps, muldiv or flush-cycles (repeatable). There is
+    /// no cache-miss event, as rsim does not model caches
+    #[arg(long, value_name = "N=EVENT", value_parser = parse_hpm_event)]
+    hpm_event: Vec<(usize, Event)>,
+
+    /// Number of bits in a vector register: a power of two from 32 to 65536
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_VLEN, value_parser = parse_vlen)]
+    vlen: usize,
 
     /// Interactive mode
     #[arg(short, long)]
@@ -22,11 +80,292 @@ struct Cli {
     /// Quiet mode
     #[arg(short, long)]
     quiet: bool,
+
+    /// Split-screen debugger view, implies interactive mode
+    #[arg(long)]
+    tui: bool,
+
+    /// Run debugger commands from FILE, then exit with non-zero status
+    /// if an assertion failed
+    #[arg(short = 'x', long, value_name = "FILE")]
+    command: Option<String>,
+
+    /// Write folded call stacks for flamegraph.pl/inferno to FILE
+    #[arg(long, value_name = "FILE")]
+    flamegraph: Option<String>,
+
+    /// Sample the call stack every N retired instructions
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    sample_period: u64,
+
+    /// Print dynamic instruction statistics
+    #[arg(long)]
+    stats: bool,
+
+    /// Write dynamic instruction statistics as JSON to FILE
+    #[arg(long, value_name = "FILE")]
+    stats_json: Option<String>,
+
+    /// Write an lcov coverage tracefile to FILE
+    #[arg(long, value_name = "FILE")]
+    coverage: Option<String>,
+
+    /// Write the per-instruction coverage hit map to FILE
+    #[arg(long, value_name = "FILE")]
+    coverage_map: Option<String>,
+
+    /// Resume from a snapshot saved by the debugger
+    #[arg(long, value_name = "SNAPSHOT")]
+    restore: Option<String>,
+
+    /// Record the last N instructions for reverse debugging (0 to disable)
+    #[arg(long, value_name = "N", default_value_t = 100000)]
+    history: usize,
 }
 
+/// Parse a (hex) address given on the command line.
+fn parse_addr(src: &str) -> Result<u64, String> {
+    u64::from_str_radix(src.to_lowercase().trim_start_matches("0x"), 16)
+        .map_err(|_| format!("bad address `{}`", src))
+}
 
-/// Read a C-style string stored in program memory.
-fn string_from_memory(prog: &mut Program, addr: u64) -> Option<String> {
+/// Parse a VLEN given on the command line.
+fn parse_vlen(src: &str) -> Result<usize, String> {
+    match src.parse::<usize>() {
+        Ok(vlen) if vlen.is_power_of_two() && (32..=65536).contains(&vlen) => Ok(vlen),
+        _ => Err(format!("bad VLEN `{}`, expected a power of two from 32 to 65536", src)),
+    }
+}
+
+/// Parse an hpm counter event selection given on the command line.
+fn parse_hpm_event(src: &str) -> Result<(usize, Event), String> {
+    let (idx, event) = src.split_once('=')
+        .ok_or(format!("expected N=EVENT, found `{}`", src))?;
+    let idx = idx.trim_start_matches("hpmcounter").parse::<usize>()
+        .map_err(|_| format!("bad counter `{}`", idx))?;
+    if !(3..32).contains(&idx) {
+        return Err(format!("hpmcounter{} does not exist, expected 3 to 31", idx));
+    }
+    Ok((idx, event.parse::<Event>()?))
+}
+
+/// Tools run instead of the simulation
+#[derive(Subcommand, Debug)]
+enum Tool {
+    /// Disassemble the functions of an ELF file
+    Disasm {
+        /// Target ELF file
+        elf: String,
+
+        /// Functions to disassemble, all if none
+        funcs: Vec<String>,
+    },
+}
+
+/// Observers of the retired instruction stream.
+struct Tracers {
+    flamegraph: Option<FoldedStacks>,
+    stats: Option<InstStats>,
+    coverage: Option<Coverage>,
+}
+
+impl Tracers {
+    /// Called once for every instruction that leaves the pipeline.
+    fn retire(&mut self, prog: &Program, pc: u64, code: InstCode, next_pc: u64) {
+        if let Some(flamegraph) = self.flamegraph.as_mut() {
+            flamegraph.tick(prog);
+        }
+        if let Some(stats) = self.stats.as_mut() {
+            stats.retire(pc, code, next_pc);
+        }
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.retire(pc, code, next_pc);
+        }
+    }
+}
+
+
+/// Tab completion of command names, and of register names and
+/// symbols in arguments.
+struct Completion {
+    symbols: Vec<String>,
+}
+
+impl Completer for Completion {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos].rfind(char::is_whitespace).map_or(0, |idx| idx + 1);
+        let word = &line[start..pos];
+        let candidates = if start == 0 {
+            COMMANDS.iter()
+                .flat_map(|(name, aliases)| std::iter::once(name).chain(aliases.iter()))
+                .filter(|name| name.starts_with(word))
+                .map(|name| String::from(*name))
+                .collect()
+        }
+        else {
+            self.symbols.iter()
+                .filter(|name| name.starts_with(word))
+                .cloned()
+                .collect()
+        };
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for Completion {
+    type Hint = String;
+}
+
+impl Highlighter for Completion {}
+
+impl Validator for Completion {}
+
+impl Helper for Completion {}
+
+/// Source of debugger commands: the terminal, or a command file in
+/// batch mode.
+struct Console {
+    /// Remaining lines of the command file.
+    script: Option<VecDeque<String>>,
+    /// Split-screen view, if enabled.
+    tui: Option<Tui>,
+    /// Line editor for the terminal, if available.
+    editor: Option<Editor<Completion, DefaultHistory>>,
+    /// File persisting the command history.
+    history: Option<PathBuf>,
+    /// The last command, repeated by an empty line.
+    last: String,
+    /// Whether an assertion failed.
+    failed: bool,
+    /// Whether the user quit, or the commands ran out.
+    quit: bool,
+}
+
+impl Console {
+    fn new(script: Option<VecDeque<String>>, tui: bool) -> Self {
+        let history = std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".rsim_history"));
+        let tui = match script {
+            None if tui => Tui::new().map_err(|e| {
+                print!("{}", "[Warning] ".yellow().bold());
+                println!("Cannot start the split-screen view: {:?}", e);
+            }).ok(),
+            _ => None,
+        };
+        let editor = match (&script, &tui) {
+            (Some(_), _) | (_, Some(_)) => None,
+            _ => Editor::new().ok().map(|mut editor: Editor<Completion, DefaultHistory>| {
+                if let Some(path) = history.as_ref() {
+                    let _ = editor.load_history(path);
+                }
+                editor
+            }),
+        };
+        Console {
+            script,
+            tui,
+            editor,
+            history,
+            last: String::new(),
+            failed: false,
+            quit: false,
+        }
+    }
+
+    /// Complete register names and the symbols of `prog`.
+    fn complete_symbols(&mut self, prog: &Program) {
+        if let Some(editor) = self.editor.as_mut() {
+            let symbols = prog.registers.registers.iter()
+                .map(|reg| reg.id.abi_name())
+                .chain(std::iter::once(String::from("pc")))
+                .chain(prog.funcs.iter().map(|(_, _, name)| name.clone()))
+                .collect();
+            editor.set_helper(Some(Completion { symbols }));
+        }
+    }
+
+    /// Read one line of commands after printing `prompt`. In batch mode,
+    /// blank lines and `#` comments are skipped. Returns None, and quits,
+    /// at the end of the command file or of the input.
+    fn read_line(&mut self, prog: &Program, prompt: &str) -> Option<String> {
+        if let Some(script) = self.script.as_mut() {
+            loop {
+                match script.pop_front() {
+                    Some(line) if line.trim().is_empty() || line.trim_start().starts_with('#') => continue,
+                    Some(line) => {
+                        println!("{}{}", prompt, line);
+                        return Some(line);
+                    },
+                    None => return self.quit(),
+                }
+            }
+        }
+        if let Some(tui) = self.tui.as_mut() {
+            return match tui.read_line(prog, prompt) {
+                Some(line) => Some(line),
+                None => self.quit(),
+            };
+        }
+
+        let editor = match self.editor.as_mut() {
+            Some(editor) => editor,
+            None => {
+                print!("{}", prompt);
+                io::stdout().flush().unwrap();
+                let mut line = String::new();
+                match io::stdin().read_line(&mut line) {
+                    Ok(0) => return self.quit(),
+                    Ok(_) => return Some(line),
+                    Err(e) => self.fatal(e),
+                }
+            }
+        };
+        loop {
+            match editor.readline(prompt) {
+                Ok(line) => {
+                    if !line.trim().is_empty() {
+                        let _ = editor.add_history_entry(line.as_str());
+                        if let Some(path) = self.history.as_ref() {
+                            let _ = editor.save_history(path);
+                        }
+                    }
+                    return Some(line);
+                },
+                // ^C discards the line
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => return self.quit(),
+                Err(e) => self.fatal(e),
+            }
+        }
+    }
+
+    /// Report an IO error of the terminal and exit. The split-screen
+    /// view is closed first, so that the terminal is usable again.
+    fn fatal(&mut self, e: impl std::fmt::Debug) -> ! {
+        self.tui = None;
+        print!("{}", "[Fatal] ".red().bold());
+        println!("IO error: {:?}", e);
+        process::exit(-1);
+    }
+
+    /// Stop reading commands. The simulation stops, and rsim exits
+    /// once the tracers are written.
+    fn quit(&mut self) -> Option<String> {
+        self.quit = true;
+        None
+    }
+
+    /// Exit status of rsim: non-zero if an assertion failed.
+    fn status(&self) -> i32 {
+        self.failed as i32
+    }
+}
+
+
+/// Read the bytes of a C-style string stored in program memory,
+/// including the terminating NUL.
+fn bytes_from_memory(prog: &Program, addr: u64) -> Option<Vec<u8>> {
     let mut bytes = Vec::new();
     let mut addr = addr;
     loop {
@@ -44,374 +383,1492 @@ fn string_from_memory(prog: &mut Program, addr: u64) -> Option<String> {
             None => return None,
         }
     }
-    Some(String::from_utf8(bytes).unwrap())
+    Some(bytes)
+}
+
+/// Read a C-style string stored in program memory.
+fn string_from_memory(prog: &mut Program, addr: u64) -> Option<String> {
+    bytes_from_memory(prog, addr).map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
 }
 
 
-/// Simulate the execution of `prog`, starting at main() function.
-/// Since compressed instructions are not yet supported, library calls
-/// and routines like _start(), __libc_init_array() are not invoked.
-/// As such, we only support:
-/// - All RV32I/64I Instructions (but those concerning memory atomicity)
-/// - Part of RV64M Instructions
-/// - Simulated `puts()` for printing string
-/// We do not support:
-/// - `printf()` (restricted by Rust)
-/// - Basically any other library functions containing RVC instructions
-/// - Usage of global variables, because `gp` is not set properly.
-/// We hope to get rid of most of the restrictions above by expanding RVC
-/// instructions, which is work in progress.
-fn sim(prog: &mut Program, quiet: bool, interactive: bool) {
+/// Simulate the execution of `prog` from its entry point. Statically
+/// linked programs start at main(), skipping _start() and the C runtime
+/// initialization, whose system calls are not simulated. So `gp` is not
+/// set up, `puts()` is simulated rather than run, and `printf()` stops
+/// the program.
+/// Dynamically linked programs run from the interpreter as a Linux
+/// process, library calls included.
+///
+/// Returns the exit status of rsim.
+fn sim(prog: &mut Program, quiet: bool, interactive: bool, tracers: &mut Tracers, console: &mut Console) -> i32 {
     if !quiet {
         print!("{}", "[Debug] ".green());
         println!("entry point: {:#x}, sp = {:#x}", prog.entry_point, prog.registers.read(RegID::X2).unwrap());
     }
     
     let mut next_program_counter = prog.program_counter;
+    let mut retiring: Option<InstCode> = None;
     loop {
-        // Update PC
-        if let Some(key) = prog.simulated_library_funcs.get(&next_program_counter) {
-            match key.as_str() {
-                "printf" => {
-                    print!("{}", "[Warning] ".yellow().bold());
-                    println!("printf() is not simulated. Aborting...");
-                    prog.program_counter = HLT_ADDR - 4;
-                },
-                "puts" => {
-                    let arg0 = prog.registers.read(RegID::X10).unwrap();
-                    let str = string_from_memory(prog, arg0);
-                    if str.is_none() {
-                        print!("{}", "[Warning] ".yellow().bold());
-                        println!("Cannot access memory at {:x}", arg0);
-                        prog.program_counter = HLT_ADDR - 4;
-                    }
-                    else {
-                        println!("{} {}", "puts():".green(), str.unwrap());
-                    }
-                },
-                _ => unreachable!(),
-            }
-            // Skip the actual control transfer and fall back to next instruction
-            next_program_counter = prog.program_counter + 4;
+        // Retire the last instruction
+        if let Some(code) = retiring.take() {
+            tracers.retire(prog, prog.program_counter, code, next_program_counter);
         }
+
+        // Update PC
+        next_program_counter = call_library(prog, next_program_counter, true);
         prog.program_counter = next_program_counter;
 
         if prog.program_counter == HLT_ADDR {
             println!("{} {}", "[Debug]".green(), "Halt from fetching HLT_ADDR");
-            break;
+            if !interactive || (prog.history.is_none() && console.script.is_none()) {
+                break;
+            }
+            // Give a chance to step back from the halt, or to check the
+            // final state in batch mode
+            interactive_cli(prog, console);
+            if console.quit {
+                return console.status();
+            }
+            if prog.program_counter == HLT_ADDR {
+                break;
+            }
         }
-
         // Interactive debugging
-        if interactive {
-            if prog.breakpoints.contains(&prog.program_counter) {
+        else if interactive {
+            let mut commands = Vec::new();
+            if let Some((idx, cmds)) = check_breakpoints(prog, true) {
                 print!("{}", "[Debug] ".green());
-                println!("Hit breakpoint at {:#x}", prog.program_counter);
+                println!("Hit breakpoint {} at {:#x}", idx, prog.program_counter);
                 prog.pause = 0;
+                commands = cmds;
+            }
+            if let (Some((file, line, depth)), Some(info)) = (&prog.line_step, &prog.debug_info) {
+                // Stop at the first statement of another line, unless
+                // it is in a deeper frame when stepping over calls.
+                let pc = prog.program_counter;
+                if prog.call_stack.len() <= *depth && info.is_stmt_start(pc) &&
+                    info.line_at(pc).is_some_and(|(f, l)| f != file || l != *line)
+                {
+                    prog.pause = 0;
+                }
+            }
+            if let Some((addr, max_depth, min_depth)) = prog.run_to {
+                let depth = prog.call_stack.len();
+                if (prog.program_counter == addr && depth <= max_depth) || depth < min_depth {
+                    prog.pause = 0;
+                }
             }
             if prog.pause == 0 {
-                interactive_cli(prog);
+                prog.line_step = None;
+                prog.run_to = None;
+                if !run_commands(prog, console, &commands) {
+                    interactive_cli(prog, console);
+                }
+                if console.quit {
+                    return console.status();
+                }
             }
             else {
                 prog.pause -= 1;
             }
         }
 
-        let inst = Inst::new();
-        // Fetch
-        let inst = inst.advance(prog);
-        if let Err(next_pc) = inst {
-            //println!("\t==> {:x}", next_pc);
-            next_program_counter = next_pc;
-            continue;
+        let pc = prog.program_counter;
+        prog.record_begin();
+        let (code, stage, next_pc) = execute(prog, quiet);
+        prog.record_commit();
+        if let Some(tui) = console.tui.as_mut() {
+            tui.retired(pc, code, stage);
         }
-        let inst = inst.unwrap();
-        if !quiet {println!("{:x}:\t {:x?}", prog.program_counter, inst.code())}
+        retiring = code;
+        next_program_counter = next_pc;
+    }
 
-        // Decode
-        let inst = inst.advance(prog);
-        if let Err(next_pc) = inst {
-            //println!("\t==> {:x}", next_pc);
-            next_program_counter = next_pc;
-            continue;
+    if !quiet {
+        print!("{}", "[Debug] ".green());
+        println!("Registers:\n{}", prog.registers);
+    }
+    console.status()
+}
+
+/// If `target` is a simulated library function, run it and return to
+/// the caller instead. Returns the PC to continue at. Output of the
+/// function is suppressed unless `echo`.
+fn call_library(prog: &mut Program, target: u64, echo: bool) -> u64 {
+    if let Some(key) = prog.simulated_library_funcs.get(&target) {
+        match key.as_str() {
+            "printf" => {
+                if echo {
+                    print!("{}", "[Warning] ".yellow().bold());
+                    println!("printf() is not simulated. Aborting...");
+                }
+                prog.program_counter = HLT_ADDR - 4;
+            },
+            "puts" => {
+                let mut arg0 = prog.registers.read(RegID::X10).unwrap();
+                if prog.isa.xlen == 32 {
+                    arg0 &= 0xFFFFFFFF;
+                }
+                match string_from_memory(prog, arg0) {
+                    Some(str) => if echo {
+                        println!("{} {}", "puts():".green(), str);
+                    },
+                    None => {
+                        if echo {
+                            print!("{}", "[Warning] ".yellow().bold());
+                            println!("Cannot access memory at {:x}", arg0);
+                        }
+                        prog.program_counter = HLT_ADDR - 4;
+                    }
+                }
+            },
+            _ => unreachable!(),
         }
-        let inst = inst.unwrap();
+        // Skip the actual control transfer and fall back to next instruction,
+        // which follows a compressed call 2 bytes later
+        return match prog.call_stack.pop() {
+            Some((_, ret)) if prog.program_counter != HLT_ADDR - 4 => ret,
+            _ => prog.program_counter + 4,
+        };
+    }
+    target
+}
 
-        // Execute
-        let inst = inst.advance(prog);
-        if let Err(next_pc) = inst {
-            //println!("\t==> {:x}", next_pc);
-            next_program_counter = next_pc;
-            continue;
+/// Push the instruction at the PC through the pipeline. Returns the
+/// instruction, if it could be fetched, the stage it left the pipeline
+/// at, and the PC of the next one.
+fn execute(prog: &mut Program, quiet: bool) -> (Option<InstCode>, Stage, u64) {
+    let pc = prog.program_counter;
+    let (code, stage, next_pc) = pipeline(prog, quiet);
+    // Instructions leaving the pipeline early to halt do not retire
+    if let Some(code) = code {
+        if stage == Stage::Writeback || next_pc != HLT_ADDR {
+            prog.counters.retire(pc, code, next_pc);
         }
-        let inst = inst.unwrap();
+    }
+    (code, stage, next_pc)
+}
 
-        // Memory
-        let inst = inst.advance(prog);
-        if let Err(next_pc) = inst {
-            //println!("\t==> {:x}", next_pc);
-            next_program_counter = next_pc;
-            continue;
+/// Advance a new instruction through the stages until it leaves the
+/// pipeline.
+fn pipeline(prog: &mut Program, quiet: bool) -> (Option<InstCode>, Stage, u64) {
+    let inst = Inst::new();
+    // Fetch
+    let inst = inst.advance(prog);
+    if let Err(next_pc) = inst {
+        //println!("\t==> {:x}", next_pc);
+        return (None, Stage::Fetch, next_pc);
+    }
+    let inst = inst.unwrap();
+    if !quiet {println!("{:x}:\t {:x?}", prog.program_counter, inst.code())}
+    let code = Some(inst.code());
+
+    // Decode
+    let inst = inst.advance(prog);
+    if let Err(next_pc) = inst {
+        //println!("\t==> {:x}", next_pc);
+        return (code, Stage::Decode, next_pc);
+    }
+    let inst = inst.unwrap();
+
+    // Execute
+    let inst = inst.advance(prog);
+    if let Err(next_pc) = inst {
+        //println!("\t==> {:x}", next_pc);
+        return (code, Stage::Execute, next_pc);
+    }
+    let inst = inst.unwrap();
+
+    // Memory
+    let inst = inst.advance(prog);
+    if let Err(next_pc) = inst {
+        //println!("\t==> {:x}", next_pc);
+        return (code, Stage::Memory, next_pc);
+    }
+    let inst = inst.unwrap();
+
+    // Writeback
+    match inst.advance(prog) {
+        Err(next_pc) => (code, Stage::Writeback, next_pc),
+        Ok(_) => unreachable!(),
+    }
+}
+
+/// Run forward silently until `icount` instructions have retired.
+fn replay(prog: &mut Program, icount: u64) {
+    while prog.history.as_ref().is_some_and(|history| history.icount() < icount) {
+        if prog.program_counter == HLT_ADDR {
+            break;
         }
-        let inst = inst.unwrap();
+        prog.record_begin();
+        let (_, _, next_pc) = execute(prog, true);
+        prog.record_commit();
+        prog.program_counter = call_library(prog, next_pc, false);
+    }
+}
 
-        // Writeback
-        let inst = inst.advance(prog);
-        assert!(inst.is_err());
-        if let Err(next_pc) = inst {
-            //println!("\t==> {:x}", next_pc);
-            next_program_counter = next_pc;
-            continue;
+/// Undo the last retired instruction, from the ring buffer if possible,
+/// otherwise by restoring an earlier snapshot and replaying up to it.
+/// Returns false if the history does not reach back that far.
+fn reverse_step(prog: &mut Program) -> bool {
+    if prog.step_back() {
+        return true;
+    }
+    let target = match prog.history.as_ref() {
+        Some(history) if history.icount() > 0 => history.icount() - 1,
+        _ => return false,
+    };
+    if prog.rewind_to_snapshot(target).is_none() {
+        return false;
+    }
+    replay(prog, target);
+    true
+}
+
+/// Read line `line` (1-based) of source file `path`. If the path recorded
+/// in the debugging information does not exist, try the file name in the
+/// current directory.
+fn source_line(path: &str, line: u32) -> Option<String> {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let text = std::fs::read_to_string(path)
+        .or_else(|_| std::fs::read_to_string(name))
+        .ok()?;
+    text.lines().nth((line as usize).checked_sub(1)?).map(String::from)
+}
+
+/// Print the source location of the PC, if known.
+fn print_location(prog: &Program) {
+    if let Some((file, line)) = prog.debug_info.as_ref().and_then(|info| info.line_at(prog.program_counter)) {
+        let func = prog.func_at(prog.program_counter).map_or("??", |(_, _, name)| name.as_str());
+        println!("{} () at {}:{}", func, file, line);
+        if let Some(text) = source_line(file, line) {
+            println!("{}\t{}", line, text);
+        }
+    }
+}
+
+/// List source lines around `line` of `file`.
+fn list_source(file: &str, line: u32) {
+    let first = std::cmp::max(line, 6) - 5;
+    for nr in first..first + 10 {
+        match source_line(file, nr) {
+            Some(text) => println!("{}\t{}", nr, text),
+            None if nr == first => {
+                println!("Cannot read source file {}.", file);
+                return;
+            },
+            None => return,
         }
     }
+}
 
-    if !quiet {
-        print!("{}", "[Debug] ".green());
-        println!("Registers:\n{}", prog.registers);
+/// Print the value of a variable from the debugging information.
+/// Frame offsets are taken relative to the frame pointer `s0`, which
+/// equals the CFA in code compiled with frame pointers (`-O0`).
+fn print_variable(prog: &Program, var: &Variable) {
+    let addr = match prog.variable_addr(var) {
+        Some(addr) => addr,
+        None => {
+            println!("\t{} : <optimized out>", var.name);
+            return;
+        }
+    };
+    let sz = var.size.unwrap_or(8) as usize;
+    match prog.mem_load(addr, sz, false) {
+        Some((data, 0)) if sz <= 8 => {
+            let mut bytes = [0u8; 8];
+            bytes[..sz].copy_from_slice(data);
+            // Sign extend for the decimal view
+            let val = u64::from_le_bytes(bytes);
+            let signed = ((val << (64 - 8 * sz)) as i64) >> (64 - 8 * sz);
+            println!("\t{} : {:#x} ({}) @ {:#x}", var.name, val, signed, addr);
+        },
+        Some((data, 0)) => {
+            let bytes: Vec<String> = data.iter().map(|b| format!("{:02x}", b)).collect();
+            println!("\t{} : [{}] @ {:#x}", var.name, bytes.join(" "), addr);
+        },
+        _ => println!("\t{} : Cannot access memory at {:#x}", var.name, addr),
     }
 }
 
 /// Disassemble function.
 fn disassemble(name: &str, addr: u64, sz: usize, prog: &mut Program) {
     println!("\nDisassembly of <{}>:", name);
-    let mut cur = 0usize;
+    let mut last_line = None;
+    let end = addr + sz as u64;
+    let lines = disasm::disassemble(prog, addr, end);
 
-    while cur < sz {
-        let pc = addr + cur as u64;
-        match prog.mem_load(pc, 4, true) {
-            Some((data, rem)) if rem == 0  => {
-                let raw = u32::from_le_bytes(data.try_into().unwrap());
-                let (code, len) = InstCode::parse((raw & 0xFFFF) as u16, (raw >> 16) as u16);
-                if pc == prog.program_counter {
-                    print!("===>");
-                }
-                println!("\t{:x}:\t {:x?}", pc, code);
-                cur += len / 8;
-            },
-            _ => {
-                println!("Cannot access memory at {:#x}", pc);
-                return;
+    for inst in lines.iter() {
+        let pc = inst.addr;
+        // Annotate with source lines
+        let line = prog.debug_info.as_ref().and_then(|info| info.line_at(pc));
+        if let Some((file, nr)) = line {
+            if last_line != Some((file, nr)) {
+                println!("{}:{}\t{}", file, nr, source_line(file, nr).unwrap_or_default().trim());
             }
         }
+        last_line = line;
+        let current = (pc..pc + inst.size() as u64).contains(&prog.program_counter);
+        println!("{:<4}{}", if current { "===>" } else { "" }, inst);
+    }
+    let done = lines.last().map_or(addr, |inst| inst.addr + inst.size() as u64);
+    if done < end {
+        println!("Cannot access memory at {:#x}", done);
+    }
+}
+
+/// Print the disassembly of `funcs` in `elf`, or of every function,
+/// like `objdump -d`.
+fn disasm_elf(elf: &str, funcs: &[String]) {
+    let prog = match Loader::new(None, elf).load() {
+        Ok(prog) => prog,
+        Err(e) => {
+            print!("{}", "[Fatal] ".red().bold());
+            println!("{:?}", e);
+            process::exit(-1);
+        }
+    };
+    for name in funcs {
+        if !prog.funcs.iter().any(|(_, _, func)| func == name) {
+            print!("{}", "[Warning] ".yellow().bold());
+            println!("No function named {}", name);
+        }
+    }
+
+    let mut symbols: Vec<&(u64, usize, String)> = prog.funcs.iter()
+        .filter(|(_, _, name)| funcs.is_empty() || funcs.contains(name))
+        .collect();
+    symbols.sort();
+    let xlen = prog.isa.xlen as usize;
+    println!("\n{}:     file format elf{}-littleriscv", elf, xlen);
+    for (addr, sz, name) in symbols {
+        println!("\n{:0width$x} <{}>:", addr, name, width = xlen / 4);
+        let end = addr + *sz as u64;
+        let lines = disasm::disassemble(&prog, *addr, end);
+        for inst in lines.iter() {
+            println!("{}", inst);
+        }
+        let done = lines.last().map_or(*addr, |inst| inst.addr + inst.size() as u64);
+        if done < end {
+            println!("Cannot access memory at {:#x}", done);
+        }
+    }
+}
+
+/// Debugger commands and their aliases. Commands may also be given as
+/// any unambiguous prefix of their name.
+const COMMANDS: [(&str, &[&str]); 41] = [
+    ("help", &["h"]),
+    ("pc", &[]),
+    ("pa", &[]),
+    ("counters", &[]),
+    ("pv", &[]),
+    ("pf", &[]),
+    ("print", &["p"]),
+    ("x", &[]),
+    ("step", &["s"]),
+    ("next", &["n"]),
+    ("stepi", &["si"]),
+    ("nexti", &["ni"]),
+    ("reverse-stepi", &["rsi"]),
+    ("reverse-continue", &["rc"]),
+    ("continue", &["c"]),
+    ("finish", &[]),
+    ("until", &[]),
+    ("advance", &[]),
+    ("backtrace", &["bt"]),
+    ("list", &["l"]),
+    ("il", &[]),
+    ("disassemble", &["disass"]),
+    ("break", &["b"]),
+    ("ib", &[]),
+    ("delete", &["d"]),
+    ("condition", &[]),
+    ("ignore", &[]),
+    ("enable", &[]),
+    ("disable", &[]),
+    ("commands", &[]),
+    ("watch", &[]),
+    ("rwatch", &[]),
+    ("awatch", &[]),
+    ("dw", &[]),
+    ("set", &[]),
+    ("load", &[]),
+    ("asm", &[]),
+    ("save", &[]),
+    ("echo", &[]),
+    ("assert", &[]),
+    ("quit", &["q"]),
+];
+
+/// Resolve a command name, alias or unambiguous prefix.
+fn resolve_command(word: &str) -> Result<&'static str, String> {
+    if let Some((name, _)) = COMMANDS.iter().find(|(name, aliases)| *name == word || aliases.contains(&word)) {
+        return Ok(name);
+    }
+    let matches: Vec<&str> = COMMANDS.iter()
+        .map(|(name, _)| *name)
+        .filter(|name| name.starts_with(word))
+        .collect();
+    match matches.len() {
+        0 => Err(format!("Undefined command \"{}\". Try \"help\".", word)),
+        1 => Ok(matches[0]),
+        _ => Err(format!("Ambiguous command \"{}\": {}.", word, matches.join(", "))),
     }
 }
 
 /// Print usage.
 fn print_usage() {
+    println!("Commands may be abbreviated to any unambiguous prefix. An empty line repeats the last command.");
+    println!("Numbers are decimal unless prefixed with 0x, as in x/4g sp+16 or b 0x10078.");
     println!("h                    - Show this message.");
     println!("pc                   - Print the program counter.");
-    println!("p reg                - Print the value of register reg.");
+    println!("p reg/var            - Print the value of register reg or variable var.");
     println!("pa                   - Dump the register file.");
-    println!("x/n addr             - Dump n bytes starting from (hex) addr.");
+    println!("counters             - Show the cycle, time, instret and selected hpm counters.");
+    println!("pv (vN)              - Dump vl, vtype and the vector registers, or register vN.");
+    println!("pf (fN)              - Dump fflags, frm and the floating-point registers, or register fN.");
+    println!("x/n addr             - Dump n bytes starting from addr.");
+    println!("x/Nfu addr/expr      - Examine N units u (b/h/w/g) in format f (x/d/u/c/a/s/i) at addr.");
     println!("disass (func)        - Disassembly current or the given function.");
     println!("si (n)               - Step by 1 or n step.");
+    println!("ni                   - Step by 1 instruction, over calls.");
+    println!("s                    - Step by source line, entering calls.");
+    println!("n                    - Step by source line, over calls.");
+    println!("l (file:line/func)   - List source around PC, or the given line or function.");
+    println!("il                   - Show local variables.");
     println!("c                    - Continue until pauses.");
-    println!("b addr/func          - Insert breakpoint at (hex) addr or function.");
-    println!("ib                   - Show all breakpoints.");
+    println!("bt                   - Print the call stack.");
+    println!("finish               - Continue until the current function returns.");
+    println!("until addr/func      - Continue until location is reached in this frame, or it returns.");
+    println!("advance addr/func    - Continue until location is reached, or this frame returns.");
+    println!("rsi (n)              - Step back by 1 or n instructions.");
+    println!("rc                   - Continue backwards until a breakpoint or watchpoint.");
+    println!("b addr/func/file:line- Insert breakpoint at addr, function or source line.");
+    println!("b ... if expr        - Insert breakpoint that pauses only if expr is non-zero.");
+    println!("condition n (expr)   - Set or remove the condition of n-th breakpoint.");
+    println!("ignore n count       - Ignore the next count hits of n-th breakpoint.");
+    println!("enable/disable n     - Enable or disable n-th breakpoint.");
+    println!("commands n           - Set commands to run when n-th breakpoint pauses.");
+    println!("ib                   - Show all breakpoints and watchpoints.");
     println!("d n                  - Delete n-th breakpoint.");
+    println!("watch addr/var (len) - Pause when addr or variable is written.");
+    println!("rwatch addr/var (len)- Pause when addr or variable is read.");
+    println!("awatch addr/var (len)- Pause when addr or variable is accessed.");
+    println!("dw n                 - Delete n-th watchpoint.");
+    println!("save file            - Save a snapshot of the machine state to file.");
+    println!("set (-f) lhs = expr  - Set a register, pc, variable or *(type*)addr; -f ignores permissions.");
+    println!("load (-f) file addr  - Copy file into memory at addr; -f ignores permissions.");
+    println!("asm addr \"inst\"      - Assemble inst, or several separated by ;, over the code at addr.");
+    println!("echo text            - Print text.");
+    println!("assert expr          - Fail the command file if expr is zero.");
     println!("q                    - Quit rsim.");
 }
 
+/// Evaluate an address expression such as `0x10078`, `sp + 8` or `$a0`.
+/// Numbers are decimal unless prefixed with `0x`, as everywhere else.
+fn address_of(prog: &Program, src: &str) -> Result<u64, String> {
+    Expr::parse(src)?.eval(prog)
+}
+
+/// Examine memory like gdb's `x/Nfu`: N units of size u (b, h, w, g)
+/// in format f (x, d, u, c, a, s for strings, i for instructions).
+/// Without format nor unit, N bytes are dumped in hex.
+fn examine(prog: &mut Program, spec: &str, addr: u64) {
+    let digits = spec.chars().take_while(|c| c.is_ascii_digit()).count();
+    let count = if digits == 0 { 1 } else {
+        match spec[..digits].parse::<usize>() {
+            Ok(count) => count,
+            Err(_) => {
+                println!("Bad length.");
+                return;
+            }
+        }
+    };
+    let mut format = 'x';
+    let mut unit = if digits == spec.len() { Some(1) } else { None };
+    for ch in spec[digits..].chars() {
+        match ch {
+            'b' => unit = Some(1),
+            'h' => unit = Some(2),
+            'w' => unit = Some(4),
+            'g' => unit = Some(8),
+            'x' | 'd' | 'u' | 'c' | 'a' | 's' | 'i' => format = ch,
+            _ => {
+                println!("Bad format '{}'.", ch);
+                return;
+            }
+        }
+    }
+    let label = |prog: &Program, addr: u64| {
+        let sym = symbolize(prog, addr);
+        if sym.is_empty() { format!("{:#x}:", addr) } else { format!("{:#x} {}:", addr, sym) }
+    };
+
+    let mut addr = addr;
+    match format {
+        's' => {
+            for _ in 0..count {
+                match bytes_from_memory(prog, addr) {
+                    Some(bytes) => {
+                        let str = string_from_memory(prog, addr).unwrap();
+                        println!("{}\t{:?}", label(prog, addr), str.trim_end_matches('\0'));
+                        addr += bytes.len() as u64;
+                    },
+                    None => {
+                        println!("Cannot access memory at {:#x}", addr);
+                        return;
+                    }
+                }
+            }
+        },
+        'i' => {
+            for _ in 0..count {
+                match disasm::disassemble_one(prog, addr) {
+                    Some(inst) => {
+                        let marker = if addr == prog.program_counter { "===>" } else { "" };
+                        println!("{}{}\t{}", marker, label(prog, addr), inst.asm());
+                        addr += inst.size() as u64;
+                    },
+                    _ => {
+                        println!("Cannot access memory at {:#x}", addr);
+                        return;
+                    }
+                }
+            }
+        },
+        _ => {
+            let size = match (format, unit) {
+                ('c', _) => 1,
+                ('a', _) => prog.isa.xlen as usize / 8,
+                (_, Some(size)) => size,
+                (_, None) => 4,
+            };
+            let per_line = if format == 'c' { 8 } else { 16 / size };
+            let mut left = count;
+            while left > 0 {
+                let n = std::cmp::min(left, per_line);
+                let data = match prog.mem_load(addr, n * size, false) {
+                    Some((data, 0)) => data.to_vec(),
+                    _ => {
+                        println!("Cannot access memory at {:#x}", addr);
+                        return;
+                    }
+                };
+                let items: Vec<String> = data.chunks(size).map(|chunk| {
+                    let mut bytes = [0u8; 8];
+                    bytes[..size].copy_from_slice(chunk);
+                    let val = u64::from_le_bytes(bytes);
+                    let signed = ((val << (64 - 8 * size)) as i64) >> (64 - 8 * size);
+                    match format {
+                        'x' => format!("0x{:0width$x}", val, width = 2 * size),
+                        'd' => signed.to_string(),
+                        'u' => val.to_string(),
+                        'c' if (val as u8).is_ascii_graphic() || val == 0x20 => format!("{} '{}'", signed, val as u8 as char),
+                        'c' => format!("{} '\\{:03o}'", signed, val as u8),
+                        _ => format!("{:#x} {}", val, symbolize(prog, val)).trim_end().to_string(),
+                    }
+                }).collect();
+                let sep = if format == 'x' { " " } else { "\t" };
+                print!("{}\t{}", label(prog, addr), items.join(sep));
+                if format == 'x' {
+                    // ASCII column, aligned to a full line
+                    let pad = (per_line - n) * (2 * size + 3);
+                    let ascii: String = data.iter()
+                        .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+                        .collect();
+                    print!("{:pad$}\t|{}|", "", ascii, pad = pad);
+                }
+                println!();
+                addr += (n * size) as u64;
+                left -= n;
+            }
+        },
+    }
+}
+
+/// Print the call chain from the shadow call stack, innermost first.
+fn backtrace(prog: &Program) {
+    let print_frame = |idx: usize, pc: u64| {
+        let func = prog.func_at(pc).map_or("??", |(_, _, name)| name.as_str());
+        print!("#{:<3}{:#x} in {} ()", idx, pc, func);
+        match prog.debug_info.as_ref().and_then(|info| info.line_at(pc)) {
+            Some((file, line)) => println!(" at {}:{}", file, line),
+            None => println!(),
+        }
+    };
+
+    print_frame(0, prog.program_counter);
+    for (idx, &(_, ret)) in prog.call_stack.iter().rev().enumerate() {
+        if ret == HLT_ADDR {
+            break;
+        }
+        print_frame(idx + 1, ret);
+    }
+}
+
+/// Resolve a function name, file:line or address expression.
+fn location_addr(prog: &Program, token: &str) -> Option<u64> {
+    if let Some((addr, _, _)) = prog.funcs.iter().find(|(_, _, name)| name == token) {
+        return Some(*addr);
+    }
+    if let Some((file, line)) = token.rsplit_once(':') {
+        let line = line.parse::<u32>().ok()?;
+        return prog.debug_info.as_ref()?.addr_of_line(file, line).map(|(addr, _)| addr);
+    }
+    address_of(prog, token).ok()
+}
+
+/// Evaluate `rhs` and store it to `lhs`, which is a register, `pc`, a
+/// variable or a memory dereference such as `*(u32*)addr`. Stores to
+/// memory obey the write permissions, unless `force`.
+fn assign(prog: &mut Program, lhs: &str, rhs: &str, force: bool) -> Result<(), String> {
+    let val = Expr::parse(rhs)?.eval(prog)?;
+    let (addr, width) = match Expr::parse(lhs)? {
+        Expr::Ident(name) if name == "pc" => {
+            prog.program_counter = val;
+            return Ok(());
+        },
+        Expr::Ident(name) => {
+            if let Some(reg) = prog.registers.registers.iter()
+                .find(|reg| name.eq_ignore_ascii_case(reg.id.abi_name().as_str()))
+            {
+                let id = reg.id;
+                // RV32 registers hold their values sign-extended
+                let val = if prog.isa.xlen == 32 { val as u32 as i32 as i64 as u64 } else { val };
+                prog.registers.write(id, val);
+                return Ok(());
+            }
+            let var = prog.debug_info.as_ref()
+                .and_then(|info| info.variable(&name, prog.program_counter))
+                .ok_or(format!("No register or variable \"{}\"", name))?;
+            let addr = prog.variable_addr(var).ok_or(format!("\"{}\" is optimized out", name))?;
+            match var.size.unwrap_or(8) {
+                sz @ (1 | 2 | 4 | 8) => (addr, sz as usize),
+                _ => return Err(format!("\"{}\" is not a scalar", name)),
+            }
+        },
+        Expr::Deref(width, _, addr) => (addr.eval(prog)?, width),
+        _ => return Err(format!("Cannot assign to \"{}\"", lhs.trim())),
+    };
+    if !prog.mem_poke(addr, &val.to_le_bytes()[..width], force) {
+        return Err(format!("Cannot write memory at {:#x}", addr));
+    }
+    Ok(())
+}
+
+/// Check the enabled breakpoints at the PC, and returns the number, from
+/// 1 as in gdb, and commands of the first one whose condition holds. Hit and ignore counts
+/// are only updated if `count`, which is not the case when going backwards.
+fn check_breakpoints(prog: &mut Program, count: bool) -> Option<(usize, Vec<String>)> {
+    let pc = prog.program_counter;
+    for idx in 0..prog.breakpoints.len() {
+        let bp = &prog.breakpoints[idx];
+        if bp.addr != pc || !bp.enabled {
+            continue;
+        }
+        if let Some((src, expr)) = bp.condition.as_ref() {
+            match expr.eval(prog) {
+                Ok(0) => continue,
+                Ok(_) => {},
+                Err(e) => {
+                    // Stop, so that the condition can be fixed
+                    print!("{}", "[Warning] ".yellow().bold());
+                    println!("Error in condition \"{}\" of breakpoint {}: {}", src, idx + 1, e);
+                }
+            }
+        }
+        if !count {
+            return Some((idx + 1, Vec::new()));
+        }
+
+        let bp = &mut prog.breakpoints[idx];
+        bp.hits += 1;
+        if bp.ignore > 0 {
+            bp.ignore -= 1;
+            continue;
+        }
+        return Some((idx + 1, bp.commands.clone()));
+    }
+    None
+}
+
+/// Run the commands attached to a breakpoint. Returns whether one of
+/// them resumed the program.
+fn run_commands(prog: &mut Program, console: &mut Console, commands: &[String]) -> bool {
+    for command in commands {
+        println!("(rsim) {}", command);
+        let tokens: Vec<&str> = command.split_whitespace().collect();
+        if !tokens.is_empty() && run_command(prog, console, &tokens) {
+            return true;
+        }
+    }
+    false
+}
+
 /// Interactive debugging
-fn interactive_cli(prog: &mut Program) {
-    let mut line = String::new();
+fn interactive_cli(prog: &mut Program, console: &mut Console) {
     let prompt = "(rsim) ";
+    print_location(prog);
 
     loop {
-        print!("{}", prompt);
-        io::stdout().flush().unwrap();
-
-        line.clear();
-        if let Err(e) = io::stdin().read_line(&mut line) {
-            print!("{}", "[Fatal] ".red().bold());
-            println!("IO error: {:?}", e);
-            process::exit(-1);
+        let mut line = match console.read_line(prog, prompt) {
+            Some(line) => line,
+            None => return,
+        };
+        if line.trim().is_empty() {
+            line = console.last.clone();
+        }
+        else {
+            console.last = line.clone();
         }
-
         let tokens: Vec<&str> = line.split_whitespace().collect();
 
         if tokens.is_empty() {
-            return;
+            continue;
         }
         
-        if tokens[0].starts_with("h") {
-            print_usage();
-        }
-        else if tokens[0].starts_with("pc") {
-            let pc = prog.program_counter;
-            match prog.mem_load(pc, 4, true) {
-                Some((data, rem)) if rem == 0  => {
-                    let raw = u32::from_le_bytes(data.try_into().unwrap());
-                    let (code, _) = InstCode::parse((raw & 0xFFFF) as u16, (raw >> 16) as u16);
-                    println!("\t{:#x} ==> {:x?}", pc, code);
-                },
-                _ => {
-                    println!("\t{:#x} ==> Cannot access memory", pc);
-                }
+        if run_command(prog, console, &tokens) {
+            return;
+        }
+    }
+}
+
+/// Run one debugger command. Returns whether the program resumes.
+fn run_command(prog: &mut Program, console: &mut Console, tokens: &[&str]) -> bool {
+    let mut tokens = tokens.to_vec();
+    if !tokens[0].starts_with("x/") {
+        match resolve_command(tokens[0]) {
+            Ok(name) => tokens[0] = name,
+            Err(e) => {
+                println!("{}", e);
+                return false;
+            }
+        }
+    }
+
+    if tokens[0] == "help" {
+        print_usage();
+    }
+    else if tokens[0] == "pc" {
+        let pc = prog.program_counter;
+        match disasm::disassemble_one(prog, pc) {
+            Some(inst) => println!("\t{:#x} ==> {}", pc, inst.asm()),
+            None => {
+                println!("\t{:#x} ==> Cannot access memory", pc);
             }
         }
-        else if tokens[0].starts_with("pa") {
-            println!("{}", prog.registers);
+    }
+    else if tokens[0] == "pa" {
+        println!("{}", prog.registers);
+    }
+    else if tokens[0] == "counters" {
+        print!("{}", prog.counters);
+    }
+    else if tokens[0] == "pv" {
+        if tokens.len() <= 1 {
+            print!("{}", prog.vector);
+            return false;
+        }
+        match tokens[1].strip_prefix('v').and_then(|num| num.parse::<u8>().ok()) {
+            Some(reg) if reg < 32 => println!("v{:<3}: {}", reg, prog.vector.format_reg(reg)),
+            _ => println!("Bad vector register {}", tokens[1]),
+        }
+    }
+    else if tokens[0] == "pf" {
+        if tokens.len() <= 1 {
+            print!("{}", prog.float);
+            return false;
+        }
+        match freg_number(tokens[1]) {
+            Some(reg) => println!("{:<5}: {}", freg_name(reg), prog.float.format_reg(reg)),
+            None => println!("Bad floating-point register {}", tokens[1]),
         }
-        else if tokens[0].starts_with("q") {
-            process::exit(0);
+    }
+    else if tokens[0] == "save" {
+        if tokens.len() <= 1 {
+            println!("No file specified.");
+            return false;
+        }
+        match prog.snapshot().save(tokens[1]) {
+            Ok(()) => println!("Snapshot saved to {}.", tokens[1]),
+            Err(e) => println!("Cannot save snapshot: {:?}", e),
         }
-        else if tokens[0].starts_with("p") {
-            if tokens.len() >= 2 {
-                let reg = prog.registers.registers
-                    .iter().find(|reg| tokens[1].eq_ignore_ascii_case(reg.id.abi_name().as_str()));
-                if let Some(reg) = reg {
-                    println!("\t{}\t: {:016x}", reg.id.abi_name(), reg.value);
+    }
+    else if tokens[0] == "echo" {
+        println!("{}", tokens[1..].join(" "));
+    }
+    else if tokens[0] == "set" {
+        let force = tokens.get(1) == Some(&"-f");
+        let assignment = tokens[if force { 2 } else { 1 }..].join(" ");
+        match assignment.split_once('=') {
+            Some((lhs, rhs)) => {
+                if let Err(e) = assign(prog, lhs, rhs, force) {
+                    println!("{}.", e);
+                }
+            },
+            None => println!("Expected \"set lhs = value\"."),
+        }
+    }
+    else if tokens[0] == "load" {
+        let force = tokens.get(1) == Some(&"-f");
+        let args = &tokens[if force { 2 } else { 1 }..];
+        if args.len() < 2 {
+            println!("Expected \"load file addr\".");
+            return false;
+        }
+        let addr = match location_addr(prog, args[1]) {
+            Some(addr) => addr,
+            None => {
+                println!("Bad address.");
+                return false;
+            }
+        };
+        match std::fs::read(args[0]) {
+            Ok(data) => {
+                if prog.mem_poke(addr, &data, force) {
+                    println!("Loaded {} bytes at {:#x}.", data.len(), addr);
                 }
                 else {
-                    println!("Unknown register name.");
+                    println!("Cannot write memory at {:#x}.", addr);
                 }
+            },
+            Err(e) => println!("Cannot read {}: {}.", args[0], e),
+        }
+    }
+    else if tokens[0] == "asm" {
+        if tokens.len() < 3 {
+            println!("Expected \"asm addr inst\".");
+            return false;
+        }
+        let addr = match address_of(prog, tokens[1]) {
+            Ok(addr) => addr,
+            Err(e) => {
+                println!("{}.", e);
+                return false;
+            }
+        };
+        let text = tokens[2..].join(" ");
+        let text = text.strip_prefix('"').and_then(|text| text.strip_suffix('"')).unwrap_or(&text);
+        match Assembler::new(text).with_xlen(prog.isa.xlen).assemble_at(addr, &prog.funcs) {
+            // Patching code ignores its permissions, like gdb does
+            Ok(code) => {
+                if prog.mem_poke(addr, &code, true) {
+                    for inst in disasm::disassemble(prog, addr, addr + code.len() as u64) {
+                        println!("{}", inst);
+                    }
+                }
+                else {
+                    println!("Cannot write memory at {:#x}.", addr);
+                }
+            },
+            Err(SimError::AsmError(e)) => println!("{}.", e),
+            Err(e) => println!("{:?}", e),
+        }
+    }
+    else if tokens[0] == "assert" {
+        let src = tokens[1..].join(" ");
+        let result = Expr::parse(&src).and_then(|expr| expr.eval(prog));
+        match result {
+            Ok(val) if val != 0 => {},
+            Ok(_) => {
+                print!("{}", "[Warning] ".yellow().bold());
+                println!("Assertion failed: {}", src);
+                console.failed = true;
+            },
+            Err(e) => {
+                print!("{}", "[Warning] ".yellow().bold());
+                println!("Assertion failed: {}: {}", src, e);
+                console.failed = true;
+            },
+        }
+    }
+    else if tokens[0] == "condition" || tokens[0] == "ignore" ||
+        tokens[0] == "enable" || tokens[0] == "disable" || tokens[0] == "commands"
+    {
+        // Breakpoints are numbered from 1
+        let idx = match tokens.get(1).map(|token| token.parse::<usize>()) {
+            Some(Ok(n)) if n >= 1 && n <= prog.breakpoints.len() => n - 1,
+            Some(_) => {
+                println!("Bad breakpoint number.");
+                return false;
+            },
+            None => {
+                println!("No breakpoint specified.");
+                return false;
+            }
+        };
+        match tokens[0] {
+            "condition" if tokens.len() <= 2 => {
+                prog.breakpoints[idx].condition = None;
+                println!("Breakpoint {} now unconditional.", idx + 1);
+            },
+            "condition" => {
+                let src = tokens[2..].join(" ");
+                match Expr::parse(&src) {
+                    Ok(expr) => prog.breakpoints[idx].condition = Some((src, expr)),
+                    Err(e) => println!("Bad condition: {}.", e),
+                }
+            },
+            "ignore" => {
+                match tokens.get(2).map(|token| token.parse::<usize>()) {
+                    Some(Ok(n)) => {
+                        prog.breakpoints[idx].ignore = n;
+                        println!("Will ignore next {} crossings of breakpoint {}.", n, idx + 1);
+                    },
+                    _ => println!("Bad count."),
+                }
+            },
+            "enable" => prog.breakpoints[idx].enabled = true,
+            "disable" => prog.breakpoints[idx].enabled = false,
+            _ => {
+                // Read the command list, up to "end"
+                println!("Type commands for breakpoint {}, one per line.", idx + 1);
+                println!("End with a line saying just \"end\".");
+                let mut commands = Vec::new();
+                loop {
+                    let line = console.read_line(prog, ">").unwrap_or_default();
+                    if line.is_empty() || line.trim() == "end" {
+                        break;
+                    }
+                    commands.push(String::from(line.trim()));
+                }
+                prog.breakpoints[idx].commands = commands;
+            }
+        }
+    }
+    else if tokens[0] == "quit" {
+        console.quit();
+        return true;
+    }
+    else if tokens[0] == "print" {
+        if tokens.len() >= 2 {
+            let reg = prog.registers.registers
+                .iter().find(|reg| tokens[1].eq_ignore_ascii_case(reg.id.abi_name().as_str()));
+            let var = prog.debug_info.as_ref()
+                .and_then(|info| info.variable(tokens[1], prog.program_counter));
+            if let Some(reg) = reg {
+                let width = prog.isa.xlen as usize / 4;
+                println!("\t{}\t: {:0width$x}", reg.id.abi_name(), reg.value & (u64::MAX >> (64 - prog.isa.xlen)), width = width);
+            }
+            else if let Some(var) = var {
+                print_variable(prog, var);
             }
             else {
-                println!("No register specified.");
+                let src = tokens[1..].join(" ");
+                match Expr::parse(&src).and_then(|expr| expr.eval(prog)) {
+                    Ok(val) => println!("\t{} : {:#x} ({})", src, val, val as i64),
+                    Err(e) => println!("{}.", e),
+                }
             }
         }
-        else if tokens[0].starts_with("x") {
-            let split: Vec<&str> = tokens[0].split("/").collect();
-            let len = usize::from_str_radix(split.last().unwrap(), 10);
-            if let Err(_) = len {
-                println!("Bad length.");
-                continue;
+        else {
+            println!("No register specified.");
+        }
+    }
+    else if tokens[0] == "x" || tokens[0].starts_with("x/") {
+        let spec = tokens[0].split_once('/').map_or("", |(_, spec)| spec);
+        if tokens.len() <= 1 {
+            println!("No address specified");
+            return false;
+        }
+        match address_of(prog, &tokens[1..].join(" ")) {
+            Ok(addr) => examine(prog, spec, addr),
+            Err(e) => println!("{}.", e),
+        }
+    }
+    else if tokens[0] == "step" || tokens[0] == "next" {
+        let pc = prog.program_counter;
+        match prog.debug_info.as_ref().and_then(|info| info.line_at(pc)) {
+            Some((file, line)) => {
+                let depth = if tokens[0] == "next" { prog.call_stack.len() } else { usize::MAX };
+                prog.line_step = Some((String::from(file), line, depth));
+                prog.pause = usize::MAX;
+            },
+            None => {
+                println!("No line information, stepping one instruction.");
+                prog.pause = 0;
             }
-            let sz = len.unwrap();
-            if tokens.len() <= 1 {
-                println!("No address specified");
-                continue;
+        }
+        return true;
+    }
+    else if tokens[0] == "list" {
+        let info = match prog.debug_info.as_ref() {
+            Some(info) => info,
+            None => {
+                println!("No debugging information.");
+                return false;
+            }
+        };
+        let location = if tokens.len() <= 1 {
+            info.line_at(prog.program_counter).map(|(file, line)| (String::from(file), line))
+        }
+        else if let Some((file, line)) = tokens[1].rsplit_once(':') {
+            line.parse::<u32>().ok().map(|line| (String::from(file), line))
+        }
+        else {
+            info.functions.iter().find(|func| func.name == tokens[1])
+                .and_then(|func| func.decl)
+                .map(|(file, line)| (info.files[file].clone(), line))
+        };
+        match location {
+            Some((file, line)) => {
+                let path = info.find_files(&file).first().map_or(file, |&idx| info.files[idx].clone());
+                list_source(&path, line);
+            },
+            None => println!("No source location."),
+        }
+    }
+    else if tokens[0] == "il" {
+        match prog.debug_info.as_ref() {
+            Some(info) => {
+                for var in info.locals(prog.program_counter) {
+                    print_variable(prog, var);
+                }
+            },
+            None => println!("No debugging information."),
+        }
+    }
+    else if tokens[0] == "reverse-stepi" || tokens[0] == "reverse-continue" {
+        let mut steps = if tokens[0] == "reverse-continue" { usize::MAX } else { 1 };
+        if tokens[0] == "reverse-stepi" && tokens.len() >= 2 {
+            let n = tokens[1].parse::<usize>();
+            if n.is_err() {
+                println!("Bad number.");
+                return false;
             }
-            let addr = u64::from_str_radix(tokens[1].to_lowercase().trim_start_matches("0x"), 16);
-            if let Err(_) = addr {
-                println!("Bad address format.");
+            steps = std::cmp::max(n.unwrap(), 1);
+        }
+        if prog.history.is_none() {
+            println!("Execution history is not recorded.");
+            return false;
+        }
+        while steps > 0 {
+            // Loads are not recorded, so only changes of the watched
+            // contents trigger a watchpoint in reverse.
+            let watched: Vec<Vec<u8>> = prog.watchpoints.iter()
+                .map(|&(addr, len, _)| prog.watched(addr, len))
+                .collect();
+            if !reverse_step(prog) {
+                match prog.history.as_ref().and_then(|history| history.barrier()) {
+                    Some(pc) => println!("Cannot step back across the system call at {:#x}.", pc),
+                    None => println!("No more execution history."),
+                }
+                break;
+            }
+            steps -= 1;
+            if tokens[0] != "reverse-continue" {
                 continue;
             }
-            let addr = addr.unwrap();
-
-            match prog.mem_load(addr, sz, false) {
-                Some((data, rem)) if rem == 0 => {
-                    for (idx, byte) in data.iter().enumerate() {
-                        if idx % 16 == 0 {
-                            print!("{:x}:\t", addr);
-                        }
-                        print!("{:02x} ", *byte);
-                        if idx % 16 == 7 {
-                            print!(" ");
-                        }
-                        if idx % 16 == 15 {
-                            println!();
+            if let Some((idx, _)) = check_breakpoints(prog, false) {
+                print!("{}", "[Debug] ".green());
+                println!("Hit breakpoint {} at {:#x}", idx, prog.program_counter);
+                break;
+            }
+            let changed: Vec<(usize, Vec<u8>)> = watched.into_iter().enumerate()
+                .filter(|(idx, old)| {
+                    let (addr, len, kind) = prog.watchpoints[*idx];
+                    kind != Watch::Read && prog.watched(addr, len) != *old
+                })
+                .collect();
+            if !changed.is_empty() {
+                // Reported as going from the later to the earlier value
+                let pc = prog.program_counter;
+                prog.watch_report(pc, changed);
+                break;
+            }
+        }
+        let pc = prog.program_counter;
+        let func = prog.func_at(pc).map_or("??", |(_, _, name)| name.as_str());
+        println!("\t{:#x} in {}", pc, func);
+        print_location(prog);
+    }
+    else if tokens[0] == "backtrace" {
+        backtrace(prog);
+    }
+    else if tokens[0] == "finish" {
+        let depth = prog.call_stack.len();
+        match prog.call_stack.last() {
+            Some(&(_, ret)) if ret != HLT_ADDR => {
+                let func = prog.func_at(prog.program_counter).map_or("??", |(_, _, name)| name.as_str());
+                println!("Run till exit from {} ()", func);
+                prog.run_to = Some((ret, depth - 1, depth - 1));
+                prog.pause = usize::MAX;
+                return true;
+            },
+            _ => println!("\"finish\" not meaningful in the outermost frame."),
+        }
+    }
+    else if tokens[0] == "nexti" {
+        // Step over calls by running to the next instruction in this frame
+        let pc = prog.program_counter;
+        let call = match prog.mem_load(pc, 4, true) {
+            Some((data, 0)) => {
+                let raw = u32::from_le_bytes(data.try_into().unwrap());
+                let (code, len) = InstCode::parse((raw & 0xFFFF) as u16, (raw >> 16) as u16, prog.isa.xlen);
+                match code {
+                    InstCode::Jal(RegID::X1 | RegID::X5, _) |
+                    InstCode::Jalr(RegID::X1 | RegID::X5, _, _) => Some(pc + len as u64 / 8),
+                    _ => None,
+                }
+            },
+            _ => None,
+        };
+        match call {
+            Some(ret) => {
+                let depth = prog.call_stack.len();
+                prog.run_to = Some((ret, depth, depth));
+                prog.pause = usize::MAX;
+            },
+            None => prog.pause = 0,
+        }
+        return true;
+    }
+    else if tokens[0] == "until" || tokens[0] == "advance" {
+        if tokens.len() <= 1 {
+            println!("No location specified.");
+            return false;
+        }
+        match location_addr(prog, tokens[1]) {
+            Some(addr) => {
+                // `until` does not stop in calls made from this frame
+                let depth = prog.call_stack.len();
+                let max_depth = if tokens[0] == "until" { depth } else { usize::MAX };
+                prog.run_to = Some((addr, max_depth, depth));
+                prog.pause = usize::MAX;
+                return true;
+            },
+            None => println!("Bad location."),
+        }
+    }
+    else if tokens[0] == "stepi" {
+        let mut steps = 1;
+        if tokens.len() >= 2 {
+            let n = tokens[1].parse::<usize>();
+            if n.is_err() {
+                println!("Bad number.");
+                return false;
+            }
+            steps = std::cmp::max(n.unwrap(), 1);
+        }
+        prog.pause = steps - 1;
+        return true;
+    }
+    else if tokens[0] == "continue" {
+        prog.pause = usize::MAX;
+        return true;
+    }
+    else if tokens[0] == "break" {
+        if tokens.len() >= 2 {
+            // b location if condition
+            let condition = match tokens.iter().position(|&token| token == "if") {
+                Some(idx) => {
+                    let src = tokens[idx + 1..].join(" ");
+                    match Expr::parse(&src) {
+                        Ok(expr) => Some((src, expr)),
+                        Err(e) => {
+                            println!("Bad condition: {}.", e);
+                            return false;
                         }
                     }
-                    println!();
                 },
-                _ => {
-                    println!("Cannot access memory at {:#x}", addr);
+                None => None,
+            };
+
+            // b func
+            if let Some((addr,_,_)) = prog.funcs.iter().find(|(_,_,name)| name.eq(tokens[1])) {
+                let addr = *addr;
+                prog.breakpoints.push(Breakpoint { condition, ..Breakpoint::new(addr) });
+                println!("Breakpoint {} at {:#x}", prog.breakpoints.len(), addr);
+                return false;
+            }
+
+            // b file:line
+            if let Some((file, line)) = tokens[1].rsplit_once(':') {
+                let addr = line.parse::<u32>().ok()
+                    .and_then(|line| prog.debug_info.as_ref()?.addr_of_line(file, line));
+                match addr {
+                    Some((addr, line)) => {
+                        prog.breakpoints.push(Breakpoint { condition, ..Breakpoint::new(addr) });
+                        println!("Breakpoint {} at {:#x}: file {}, line {}.", prog.breakpoints.len(), addr, file, line);
+                    },
+                    None => println!("No line {} in file {}.", line, file),
                 }
+                return false;
             }
-        }
-        else if tokens[0].starts_with("si") {
-            let mut steps = 1;
-            if tokens.len() >= 2 {
-                let n = usize::from_str_radix(tokens[1], 10);
-                if let Err(_) = n {
-                    println!("Bad number.");
-                    continue;
+
+            // b addr
+            let addr = match address_of(prog, tokens[1]) {
+                Ok(addr) => addr,
+                Err(e) => {
+                    println!("{}.", e);
+                    return false;
                 }
-                steps = std::cmp::max(n.unwrap(), 1);
+            };
+            prog.breakpoints.push(Breakpoint { condition, ..Breakpoint::new(addr) });
+            println!("Breakpoint {} at {:#x}", prog.breakpoints.len(), addr);
+        }
+    }
+    else if tokens[0] == "ib" {
+        println!("Breakpoints:");
+        for (idx, bp) in prog.breakpoints.iter().enumerate() {
+            print!(" {} - {:#x}", idx + 1, bp.addr);
+            if !bp.enabled {
+                print!(" (disabled)");
+            }
+            if let Some((src, _)) = bp.condition.as_ref() {
+                print!(" if {}", src);
+            }
+            println!(", hit {} times", bp.hits);
+            if bp.ignore > 0 {
+                println!("     Will ignore next {} hits", bp.ignore);
+            }
+            for command in bp.commands.iter() {
+                println!("     {}", command);
             }
-            prog.pause = steps - 1;
-            return;
         }
-        else if tokens[0].starts_with("c") {
-            prog.pause = usize::MAX;
-            return;
+        println!("Watchpoints:");
+        for (idx, (addr, len, kind)) in prog.watchpoints.iter().enumerate() {
+            println!(" {} - {} {:#x}, {} bytes", idx + 1, kind, addr, len);
+        }
+    }
+    else if tokens[0] == "watch" || tokens[0] == "rwatch" || tokens[0] == "awatch" {
+        if tokens.len() <= 1 {
+            println!("No address specified.");
+            return false;
         }
-        else if tokens[0].starts_with("b") {
-            if tokens.len() >= 2 {
-                // b func
-                if let Some((addr,_,_)) = prog.funcs.iter().find(|(_,_,name)| name.eq(tokens[1])) {
-                    prog.breakpoints.push(*addr);
-                    println!("Breakpoint {} at {:#x}", prog.breakpoints.len(), *addr);
-                    continue;
+        // watch var, for variables at fixed addresses
+        let var = prog.debug_info.as_ref()
+            .and_then(|info| info.variable(tokens[1], prog.program_counter))
+            .and_then(|var| match var.location {
+                Location::Address(addr) => Some((addr, var.size.unwrap_or(8) as usize)),
+                _ => None,
+            });
+        let (addr, mut len) = match var {
+            Some(var) => var,
+            None => match address_of(prog, tokens[1]) {
+                Ok(addr) => (addr, 8),
+                Err(e) => {
+                    println!("{}.", e);
+                    return false;
                 }
-
-                // b addr
-                let addr = u64::from_str_radix(tokens[1].to_lowercase().trim_start_matches("0x"), 16);
-                if let Err(_) = addr {
-                    println!("Bad address.");
-                    continue;
+            }
+        };
+        if tokens.len() >= 3 {
+            match tokens[2].parse::<usize>() {
+                Ok(n) if n > 0 => len = n,
+                _ => {
+                    println!("Bad length.");
+                    return false;
                 }
-                let addr = addr.unwrap();
-                prog.breakpoints.push(addr);
-                println!("Breakpoint {} at {:#x}", prog.breakpoints.len(), addr);
             }
         }
-        else if tokens[0].starts_with("ib") {
-            println!("Breakpoints:");
-            for (idx, addr) in prog.breakpoints.iter().enumerate() {
-                println!(" {} - {:#x}", idx, addr);
+        let kind = match tokens[0] {
+            "watch" => Watch::Write,
+            "rwatch" => Watch::Read,
+            _ => Watch::Access,
+        };
+        prog.watchpoints.push((addr, len, kind));
+        println!("Watchpoint {} ({}) at {:#x}, {} bytes", prog.watchpoints.len(), kind, addr, len);
+    }
+    else if tokens[0] == "dw" {
+        if tokens.len() >= 2 {
+            let n = tokens[1].parse::<usize>();
+            if n.is_err() {
+                println!("Bad number.");
+                return false;
+            }
+            let n = n.unwrap();
+            if n >= 1 && n <= prog.watchpoints.len() {
+                prog.watchpoints.remove(n - 1);
             }
         }
-        else if tokens[0].starts_with("disass") {
-            if tokens.len() <= 1 {
-                // disassemble current
-                if let Some((addr, sz, name)) = prog.funcs
-                    .iter().find(|(addr, sz, _)| {
-                        prog.program_counter >= *addr &&
-                        prog.program_counter < *addr + (*sz) as u64
-                    }) 
-                {
-                    disassemble(name.clone().as_str(), *addr, *sz, prog);
-                }
+        else {
+            println!("No watchpoint specified.");
+        }
+    }
+    else if tokens[0] == "disassemble" {
+        if tokens.len() <= 1 {
+            // disassemble current
+            if let Some((addr, sz, name)) = prog.funcs
+                .iter().find(|(addr, sz, _)| {
+                    prog.program_counter >= *addr &&
+                    prog.program_counter < *addr + (*sz) as u64
+                }) 
+            {
+                disassemble(name.clone().as_str(), *addr, *sz, prog);
+            }
+        }
+        else {
+            // disassemble named function
+            if let Some((addr, sz, name)) = prog.funcs
+                .iter().find(|(_, _, name)| name.eq(tokens[1])) 
+            {
+                disassemble(name.clone().as_str(), *addr, *sz, prog);
             }
             else {
-                // disassemble named function
-                if let Some((addr, sz, name)) = prog.funcs
-                    .iter().find(|(_, _, name)| name.eq(tokens[1])) 
-                {
-                    disassemble(name.clone().as_str(), *addr, *sz, prog);
-                }
-                else {
-                    println!("Bad function name.");
-                }
+                println!("Bad function name.");
             }
         }
-        else if tokens[0].starts_with("d") {
-            if tokens.len() >= 2 {
-                let n = usize::from_str_radix(tokens[1], 10);
-                if let Err(_) = n {
-                    println!("Bad number.");
-                    continue;
-                }
-                let n = n.unwrap();
-                if n < prog.breakpoints.len() {
-                    prog.breakpoints.remove(n);
-                }
+    }
+    else if tokens[0] == "delete" {
+        if tokens.len() >= 2 {
+            let n = tokens[1].parse::<usize>();
+            if n.is_err() {
+                println!("Bad number.");
+                return false;
             }
-            else {
-                println!("No breakpoint specified.");
+            let n = n.unwrap();
+            if n >= 1 && n <= prog.breakpoints.len() {
+                prog.breakpoints.remove(n - 1);
             }
         }
+        else {
+            println!("No breakpoint specified.");
+        }
     }
+    false
 }
 
 
 fn main() {
 
     let cli = Cli::parse();
-    let elf = cli.elf;
+    if let Some(Tool::Disasm { elf, funcs }) = cli.tool {
+        disasm_elf(&elf, &funcs);
+        return;
+    }
+    let elf = cli.elf.unwrap();
     let quiet = cli.quiet;
-    let interactive = cli.interactive;
+    let interactive = cli.interactive || cli.command.is_some() || cli.tui;
+    let script = cli.command.as_ref().map(|path| match std::fs::read_to_string(path) {
+        Ok(text) => text.lines().map(String::from).collect(),
+        Err(e) => {
+            print!("{}", "[Fatal] ".red().bold());
+            println!("Cannot read {}: {}", path, e);
+            process::exit(-1);
+        }
+    });
+    let mut tracers = Tracers {
+        flamegraph: cli.flamegraph.as_ref().map(|_| FoldedStacks::new(cli.sample_period)),
+        stats: (cli.stats || cli.stats_json.is_some()).then(InstStats::new),
+        coverage: (cli.coverage.is_some() || cli.coverage_map.is_some()).then(Coverage::new),
+    };
 
-    let loader = Loader::new(ELFArch::Rv64I, elf.as_str());
-    match loader.load() {
-        Ok(mut prog) => {
-            sim(&mut prog, quiet, interactive);
-        },
+    let format = cli.format.unwrap_or_else(|| ImageFormat::guess(&elf));
+    let loader = ImageLoader::new(format, &elf, cli.base, cli.perms, cli.entry, cli.sysroot.as_deref(), cli.isa.clone());
+    let mut prog = match loader.load() {
+        Ok(prog) => prog,
         Err(e) => {
             print!("{}", "[Fatal] ".red().bold());
             println!("{:?}", e);
+            process::exit(-1);
         }
+    };
+    if cli.vlen < prog.isa.min_vlen() {
+        print!("{}", "[Fatal] ".red().bold());
+        println!("VLEN {} is below the {} bits required by {}", cli.vlen, prog.isa.min_vlen(), prog.isa);
+        process::exit(-1);
+    }
+    prog.vector = VectorUnit::new(cli.vlen);
+    if let Some(path) = cli.restore.as_ref() {
+        if let Err(e) = Snapshot::load(path).and_then(|snapshot| prog.restore(&snapshot)) {
+            print!("{}", "[Fatal] ".red().bold());
+            println!("Cannot restore from {}: {:?}", path, e);
+            process::exit(-1);
+        }
+    }
+    for &(idx, event) in cli.hpm_event.iter() {
+        prog.counters.select(idx, event);
+    }
+    if interactive && cli.history > 0 {
+        // Reverse execution would count the instructions it replays twice
+        if tracers.flamegraph.is_some() || tracers.stats.is_some() || tracers.coverage.is_some() {
+            print!("{}", "[Warning] ".yellow().bold());
+            println!("Reverse execution is disabled, as --flamegraph, --stats and --coverage cannot be rewound");
+        }
+        else {
+            prog.history = Some(History::new(cli.history, cli.history as u64, 16));
+        }
+    }
+
+    // The view opens once the program is ready, so that errors up to
+    // here stay on the terminal
+    let mut console = Console::new(script, cli.tui);
+    console.complete_symbols(&prog);
+    let status = sim(&mut prog, quiet, interactive, &mut tracers, &mut console);
+
+    // Restore the terminal before reporting
+    drop(console);
+
+    if let (Some(path), Some(flamegraph)) = (cli.flamegraph, tracers.flamegraph) {
+        if let Err(e) = File::create(&path).and_then(|mut file| flamegraph.write_to(&mut file)) {
+            print!("{}", "[Warning] ".yellow().bold());
+            println!("Cannot write folded stacks to {}: {}", path, e);
+        }
+    }
+    if let Some(stats) = tracers.stats {
+        if cli.stats {
+            println!("{}", stats);
+        }
+        if let Some(path) = cli.stats_json {
+            if let Err(e) = File::create(&path).and_then(|mut file| stats.write_json(&mut file)) {
+                print!("{}", "[Warning] ".yellow().bold());
+                println!("Cannot write statistics to {}: {}", path, e);
+            }
+        }
+    }
+    if let Some(coverage) = tracers.coverage {
+        let (hit, total) = coverage.summary(&prog);
+        print!("{}", "[Debug] ".green());
+        println!("Coverage: {}/{} instructions executed", hit, total);
+
+        if let Some(path) = cli.coverage {
+            if let Err(e) = File::create(&path)
+                .and_then(|mut file| coverage.write_lcov(&prog, &elf, |pc| {
+                    prog.debug_info.as_ref()
+                        .and_then(|info| info.line_at(pc))
+                        .map(|(file, line)| (String::from(file), line))
+                }, &mut file))
+            {
+                print!("{}", "[Warning] ".yellow().bold());
+                println!("Cannot write coverage to {}: {}", path, e);
+            }
+        }
+        if let Some(path) = cli.coverage_map {
+            if let Err(e) = File::create(&path).and_then(|mut file| coverage.write_hit_map(&prog, &mut file)) {
+                print!("{}", "[Warning] ".yellow().bold());
+                println!("Cannot write coverage hit map to {}: {}", path, e);
+            }
+        }
+    }
+
+    if status != 0 {
+        process::exit(status);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Numbers are decimal unless prefixed with 0x, in addresses as in
+    /// values.
+    #[test]
+    fn radix() {
+        let mut prog = Assembler::new("main: ret\n.data\n.dword 0").load().unwrap();
+        prog.registers.write(RegID::X10, 0x11000);
+        assert_eq!(address_of(&prog, "69632"), Ok(0x11000));
+        assert_eq!(address_of(&prog, "0x11000"), Ok(0x11000));
+        assert_eq!(address_of(&prog, "a0 + 16"), Ok(0x11010));
+        assert_eq!(location_addr(&prog, "65536"), Some(0x10000));
+        assert_eq!(location_addr(&prog, "main"), Some(0x10000));
+        assert!(address_of(&prog, "11000f").is_err());
+
+        assert_eq!(assign(&mut prog, "*(u64*)69632", "10", false), Ok(()));
+        assert_eq!(prog.mem_load(0x11000, 8, false).unwrap().0, 10u64.to_le_bytes());
+        assert_eq!(assign(&mut prog, "*(u32*)(0x11000 + 4)", "0x10", false), Ok(()));
+        assert_eq!(prog.mem_load(0x11004, 4, false).unwrap().0, 16u32.to_le_bytes());
     }
 }
@@ -0,0 +1,335 @@
+#![allow(clippy::module_inception)]
+#[allow(dead_code)]
+pub mod profile {
+    //! This module implements profiling of the retired instruction stream:
+    //! dynamic instruction statistics, and call stack samples on top of
+    //! the shadow call stack kept in `Program`, aggregated as folded stacks
+    //! (`main;foo;puts 1234`), the input format of flamegraph.pl and inferno.
+
+    use std::collections::{HashMap, HashSet};
+    use std::io::{self, Write};
+    use std::fmt;
+    use crate::{Program, InstCode, InstCategory};
+
+    /// A sampling profiler producing folded call stacks.
+    pub struct FoldedStacks {
+        /// Sample once every `period` retired instructions.
+        period: u64,
+        /// Instructions left until the next sample.
+        countdown: u64,
+        /// Folded stack => weight, in retired instructions.
+        samples: HashMap<String, u64>,
+    }
+
+    impl FoldedStacks {
+        pub fn new(period: u64) -> Self {
+            let period = std::cmp::max(period, 1);
+            FoldedStacks {
+                period,
+                countdown: period,
+                samples: HashMap::new(),
+            }
+        }
+
+        /// Account one retired instruction. Every `period` instructions the
+        /// current call stack is sampled, weighted by `period`, so that the
+        /// output is in units of instructions regardless of the sampling rate.
+        pub fn tick(&mut self, prog: &Program) {
+            self.countdown -= 1;
+            if self.countdown != 0 {
+                return;
+            }
+            self.countdown = self.period;
+            if prog.call_stack.is_empty() {
+                return;
+            }
+
+            let stack = prog.call_stack.iter()
+                .map(|&(func, _)| match prog.func_at(func) {
+                    Some((_, _, name)) => name.clone(),
+                    None => format!("{:#x}", func),
+                })
+                .collect::<Vec<String>>()
+                .join(";");
+            *self.samples.entry(stack).or_insert(0) += self.period;
+        }
+
+        /// Write the folded stacks, one per line, in lexical order.
+        pub fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+            let mut stacks: Vec<(&String, &u64)> = self.samples.iter().collect();
+            stacks.sort();
+            for (stack, weight) in stacks {
+                writeln!(out, "{} {}", stack, weight)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Memory access widths, in bytes, and their names.
+    const WIDTHS: [(usize, &str); 4] = [(1, "byte"), (2, "half"), (4, "word"), (8, "double")];
+
+    /// Dynamic instruction mix statistics.
+    #[derive(Default)]
+    pub struct InstStats {
+        /// Total retired instructions.
+        instructions: u64,
+        /// Retired instructions by mnemonic.
+        by_code: HashMap<&'static str, u64>,
+        /// Retired instructions by category.
+        by_category: HashMap<InstCategory, u64>,
+        /// Branch mnemonic => (taken, not taken).
+        branches: HashMap<&'static str, (u64, u64)>,
+        /// Access counts indexed as `WIDTHS`.
+        loads: [u64; 4],
+        stores: [u64; 4],
+        /// Distinct PCs executed.
+        pcs: HashSet<u64>,
+    }
+
+    impl InstStats {
+        pub fn new() -> Self {
+            InstStats::default()
+        }
+
+        /// Account one retired instruction.
+        pub fn retire(&mut self, pc: u64, code: InstCode, next_pc: u64) {
+            self.instructions += 1;
+            *self.by_code.entry(code.mnemonic()).or_insert(0) += 1;
+            *self.by_category.entry(code.category()).or_insert(0) += 1;
+            self.pcs.insert(pc);
+
+            match code {
+                InstCode::Beq(_,_,ofs) | InstCode::Bne(_,_,ofs) |
+                InstCode::Blt(_,_,ofs) | InstCode::Bge(_,_,ofs) |
+                InstCode::Bltu(_,_,ofs) | InstCode::Bgeu(_,_,ofs) => {
+                    let counts = self.branches.entry(code.mnemonic()).or_insert((0, 0));
+                    if next_pc == (pc as i64 + ofs as i64) as u64 {
+                        counts.0 += 1;
+                    }
+                    else {
+                        counts.1 += 1;
+                    }
+                },
+                _ => {}
+            }
+
+            if let Some(width) = code.mem_width() {
+                let idx = WIDTHS.iter().position(|&(w, _)| w == width).unwrap();
+                match code.category() {
+                    InstCategory::Load => self.loads[idx] += 1,
+                    _ => self.stores[idx] += 1,
+                }
+            }
+        }
+
+        /// Total (taken, not taken) over all branches.
+        fn branch_totals(&self) -> (u64, u64) {
+            self.branches.values()
+                .fold((0, 0), |(t, n), &(taken, not_taken)| (t + taken, n + not_taken))
+        }
+
+        fn sorted_codes(&self) -> Vec<(&'static str, u64)> {
+            let mut codes: Vec<(&'static str, u64)> = self.by_code.iter()
+                .map(|(&code, &cnt)| (code, cnt))
+                .collect();
+            // Most frequent first
+            codes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+            codes
+        }
+
+        fn ratio(taken: u64, not_taken: u64) -> f64 {
+            if taken + not_taken == 0 {
+                0.0
+            }
+            else {
+                taken as f64 / (taken + not_taken) as f64
+            }
+        }
+
+        /// Write the statistics as a JSON object.
+        pub fn write_json<W: Write>(&self, out: &mut W) -> io::Result<()> {
+            writeln!(out, "{{")?;
+            writeln!(out, "  \"instructions\": {},", self.instructions)?;
+            writeln!(out, "  \"distinct_pcs\": {},", self.pcs.len())?;
+
+            let codes: Vec<String> = self.sorted_codes().iter()
+                .map(|(code, cnt)| format!("\"{}\": {}", code, cnt))
+                .collect();
+            writeln!(out, "  \"by_opcode\": {{{}}},", codes.join(", "))?;
+
+            let categories: Vec<String> = InstCategory::ALL.iter()
+                .map(|cat| format!("\"{}\": {}", cat, self.by_category.get(cat).unwrap_or(&0)))
+                .collect();
+            writeln!(out, "  \"by_category\": {{{}}},", categories.join(", "))?;
+
+            let (taken, not_taken) = self.branch_totals();
+            let mut branches: Vec<(&&str, &(u64, u64))> = self.branches.iter().collect();
+            branches.sort();
+            let branches: Vec<String> = branches.iter()
+                .map(|(code, &(t, n))| format!(
+                    "\"{}\": {{\"taken\": {}, \"not_taken\": {}, \"taken_ratio\": {:.4}}}",
+                    code, t, n, InstStats::ratio(t, n)))
+                .collect();
+            writeln!(out, "  \"branches\": {{\"taken\": {}, \"not_taken\": {}, \"taken_ratio\": {:.4}, \"by_opcode\": {{{}}}}},",
+                taken, not_taken, InstStats::ratio(taken, not_taken), branches.join(", "))?;
+
+            for (name, counts, last) in [("loads", &self.loads, false), ("stores", &self.stores, true)] {
+                let widths: Vec<String> = WIDTHS.iter().zip(counts.iter())
+                    .map(|(&(width, wname), &cnt)| format!(
+                        "\"{}\": {{\"count\": {}, \"bytes\": {}}}", wname, cnt, cnt * width as u64))
+                    .collect();
+                writeln!(out, "  \"{}\": {{{}}}{}", name, widths.join(", "), if last {""} else {","})?;
+            }
+            writeln!(out, "}}")
+        }
+    }
+
+    impl fmt::Display for InstStats {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let percent = |cnt: u64| {
+                if self.instructions == 0 { 0.0 } else { cnt as f64 * 100.0 / self.instructions as f64 }
+            };
+
+            writeln!(f, "Instructions retired : {}", self.instructions)?;
+            writeln!(f, "Distinct PCs         : {}", self.pcs.len())?;
+
+            writeln!(f, "\nBy category:")?;
+            for cat in InstCategory::ALL.iter() {
+                let cnt = *self.by_category.get(cat).unwrap_or(&0);
+                writeln!(f, "  {:<12}{:>12}  {:>6.2}%", cat.to_string(), cnt, percent(cnt))?;
+            }
+
+            writeln!(f, "\nBy opcode:")?;
+            for (code, cnt) in self.sorted_codes() {
+                writeln!(f, "  {:<12}{:>12}  {:>6.2}%", code, cnt, percent(cnt))?;
+            }
+
+            let (taken, not_taken) = self.branch_totals();
+            writeln!(f, "\nBranches:    {:>12}{:>12}{:>8}", "taken", "not taken", "ratio")?;
+            let mut branches: Vec<(&&str, &(u64, u64))> = self.branches.iter().collect();
+            branches.sort();
+            for (code, &(t, n)) in branches {
+                writeln!(f, "  {:<10}{:>12}{:>12}{:>8.3}", code, t, n, InstStats::ratio(t, n))?;
+            }
+            writeln!(f, "  {:<10}{:>12}{:>12}{:>8.3}", "total", taken, not_taken, InstStats::ratio(taken, not_taken))?;
+
+            writeln!(f, "\nMemory:      {:>12}{:>12}{:>12}{:>12}", "loads", "bytes", "stores", "bytes")?;
+            for (idx, &(width, name)) in WIDTHS.iter().enumerate() {
+                writeln!(f, "  {:<10}{:>12}{:>12}{:>12}{:>12}", name,
+                    self.loads[idx], self.loads[idx] * width as u64,
+                    self.stores[idx], self.stores[idx] * width as u64)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::{Assembler, Inst, HLT_ADDR};
+
+        /// Run `source` to its end, passing each retired instruction to
+        /// `retire` with the state after it.
+        fn run(source: &str, mut retire: impl FnMut(&Program, u64, InstCode, u64)) {
+            let mut prog = Assembler::new(source).load().unwrap();
+            while prog.program_counter != HLT_ADDR {
+                let pc = prog.program_counter;
+                let mut inst = Inst::new().advance(&mut prog).unwrap();
+                let code = inst.code();
+                prog.program_counter = loop {
+                    match inst.advance(&mut prog) {
+                        Ok(next) => inst = next,
+                        Err(next_pc) => break next_pc,
+                    }
+                };
+                retire(&prog, pc, code, prog.program_counter);
+            }
+        }
+
+        #[test]
+        fn folded_stacks() {
+            // foo links through t0, as calling puts overwrites ra
+            let source = "
+                main:   mv s0, ra
+                        jal t0, foo
+                        mv ra, s0
+                        ret
+                foo:    call puts
+                        jr t0";
+            for (period, folded) in [
+                (1, "main 3\nmain;foo 3\nmain;foo;puts 1\n"),
+                (2, "main 2\nmain;foo 2\nmain;foo;puts 2\n"),
+                (100, ""),
+            ] {
+                let mut stacks = FoldedStacks::new(period);
+                run(source, |prog, _, _, _| stacks.tick(prog));
+                let mut out = Vec::new();
+                stacks.write_to(&mut out).unwrap();
+                assert_eq!(String::from_utf8(out).unwrap(), folded, "period {}", period);
+            }
+        }
+
+        #[test]
+        fn stats() {
+            let mut stats = InstStats::new();
+            run("
+                        .data
+                buf:    .dword 0
+                        .text
+                main:   la a0, buf
+                        li t0, 2
+                loop:   sw t0, 0(a0)
+                        lb t1, 0(a0)
+                        addi t0, t0, -1
+                        bnez t0, loop
+                        ret", |_, pc, code, next_pc| stats.retire(pc, code, next_pc));
+
+            assert_eq!(stats.to_string(), "\
+Instructions retired : 12
+Distinct PCs         : 8
+
+By category:
+  alu                    5   41.67%
+  muldiv                 0    0.00%
+  load                   2   16.67%
+  store                  2   16.67%
+  branch                 2   16.67%
+  jump                   1    8.33%
+  system                 0    0.00%
+  vector                 0    0.00%
+  float                  0    0.00%
+  illegal                0    0.00%
+
+By opcode:
+  addi                   4   33.33%
+  bne                    2   16.67%
+  lb                     2   16.67%
+  sw                     2   16.67%
+  auipc                  1    8.33%
+  jalr                   1    8.33%
+
+Branches:           taken   not taken   ratio
+  bne                  1           1   0.500
+  total                1           1   0.500
+
+Memory:             loads       bytes      stores       bytes
+  byte                 2           2           0           0
+  half                 0           0           0           0
+  word                 0           0           2           8
+  double               0           0           0           0
+");
+            let mut json = Vec::new();
+            stats.write_json(&mut json).unwrap();
+            assert_eq!(String::from_utf8(json).unwrap(), r#"{
+  "instructions": 12,
+  "distinct_pcs": 8,
+  "by_opcode": {"addi": 4, "bne": 2, "lb": 2, "sw": 2, "auipc": 1, "jalr": 1},
+  "by_category": {"alu": 5, "muldiv": 0, "load": 2, "store": 2, "branch": 2, "jump": 1, "system": 0, "vector": 0, "float": 0, "illegal": 0},
+  "branches": {"taken": 1, "not_taken": 1, "taken_ratio": 0.5000, "by_opcode": {"bne": {"taken": 1, "not_taken": 1, "taken_ratio": 0.5000}}},
+  "loads": {"byte": {"count": 2, "bytes": 2}, "half": {"count": 0, "bytes": 0}, "word": {"count": 0, "bytes": 0}, "double": {"count": 0, "bytes": 0}},
+  "stores": {"byte": {"count": 0, "bytes": 0}, "half": {"count": 0, "bytes": 0}, "word": {"count": 2, "bytes": 8}, "double": {"count": 0, "bytes": 0}}
+}
+"#);
+        }
+    }
+}
@@ -0,0 +1,549 @@
+#![allow(clippy::module_inception)]
+#[allow(dead_code)]
+pub mod snapshot {
+    //! This module implements checkpointing of the full machine state of a
+    //! `Program`, either in memory or as a versioned on-disk snapshot, so
+    //! that long runs can be resumed past their initialization.
+    //!
+    //! A snapshot holds the PC, the register file, the counters, the vector
+    //! registers with VLEN, vl and vtype, the floating-point registers with
+    //! fflags and frm, the LR/SC reservation, every VMA with its
+    //! permissions and contents, the breakpoints and watchpoints, the
+    //! shadow call stack, the symbols and the state of the Linux process.
+    //! The counters, vector and floating-point CSRs are the only CSRs
+    //! simulated, and the events of the hpm counters are given on the
+    //! command line again. There are no devices simulated yet, hence
+    //! nothing more to save. Debugging information is not saved, as it is
+    //! read again from the ELF.
+    //!
+    //! A snapshot only restores into the image it was taken from, with the
+    //! same ISA and VLEN, all of which it records.
+
+    use std::collections::HashMap;
+    use std::fs;
+    use crate::{SimError, VMA, Program, Breakpoint, Watch, Expr, Isa, VectorUnit, FloatUnit, Linux, LinuxState};
+
+    /// Magic number of snapshot files.
+    const MAGIC: &[u8; 8] = b"RSIMSNAP";
+    /// Bumped whenever the layout changes.
+    pub const VERSION: u32 = 5;
+
+    /// A checkpoint of the machine state of a `Program`.
+    #[derive(Debug, Clone)]
+    pub struct Snapshot {
+        pub isa: Isa,
+        /// `image_hash` of the loaded image
+        pub image_hash: u64,
+        pub entry_point: u64,
+        pub program_counter: u64,
+        pub registers: [u64; 32],
+        /// Values of the counters, indexed as `Counters::values`
+        pub counters: [u64; 32],
+        pub vector: VectorUnit,
+        pub float: FloatUnit,
+        pub reservation: Option<u64>,
+        pub vmas: Vec<VMA>,
+        pub breakpoints: Vec<Breakpoint>,
+        pub watchpoints: Vec<(u64, usize, Watch)>,
+        pub call_stack: Vec<(u64, u64)>,
+        pub funcs: Vec<(u64, usize, String)>,
+        pub simulated_library_funcs: HashMap<u64, String>,
+        pub linux: Option<LinuxState>,
+    }
+
+    /// FNV-1a hash of the VMAs of an image as loaded, before it runs.
+    pub fn image_hash(vmas: &[VMA]) -> u64 {
+        let mut hash = 0xcbf29ce484222325u64;
+        for vma in vmas.iter() {
+            let perms = [vma.readable as u8 | (vma.writeble as u8) << 1 | (vma.executable as u8) << 2];
+            for &byte in vma.lower_bound.to_le_bytes().iter().chain(vma.size.to_le_bytes().iter())
+                .chain(perms.iter()).chain(vma.memory.iter())
+            {
+                hash = (hash ^ byte as u64).wrapping_mul(0x100000001b3);
+            }
+        }
+        hash
+    }
+
+    impl Program {
+        /// Take an in-memory snapshot of the machine state.
+        pub fn snapshot(&self) -> Snapshot {
+            let mut registers = [0u64; 32];
+            for (idx, reg) in self.registers.registers.iter().enumerate() {
+                registers[idx] = reg.value;
+            }
+            Snapshot {
+                isa: self.isa.clone(),
+                image_hash: self.image_hash,
+                entry_point: self.entry_point,
+                program_counter: self.program_counter,
+                registers,
+                counters: self.counters.values,
+                vector: self.vector.clone(),
+                float: self.float,
+                reservation: self.reservation,
+                vmas: self.vmas.clone(),
+                breakpoints: self.breakpoints.clone(),
+                watchpoints: self.watchpoints.clone(),
+                call_stack: self.call_stack.clone(),
+                funcs: self.funcs.clone(),
+                simulated_library_funcs: self.simulated_library_funcs.clone(),
+                linux: self.linux.as_ref().map(|linux| linux.state()),
+            }
+        }
+
+        /// Restore the machine state from `snapshot`, which must have been
+        /// taken from the same image, ISA and VLEN.
+        pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), SimError> {
+            if snapshot.image_hash != self.image_hash {
+                return Err(SimError::SnapshotError(String::from("Snapshot of another image")));
+            }
+            if snapshot.isa != self.isa {
+                return Err(SimError::SnapshotError(format!("Snapshot of {}, running {}", snapshot.isa, self.isa)));
+            }
+            if snapshot.vector.vlen != self.vector.vlen {
+                return Err(SimError::SnapshotError(
+                    format!("Snapshot with VLEN {}, running with VLEN {}", snapshot.vector.vlen, self.vector.vlen)
+                ));
+            }
+            let linux = match snapshot.linux.as_ref() {
+                Some(state) => Some(Linux::from_state(state).map_err(SimError::SnapshotError)?),
+                None => None,
+            };
+
+            self.entry_point = snapshot.entry_point;
+            self.program_counter = snapshot.program_counter;
+            for (reg, &value) in self.registers.registers.iter_mut().zip(snapshot.registers.iter()) {
+                reg.write(value);
+            }
+            self.counters.values = snapshot.counters;
+            self.vector = snapshot.vector.clone();
+            self.float = snapshot.float;
+            self.reservation = snapshot.reservation;
+            self.vmas = snapshot.vmas.clone();
+            self.breakpoints = snapshot.breakpoints.clone();
+            self.watchpoints = snapshot.watchpoints.clone();
+            self.call_stack = snapshot.call_stack.clone();
+            self.funcs = snapshot.funcs.clone();
+            self.simulated_library_funcs = snapshot.simulated_library_funcs.clone();
+            self.linux = linux;
+            Ok(())
+        }
+    }
+
+    /// Little-endian serializer.
+    struct Writer {
+        data: Vec<u8>,
+    }
+
+    impl Writer {
+        fn u8(&mut self, val: u8) {
+            self.data.push(val);
+        }
+
+        fn u32(&mut self, val: u32) {
+            self.data.extend_from_slice(&val.to_le_bytes());
+        }
+
+        fn u64(&mut self, val: u64) {
+            self.data.extend_from_slice(&val.to_le_bytes());
+        }
+
+        fn bytes(&mut self, val: &[u8]) {
+            self.u64(val.len() as u64);
+            self.data.extend_from_slice(val);
+        }
+
+        fn str(&mut self, val: &str) {
+            self.bytes(val.as_bytes());
+        }
+    }
+
+    /// Little-endian deserializer.
+    struct Reader<'a> {
+        data: &'a [u8],
+        pos: usize,
+    }
+
+    impl<'a> Reader<'a> {
+        fn take(&mut self, n: usize) -> Result<&'a [u8], SimError> {
+            match self.pos.checked_add(n).and_then(|end| self.data.get(self.pos..end)) {
+                Some(bytes) => {
+                    self.pos += n;
+                    Ok(bytes)
+                },
+                None => Err(SimError::SnapshotError(String::from("Truncated snapshot"))),
+            }
+        }
+
+        fn u8(&mut self) -> Result<u8, SimError> {
+            Ok(self.take(1)?[0])
+        }
+
+        fn u32(&mut self) -> Result<u32, SimError> {
+            Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+        }
+
+        fn u64(&mut self) -> Result<u64, SimError> {
+            Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+        }
+
+        fn bytes(&mut self) -> Result<&'a [u8], SimError> {
+            let len = self.u64()? as usize;
+            self.take(len)
+        }
+
+        fn str(&mut self) -> Result<String, SimError> {
+            String::from_utf8(self.bytes()?.to_vec())
+                .map_err(|_| SimError::SnapshotError(String::from("Bad string in snapshot")))
+        }
+    }
+
+    impl Snapshot {
+        /// Serialize into the on-disk format.
+        pub fn to_bytes(&self) -> Vec<u8> {
+            let mut w = Writer { data: Vec::new() };
+            w.data.extend_from_slice(MAGIC);
+            w.u32(VERSION);
+
+            w.str(&self.isa.to_string());
+            w.u64(self.image_hash);
+            w.u64(self.entry_point);
+            w.u64(self.program_counter);
+            for &reg in self.registers.iter() {
+                w.u64(reg);
+            }
+            for &counter in self.counters.iter() {
+                w.u64(counter);
+            }
+            w.u64(self.vector.vlen as u64);
+            w.u64(self.vector.vl);
+            w.u64(self.vector.vtype);
+            w.u8(self.vector.vill as u8);
+            w.bytes(&self.vector.regs);
+            for &reg in self.float.regs.iter() {
+                w.u64(reg);
+            }
+            w.u8(self.float.fflags);
+            w.u8(self.float.frm);
+            w.u8(self.reservation.is_some() as u8);
+            w.u64(self.reservation.unwrap_or(0));
+
+            w.u32(self.vmas.len() as u32);
+            for vma in self.vmas.iter() {
+                w.u64(vma.lower_bound);
+                w.u64(vma.size);
+                w.u8(vma.readable as u8 | (vma.writeble as u8) << 1 | (vma.executable as u8) << 2);
+                w.bytes(&vma.memory);
+            }
+
+            w.u32(self.breakpoints.len() as u32);
+            for bp in self.breakpoints.iter() {
+                w.u64(bp.addr);
+                w.u8(bp.enabled as u8);
+                w.str(bp.condition.as_ref().map_or("", |(src, _)| src));
+                w.u64(bp.ignore as u64);
+                w.u64(bp.hits as u64);
+                w.u32(bp.commands.len() as u32);
+                for command in bp.commands.iter() {
+                    w.str(command);
+                }
+            }
+
+            w.u32(self.watchpoints.len() as u32);
+            for &(addr, len, kind) in self.watchpoints.iter() {
+                w.u64(addr);
+                w.u64(len as u64);
+                w.u8(match kind {
+                    Watch::Write => 0,
+                    Watch::Read => 1,
+                    Watch::Access => 2,
+                });
+            }
+
+            w.u32(self.call_stack.len() as u32);
+            for &(func, ret) in self.call_stack.iter() {
+                w.u64(func);
+                w.u64(ret);
+            }
+
+            w.u32(self.funcs.len() as u32);
+            for (start, sz, name) in self.funcs.iter() {
+                w.u64(*start);
+                w.u64(*sz as u64);
+                w.str(name);
+            }
+
+            // Sorted, so that equal snapshots serialize equally
+            let mut library_funcs: Vec<_> = self.simulated_library_funcs.iter().collect();
+            library_funcs.sort();
+            w.u32(library_funcs.len() as u32);
+            for (addr, name) in library_funcs {
+                w.u64(*addr);
+                w.str(name);
+            }
+
+            w.u8(self.linux.is_some() as u8);
+            if let Some(linux) = self.linux.as_ref() {
+                w.str(&linux.sysroot);
+                w.str(&linux.exe);
+                w.u32(linux.files.len() as u32);
+                for file in linux.files.iter() {
+                    w.u8(file.is_some() as u8);
+                    if let Some((path, offset)) = file {
+                        w.str(path);
+                        w.u64(*offset);
+                    }
+                }
+                w.u64(linux.brk_start);
+                w.u64(linux.brk);
+                w.u64(linux.mmap_base);
+                w.u64(linux.random);
+            }
+            w.data
+        }
+
+        /// Deserialize from the on-disk format.
+        pub fn from_bytes(data: &[u8]) -> Result<Snapshot, SimError> {
+            let mut r = Reader { data, pos: 0 };
+            if r.take(MAGIC.len())? != MAGIC {
+                return Err(SimError::SnapshotError(String::from("Not an rsim snapshot")));
+            }
+            let version = r.u32()?;
+            if version != VERSION {
+                return Err(SimError::SnapshotError(
+                    format!("Snapshot version {}, expected {}", version, VERSION)
+                ));
+            }
+
+            let isa = r.str()?;
+            let isa = Isa::parse(&isa).map_err(|e| SimError::SnapshotError(format!("Bad ISA {}: {}", isa, e)))?;
+            let image_hash = r.u64()?;
+            let entry_point = r.u64()?;
+            let program_counter = r.u64()?;
+            let mut registers = [0u64; 32];
+            for reg in registers.iter_mut() {
+                *reg = r.u64()?;
+            }
+            let mut counters = [0u64; 32];
+            for counter in counters.iter_mut() {
+                *counter = r.u64()?;
+            }
+            let vlen = r.u64()? as usize;
+            if !vlen.is_power_of_two() || !(32..=65536).contains(&vlen) {
+                return Err(SimError::SnapshotError(format!("Bad VLEN {}", vlen)));
+            }
+            let mut vector = VectorUnit::new(vlen);
+            vector.vl = r.u64()?;
+            vector.vtype = r.u64()?;
+            vector.vill = r.u8()? != 0;
+            let regs = r.bytes()?;
+            if regs.len() != vector.regs.len() {
+                return Err(SimError::SnapshotError(String::from("Bad vector register size")));
+            }
+            vector.regs = regs.to_vec();
+            let mut float = FloatUnit::new();
+            for reg in float.regs.iter_mut() {
+                *reg = r.u64()?;
+            }
+            float.fflags = r.u8()? & 0b11111;
+            float.frm = r.u8()? & 0b111;
+            let reservation = match (r.u8()?, r.u64()?) {
+                (0, _) => None,
+                (_, addr) => Some(addr),
+            };
+
+            let mut vmas = Vec::new();
+            for _ in 0..r.u32()? {
+                let lower_bound = r.u64()?;
+                let size = r.u64()?;
+                let flags = r.u8()?;
+                let memory = r.bytes()?.to_vec();
+                if memory.len() as u64 != size {
+                    return Err(SimError::SnapshotError(format!("Bad VMA size at {:#x}", lower_bound)));
+                }
+                vmas.push(VMA {
+                    lower_bound,
+                    size,
+                    readable: flags & 1 != 0,
+                    writeble: flags & 2 != 0,
+                    executable: flags & 4 != 0,
+                    memory,
+                });
+            }
+
+            let mut breakpoints = Vec::new();
+            for _ in 0..r.u32()? {
+                let mut bp = Breakpoint::new(r.u64()?);
+                bp.enabled = r.u8()? != 0;
+                let condition = r.str()?;
+                if !condition.is_empty() {
+                    let expr = Expr::parse(&condition)
+                        .map_err(|e| SimError::SnapshotError(format!("Bad condition {}: {}", condition, e)))?;
+                    bp.condition = Some((condition, expr));
+                }
+                bp.ignore = r.u64()? as usize;
+                bp.hits = r.u64()? as usize;
+                for _ in 0..r.u32()? {
+                    bp.commands.push(r.str()?);
+                }
+                breakpoints.push(bp);
+            }
+
+            let mut watchpoints = Vec::new();
+            for _ in 0..r.u32()? {
+                let (addr, len) = (r.u64()?, r.u64()? as usize);
+                let kind = match r.u8()? {
+                    0 => Watch::Write,
+                    1 => Watch::Read,
+                    2 => Watch::Access,
+                    kind => return Err(SimError::SnapshotError(format!("Bad watchpoint kind {}", kind))),
+                };
+                watchpoints.push((addr, len, kind));
+            }
+
+            let mut call_stack = Vec::new();
+            for _ in 0..r.u32()? {
+                call_stack.push((r.u64()?, r.u64()?));
+            }
+
+            let mut funcs = Vec::new();
+            for _ in 0..r.u32()? {
+                funcs.push((r.u64()?, r.u64()? as usize, r.str()?));
+            }
+
+            let mut simulated_library_funcs = HashMap::new();
+            for _ in 0..r.u32()? {
+                let addr = r.u64()?;
+                simulated_library_funcs.insert(addr, r.str()?);
+            }
+
+            let linux = match r.u8()? {
+                0 => None,
+                _ => {
+                    let (sysroot, exe) = (r.str()?, r.str()?);
+                    let mut files = Vec::new();
+                    for _ in 0..r.u32()? {
+                        files.push(match r.u8()? {
+                            0 => None,
+                            _ => Some((r.str()?, r.u64()?)),
+                        });
+                    }
+                    Some(LinuxState {
+                        sysroot,
+                        exe,
+                        files,
+                        brk_start: r.u64()?,
+                        brk: r.u64()?,
+                        mmap_base: r.u64()?,
+                        random: r.u64()?,
+                    })
+                },
+            };
+
+            Ok(Snapshot {
+                isa,
+                image_hash,
+                entry_point,
+                program_counter,
+                registers,
+                counters,
+                vector,
+                float,
+                reservation,
+                vmas,
+                breakpoints,
+                watchpoints,
+                call_stack,
+                funcs,
+                simulated_library_funcs,
+                linux,
+            })
+        }
+
+        /// Write the snapshot to `path`.
+        pub fn save(&self, path: &str) -> Result<(), SimError> {
+            fs::write(path, self.to_bytes()).map_err(SimError::IOError)
+        }
+
+        /// Read a snapshot from `path`.
+        pub fn load(path: &str) -> Result<Snapshot, SimError> {
+            let data = fs::read(path).map_err(SimError::IOError)?;
+            Snapshot::from_bytes(&data)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::{Loader, RegID};
+
+        /// A program with one page of code, and some state of every kind.
+        fn program() -> Program {
+            let text = VMA {
+                lower_bound: 0x1000,
+                size: 0x1000,
+                readable: true,
+                writeble: false,
+                executable: true,
+                memory: vec![0x13; 0x1000],
+            };
+            let library_funcs = HashMap::from([(0x1800, String::from("printf")), (0x1900, String::from("puts"))]);
+            let mut prog = Loader::program(0x1000, vec![text], library_funcs, Vec::new(), None);
+            prog.isa = Isa::parse("rv64gcv").unwrap();
+            prog.vector = VectorUnit::new(256);
+            prog
+        }
+
+        #[test]
+        fn round_trip() {
+            let mut prog = program();
+            prog.program_counter = 0x1234;
+            prog.registers.write(RegID::X10, 0x2a);
+            prog.vector.regs[3] = 0x5a;
+            prog.float.regs[1] = 0xffffffff3f800000;
+            prog.float.frm = 2;
+            prog.reservation = Some(0x2000);
+            let mut bp = Breakpoint::new(0x1004);
+            bp.enabled = false;
+            bp.condition = Some((String::from("a0 == 42"), Expr::parse("a0 == 42").unwrap()));
+            bp.ignore = 2;
+            bp.commands = vec![String::from("pa"), String::from("c")];
+            prog.breakpoints = vec![bp, Breakpoint::new(0x1008)];
+            prog.watchpoints = vec![(0x3ff000, 8, Watch::Access)];
+            prog.linux = Some(Linux::new("/", "a.out", 0x10000, 0x3ff0000000));
+            prog.mem_poke(Loader::STACK_BOTTOM - 8, &[1, 2, 3], true);
+
+            let bytes = prog.snapshot().to_bytes();
+            let mut other = program();
+            other.restore(&Snapshot::from_bytes(&bytes).unwrap()).unwrap();
+            assert_eq!(other.snapshot().to_bytes(), bytes);
+            assert_eq!(other.breakpoints[0].condition, prog.breakpoints[0].condition);
+            assert_eq!(other.reservation, Some(0x2000));
+            assert_eq!(other.linux.as_ref().map(Linux::state), prog.linux.as_ref().map(Linux::state));
+        }
+
+        #[test]
+        fn mismatches() {
+            let snapshot = program().snapshot();
+
+            let mut other = program();
+            other.vector = VectorUnit::new(128);
+            assert!(other.restore(&snapshot).is_err());
+            assert_eq!(other.vector.vlen, 128);
+
+            let mut other = program();
+            other.isa = Isa::parse("rv64gc").unwrap();
+            assert!(other.restore(&snapshot).is_err());
+
+            let mut other = program();
+            other.image_hash ^= 1;
+            assert!(other.restore(&snapshot).is_err());
+
+            let mut bytes = snapshot.to_bytes();
+            bytes[MAGIC.len()] += 1;
+            assert!(Snapshot::from_bytes(&bytes).is_err());
+            assert!(Snapshot::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        }
+    }
+}
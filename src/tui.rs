@@ -0,0 +1,434 @@
+#![allow(clippy::module_inception)]
+#[allow(dead_code)]
+pub mod tui {
+    //! This module implements the split-screen view of the debugger
+    //! (`--tui`). Whenever the debugger prompts for a command, the screen
+    //! is redrawn with the disassembly around the PC, the register file
+    //! with the registers changed since the last prompt highlighted, the
+    //! stack from `sp`, the pipeline stages of the last instruction, and
+    //! the output of the simulator above the command line.
+    //!
+    //! While the view is active, the standard output is redirected to a
+    //! temporary file, from which the output pane is filled. The file is
+    //! emptied whenever it has been read, and only the last `SCROLLBACK`
+    //! lines are kept for the pane. Everything printed since the last
+    //! prompt, up to that limit, is echoed to the terminal again when the
+    //! view is closed.
+
+    use std::fs::{self, File, OpenOptions};
+    use std::io::{self, Read, Seek, SeekFrom, Write};
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+    use std::path::PathBuf;
+    use ratatui::{Frame, Terminal};
+    use ratatui::backend::CrosstermBackend;
+    use ratatui::layout::{Constraint, Direction, Layout, Position, Rect};
+    use ratatui::style::{Color, Modifier, Style};
+    use ratatui::text::{Line, Span};
+    use ratatui::widgets::{Block, Borders, Paragraph};
+    use ratatui::crossterm::{execute, terminal, cursor};
+    use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+    use crate::{Program, InstCode, SimError, RegID};
+    use crate::inst::inst::Stage;
+    use crate::disasm::disasm::{self, Disasm};
+
+    const STAGES: [Stage; 5] = [Stage::Fetch, Stage::Decode, Stage::Execute, Stage::Memory, Stage::Writeback];
+    /// Lines of output that can be scrolled back to.
+    const SCROLLBACK: usize = 1000;
+
+    /// The split-screen debugger view.
+    pub struct Tui {
+        terminal: Terminal<CrosstermBackend<File>>,
+        /// The terminal, while fd 1 is redirected to `capture`
+        stdout: i32,
+        capture: PathBuf,
+        /// Reads back and empties what was written to `capture`
+        reader: File,
+        /// Captured output, by line, and the unterminated last line
+        output: Vec<String>,
+        partial: String,
+        /// Number of lines of `output` already shown
+        shown: usize,
+        /// Lines scrolled back in the output pane
+        scroll: usize,
+        /// Register values at the last prompt
+        registers: [u64; 32],
+        /// The last instruction: (pc, code, stage it left the pipeline at)
+        last: Option<(u64, InstCode, Stage)>,
+        /// Command history, oldest first
+        history: Vec<String>,
+    }
+
+    fn io_error(e: io::Error) -> SimError {
+        SimError::IOError(e)
+    }
+
+    impl Tui {
+        /// Switch the terminal to the split-screen view, and redirect the
+        /// standard output to the output pane.
+        pub fn new() -> Result<Self, SimError> {
+            let capture = std::env::temp_dir().join(format!("rsim-tui-{}.out", std::process::id()));
+            File::create(&capture).map_err(io_error)?;
+            let writer = OpenOptions::new().append(true).open(&capture).map_err(io_error)?;
+            let reader = OpenOptions::new().read(true).write(true).open(&capture).map_err(io_error)?;
+
+            io::stdout().flush().map_err(io_error)?;
+            // SAFETY: plain descriptor duplication; `stdout` stays open
+            // until it is put back on fd 1 when the view is dropped.
+            let (stdout, tty) = unsafe {
+                let stdout = libc::dup(1);
+                if stdout < 0 {
+                    return Err(io_error(io::Error::last_os_error()));
+                }
+                let tty = libc::dup(stdout);
+                if tty < 0 || libc::dup2(writer.as_raw_fd(), 1) < 0 {
+                    let e = io::Error::last_os_error();
+                    libc::close(stdout);
+                    return Err(io_error(e));
+                }
+                (stdout, File::from_raw_fd(tty))
+            };
+
+            let mut tui = Tui {
+                terminal: Terminal::new(CrosstermBackend::new(tty)).map_err(io_error)?,
+                stdout,
+                capture,
+                reader,
+                output: Vec::new(),
+                partial: String::new(),
+                shown: 0,
+                scroll: 0,
+                registers: [0; 32],
+                last: None,
+                history: Vec::new(),
+            };
+            terminal::enable_raw_mode().map_err(io_error)?;
+            execute!(tui.terminal.backend_mut(), terminal::EnterAlternateScreen).map_err(io_error)?;
+            tui.terminal.clear().map_err(io_error)?;
+            Ok(tui)
+        }
+
+        /// Record the instruction that just left the pipeline.
+        pub fn retired(&mut self, pc: u64, code: Option<InstCode>, stage: Stage) {
+            self.last = code.map(|code| (pc, code, stage));
+        }
+
+        /// Redraw the view and read one command line after `prompt`.
+        /// Returns None on ^D at an empty line.
+        pub fn read_line(&mut self, prog: &Program, prompt: &str) -> Option<String> {
+            let mut input = String::new();
+            let mut recall = self.history.len();
+            self.scroll = 0;
+            let line = loop {
+                self.collect_output();
+                let (regs, last) = (self.registers, self.last);
+                let (output, scroll) = (&self.output, self.scroll);
+                let drawn = self.terminal.draw(|frame| {
+                    draw(frame, prog, &regs, last, output, scroll, prompt, &input);
+                });
+                if drawn.is_err() {
+                    return None;
+                }
+                self.shown = self.output.len();
+
+                let key = match event::read() {
+                    Ok(Event::Key(key)) if key.kind != KeyEventKind::Release => key,
+                    Ok(_) => continue,
+                    Err(_) => return None,
+                };
+                let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+                match key.code {
+                    KeyCode::Enter => break input,
+                    KeyCode::Char('d') if ctrl && input.is_empty() => return None,
+                    KeyCode::Char('c') if ctrl => input.clear(),
+                    KeyCode::Char('l') if ctrl => { let _ = self.terminal.clear(); },
+                    KeyCode::Char('u') if ctrl => input.clear(),
+                    KeyCode::Char(_) if ctrl => {},
+                    KeyCode::Char(ch) => input.push(ch),
+                    KeyCode::Backspace => { input.pop(); },
+                    KeyCode::Up if recall > 0 => {
+                        recall -= 1;
+                        input = self.history[recall].clone();
+                    },
+                    KeyCode::Down if recall < self.history.len() => {
+                        recall += 1;
+                        input = self.history.get(recall).cloned().unwrap_or_default();
+                    },
+                    KeyCode::PageUp => self.scroll = std::cmp::min(self.scroll + 10, self.output.len()),
+                    KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(10),
+                    _ => {},
+                }
+            };
+
+            // Echo the command, as a terminal would
+            println!("{}{}", prompt, line);
+            if !line.trim().is_empty() && self.history.last() != Some(&line) {
+                self.history.push(line.clone());
+            }
+            for (idx, reg) in prog.registers.registers.iter().enumerate() {
+                self.registers[idx] = reg.value;
+            }
+            Some(line)
+        }
+
+        /// Append the output printed since the last call to `output`,
+        /// keeping the last `SCROLLBACK` lines.
+        fn collect_output(&mut self) {
+            let _ = io::stdout().flush();
+            let mut buf = Vec::new();
+            if self.reader.read_to_end(&mut buf).is_err() {
+                return;
+            }
+            // The standard output appends, so it continues at the start
+            if !buf.is_empty() && self.reader.set_len(0).is_ok() {
+                let _ = self.reader.seek(SeekFrom::Start(0));
+            }
+            self.partial.push_str(&String::from_utf8_lossy(&buf));
+            while let Some(end) = self.partial.find('\n') {
+                let line: String = self.partial.drain(..=end).collect();
+                self.output.push(expand_tabs(line.trim_end_matches(['\n', '\r'])));
+            }
+            if self.output.len() > SCROLLBACK {
+                let excess = self.output.len() - SCROLLBACK;
+                self.output.drain(..excess);
+                self.shown = self.shown.saturating_sub(excess);
+            }
+        }
+    }
+
+    impl Drop for Tui {
+        fn drop(&mut self) {
+            self.collect_output();
+            if !self.partial.is_empty() {
+                self.output.push(std::mem::take(&mut self.partial));
+            }
+            let _ = execute!(self.terminal.backend_mut(), terminal::LeaveAlternateScreen, cursor::Show);
+            let _ = terminal::disable_raw_mode();
+            // SAFETY: see `Tui::new`
+            unsafe {
+                libc::dup2(self.stdout, 1);
+                libc::close(self.stdout);
+            }
+            let _ = fs::remove_file(&self.capture);
+
+            let mut stdout = io::stdout();
+            for line in &self.output[self.shown..] {
+                let _ = writeln!(stdout, "{}", line);
+            }
+            let _ = stdout.flush();
+        }
+    }
+
+    /// Replace tabs with spaces up to the next multiple of 8 columns, and
+    /// drop other control characters but escapes.
+    fn expand_tabs(line: &str) -> String {
+        let mut text = String::new();
+        let mut col = 0;
+        for ch in line.chars() {
+            if ch == '\t' {
+                let width = 8 - col % 8;
+                text.extend(std::iter::repeat_n(' ', width));
+                col += width;
+            }
+            else if ch == '\x1b' || !ch.is_control() {
+                text.push(ch);
+                // Escape sequences take no room, but are rare before tabs
+                col += 1;
+            }
+        }
+        text
+    }
+
+    fn pane(title: &str) -> Block<'_> {
+        Block::default().borders(Borders::ALL).title(title)
+    }
+
+    /// Lay out and render all panes.
+    #[allow(clippy::too_many_arguments)]
+    fn draw(frame: &mut Frame, prog: &Program, regs: &[u64; 32], last: Option<(u64, InstCode, Stage)>,
+        output: &[String], scroll: usize, prompt: &str, input: &str)
+    {
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Percentage(65), Constraint::Percentage(35)])
+            .split(frame.area());
+        let cols = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(55), Constraint::Percentage(45)])
+            .split(rows[0]);
+        let left = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(5), Constraint::Length(7)])
+            .split(cols[0]);
+        let right = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(19), Constraint::Min(3)])
+            .split(cols[1]);
+
+        draw_disassembly(frame, left[0], prog);
+        draw_pipeline(frame, left[1], prog, last);
+        draw_registers(frame, right[0], prog, regs);
+        draw_stack(frame, right[1], prog);
+        draw_output(frame, rows[1], output, scroll, prompt, input);
+    }
+
+    /// Instructions around the PC, within its function if known.
+    fn draw_disassembly(frame: &mut Frame, area: Rect, prog: &Program) {
+        let pc = prog.program_counter;
+        let (start, end, title) = match prog.func_at(pc) {
+            Some((start, sz, name)) => (*start, *start + *sz as u64, format!(" Disassembly of <{}> ", name)),
+            None => (pc, pc.saturating_add(4 * area.height as u64), String::from(" Disassembly ")),
+        };
+
+        let insts = disasm::disassemble(prog, start, end);
+
+        // Keep the PC about a third down the pane
+        let height = area.height.saturating_sub(2) as usize;
+        let current = |inst: &Disasm| (inst.addr..inst.addr + inst.size() as u64).contains(&pc);
+        let at = insts.iter().position(current).unwrap_or(0);
+        let first = std::cmp::min(at.saturating_sub(height / 3), insts.len().saturating_sub(height));
+        let lines: Vec<Line> = insts.iter().skip(first).take(height).map(|inst| {
+            let mark = if prog.breakpoints.iter().any(|bp| bp.addr == inst.addr && bp.enabled) { "b" } else { " " };
+            let text = format!("{}{} {:x}:  {:<18} {}", mark, if current(inst) { ">" } else { " " },
+                inst.addr, inst.raw_hex(), inst.asm());
+            if current(inst) {
+                Line::styled(text, Style::default().fg(Color::Black).bg(Color::Green))
+            }
+            else if mark == "b" {
+                Line::styled(text, Style::default().fg(Color::Red))
+            }
+            else {
+                Line::raw(text)
+            }
+        }).collect();
+        frame.render_widget(Paragraph::new(lines).block(pane(&title)), area);
+    }
+
+    /// The stages the last instruction went through. The sequential
+    /// model has a single instruction in flight, which leaves the
+    /// pipeline early when a stage has nothing left to do.
+    fn draw_pipeline(frame: &mut Frame, area: Rect, prog: &Program, last: Option<(u64, InstCode, Stage)>) {
+        let lines: Vec<Line> = STAGES.iter().map(|stage| {
+            let name = format!("{:<10}", format!("{:?}", stage));
+            match last {
+                Some((pc, code, left)) if Into::<usize>::into(*stage) <= Into::<usize>::into(left) => Line::from(vec![
+                    Span::styled(name, Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(format!("{:x}:  {}", pc, asm(prog, pc, code))),
+                ]),
+                _ => Line::from(vec![
+                    Span::styled(name, Style::default().add_modifier(Modifier::DIM)),
+                    Span::styled("-", Style::default().add_modifier(Modifier::DIM)),
+                ]),
+            }
+        }).collect();
+        let title = format!(" Pipeline (next fetch at {:#x}) ", prog.program_counter);
+        frame.render_widget(Paragraph::new(lines).block(pane(&title)), area);
+    }
+
+    fn asm(prog: &Program, pc: u64, code: InstCode) -> String {
+        let (mnemonic, operands) = disasm::format(code, pc, prog);
+        format!("{:<7} {}", mnemonic, operands)
+    }
+
+    /// The register file, two registers a row.
+    fn draw_registers(frame: &mut Frame, area: Rect, prog: &Program, regs: &[u64; 32]) {
+        let changed = Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD);
+        let cell = |idx: usize| {
+            let reg = &prog.registers.registers[idx];
+            let text = format!("{:<5}{:016x}  ", reg.id.abi_name(), reg.value);
+            if reg.value != regs[idx] { Span::styled(text, changed) } else { Span::raw(text) }
+        };
+        let mut lines = vec![Line::raw(format!("{:<5}{:016x}", "pc", prog.program_counter))];
+        for row in 0..16 {
+            lines.push(Line::from(vec![cell(row), cell(row + 16)]));
+        }
+        frame.render_widget(Paragraph::new(lines).block(pane(" Registers ")), area);
+    }
+
+    /// Doublewords from `sp` up, with the frame pointer marked and code
+    /// addresses symbolized.
+    fn draw_stack(frame: &mut Frame, area: Rect, prog: &Program) {
+        let sp = prog.registers.read(RegID::X2).unwrap_or(0);
+        let fp = prog.registers.read(RegID::X8).unwrap_or(0);
+        let height = area.height.saturating_sub(2) as u64;
+        let lines: Vec<Line> = (0..height).map(|idx| {
+            let addr = sp.wrapping_add(8 * idx);
+            let mut text = match prog.mem_load(addr, 8, false) {
+                Some((data, 0)) => format!("{:x}:  {:016x}", addr, u64::from_le_bytes(data.try_into().unwrap())),
+                _ => format!("{:x}:  <unmapped>", addr),
+            };
+            if let Some((data, 0)) = prog.mem_load(addr, 8, false) {
+                let sym = disasm::symbolize(prog, u64::from_le_bytes(data.try_into().unwrap()));
+                if !sym.is_empty() {
+                    text += &format!(" {}", sym);
+                }
+            }
+            if addr == fp {
+                text += "  <- s0";
+            }
+            Line::raw(text)
+        }).collect();
+        let title = format!(" Stack (sp = {:#x}) ", sp);
+        frame.render_widget(Paragraph::new(lines).block(pane(&title)), area);
+    }
+
+    /// Output of the simulator, with the command line at the bottom.
+    fn draw_output(frame: &mut Frame, area: Rect, output: &[String], scroll: usize, prompt: &str, input: &str) {
+        let height = area.height.saturating_sub(3) as usize;
+        let end = output.len().saturating_sub(scroll);
+        let start = end.saturating_sub(height);
+        let mut lines: Vec<Line> = output[start..end].iter().map(|line| ansi_line(line)).collect();
+        while lines.len() < height {
+            lines.insert(0, Line::raw(""));
+        }
+        lines.push(Line::from(vec![
+            Span::styled(String::from(prompt), Style::default().add_modifier(Modifier::BOLD)),
+            Span::raw(String::from(input)),
+        ]));
+        let title = if scroll > 0 { format!(" Output (-{} lines) ", scroll) } else { String::from(" Output ") };
+        frame.render_widget(Paragraph::new(lines).block(pane(&title)), area);
+
+        let x = area.x + 1 + (prompt.chars().count() + input.chars().count()) as u16;
+        let y = area.y + 1 + height as u16;
+        frame.set_cursor_position(Position::new(std::cmp::min(x, area.right().saturating_sub(2)), y));
+    }
+
+    /// Convert a line with the SGR escape sequences printed by `colored`
+    /// into styled spans.
+    fn ansi_line(text: &str) -> Line<'static> {
+        let mut spans = Vec::new();
+        let mut style = Style::default();
+        let mut rest = text;
+        while let Some(start) = rest.find("\x1b[") {
+            if start > 0 {
+                spans.push(Span::styled(String::from(&rest[..start]), style));
+            }
+            let end = match rest[start..].find('m') {
+                Some(end) => start + end,
+                None => {
+                    rest = &rest[start..];
+                    break;
+                },
+            };
+            for code in rest[start + 2..end].split(';') {
+                style = match code {
+                    "" | "0" => Style::default(),
+                    "1" => style.add_modifier(Modifier::BOLD),
+                    "2" => style.add_modifier(Modifier::DIM),
+                    "31" => style.fg(Color::Red),
+                    "32" => style.fg(Color::Green),
+                    "33" => style.fg(Color::Yellow),
+                    "34" => style.fg(Color::Blue),
+                    "35" => style.fg(Color::Magenta),
+                    "36" => style.fg(Color::Cyan),
+                    "39" => style.fg(Color::Reset),
+                    _ => style,
+                };
+            }
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            spans.push(Span::styled(String::from(rest), style));
+        }
+        Line::from(spans)
+    }
+}
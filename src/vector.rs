@@ -0,0 +1,1074 @@
+#![allow(clippy::module_inception)]
+#[allow(dead_code)]
+pub mod vector {
+    //! This module implements the integer subset of the RISC-V Vector
+    //! extension 1.0 that compilers emit for loops like benchmark/mm.c:
+    //! a vector register file with a configurable VLEN, vsetvli, vsetivli
+    //! and vsetvl, unit-stride, strided and indexed loads and stores, the
+    //! whole register and mask loads and stores, integer arithmetic,
+    //! comparisons, multiply-add, reductions, mask logical operations and
+    //! moves, all with masking and LMUL register groups. ELEN is 64 with
+    //! Zve64x, 32 otherwise.
+    //!
+    //! Tail and inactive elements are always left undisturbed, which the
+    //! agnostic policies allow too. No instruction traps halfway, so vstart
+    //! stays zero and is not provided. Floating-point, fixed-point, widening,
+    //! narrowing, segment, fault-only-first, slide and gather instructions
+    //! are not simulated.
+
+    use std::fmt;
+    use crate::{InstCode, Program, RegID};
+
+    /// Default number of bits in a vector register.
+    pub const DEFAULT_VLEN: usize = 128;
+
+    /// The vector CSRs, all read-only.
+    const CSRS: [(u16, &str); 3] = [(0xc20, "vl"), (0xc21, "vtype"), (0xc22, "vlenb")];
+
+    /// The last source operand of integer arithmetic: vs1, rs1 or an
+    /// immediate. Shifts take an unsigned immediate, the rest a signed one.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum VSrc {
+        V(u8),
+        X(RegID),
+        I(i8),
+    }
+
+    impl VSrc {
+        /// One of the `.vv`, `.vx` and `.vi` forms of a mnemonic.
+        pub fn pick(&self, vv: &'static str, vx: &'static str, vi: &'static str) -> &'static str {
+            match self {
+                VSrc::V(_) => vv,
+                VSrc::X(_) => vx,
+                VSrc::I(_) => vi,
+            }
+        }
+    }
+
+    impl fmt::Display for VSrc {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                VSrc::V(vs1) => write!(f, "v{}", vs1),
+                VSrc::X(rs1) => write!(f, "{}", rs1),
+                VSrc::I(imm) => write!(f, "{}", imm),
+            }
+        }
+    }
+
+    /// Constructors of the instructions by operand types.
+    type Arith = fn(u8, u8, VSrc, bool) -> InstCode;
+    type Reduction = fn(u8, u8, u8, bool) -> InstCode;
+    type MaskLogical = fn(u8, u8, u8) -> InstCode;
+    /// Offset from rs1 of element idx of a load or store.
+    type ElementOffset = Box<dyn Fn(&VectorUnit, usize) -> u64>;
+
+    /// Integer arithmetic: (name, funct6, whether in the OPM rather than
+    /// the OPI space, forms, constructor). The forms are `v`ector, `x`
+    /// register, `i` signed and `u` unsigned immediate. vmerge and vmv
+    /// share their funct6, told apart by vm.
+    pub(crate) const ARITH: [(&str, u32, bool, &str, Arith); 35] = [
+        ("vadd", 0b000000, false, "vxi", InstCode::Vadd),
+        ("vsub", 0b000010, false, "vx", InstCode::Vsub),
+        ("vrsub", 0b000011, false, "xi", InstCode::Vrsub),
+        ("vminu", 0b000100, false, "vx", InstCode::Vminu),
+        ("vmin", 0b000101, false, "vx", InstCode::Vmin),
+        ("vmaxu", 0b000110, false, "vx", InstCode::Vmaxu),
+        ("vmax", 0b000111, false, "vx", InstCode::Vmax),
+        ("vand", 0b001001, false, "vxi", InstCode::Vand),
+        ("vor", 0b001010, false, "vxi", InstCode::Vor),
+        ("vxor", 0b001011, false, "vxi", InstCode::Vxor),
+        ("vmerge", 0b010111, false, "vxi", InstCode::Vmerge),
+        ("vmv", 0b010111, false, "vxi", InstCode::Vmv),
+        ("vmseq", 0b011000, false, "vxi", InstCode::Vmseq),
+        ("vmsne", 0b011001, false, "vxi", InstCode::Vmsne),
+        ("vmsltu", 0b011010, false, "vx", InstCode::Vmsltu),
+        ("vmslt", 0b011011, false, "vx", InstCode::Vmslt),
+        ("vmsleu", 0b011100, false, "vxi", InstCode::Vmsleu),
+        ("vmsle", 0b011101, false, "vxi", InstCode::Vmsle),
+        ("vmsgtu", 0b011110, false, "xi", InstCode::Vmsgtu),
+        ("vmsgt", 0b011111, false, "xi", InstCode::Vmsgt),
+        ("vsll", 0b100101, false, "vxu", InstCode::Vsll),
+        ("vsrl", 0b101000, false, "vxu", InstCode::Vsrl),
+        ("vsra", 0b101001, false, "vxu", InstCode::Vsra),
+        ("vdivu", 0b100000, true, "vx", InstCode::Vdivu),
+        ("vdiv", 0b100001, true, "vx", InstCode::Vdiv),
+        ("vremu", 0b100010, true, "vx", InstCode::Vremu),
+        ("vrem", 0b100011, true, "vx", InstCode::Vrem),
+        ("vmulhu", 0b100100, true, "vx", InstCode::Vmulhu),
+        ("vmul", 0b100101, true, "vx", InstCode::Vmul),
+        ("vmulhsu", 0b100110, true, "vx", InstCode::Vmulhsu),
+        ("vmulh", 0b100111, true, "vx", InstCode::Vmulh),
+        ("vmadd", 0b101001, true, "vx", InstCode::Vmadd),
+        ("vnmsub", 0b101011, true, "vx", InstCode::Vnmsub),
+        ("vmacc", 0b101101, true, "vx", InstCode::Vmacc),
+        ("vnmsac", 0b101111, true, "vx", InstCode::Vnmsac),
+    ];
+
+    /// Integer reductions `.vs`: (name, funct6, constructor), in the OPMVV space.
+    pub(crate) const REDUCTIONS: [(&str, u32, Reduction); 8] = [
+        ("vredsum", 0b000000, InstCode::Vredsum),
+        ("vredand", 0b000001, InstCode::Vredand),
+        ("vredor", 0b000010, InstCode::Vredor),
+        ("vredxor", 0b000011, InstCode::Vredxor),
+        ("vredminu", 0b000100, InstCode::Vredminu),
+        ("vredmin", 0b000101, InstCode::Vredmin),
+        ("vredmaxu", 0b000110, InstCode::Vredmaxu),
+        ("vredmax", 0b000111, InstCode::Vredmax),
+    ];
+
+    /// Mask logical operations `.mm`: (name, funct6, constructor), in the OPMVV space.
+    pub(crate) const MASK_LOGICAL: [(&str, u32, MaskLogical); 8] = [
+        ("vmandn", 0b011000, InstCode::Vmandn),
+        ("vmand", 0b011001, InstCode::Vmand),
+        ("vmor", 0b011010, InstCode::Vmor),
+        ("vmxor", 0b011011, InstCode::Vmxor),
+        ("vmorn", 0b011100, InstCode::Vmorn),
+        ("vmnand", 0b011101, InstCode::Vmnand),
+        ("vmnor", 0b011110, InstCode::Vmnor),
+        ("vmxnor", 0b011111, InstCode::Vmxnor),
+    ];
+
+    /// The width field of loads and stores for an EEW, and back.
+    fn width(eew: u8) -> u32 {
+        match eew {
+            8 => 0,
+            16 => 5,
+            32 => 6,
+            _ => 7,
+        }
+    }
+
+    fn eew(width: u32) -> Option<u8> {
+        match width {
+            0 => Some(8),
+            5 => Some(16),
+            6 => Some(32),
+            7 => Some(64),
+            _ => None,
+        }
+    }
+
+    /// Index of an EEW in the tables of mnemonics.
+    fn by_eew(eew: u8, names: [&'static str; 4]) -> &'static str {
+        names[(eew.trailing_zeros() - 3) as usize]
+    }
+
+    /// Format a vtype as in `e32,m2,ta,ma`, or as a number if reserved.
+    pub fn vtype_name(vtype: u64) -> String {
+        let lmul = match vtype & 0b111 {
+            0 => "m1",
+            1 => "m2",
+            2 => "m4",
+            3 => "m8",
+            5 => "mf8",
+            6 => "mf4",
+            7 => "mf2",
+            _ => return format!("{:#x}", vtype),
+        };
+        if vtype >> 8 != 0 || (vtype >> 3) & 0b111 > 3 {
+            return format!("{:#x}", vtype);
+        }
+        format!("e{},{},{},{}", 8 << ((vtype >> 3) & 0b111), lmul,
+            if vtype & 0x40 != 0 { "ta" } else { "tu" },
+            if vtype & 0x80 != 0 { "ma" } else { "mu" })
+    }
+
+    /// The name of vector CSR `csr`.
+    pub fn vector_csr_name(csr: u16) -> Option<&'static str> {
+        CSRS.iter().find(|(num, _)| *num == csr).map(|(_, name)| *name)
+    }
+
+    /// The number of the vector CSR named `name`.
+    pub fn vector_csr_number(name: &str) -> Option<u16> {
+        CSRS.iter().find(|(_, csr)| *csr == name).map(|(num, _)| *num)
+    }
+
+    /// Lower `sew` bits of all ones.
+    fn ones(sew: usize) -> u64 {
+        u64::MAX >> (64 - sew)
+    }
+
+    /// Sign-extend the lower `sew` bits of `val`.
+    fn sext(val: u64, sew: usize) -> i64 {
+        ((val << (64 - sew)) as i64) >> (64 - sew)
+    }
+
+    /// The vector unit of the hart: the register file and the vl and
+    /// vtype CSRs.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct VectorUnit {
+        /// Number of bits in a register
+        pub vlen: usize,
+        pub vl: u64,
+        /// vtype without the vill bit
+        pub vtype: u64,
+        pub vill: bool,
+        /// The 32 registers, in little-endian order. Register groups are
+        /// contiguous.
+        pub regs: Vec<u8>,
+    }
+
+    impl VectorUnit {
+        /// A vector unit with `vlen` bits per register, in the reset state
+        /// where vtype is illegal.
+        pub fn new(vlen: usize) -> Self {
+            VectorUnit { vlen, vl: 0, vtype: 0, vill: true, regs: vec![0; 32 * vlen / 8] }
+        }
+
+        /// Number of bytes in a register.
+        pub fn vlenb(&self) -> usize {
+            self.vlen / 8
+        }
+
+        pub fn sew(&self) -> usize {
+            8 << ((self.vtype >> 3) & 0b111)
+        }
+
+        /// LMUL in eighths, 0 if reserved.
+        pub fn lmul8(&self) -> usize {
+            match self.vtype & 0b111 {
+                vlmul @ 0..=3 => 8 << vlmul,
+                4 => 0,
+                vlmul => 1 << (vlmul - 5),
+            }
+        }
+
+        pub fn vlmax(&self) -> usize {
+            self.vlen * self.lmul8() / (8 * self.sew())
+        }
+
+        /// Element `idx` of width `eew` of the group starting at `reg`.
+        pub fn element(&self, reg: u8, idx: usize, eew: usize) -> u64 {
+            let start = reg as usize * self.vlenb() + idx * eew / 8;
+            let mut bytes = [0u8; 8];
+            bytes[..eew / 8].copy_from_slice(&self.regs[start..start + eew / 8]);
+            u64::from_le_bytes(bytes)
+        }
+
+        pub fn set_element(&mut self, reg: u8, idx: usize, eew: usize, val: u64) {
+            let start = reg as usize * self.vlenb() + idx * eew / 8;
+            self.regs[start..start + eew / 8].copy_from_slice(&val.to_le_bytes()[..eew / 8]);
+        }
+
+        /// Bit `idx` of mask register `reg`.
+        pub fn mask_bit(&self, reg: u8, idx: usize) -> bool {
+            self.regs[reg as usize * self.vlenb() + idx / 8] & (1 << (idx % 8)) != 0
+        }
+
+        pub fn set_mask_bit(&mut self, reg: u8, idx: usize, bit: bool) {
+            let start = reg as usize * self.vlenb();
+            let byte = &mut self.regs[start + idx / 8];
+            *byte = (*byte & !(1 << (idx % 8))) | ((bit as u8) << (idx % 8));
+        }
+
+        /// The value of vector CSR `csr`, or None if it is not one. The
+        /// vill bit is the MSB of vtype.
+        pub fn read_csr(&self, csr: u16, xlen: u32) -> Option<u64> {
+            match csr {
+                0xc20 => Some(self.vl),
+                0xc21 => Some(self.vtype | ((self.vill as u64) << (xlen - 1))),
+                0xc22 => Some(self.vlenb() as u64),
+                _ => None,
+            }
+        }
+
+        /// Set vtype and vl as vsetvl does, from the requested application
+        /// vector length `avl`. An illegal `vtype` sets vill and clears vl.
+        /// Returns the new vl.
+        pub fn configure(&mut self, vtype: u64, avl: u64, elen: usize) -> u64 {
+            self.vtype = vtype;
+            let (sew, lmul8) = (self.sew(), self.lmul8());
+            let legal = vtype >> 8 == 0 && (vtype >> 3) & 0b111 <= 3 && sew <= elen &&
+                lmul8 != 0 && lmul8 * elen >= 8 * sew && self.vlmax() > 0;
+            if legal {
+                self.vill = false;
+                self.vl = std::cmp::min(avl, self.vlmax() as u64);
+            }
+            else {
+                self.vtype = 0;
+                self.vill = true;
+                self.vl = 0;
+            }
+            self.vl
+        }
+
+        /// Format register `reg` as elements of SEW bits, element 0 first.
+        pub fn format_reg(&self, reg: u8) -> String {
+            let eew = if self.vill { 64 } else { self.sew() };
+            (0..self.vlen / eew)
+                .map(|idx| format!("{:0width$x}", self.element(reg, idx, eew), width = eew / 4))
+                .collect::<Vec<String>>()
+                .join(" ")
+        }
+    }
+
+    impl Default for VectorUnit {
+        fn default() -> Self {
+            VectorUnit::new(DEFAULT_VLEN)
+        }
+    }
+
+    impl fmt::Display for VectorUnit {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let vtype = if self.vill { String::from("vill") } else { vtype_name(self.vtype) };
+            writeln!(f, "vl = {}, vtype = {}, vlenb = {}", self.vl, vtype, self.vlenb())?;
+            for reg in 0..32u8 {
+                writeln!(f, "v{:<3}: {}", reg, self.format_reg(reg))?;
+            }
+            Ok(())
+        }
+    }
+
+    impl InstCode {
+        /// Parse a vector instruction, from the LOAD-FP, STORE-FP and OP-V
+        /// major opcodes. The scalar floating-point loads and stores there
+        /// are parsed by `parse_float`.
+        pub(crate) fn parse_vector(raw: u32) -> Self {
+            let vd = ((raw >> 7) & 0b11111) as u8;
+            let func3 = (raw >> 12) & 0b111;
+            let rs1 = ((raw >> 15) & 0b11111) as u8;
+            let vs2 = ((raw >> 20) & 0b11111) as u8;
+            let vm = (raw >> 25) & 1 == 1;
+            let funct6 = raw >> 26;
+            let (rd, base) = (RegID::decode(vd), RegID::decode(rs1));
+
+            if raw & 0x7f != 0x57 {
+                let store = raw & 0x7f == 0x27;
+                let nf = (raw >> 29) as u8;
+                let eew = match eew(func3) {
+                    Some(eew) if (raw >> 28) & 1 == 0 => eew,
+                    _ => return InstCode::Unknown(raw),
+                };
+                return match ((raw >> 26) & 0b11, vs2) {
+                    (0b00, 0b00000) if nf == 0 => if store { InstCode::Vse(vd, base, eew, vm) } else { InstCode::Vle(vd, base, eew, vm) },
+                    (0b00, 0b01000) if vm && matches!(nf, 0 | 1 | 3 | 7) => match store {
+                        false => InstCode::Vlre(vd, base, nf + 1, eew),
+                        true if eew == 8 => InstCode::Vsr(vd, base, nf + 1),
+                        true => InstCode::Unknown(raw),
+                    },
+                    (0b00, 0b01011) if vm && nf == 0 && eew == 8 => if store { InstCode::Vsm(vd, base) } else { InstCode::Vlm(vd, base) },
+                    (0b10, _) if nf == 0 => {
+                        let stride = RegID::decode(vs2);
+                        if store { InstCode::Vsse(vd, base, stride, eew, vm) } else { InstCode::Vlse(vd, base, stride, eew, vm) }
+                    },
+                    (0b01, _) if nf == 0 => if store { InstCode::Vsuxei(vd, base, vs2, eew, vm) } else { InstCode::Vluxei(vd, base, vs2, eew, vm) },
+                    (0b11, _) if nf == 0 => if store { InstCode::Vsoxei(vd, base, vs2, eew, vm) } else { InstCode::Vloxei(vd, base, vs2, eew, vm) },
+                    _ => InstCode::Unknown(raw),
+                };
+            }
+
+            // The simm5 or uimm5 in the rs1 field
+            let simm5 = ((rs1 << 3) as i8) >> 3;
+            let (src, opm, form) = match func3 {
+                0b000 => (VSrc::V(rs1), false, 'v'),
+                0b010 => (VSrc::V(rs1), true, 'v'),
+                0b011 => (VSrc::I(simm5), false, 'i'),
+                0b100 => (VSrc::X(base), false, 'x'),
+                0b110 => (VSrc::X(base), true, 'x'),
+                0b111 => {
+                    return match raw >> 30 {
+                        0b00 | 0b01 => InstCode::Vsetvli(rd, base, ((raw >> 20) & 0x7ff) as u16),
+                        0b11 => InstCode::Vsetivli(rd, rs1, ((raw >> 20) & 0x3ff) as u16),
+                        _ if funct6 == 0b100000 && !vm => InstCode::Vsetvl(rd, base, RegID::decode(vs2)),
+                        _ => InstCode::Unknown(raw),
+                    };
+                },
+                _ => return InstCode::Unknown(raw),
+            };
+
+            match (func3, funct6) {
+                (0b000 | 0b011 | 0b100, 0b010111) => match (vm, vs2) {
+                    (false, _) => InstCode::Vmerge(vd, vs2, src, vm),
+                    (true, 0) => InstCode::Vmv(vd, 0, src, vm),
+                    _ => InstCode::Unknown(raw),
+                },
+                (0b011, 0b100111) if vm && matches!(rs1, 0 | 1 | 3 | 7) => InstCode::VmvNr(vd, vs2, rs1 + 1),
+                (0b010, 0b000000..=0b000111) => match REDUCTIONS.iter().find(|(_, func, _)| *func == funct6) {
+                    Some((_, _, op)) => op(vd, vs2, rs1, vm),
+                    None => InstCode::Unknown(raw),
+                },
+                (0b010, 0b010000) => match rs1 {
+                    0b00000 if vm => InstCode::VmvXS(rd, vs2),
+                    0b10000 => InstCode::Vcpop(rd, vs2, vm),
+                    0b10001 => InstCode::Vfirst(rd, vs2, vm),
+                    _ => InstCode::Unknown(raw),
+                },
+                (0b010, 0b010100) if rs1 == 0b10001 && vs2 == 0 => InstCode::Vid(vd, vm),
+                (0b010, 0b011000..=0b011111) if vm => match MASK_LOGICAL.iter().find(|(_, func, _)| *func == funct6) {
+                    Some((_, _, op)) => op(vd, vs2, rs1),
+                    None => InstCode::Unknown(raw),
+                },
+                (0b110, 0b010000) if vm && vs2 == 0 => InstCode::VmvSX(vd, base),
+                _ => {
+                    let found = ARITH.iter().find(|(_, func, space, forms, _)| {
+                        *func == funct6 && *space == opm && funct6 != 0b010111 &&
+                        (forms.contains(form) || (form == 'i' && forms.contains('u')))
+                    });
+                    match found {
+                        Some((_, _, _, forms, op)) if forms.contains('u') && form == 'i' => op(vd, vs2, VSrc::I(rs1 as i8), vm),
+                        Some((_, _, _, _, op)) => op(vd, vs2, src, vm),
+                        None => InstCode::Unknown(raw),
+                    }
+                },
+            }
+        }
+
+        /// Encode a vector instruction, see `encode`.
+        pub(crate) fn encode_vector(&self) -> u32 {
+            let reg = |reg: RegID| reg.encode() as u32;
+            let op = |funct6: u32, vm: bool, vs2: u8, src: u32, func3: u32, vd: u8| {
+                (funct6 << 26) | ((vm as u32) << 25) | ((vs2 as u32) << 20) | (src << 15) |
+                (func3 << 12) | ((vd as u32) << 7) | 0x57
+            };
+            let mem = |nf: u8, mop: u32, vm: bool, field: u32, base: RegID, eew: u8, vd: u8, store: bool| {
+                (((nf as u32) - 1) << 29) | (mop << 26) | ((vm as u32) << 25) | (field << 20) | (reg(base) << 15) |
+                (width(eew) << 12) | ((vd as u32) << 7) | if store { 0x27 } else { 0x07 }
+            };
+
+            if let Some((vd, vs2, src, vm)) = self.varith() {
+                let name = self.mnemonic().split('.').next().unwrap();
+                let (_, funct6, opm, _, _) = ARITH.iter().find(|(op, _, _, _, _)| *op == name).unwrap();
+                let (func3, src) = match src {
+                    VSrc::V(vs1) => (if *opm { 0b010 } else { 0b000 }, vs1 as u32),
+                    VSrc::X(rs1) => (if *opm { 0b110 } else { 0b100 }, reg(rs1)),
+                    VSrc::I(imm) => (0b011, (imm as u32) & 0b11111),
+                };
+                return op(*funct6, vm, vs2, src, func3, vd);
+            }
+            if let Some((vd, vs2, vs1, vm)) = self.vreduction() {
+                let (_, funct6, _) = REDUCTIONS.iter().find(|(op, _, _)| *op == self.mnemonic().trim_end_matches(".vs")).unwrap();
+                return op(*funct6, vm, vs2, vs1 as u32, 0b010, vd);
+            }
+            if let Some((vd, vs2, vs1)) = self.vmask_logical() {
+                let (_, funct6, _) = MASK_LOGICAL.iter().find(|(op, _, _)| *op == self.mnemonic().trim_end_matches(".mm")).unwrap();
+                return op(*funct6, true, vs2, vs1 as u32, 0b010, vd);
+            }
+
+            match *self {
+                InstCode::Vsetvli(rd, rs1, vtypei) => (((vtypei as u32) & 0x7ff) << 20) | (reg(rs1) << 15) | (0b111 << 12) | (reg(rd) << 7) | 0x57,
+                InstCode::Vsetivli(rd, uimm, vtypei) => (0b11 << 30) | (((vtypei as u32) & 0x3ff) << 20) | (((uimm as u32) & 0b11111) << 15) |
+                    (0b111 << 12) | (reg(rd) << 7) | 0x57,
+                InstCode::Vsetvl(rd, rs1, rs2) => (0b1000000 << 25) | (reg(rs2) << 20) | (reg(rs1) << 15) | (0b111 << 12) | (reg(rd) << 7) | 0x57,
+                InstCode::Vle(vd, rs1, eew, vm) => mem(1, 0b00, vm, 0b00000, rs1, eew, vd, false),
+                InstCode::Vse(vs3, rs1, eew, vm) => mem(1, 0b00, vm, 0b00000, rs1, eew, vs3, true),
+                InstCode::Vlse(vd, rs1, rs2, eew, vm) => mem(1, 0b10, vm, reg(rs2), rs1, eew, vd, false),
+                InstCode::Vsse(vs3, rs1, rs2, eew, vm) => mem(1, 0b10, vm, reg(rs2), rs1, eew, vs3, true),
+                InstCode::Vluxei(vd, rs1, vs2, eew, vm) => mem(1, 0b01, vm, vs2 as u32, rs1, eew, vd, false),
+                InstCode::Vloxei(vd, rs1, vs2, eew, vm) => mem(1, 0b11, vm, vs2 as u32, rs1, eew, vd, false),
+                InstCode::Vsuxei(vs3, rs1, vs2, eew, vm) => mem(1, 0b01, vm, vs2 as u32, rs1, eew, vs3, true),
+                InstCode::Vsoxei(vs3, rs1, vs2, eew, vm) => mem(1, 0b11, vm, vs2 as u32, rs1, eew, vs3, true),
+                InstCode::Vlm(vd, rs1) => mem(1, 0b00, true, 0b01011, rs1, 8, vd, false),
+                InstCode::Vsm(vs3, rs1) => mem(1, 0b00, true, 0b01011, rs1, 8, vs3, true),
+                InstCode::Vlre(vd, rs1, nf, eew) => mem(nf, 0b00, true, 0b01000, rs1, eew, vd, false),
+                InstCode::Vsr(vs3, rs1, nf) => mem(nf, 0b00, true, 0b01000, rs1, 8, vs3, true),
+                InstCode::VmvNr(vd, vs2, nr) => op(0b100111, true, vs2, (nr - 1) as u32, 0b011, vd),
+                InstCode::VmvXS(rd, vs2) => op(0b010000, true, vs2, 0b00000, 0b010, rd.encode()),
+                InstCode::Vcpop(rd, vs2, vm) => op(0b010000, vm, vs2, 0b10000, 0b010, rd.encode()),
+                InstCode::Vfirst(rd, vs2, vm) => op(0b010000, vm, vs2, 0b10001, 0b010, rd.encode()),
+                InstCode::Vid(vd, vm) => op(0b010100, vm, 0, 0b10001, 0b010, vd),
+                InstCode::VmvSX(vd, rs1) => op(0b010000, true, 0, reg(rs1), 0b110, vd),
+                _ => unreachable!(),
+            }
+        }
+
+        /// The mnemonic of a vector instruction, see `mnemonic`.
+        pub(crate) fn vector_mnemonic(&self) -> &'static str {
+            match *self {
+                InstCode::Vsetvli(_,_,_) => "vsetvli",
+                InstCode::Vsetivli(_,_,_) => "vsetivli",
+                InstCode::Vsetvl(_,_,_) => "vsetvl",
+                InstCode::Vle(_,_,eew,_) => by_eew(eew, ["vle8.v", "vle16.v", "vle32.v", "vle64.v"]),
+                InstCode::Vse(_,_,eew,_) => by_eew(eew, ["vse8.v", "vse16.v", "vse32.v", "vse64.v"]),
+                InstCode::Vlse(_,_,_,eew,_) => by_eew(eew, ["vlse8.v", "vlse16.v", "vlse32.v", "vlse64.v"]),
+                InstCode::Vsse(_,_,_,eew,_) => by_eew(eew, ["vsse8.v", "vsse16.v", "vsse32.v", "vsse64.v"]),
+                InstCode::Vluxei(_,_,_,eew,_) => by_eew(eew, ["vluxei8.v", "vluxei16.v", "vluxei32.v", "vluxei64.v"]),
+                InstCode::Vloxei(_,_,_,eew,_) => by_eew(eew, ["vloxei8.v", "vloxei16.v", "vloxei32.v", "vloxei64.v"]),
+                InstCode::Vsuxei(_,_,_,eew,_) => by_eew(eew, ["vsuxei8.v", "vsuxei16.v", "vsuxei32.v", "vsuxei64.v"]),
+                InstCode::Vsoxei(_,_,_,eew,_) => by_eew(eew, ["vsoxei8.v", "vsoxei16.v", "vsoxei32.v", "vsoxei64.v"]),
+                InstCode::Vlm(_,_) => "vlm.v",
+                InstCode::Vsm(_,_) => "vsm.v",
+                InstCode::Vlre(_,_,nf,eew) => match nf {
+                    1 => by_eew(eew, ["vl1re8.v", "vl1re16.v", "vl1re32.v", "vl1re64.v"]),
+                    2 => by_eew(eew, ["vl2re8.v", "vl2re16.v", "vl2re32.v", "vl2re64.v"]),
+                    4 => by_eew(eew, ["vl4re8.v", "vl4re16.v", "vl4re32.v", "vl4re64.v"]),
+                    _ => by_eew(eew, ["vl8re8.v", "vl8re16.v", "vl8re32.v", "vl8re64.v"]),
+                },
+                InstCode::Vsr(_,_,nf) => ["vs1r.v", "vs2r.v", "vs4r.v", "vs8r.v"][nf.trailing_zeros() as usize],
+                InstCode::Vadd(_,_,src,_) => src.pick("vadd.vv", "vadd.vx", "vadd.vi"),
+                InstCode::Vsub(_,_,src,_) => src.pick("vsub.vv", "vsub.vx", "vsub.vi"),
+                InstCode::Vrsub(_,_,src,_) => src.pick("vrsub.vv", "vrsub.vx", "vrsub.vi"),
+                InstCode::Vminu(_,_,src,_) => src.pick("vminu.vv", "vminu.vx", "vminu.vi"),
+                InstCode::Vmin(_,_,src,_) => src.pick("vmin.vv", "vmin.vx", "vmin.vi"),
+                InstCode::Vmaxu(_,_,src,_) => src.pick("vmaxu.vv", "vmaxu.vx", "vmaxu.vi"),
+                InstCode::Vmax(_,_,src,_) => src.pick("vmax.vv", "vmax.vx", "vmax.vi"),
+                InstCode::Vand(_,_,src,_) => src.pick("vand.vv", "vand.vx", "vand.vi"),
+                InstCode::Vor(_,_,src,_) => src.pick("vor.vv", "vor.vx", "vor.vi"),
+                InstCode::Vxor(_,_,src,_) => src.pick("vxor.vv", "vxor.vx", "vxor.vi"),
+                InstCode::Vsll(_,_,src,_) => src.pick("vsll.vv", "vsll.vx", "vsll.vi"),
+                InstCode::Vsrl(_,_,src,_) => src.pick("vsrl.vv", "vsrl.vx", "vsrl.vi"),
+                InstCode::Vsra(_,_,src,_) => src.pick("vsra.vv", "vsra.vx", "vsra.vi"),
+                InstCode::Vmseq(_,_,src,_) => src.pick("vmseq.vv", "vmseq.vx", "vmseq.vi"),
+                InstCode::Vmsne(_,_,src,_) => src.pick("vmsne.vv", "vmsne.vx", "vmsne.vi"),
+                InstCode::Vmsltu(_,_,src,_) => src.pick("vmsltu.vv", "vmsltu.vx", "vmsltu.vi"),
+                InstCode::Vmslt(_,_,src,_) => src.pick("vmslt.vv", "vmslt.vx", "vmslt.vi"),
+                InstCode::Vmsleu(_,_,src,_) => src.pick("vmsleu.vv", "vmsleu.vx", "vmsleu.vi"),
+                InstCode::Vmsle(_,_,src,_) => src.pick("vmsle.vv", "vmsle.vx", "vmsle.vi"),
+                InstCode::Vmsgtu(_,_,src,_) => src.pick("vmsgtu.vv", "vmsgtu.vx", "vmsgtu.vi"),
+                InstCode::Vmsgt(_,_,src,_) => src.pick("vmsgt.vv", "vmsgt.vx", "vmsgt.vi"),
+                InstCode::Vmerge(_,_,src,_) => src.pick("vmerge.vvm", "vmerge.vxm", "vmerge.vim"),
+                InstCode::Vmv(_,_,src,_) => src.pick("vmv.v.v", "vmv.v.x", "vmv.v.i"),
+                InstCode::Vmul(_,_,src,_) => src.pick("vmul.vv", "vmul.vx", "vmul.vi"),
+                InstCode::Vmulh(_,_,src,_) => src.pick("vmulh.vv", "vmulh.vx", "vmulh.vi"),
+                InstCode::Vmulhu(_,_,src,_) => src.pick("vmulhu.vv", "vmulhu.vx", "vmulhu.vi"),
+                InstCode::Vmulhsu(_,_,src,_) => src.pick("vmulhsu.vv", "vmulhsu.vx", "vmulhsu.vi"),
+                InstCode::Vdivu(_,_,src,_) => src.pick("vdivu.vv", "vdivu.vx", "vdivu.vi"),
+                InstCode::Vdiv(_,_,src,_) => src.pick("vdiv.vv", "vdiv.vx", "vdiv.vi"),
+                InstCode::Vremu(_,_,src,_) => src.pick("vremu.vv", "vremu.vx", "vremu.vi"),
+                InstCode::Vrem(_,_,src,_) => src.pick("vrem.vv", "vrem.vx", "vrem.vi"),
+                InstCode::Vmacc(_,_,src,_) => src.pick("vmacc.vv", "vmacc.vx", "vmacc.vi"),
+                InstCode::Vnmsac(_,_,src,_) => src.pick("vnmsac.vv", "vnmsac.vx", "vnmsac.vi"),
+                InstCode::Vmadd(_,_,src,_) => src.pick("vmadd.vv", "vmadd.vx", "vmadd.vi"),
+                InstCode::Vnmsub(_,_,src,_) => src.pick("vnmsub.vv", "vnmsub.vx", "vnmsub.vi"),
+                InstCode::VmvNr(_,_,nr) => ["vmv1r.v", "vmv2r.v", "vmv4r.v", "vmv8r.v"][nr.trailing_zeros() as usize],
+                InstCode::Vredsum(_,_,_,_) => "vredsum.vs",
+                InstCode::Vredand(_,_,_,_) => "vredand.vs",
+                InstCode::Vredor(_,_,_,_) => "vredor.vs",
+                InstCode::Vredxor(_,_,_,_) => "vredxor.vs",
+                InstCode::Vredminu(_,_,_,_) => "vredminu.vs",
+                InstCode::Vredmin(_,_,_,_) => "vredmin.vs",
+                InstCode::Vredmaxu(_,_,_,_) => "vredmaxu.vs",
+                InstCode::Vredmax(_,_,_,_) => "vredmax.vs",
+                InstCode::Vmandn(_,_,_) => "vmandn.mm",
+                InstCode::Vmand(_,_,_) => "vmand.mm",
+                InstCode::Vmor(_,_,_) => "vmor.mm",
+                InstCode::Vmxor(_,_,_) => "vmxor.mm",
+                InstCode::Vmorn(_,_,_) => "vmorn.mm",
+                InstCode::Vmnand(_,_,_) => "vmnand.mm",
+                InstCode::Vmnor(_,_,_) => "vmnor.mm",
+                InstCode::Vmxnor(_,_,_) => "vmxnor.mm",
+                InstCode::VmvXS(_,_) => "vmv.x.s",
+                InstCode::VmvSX(_,_) => "vmv.s.x",
+                InstCode::Vcpop(_,_,_) => "vcpop.m",
+                InstCode::Vfirst(_,_,_) => "vfirst.m",
+                InstCode::Vid(_,_) => "vid.v",
+                _ => unreachable!(),
+            }
+        }
+
+        /// Whether the instruction is a vector instruction.
+        pub fn is_vector(&self) -> bool {
+            self.varith().is_some() || self.vreduction().is_some() || self.vmask_logical().is_some() ||
+            self.is_vector_load() || self.is_vector_store() || matches!(self,
+                InstCode::Vsetvli(_,_,_) | InstCode::Vsetivli(_,_,_) | InstCode::Vsetvl(_,_,_) |
+                InstCode::VmvNr(_,_,_) | InstCode::VmvXS(_,_) | InstCode::VmvSX(_,_) |
+                InstCode::Vcpop(_,_,_) | InstCode::Vfirst(_,_,_) | InstCode::Vid(_,_))
+        }
+
+        pub fn is_vector_load(&self) -> bool {
+            matches!(self,
+                InstCode::Vle(_,_,_,_) | InstCode::Vlse(_,_,_,_,_) | InstCode::Vluxei(_,_,_,_,_) |
+                InstCode::Vloxei(_,_,_,_,_) | InstCode::Vlm(_,_) | InstCode::Vlre(_,_,_,_))
+        }
+
+        pub fn is_vector_store(&self) -> bool {
+            matches!(self,
+                InstCode::Vse(_,_,_,_) | InstCode::Vsse(_,_,_,_,_) | InstCode::Vsuxei(_,_,_,_,_) |
+                InstCode::Vsoxei(_,_,_,_,_) | InstCode::Vsm(_,_) | InstCode::Vsr(_,_,_))
+        }
+
+        /// The scalar registers read by a vector instruction, as (rs1, rs2).
+        pub fn vector_sources(&self) -> (Option<RegID>, Option<RegID>) {
+            match *self {
+                InstCode::Vsetvli(_, rs1, _) => (Some(rs1), None),
+                InstCode::Vsetvl(_, rs1, rs2) => (Some(rs1), Some(rs2)),
+                InstCode::Vle(_, rs1, _, _) | InstCode::Vse(_, rs1, _, _) |
+                InstCode::Vluxei(_, rs1, _, _, _) | InstCode::Vloxei(_, rs1, _, _, _) |
+                InstCode::Vsuxei(_, rs1, _, _, _) | InstCode::Vsoxei(_, rs1, _, _, _) |
+                InstCode::Vlm(_, rs1) | InstCode::Vsm(_, rs1) |
+                InstCode::Vlre(_, rs1, _, _) | InstCode::Vsr(_, rs1, _) |
+                InstCode::VmvSX(_, rs1) => (Some(rs1), None),
+                InstCode::Vlse(_, rs1, rs2, _, _) | InstCode::Vsse(_, rs1, rs2, _, _) => (Some(rs1), Some(rs2)),
+                _ => match self.varith() {
+                    Some((_, _, VSrc::X(rs1), _)) => (Some(rs1), None),
+                    _ => (None, None),
+                },
+            }
+        }
+
+        /// The scalar destination of a vector instruction.
+        pub fn vector_rd(&self) -> Option<RegID> {
+            match *self {
+                InstCode::Vsetvli(rd,_,_) | InstCode::Vsetivli(rd,_,_) | InstCode::Vsetvl(rd,_,_) |
+                InstCode::VmvXS(rd,_) | InstCode::Vcpop(rd,_,_) | InstCode::Vfirst(rd,_,_) => Some(rd),
+                _ => None,
+            }
+        }
+
+        /// The operands of integer arithmetic: (vd, vs2, vs1/rs1/imm, vm).
+        pub(crate) fn varith(&self) -> Option<(u8, u8, VSrc, bool)> {
+            match *self {
+                InstCode::Vadd(vd, vs2, src, vm) | InstCode::Vsub(vd, vs2, src, vm) | InstCode::Vrsub(vd, vs2, src, vm) |
+                InstCode::Vminu(vd, vs2, src, vm) | InstCode::Vmin(vd, vs2, src, vm) | InstCode::Vmaxu(vd, vs2, src, vm) |
+                InstCode::Vmax(vd, vs2, src, vm) | InstCode::Vand(vd, vs2, src, vm) | InstCode::Vor(vd, vs2, src, vm) |
+                InstCode::Vxor(vd, vs2, src, vm) | InstCode::Vsll(vd, vs2, src, vm) | InstCode::Vsrl(vd, vs2, src, vm) |
+                InstCode::Vsra(vd, vs2, src, vm) | InstCode::Vmseq(vd, vs2, src, vm) | InstCode::Vmsne(vd, vs2, src, vm) |
+                InstCode::Vmsltu(vd, vs2, src, vm) | InstCode::Vmslt(vd, vs2, src, vm) | InstCode::Vmsleu(vd, vs2, src, vm) |
+                InstCode::Vmsle(vd, vs2, src, vm) | InstCode::Vmsgtu(vd, vs2, src, vm) | InstCode::Vmsgt(vd, vs2, src, vm) |
+                InstCode::Vmerge(vd, vs2, src, vm) | InstCode::Vmv(vd, vs2, src, vm) | InstCode::Vmul(vd, vs2, src, vm) |
+                InstCode::Vmulh(vd, vs2, src, vm) | InstCode::Vmulhu(vd, vs2, src, vm) | InstCode::Vmulhsu(vd, vs2, src, vm) |
+                InstCode::Vdivu(vd, vs2, src, vm) | InstCode::Vdiv(vd, vs2, src, vm) | InstCode::Vremu(vd, vs2, src, vm) |
+                InstCode::Vrem(vd, vs2, src, vm) | InstCode::Vmacc(vd, vs2, src, vm) | InstCode::Vnmsac(vd, vs2, src, vm) |
+                InstCode::Vmadd(vd, vs2, src, vm) | InstCode::Vnmsub(vd, vs2, src, vm) => Some((vd, vs2, src, vm)),
+                _ => None,
+            }
+        }
+
+        /// The operands of a reduction: (vd, vs2, vs1, vm).
+        pub(crate) fn vreduction(&self) -> Option<(u8, u8, u8, bool)> {
+            match *self {
+                InstCode::Vredsum(vd, vs2, vs1, vm) | InstCode::Vredand(vd, vs2, vs1, vm) |
+                InstCode::Vredor(vd, vs2, vs1, vm) | InstCode::Vredxor(vd, vs2, vs1, vm) |
+                InstCode::Vredminu(vd, vs2, vs1, vm) | InstCode::Vredmin(vd, vs2, vs1, vm) |
+                InstCode::Vredmaxu(vd, vs2, vs1, vm) | InstCode::Vredmax(vd, vs2, vs1, vm) => Some((vd, vs2, vs1, vm)),
+                _ => None,
+            }
+        }
+
+        /// The operands of a mask logical operation: (vd, vs2, vs1).
+        pub(crate) fn vmask_logical(&self) -> Option<(u8, u8, u8)> {
+            match *self {
+                InstCode::Vmandn(vd, vs2, vs1) | InstCode::Vmand(vd, vs2, vs1) | InstCode::Vmor(vd, vs2, vs1) |
+                InstCode::Vmxor(vd, vs2, vs1) | InstCode::Vmorn(vd, vs2, vs1) | InstCode::Vmnand(vd, vs2, vs1) |
+                InstCode::Vmnor(vd, vs2, vs1) | InstCode::Vmxnor(vd, vs2, vs1) => Some((vd, vs2, vs1)),
+                _ => None,
+            }
+        }
+
+        /// Whether the destination of integer arithmetic is a mask.
+        fn is_compare(&self) -> bool {
+            matches!(self,
+                InstCode::Vmseq(_,_,_,_) | InstCode::Vmsne(_,_,_,_) | InstCode::Vmsltu(_,_,_,_) |
+                InstCode::Vmslt(_,_,_,_) | InstCode::Vmsleu(_,_,_,_) | InstCode::Vmsle(_,_,_,_) |
+                InstCode::Vmsgtu(_,_,_,_) | InstCode::Vmsgt(_,_,_,_))
+        }
+    }
+
+    /// One element of integer arithmetic of SEW bits, from `a` of vs2,
+    /// `b` of vs1, rs1 or the immediate, and `d` of vd. Compares give 0
+    /// or 1.
+    fn arith(code: InstCode, sew: usize, a: u64, b: u64, d: u64) -> u64 {
+        let (sa, sb) = (sext(a, sew), sext(b, sew));
+        let shamt = (b & (sew as u64 - 1)) as u32;
+        let val = match code {
+            InstCode::Vadd(_,_,_,_) => a.wrapping_add(b),
+            InstCode::Vsub(_,_,_,_) => a.wrapping_sub(b),
+            InstCode::Vrsub(_,_,_,_) => b.wrapping_sub(a),
+            InstCode::Vminu(_,_,_,_) => std::cmp::min(a, b),
+            InstCode::Vmin(_,_,_,_) => std::cmp::min(sa, sb) as u64,
+            InstCode::Vmaxu(_,_,_,_) => std::cmp::max(a, b),
+            InstCode::Vmax(_,_,_,_) => std::cmp::max(sa, sb) as u64,
+            InstCode::Vand(_,_,_,_) => a & b,
+            InstCode::Vor(_,_,_,_) => a | b,
+            InstCode::Vxor(_,_,_,_) => a ^ b,
+            InstCode::Vsll(_,_,_,_) => a << shamt,
+            InstCode::Vsrl(_,_,_,_) => a >> shamt,
+            InstCode::Vsra(_,_,_,_) => (sa >> shamt) as u64,
+            InstCode::Vmseq(_,_,_,_) => (a == b) as u64,
+            InstCode::Vmsne(_,_,_,_) => (a != b) as u64,
+            InstCode::Vmsltu(_,_,_,_) => (a < b) as u64,
+            InstCode::Vmslt(_,_,_,_) => (sa < sb) as u64,
+            InstCode::Vmsleu(_,_,_,_) => (a <= b) as u64,
+            InstCode::Vmsle(_,_,_,_) => (sa <= sb) as u64,
+            InstCode::Vmsgtu(_,_,_,_) => (a > b) as u64,
+            InstCode::Vmsgt(_,_,_,_) => (sa > sb) as u64,
+            InstCode::Vmv(_,_,_,_) => b,
+            InstCode::Vmul(_,_,_,_) => a.wrapping_mul(b),
+            InstCode::Vmulh(_,_,_,_) => ((sa as i128 * sb as i128) >> sew) as u64,
+            InstCode::Vmulhu(_,_,_,_) => ((a as u128 * b as u128) >> sew) as u64,
+            InstCode::Vmulhsu(_,_,_,_) => ((sa as i128 * b as i128) >> sew) as u64,
+            InstCode::Vdivu(_,_,_,_) => a.checked_div(b).unwrap_or(u64::MAX),
+            InstCode::Vdiv(_,_,_,_) => if sb == 0 { u64::MAX } else { sa.wrapping_div(sb) as u64 },
+            InstCode::Vremu(_,_,_,_) => if b == 0 { a } else { a % b },
+            InstCode::Vrem(_,_,_,_) => if sb == 0 { a } else { sa.wrapping_rem(sb) as u64 },
+            InstCode::Vmacc(_,_,_,_) => b.wrapping_mul(a).wrapping_add(d),
+            InstCode::Vnmsac(_,_,_,_) => d.wrapping_sub(b.wrapping_mul(a)),
+            InstCode::Vmadd(_,_,_,_) => b.wrapping_mul(d).wrapping_add(a),
+            InstCode::Vnmsub(_,_,_,_) => a.wrapping_sub(b.wrapping_mul(d)),
+            _ => unreachable!(),
+        };
+        val & ones(sew)
+    }
+
+    /// Fold one element of SEW bits into the accumulator of a reduction.
+    fn reduce(code: InstCode, sew: usize, acc: u64, val: u64) -> u64 {
+        let (sacc, sval) = (sext(acc, sew), sext(val, sew));
+        let acc = match code {
+            InstCode::Vredsum(_,_,_,_) => acc.wrapping_add(val),
+            InstCode::Vredand(_,_,_,_) => acc & val,
+            InstCode::Vredor(_,_,_,_) => acc | val,
+            InstCode::Vredxor(_,_,_,_) => acc ^ val,
+            InstCode::Vredminu(_,_,_,_) => std::cmp::min(acc, val),
+            InstCode::Vredmin(_,_,_,_) => std::cmp::min(sacc, sval) as u64,
+            InstCode::Vredmaxu(_,_,_,_) => std::cmp::max(acc, val),
+            InstCode::Vredmax(_,_,_,_) => std::cmp::max(sacc, sval) as u64,
+            _ => unreachable!(),
+        };
+        acc & ones(sew)
+    }
+
+    fn mask_logical(code: InstCode, a: bool, b: bool) -> bool {
+        match code {
+            InstCode::Vmandn(_,_,_) => a & !b,
+            InstCode::Vmand(_,_,_) => a & b,
+            InstCode::Vmor(_,_,_) => a | b,
+            InstCode::Vmxor(_,_,_) => a ^ b,
+            InstCode::Vmorn(_,_,_) => a | !b,
+            InstCode::Vmnand(_,_,_) => !(a & b),
+            InstCode::Vmnor(_,_,_) => !(a | b),
+            InstCode::Vmxnor(_,_,_) => !(a ^ b),
+            _ => unreachable!(),
+        }
+    }
+
+    impl Program {
+        /// Execute a vector instruction at `pc` other than a load or store,
+        /// with `x1` and `x2` the values of its scalar sources. Returns the
+        /// value of its scalar destination, if any, or the reason it is
+        /// illegal.
+        pub fn vector_execute(&mut self, code: InstCode, pc: u64, x1: u64, x2: u64) -> Result<u64, String> {
+            let illegal = |reason: String| format!("Illegal instruction {} at {:x}, {}", code.mnemonic(), pc, reason);
+            let xmask = if self.isa.xlen == 32 { 0xFFFFFFFF } else { u64::MAX };
+            let elen = self.isa.elen();
+
+            // The configuration instructions set vtype, even from vill
+            let config = match code {
+                InstCode::Vsetvli(rd, rs1, vtypei) => Some((rd, Some(rs1), vtypei as u64)),
+                InstCode::Vsetivli(rd, _, vtypei) => Some((rd, None, vtypei as u64)),
+                InstCode::Vsetvl(rd, rs1, _) => Some((rd, Some(rs1), x2 & xmask)),
+                _ => None,
+            };
+            if let Some((rd, rs1, vtype)) = config {
+                let avl = match (code, rs1) {
+                    (InstCode::Vsetivli(_, uimm, _), _) => uimm as u64,
+                    (_, Some(rs1)) if rs1 != RegID::X0 => x1 & xmask,
+                    // Keep vl with both x0, else set it to VLMAX
+                    _ if rd == RegID::X0 => self.vector.vl,
+                    _ => u64::MAX,
+                };
+                return Ok(self.vector.configure(vtype, avl, elen));
+            }
+
+            if let InstCode::VmvNr(vd, vs2, nr) = code {
+                if vd % nr != 0 || vs2 % nr != 0 {
+                    return Err(illegal(format!("registers must be aligned to {}", nr)));
+                }
+                let (vlenb, nr) = (self.vector.vlenb(), nr as usize);
+                self.record_vregs(vd, nr);
+                let src = vs2 as usize * vlenb;
+                self.vector.regs.copy_within(src..src + nr * vlenb, vd as usize * vlenb);
+                return Ok(0);
+            }
+            // Whole register loads and stores do not depend on vtype
+            if let InstCode::Vlre(_,_,_,_) | InstCode::Vsr(_,_,_) = code {
+                return Ok(0);
+            }
+
+            if self.vector.vill {
+                return Err(illegal(String::from("vtype is illegal")));
+            }
+            let (sew, vl) = (self.vector.sew(), self.vector.vl as usize);
+            let group = std::cmp::max(1, self.vector.lmul8() / 8) as u8;
+            let aligned = |regs: &[u8]| regs.iter().all(|reg| reg % group == 0);
+            let active = |vector: &VectorUnit, vm: bool, idx: usize| vm || vector.mask_bit(0, idx);
+
+            if let Some((vd, vs2, src, vm)) = code.varith() {
+                let vs1 = match src {
+                    VSrc::V(vs1) => vs1,
+                    _ => 0,
+                };
+                let compare = code.is_compare();
+                if !aligned(&[vs2, vs1]) || (!compare && !aligned(&[vd])) {
+                    return Err(illegal(format!("register groups must be aligned to LMUL={}", group)));
+                }
+                if !vm && vd == 0 && !compare {
+                    return Err(illegal(String::from("v0 is both the mask and the destination")));
+                }
+                let scalar = match src {
+                    VSrc::V(_) => 0,
+                    VSrc::X(_) => x1 & ones(sew),
+                    VSrc::I(imm) => (imm as i64 as u64) & ones(sew),
+                };
+
+                // All the results are computed before writing any
+                let mut results = Vec::new();
+                for idx in 0..vl {
+                    let b = match src {
+                        VSrc::V(vs1) => self.vector.element(vs1, idx, sew),
+                        _ => scalar,
+                    };
+                    let a = self.vector.element(vs2, idx, sew);
+                    let val = match code {
+                        InstCode::Vmerge(_,_,_,_) => if self.vector.mask_bit(0, idx) { b } else { a },
+                        _ if !active(&self.vector, vm, idx) => continue,
+                        _ => arith(code, sew, a, b, self.vector.element(vd, idx, sew)),
+                    };
+                    results.push((idx, val));
+                }
+                self.record_vregs(vd, if compare { 1 } else { group as usize });
+                for (idx, val) in results {
+                    if compare {
+                        self.vector.set_mask_bit(vd, idx, val != 0);
+                    }
+                    else {
+                        self.vector.set_element(vd, idx, sew, val);
+                    }
+                }
+                return Ok(0);
+            }
+
+            if let Some((vd, vs2, vs1, vm)) = code.vreduction() {
+                if !aligned(&[vs2]) {
+                    return Err(illegal(format!("register groups must be aligned to LMUL={}", group)));
+                }
+                if vl == 0 {
+                    return Ok(0);
+                }
+                let mut acc = self.vector.element(vs1, 0, sew);
+                for idx in (0..vl).filter(|idx| active(&self.vector, vm, *idx)) {
+                    acc = reduce(code, sew, acc, self.vector.element(vs2, idx, sew));
+                }
+                self.record_vregs(vd, 1);
+                self.vector.set_element(vd, 0, sew, acc);
+                return Ok(0);
+            }
+
+            if let Some((vd, vs2, vs1)) = code.vmask_logical() {
+                let bits: Vec<bool> = (0..vl)
+                    .map(|idx| mask_logical(code, self.vector.mask_bit(vs2, idx), self.vector.mask_bit(vs1, idx)))
+                    .collect();
+                self.record_vregs(vd, 1);
+                for (idx, bit) in bits.into_iter().enumerate() {
+                    self.vector.set_mask_bit(vd, idx, bit);
+                }
+                return Ok(0);
+            }
+
+            match code {
+                InstCode::VmvXS(_, vs2) => Ok(sext(self.vector.element(vs2, 0, sew), sew) as u64),
+                InstCode::VmvSX(vd, _) => {
+                    if vl > 0 {
+                        self.record_vregs(vd, 1);
+                        self.vector.set_element(vd, 0, sew, x1 & ones(sew));
+                    }
+                    Ok(0)
+                },
+                InstCode::Vcpop(_, vs2, vm) => {
+                    Ok((0..vl).filter(|idx| active(&self.vector, vm, *idx) && self.vector.mask_bit(vs2, *idx)).count() as u64)
+                },
+                InstCode::Vfirst(_, vs2, vm) => {
+                    Ok((0..vl).find(|idx| active(&self.vector, vm, *idx) && self.vector.mask_bit(vs2, *idx))
+                        .map_or(u64::MAX, |idx| idx as u64))
+                },
+                InstCode::Vid(vd, vm) => {
+                    if !aligned(&[vd]) {
+                        return Err(illegal(format!("register groups must be aligned to LMUL={}", group)));
+                    }
+                    if !vm && vd == 0 {
+                        return Err(illegal(String::from("v0 is both the mask and the destination")));
+                    }
+                    let elements: Vec<usize> = (0..vl).filter(|idx| active(&self.vector, vm, *idx)).collect();
+                    self.record_vregs(vd, group as usize);
+                    for idx in elements {
+                        self.vector.set_element(vd, idx, sew, idx as u64 & ones(sew));
+                    }
+                    Ok(0)
+                },
+                // Loads and stores access memory in `vector_access`
+                _ => Ok(0),
+            }
+        }
+
+        /// Perform the accesses of a vector load or store at `pc`, with `x1`
+        /// and `x2` the values of its scalar sources. Returns the triggered
+        /// watchpoints with their old contents, or the reason it failed.
+        pub fn vector_access(&mut self, code: InstCode, pc: u64, x1: u64, x2: u64) -> Result<Vec<(usize, Vec<u8>)>, String> {
+            let illegal = |reason: String| format!("Illegal instruction {} at {:x}, {}", code.mnemonic(), pc, reason);
+            let xmask = if self.isa.xlen == 32 { 0xFFFFFFFF } else { u64::MAX };
+            let store = code.is_vector_store();
+            let (vlenb, vl) = (self.vector.vlenb(), self.vector.vl as usize);
+            let (sew, lmul8) = (self.vector.sew(), self.vector.lmul8());
+
+            // (data register, data EEW, number of registers, evl, vm, offset of element idx)
+            let (vd, eew, regs, evl, vm, offset): (u8, usize, usize, usize, bool, ElementOffset) = match code {
+                InstCode::Vlm(vd, _) | InstCode::Vsm(vd, _) => (vd, 8, 1, vl.div_ceil(8), true, Box::new(|_, idx| idx as u64)),
+                InstCode::Vlre(vd, _, nf, eew) => (vd, eew as usize, nf as usize, nf as usize * vlenb * 8 / eew as usize, true,
+                    Box::new(move |_, idx| (idx * eew as usize / 8) as u64)),
+                InstCode::Vsr(vd, _, nf) => (vd, 8, nf as usize, nf as usize * vlenb, true, Box::new(|_, idx| idx as u64)),
+                _ if self.vector.vill => return Err(illegal(String::from("vtype is illegal"))),
+                InstCode::Vle(vd, _, eew, vm) | InstCode::Vse(vd, _, eew, vm) |
+                InstCode::Vlse(vd, _, _, eew, vm) | InstCode::Vsse(vd, _, _, eew, vm) => {
+                    let emul8 = eew as usize * lmul8 / sew;
+                    if !(1..=64).contains(&emul8) {
+                        return Err(illegal(format!("EMUL of EEW={} is out of range", eew)));
+                    }
+                    let stride = match code {
+                        InstCode::Vlse(_,_,_,_,_) | InstCode::Vsse(_,_,_,_,_) => x2,
+                        _ => eew as u64 / 8,
+                    };
+                    (vd, eew as usize, std::cmp::max(1, emul8 / 8), vl, vm, Box::new(move |_, idx| (idx as u64).wrapping_mul(stride)))
+                },
+                InstCode::Vluxei(vd, _, vs2, eew, vm) | InstCode::Vloxei(vd, _, vs2, eew, vm) |
+                InstCode::Vsuxei(vd, _, vs2, eew, vm) | InstCode::Vsoxei(vd, _, vs2, eew, vm) => {
+                    // The offsets have the EEW of the encoding, the data has SEW
+                    let emul8 = eew as usize * lmul8 / sew;
+                    if !(1..=64).contains(&emul8) {
+                        return Err(illegal(format!("EMUL of EEW={} is out of range", eew)));
+                    }
+                    if !(vs2 as usize).is_multiple_of(std::cmp::max(1, emul8 / 8)) {
+                        return Err(illegal(format!("v{} is not aligned to its EMUL", vs2)));
+                    }
+                    (vd, sew, std::cmp::max(1, lmul8 / 8), vl, vm, Box::new(move |vector, idx| vector.element(vs2, idx, eew as usize)))
+                },
+                _ => unreachable!(),
+            };
+            if eew > self.isa.elen() {
+                return Err(illegal(format!("EEW={} exceeds ELEN={}", eew, self.isa.elen())));
+            }
+            if !(vd as usize).is_multiple_of(regs) {
+                return Err(illegal(format!("v{} is not aligned to its EMUL", vd)));
+            }
+            if !vm && vd == 0 && !store {
+                return Err(illegal(String::from("v0 is both the mask and the destination")));
+            }
+
+            let sz = eew / 8;
+            let mut hits: Vec<(usize, Vec<u8>)> = Vec::new();
+            let mut loaded = Vec::new();
+            let elements: Vec<usize> = (0..evl).filter(|idx| vm || self.vector.mask_bit(0, *idx)).collect();
+            for idx in elements {
+                let addr = x1.wrapping_add(offset(&self.vector, idx)) & xmask;
+                for (watch, old) in self.watch_hits(addr, sz, store) {
+                    if !hits.iter().any(|(seen, _)| *seen == watch) {
+                        hits.push((watch, old));
+                    }
+                }
+                if store {
+                    let val = self.vector.element(vd, idx, eew);
+                    if !self.mem_store(addr, &val.to_le_bytes()[..sz]) {
+                        return Err(format!("Cannot access memory at {:x}", addr));
+                    }
+                    continue;
+                }
+                match self.mem_load(addr, sz, false) {
+                    Some((data, 0)) => {
+                        let mut bytes = [0u8; 8];
+                        bytes[..sz].copy_from_slice(data);
+                        loaded.push((idx, u64::from_le_bytes(bytes)));
+                    },
+                    Some(_) => return Err(format!("Access memory at {:x} across VMAs", addr)),
+                    None => return Err(format!("Cannot access memory at {:x}", addr)),
+                }
+            }
+            if !store {
+                self.record_vregs(vd, regs);
+                for (idx, val) in loaded {
+                    self.vector.set_element(vd, idx, eew, val);
+                }
+            }
+            Ok(hits)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::{Assembler, Inst, Isa};
+
+        /// Assemble `source` and run it on a hart with `vlen`, until the
+        /// label `done`.
+        fn run(source: &str, vlen: usize) -> Program {
+            let mut prog = Assembler::new(source).load().unwrap();
+            prog.isa = Isa::parse("rv64gcv").unwrap();
+            prog.vector = VectorUnit::new(vlen);
+            let done = prog.funcs.iter().find(|(_, _, name)| name == "done").unwrap().0;
+            while prog.program_counter != done {
+                let mut inst = Inst::new();
+                prog.program_counter = loop {
+                    match inst.advance(&mut prog) {
+                        Ok(next) => inst = next,
+                        Err(next_pc) => break next_pc,
+                    }
+                };
+            }
+            prog
+        }
+
+        /// `count` words at `addr`.
+        fn words(prog: &Program, addr: u64, count: usize) -> Vec<u32> {
+            let (data, _) = prog.mem_load(addr, 4 * count, false).unwrap();
+            data.chunks(4).map(|word| u32::from_le_bytes(word.try_into().unwrap())).collect()
+        }
+
+        /// z[i] = x[i] + y[i] over 10 elements, 4 at a time with VLEN=128,
+        /// leaving the word after z alone.
+        #[test]
+        fn strip_mining() {
+            let prog = run("
+                main:   li a0, 10
+                        la a1, x
+                        la a2, y
+                        la a3, z
+                loop:   vsetvli t0, a0, e32,m1,ta,ma
+                        vle32.v v1, (a1)
+                        vle32.v v2, (a2)
+                        vadd.vv v3, v1, v2
+                        vse32.v v3, (a3)
+                        slli t1, t0, 2
+                        add a1, a1, t1
+                        add a2, a2, t1
+                        add a3, a3, t1
+                        sub a0, a0, t0
+                        bnez a0, loop
+                done:   nop
+                        .data
+                x:      .word 1, 2, 3, 4, 5, 6, 7, 8, 9, 10
+                y:      .word 100, 200, 300, 400, 500, 600, 700, 800, 900, -1
+                z:      .zero 44, 0xAA", 128);
+            let z = 0x11000 + 80;
+            assert_eq!(words(&prog, z, 11), [101, 202, 303, 404, 505, 606, 707, 808, 909, 9, 0xAAAAAAAA]);
+            assert_eq!(prog.vector.vl, 2);
+            assert_eq!(prog.vector.vlmax(), 4);
+            // Tail elements are left undisturbed
+            assert_eq!((0..4).map(|idx| prog.vector.element(3, idx, 32)).collect::<Vec<u64>>(), [909, 9, 707, 808]);
+        }
+
+        /// The sum of 20 elements, 8 at a time with LMUL=2 and VLEN=128,
+        /// accumulated in element 0 of v8.
+        #[test]
+        fn reduction() {
+            let prog = run("
+                main:   li a0, 20
+                        la a1, x
+                        vsetivli zero, 1, e32,m1,ta,ma
+                        vmv.s.x v8, zero
+                loop:   vsetvli t0, a0, e32,m2,ta,ma
+                        vle32.v v2, (a1)
+                        vredsum.vs v8, v2, v8
+                        slli t1, t0, 2
+                        add a1, a1, t1
+                        sub a0, a0, t0
+                        bnez a0, loop
+                        vmv.x.s a0, v8
+                done:   nop
+                        .data
+                x:      .word 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, -100", 128);
+            assert_eq!(prog.registers.read(RegID::X10), Some(90));
+            assert_eq!(prog.vector.vlmax(), 8);
+            assert_eq!(prog.vector.vl, 4);
+            // The group v2-v3 holds the last 4 elements, and in its tail the
+            // ones loaded before
+            let group: Vec<u64> = (0..8).map(|idx| prog.vector.element(2, idx, 32)).collect();
+            assert_eq!(group, [17, 18, 19, (-100i32) as u32 as u64, 13, 14, 15, 16]);
+        }
+    }
+}